@@ -16,8 +16,42 @@ pub enum DecoderError<H: HandlePacket> {
     #[error("Packet handler error")]
     PacketHandler(#[source] H::Error),
     /// Invalid packet
-    #[error("Invalid packet")]
-    InvalidPacket,
+    #[error("Invalid packet {byte:#04x} at offset {offset:#x}")]
+    InvalidPacket {
+        /// Header byte of the invalid packet. For packets starting with `0x02`,
+        /// this is the second byte.
+        byte: u8,
+        /// Offset of the start of the invalid packet in the decoded buffer
+        offset: usize,
+    },
+    /// IP packet (TIP, TIP.PGE, TIP.PGD or FUP) with reserved `IPBytes` (`0b101`
+    /// or `0b111`)
+    #[error("Reserved IPBytes {ip_bytes:#05b} in IP packet {byte:#04x} at offset {offset:#x}")]
+    ReservedIpBytes {
+        /// Header byte of the IP packet
+        byte: u8,
+        /// The reserved `IPBytes`, i.e., bits 7:5 of the header byte
+        ip_bytes: u8,
+        /// Offset of the start of the IP packet in the decoded buffer
+        offset: usize,
+    },
+    /// Packet that cannot appear in PSB+, e.g., TNT, TIP or another PSB packet,
+    /// while the PSBEND packet of the last PSB packet is not decoded yet.
+    ///
+    /// This usually indicates a truncated capture where the PSBEND packet is lost,
+    /// and the following packets would otherwise be treated as status packets
+    /// of PSB+ instead of live execution.
+    ///
+    /// This is only reported in strict mode, see
+    /// [`DecodeOptions::strict`][crate::DecodeOptions::strict].
+    #[error("Missing PSBEND before packet {byte:#04x} at offset {offset:#x}")]
+    MissingPsbend {
+        /// Header byte of the packet. For packets starting with `0x02`, this is
+        /// the second byte.
+        byte: u8,
+        /// Offset of the start of the packet in the decoded buffer
+        offset: usize,
+    },
     /// No PSB packet found
     ///
     /// The PSB packet is required to be the start position
@@ -27,6 +61,15 @@ pub enum DecoderError<H: HandlePacket> {
     /// Unexpected EOF
     #[error("Unexpected EOF")]
     UnexpectedEOF,
+    /// Start offset given by [`DecodeOptions::start_offset`][crate::DecodeOptions::start_offset]
+    /// exceeds the length of the decoded buffer
+    #[error("Start offset {offset:#x} exceeds buffer length {len:#x}")]
+    StartOffsetOutOfBounds {
+        /// The start offset
+        offset: usize,
+        /// Length of the decoded buffer
+        len: usize,
+    },
 }
 
 pub(crate) type DecoderResult<T, H> = core::result::Result<T, DecoderError<H>>;
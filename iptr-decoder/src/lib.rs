@@ -7,15 +7,28 @@
 extern crate alloc;
 
 pub mod error;
+pub mod libipt;
 pub mod packet_handler;
 mod raw_packet_handler;
+mod stream;
+mod summary;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
 
 use core::num::NonZero;
 
-pub use raw_packet_handler::{level1::IpReconstructionPattern, level2::PtwPayload};
+pub use raw_packet_handler::{
+    level1::IpReconstructionPattern,
+    level2::{CState, EvdType, PtwPayload, WakeReason},
+};
+pub use stream::StreamDecoder;
+pub use summary::{DecodeSummary, StopReason, ValidationSummary};
 
-use crate::error::{DecoderError, DecoderResult};
+use crate::{
+    error::{DecoderError, DecoderResult},
+    packet_handler::packet_counter::PacketCounter,
+};
 
 /// Packet handler trait
 ///
@@ -27,10 +40,24 @@ pub trait HandlePacket {
 
     /// Callback at begin of decoding.
     ///
-    /// This is useful when using the same handler to process multiple Intel PT
-    /// traces
+    /// This is invoked once by each call of [`decode`], [`decode_with_summary`]
+    /// and [`decode_segments`], and by the first
+    /// [`StreamDecoder::feed`] of a stream. This is useful when using the same
+    /// handler to process multiple Intel PT traces, e.g., to reset per-trace
+    /// states.
     fn at_decode_begin(&mut self) -> Result<(), Self::Error>;
 
+    /// Callback when the decoder synchronizes to the first PSB packet
+    ///
+    /// `psb_offset` is the offset of the PSB packet in the decoded buffer, i.e.,
+    /// the number of leading bytes skipped. This is invoked once per buffer
+    /// before any packet is handled, and is not invoked if
+    /// [`DecodeOptions::sync`] is disabled.
+    #[expect(unused)]
+    fn on_sync(&mut self, context: &DecoderContext, psb_offset: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Handle short TNT packet
     ///
     /// `packet_byte` is the whole byte of short TNT packet. `highest_bit`
@@ -53,9 +80,7 @@ pub trait HandlePacket {
     /// `packet_bytes` is the whole 6 bytes of long TNT packet payload. The
     /// upper 2 bytes are guaranteed to be cleared.
     /// `highest_bit` is the index of highest bit that represents a valid
-    /// Taken/Not-taken bit, guaranteed to be in range 0..=46 or [`u32::MAX`]
-    ///
-    /// If `highest_bit` is [`u32::MAX`], this means there is no Taken/Not-taken bits.
+    /// Taken/Not-taken bit, guaranteed to be in range 0..=46.
     #[expect(unused)]
     fn on_long_tnt_packet(
         &mut self,
@@ -107,11 +132,25 @@ pub trait HandlePacket {
     }
 
     /// Handle PAD packet
+    ///
+    /// This is not invoked if [`DecodeOptions::coalesce_pad`] is set, see
+    /// [`on_pad_run`][HandlePacket::on_pad_run].
     #[expect(unused)]
     fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
         Ok(())
     }
 
+    /// Handle a run of `count` continuous PAD packets.
+    ///
+    /// This is only invoked if [`DecodeOptions::coalesce_pad`] is set, in which
+    /// case [`on_pad_packet`][HandlePacket::on_pad_packet] is not invoked. A run
+    /// split across multiple buffers (e.g., by [`StreamDecoder`]) is reported
+    /// once for each buffer.
+    #[expect(unused)]
+    fn on_pad_run(&mut self, context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Handle CYC packet
     ///
     /// `cyc_packet` is the total content of the CYC packet
@@ -137,6 +176,46 @@ pub trait HandlePacket {
         Ok(())
     }
 
+    /// Handle the change of tracee mode caused by MODE.exec packet, or by
+    /// [`override_tracee_mode`][Self::override_tracee_mode]
+    ///
+    /// This is only invoked when `new_mode` differs from `old_mode`, before
+    /// [`on_mode_packet`][Self::on_mode_packet] (for MODE.exec packets). When
+    /// invoked, [`context.tracee_mode()`][DecoderContext::tracee_mode] is still
+    /// `old_mode`.
+    #[expect(unused)]
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Override current tracee mode after each PSBEND packet.
+    ///
+    /// Some trace sources lack the MODE.exec packet in PSB+ (e.g., when the
+    /// trace is cut at a PSB packet in the middle of a buffer), so that the
+    /// tracee mode set by [`DecodeOptions::tracee_mode`] or the last MODE.exec
+    /// packet may be wrong. Returning `Some(mode)` forces the tracee mode to be
+    /// `mode`, while returning [`None`] keeps current tracee mode.
+    ///
+    /// This is invoked after [`on_psbend_packet`][Self::on_psbend_packet], so
+    /// the override takes precedence over MODE.exec packets in the same PSB+,
+    /// whose result can be read via
+    /// [`context.tracee_mode()`][DecoderContext::tracee_mode]. An override is
+    /// only effective until the next MODE.exec packet, which changes the tracee
+    /// mode as usual. If the tracee mode is changed by override,
+    /// [`on_mode_exec_change`][Self::on_mode_exec_change] is invoked as well.
+    #[expect(unused)]
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, Self::Error> {
+        Ok(None)
+    }
+
     /// Handle MTC packet
     ///
     /// `ctc_payload` is the 8-bit CTC payload value
@@ -217,6 +296,22 @@ pub trait HandlePacket {
         Ok(())
     }
 
+    /// Callback when a packet that cannot appear in PSB+ is decoded before the
+    /// PSBEND packet
+    ///
+    /// `byte` is the header byte of that packet (the second header byte for packets
+    /// starting with `0x02`), and [`DecoderContext::packet_offset`] is its offset.
+    /// This usually indicates a truncated capture where the PSBEND packet is lost.
+    /// After this callback, the decoder leaves PSB+ and handles the packet as live
+    /// execution.
+    ///
+    /// This is not invoked in strict mode, where [`DecoderError::MissingPsbend`]
+    /// is returned instead, see [`DecodeOptions::strict`].
+    #[expect(unused)]
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Handle TraceStop packet
     #[expect(unused)]
     fn on_trace_stop_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
@@ -273,12 +368,35 @@ pub trait HandlePacket {
     /// Handle MWAIT packet
     ///
     /// `mwait_hints` is `MWAIT Hints[7:0]`, `ext` is `EXT[1:0]` (upper 6 bits guaranteed cleared)
-    #[expect(unused)]
+    ///
+    /// By default this invokes [`on_mwait`][HandlePacket::on_mwait] with decoded hints.
     fn on_mwait_packet(
         &mut self,
         context: &DecoderContext,
         mwait_hints: u8,
         ext: u8,
+    ) -> Result<(), Self::Error> {
+        self.on_mwait(
+            context,
+            CState::from_encoding(mwait_hints >> 4),
+            mwait_hints & 0b1111,
+            ext,
+        )
+    }
+
+    /// Handle MWAIT packet with decoded hints
+    ///
+    /// `target_c_state` and `target_sub_c_state` are the target C-state and sub C-state
+    /// in MWAIT hints, `ext` is `EXT[1:0]` (upper 6 bits guaranteed cleared). This is
+    /// only invoked by the default implementation of
+    /// [`on_mwait_packet`][HandlePacket::on_mwait_packet].
+    #[expect(unused)]
+    fn on_mwait(
+        &mut self,
+        context: &DecoderContext,
+        target_c_state: CState,
+        target_sub_c_state: u8,
+        ext: u8,
     ) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -287,13 +405,34 @@ pub trait HandlePacket {
     ///
     /// `hw` is HW, `resolved_thread_c_state` is Resolved Thread C-State (upper 4 bits guaranteed cleared),
     /// `resolved_thread_sub_c_state` is Resolved Thread Sub C-State (upper 4 bits guaranteed cleared)
-    #[expect(unused)]
+    ///
+    /// By default this invokes [`on_pwre`][HandlePacket::on_pwre] with decoded C-state.
     fn on_pwre_packet(
         &mut self,
         context: &DecoderContext,
         hw: bool,
         resolved_thread_c_state: u8,
         resolved_thread_sub_c_state: u8,
+    ) -> Result<(), Self::Error> {
+        self.on_pwre(
+            context,
+            hw,
+            CState::from_encoding(resolved_thread_c_state),
+            resolved_thread_sub_c_state,
+        )
+    }
+
+    /// Handle PWRE packet with decoded C-state
+    ///
+    /// This is only invoked by the default implementation of
+    /// [`on_pwre_packet`][HandlePacket::on_pwre_packet].
+    #[expect(unused)]
+    fn on_pwre(
+        &mut self,
+        context: &DecoderContext,
+        hw: bool,
+        resolved_thread_c_state: CState,
+        resolved_thread_sub_c_state: u8,
     ) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -303,13 +442,35 @@ pub trait HandlePacket {
     /// `last_core_c_state` is Last Core C-State (upper 4 bits guaranteed cleared),
     /// `deepest_core_c_state` is Deepest Core C-State (upper 4 bits guaranteed cleared),
     /// `wake_reason` is Wake Reason (upper 4 bits guaranteed cleared)
-    #[expect(unused)]
+    ///
+    /// By default this invokes [`on_pwrx`][HandlePacket::on_pwrx] with decoded C-states
+    /// and wake reason.
     fn on_pwrx_packet(
         &mut self,
         context: &DecoderContext,
         last_core_c_state: u8,
         deepest_core_c_state: u8,
         wake_reason: u8,
+    ) -> Result<(), Self::Error> {
+        self.on_pwrx(
+            context,
+            CState::from_encoding(last_core_c_state),
+            CState::from_encoding(deepest_core_c_state),
+            WakeReason::from_encoding(wake_reason),
+        )
+    }
+
+    /// Handle PWRX packet with decoded C-states and wake reason
+    ///
+    /// This is only invoked by the default implementation of
+    /// [`on_pwrx_packet`][HandlePacket::on_pwrx_packet].
+    #[expect(unused)]
+    fn on_pwrx(
+        &mut self,
+        context: &DecoderContext,
+        last_core_c_state: CState,
+        deepest_core_c_state: CState,
+        wake_reason: WakeReason,
     ) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -317,12 +478,27 @@ pub trait HandlePacket {
     /// Handle EVD packet
     ///
     /// `r#type` is `Type[5:0]` (upper 2 bits guaranteed cleared), `payload` is `Payload[63:0]`
-    #[expect(unused)]
+    ///
+    /// By default this invokes [`on_evd`][HandlePacket::on_evd] with decoded type.
     fn on_evd_packet(
         &mut self,
         context: &DecoderContext,
         r#type: u8,
         payload: u64,
+    ) -> Result<(), Self::Error> {
+        self.on_evd(context, EvdType::from_encoding(r#type), payload)
+    }
+
+    /// Handle EVD packet with decoded type
+    ///
+    /// This is only invoked by the default implementation of
+    /// [`on_evd_packet`][HandlePacket::on_evd_packet].
+    #[expect(unused)]
+    fn on_evd(
+        &mut self,
+        context: &DecoderContext,
+        evd_type: EvdType,
+        payload: u64,
     ) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -381,7 +557,7 @@ pub trait HandlePacket {
 }
 
 /// Execution mode
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TraceeMode {
     /// 16-bit mode
     Mode16 = 16,
@@ -397,12 +573,43 @@ impl TraceeMode {
     pub fn bitness(self) -> u32 {
         self as u32
     }
+
+    /// Get tracee mode from the given bitness, returns [`None`] if the
+    /// bitness is not one of 16, 32 and 64.
+    #[must_use]
+    pub fn try_from_bitness(bitness: u32) -> Option<Self> {
+        match bitness {
+            16 => Some(Self::Mode16),
+            32 => Some(Self::Mode32),
+            64 => Some(Self::Mode64),
+            _ => None,
+        }
+    }
+
+    /// Wrap the address to the address width of current tracee mode.
+    ///
+    /// 64-bit addresses are kept as-is, since canonical addresses are
+    /// already sign-extended by the IP reconstruction.
+    #[must_use]
+    pub fn mask_address(self, address: u64) -> u64 {
+        match self {
+            Self::Mode16 => address & 0xFFFF,
+            Self::Mode32 => address & 0xFFFF_FFFF,
+            Self::Mode64 => address,
+        }
+    }
 }
 
 /// Decoder context during decoding
+#[expect(clippy::struct_excessive_bools)]
 pub struct DecoderContext {
     /// Next position in target buffer
     pos: usize,
+    /// Start position of current packet in target buffer
+    packet_start: usize,
+    /// Offset of target buffer in the whole trace, which is non-zero when the
+    /// trace is decoded in chunks by [`StreamDecoder`]
+    base_offset: usize,
     /// Current tracee mode (will be modified by MODE.exec packet)
     tracee_mode: TraceeMode,
     /// Information about packet block.
@@ -410,6 +617,26 @@ pub struct DecoderContext {
     /// If this field is [`Some`], this indicates that current mode
     /// is packet block mode, which means we are between a BBP and BEP
     packet_block: Option<PacketBlockInformation>,
+    /// Whether we are between a PSB and PSBEND packets
+    in_psb: bool,
+    /// Number of PSB packets decoded
+    psb_count: usize,
+    /// End position of the last TraceStop packet
+    trace_stop_end: Option<usize>,
+    /// Whether to stop decoding at TraceStop packet
+    stop_at_trace_stop: bool,
+    /// Whether to report continuous PAD packets at once
+    coalesce_pad: bool,
+    /// Interval in bytes of reporting progress, see [`decode_with_progress`]
+    progress_interval: Option<NonZero<usize>>,
+    /// Position at or after which the progress is reported next time
+    next_progress_pos: usize,
+    /// Maximum number of packet handler errors to skip
+    handler_error_budget: usize,
+    /// Number of packet handler errors skipped
+    skipped_handler_errors: usize,
+    /// Whether to reject packets with reserved bits set
+    strict: bool,
 }
 
 /// Size of packet block
@@ -444,12 +671,68 @@ struct PacketBlockInformation {
 }
 
 impl DecoderContext {
+    /// Create a context that is not bound to any decoding, with given
+    /// tracee mode.
+    ///
+    /// This is useful when invoking methods of packet handlers outside
+    /// of decoding, e.g., flushing states after decoding is finished, or unit
+    /// testing packet handlers (see [`in_psb`][Self::in_psb]).
+    #[must_use]
+    pub fn new(tracee_mode: TraceeMode) -> Self {
+        Self {
+            pos: 0,
+            packet_start: 0,
+            base_offset: 0,
+            tracee_mode,
+            packet_block: None,
+            in_psb: false,
+            psb_count: 0,
+            trace_stop_end: None,
+            stop_at_trace_stop: false,
+            coalesce_pad: false,
+            progress_interval: None,
+            next_progress_pos: usize::MAX,
+            handler_error_budget: 0,
+            skipped_handler_errors: 0,
+            strict: false,
+        }
+    }
+
+    /// Set whether the packets are in PSB+, see [`is_in_psb`][Self::is_in_psb].
+    ///
+    /// Together with [`new`][Self::new], this is a testing aid, which allows
+    /// invoking methods of custom packet handlers directly, e.g.,
+    /// `handler.on_fup_packet(&context, pattern)`, without encoding packets into
+    /// a buffer and decoding it.
+    pub fn in_psb(&mut self, in_psb: bool) -> &mut Self {
+        self.in_psb = in_psb;
+        self
+    }
+
     /// Get current tracee mode
     #[must_use]
     pub fn tracee_mode(&self) -> TraceeMode {
         self.tracee_mode
     }
 
+    /// Get the offset of current packet in the decoded trace.
+    ///
+    /// In packet handlers, this is the offset of the first byte of the packet
+    /// being handled. For [`HandlePacket::on_pad_run`], this is the offset of the
+    /// first PAD packet in the run. When decoding with [`StreamDecoder`], this is
+    /// the offset in the whole stream instead of the fed chunk.
+    #[must_use]
+    pub fn packet_offset(&self) -> usize {
+        self.base_offset + self.packet_start
+    }
+
+    /// Get the number of packet handler errors skipped so far, see
+    /// [`DecodeOptions::handler_error_budget`].
+    #[must_use]
+    pub fn skipped_handler_errors(&self) -> usize {
+        self.skipped_handler_errors
+    }
+
     /// Whether we are between a BBP and BEP packets.
     ///
     /// When you invokes this method in a BBP packet handler,
@@ -459,15 +742,57 @@ impl DecoderContext {
     pub fn is_in_packet_blocks(&self) -> bool {
         self.packet_block.is_some()
     }
+
+    /// Report progress of decoding a buffer of `len` bytes, and schedule the
+    /// next report.
+    #[cold]
+    fn report_progress(&mut self, len: usize, on_progress: &mut dyn FnMut(usize, usize)) {
+        if let Some(interval) = self.progress_interval {
+            on_progress(self.pos, len);
+            self.next_progress_pos = self.pos.saturating_add(interval.get());
+        }
+    }
+
+    /// Whether we are between a PSB and PSBEND packets, i.e., in PSB+.
+    ///
+    /// Packets in PSB+ are status-only packets that describe current
+    /// processor state, instead of live execution. For example, a FUP packet
+    /// in PSB+ only indicates current IP.
+    ///
+    /// When you invokes this method in a PSB packet handler,
+    /// this will return the status **before** current PSB packet.
+    /// Same for PSBEND packet.
+    #[must_use]
+    pub fn is_in_psb(&self) -> bool {
+        self.in_psb
+    }
+
+    /// Get the zero-based index of the last PSB packet, i.e., the PSB region
+    /// current packet belongs to, or `None` if no PSB packet is decoded yet
+    /// (e.g., when decoding from [`DecodeOptions::start_offset`]).
+    ///
+    /// Different from [`is_in_psb`][Self::is_in_psb], when you invokes this
+    /// method in a PSB packet handler, this will return the index of current
+    /// PSB packet.
+    #[must_use]
+    pub fn psb_index(&self) -> Option<usize> {
+        self.psb_count.checked_sub(1)
+    }
 }
 
 /// Options for [`decode`].
 ///
 /// You can create default options via [`DecodeOptions::default`].
 #[derive(Clone, Copy)]
+#[expect(clippy::struct_excessive_bools)]
 pub struct DecodeOptions {
     tracee_mode: TraceeMode,
     no_sync: bool,
+    stop_at_trace_stop: bool,
+    coalesce_pad: bool,
+    handler_error_budget: usize,
+    strict: bool,
+    start_offset: Option<usize>,
 }
 
 impl Default for DecodeOptions {
@@ -475,6 +800,11 @@ impl Default for DecodeOptions {
         Self {
             tracee_mode: TraceeMode::Mode64,
             no_sync: false,
+            stop_at_trace_stop: false,
+            coalesce_pad: false,
+            handler_error_budget: 0,
+            strict: false,
+            start_offset: None,
         }
     }
 }
@@ -496,6 +826,94 @@ impl DecodeOptions {
         self.no_sync = !sync;
         self
     }
+
+    /// Start decoding at exactly `offset` of the buffer, without syncing forward
+    /// for a PSB packet.
+    ///
+    /// This is useful when the offset of a packet boundary is already known, e.g.,
+    /// from the AUX offset of a perf sample, where syncing forward would skip the
+    /// packets before the next PSB packet. This overrides [`sync`][Self::sync], and
+    /// [`HandlePacket::on_sync`] is not invoked. The context is set up as decoding
+    /// from the beginning, so the tracee mode is [`tracee_mode`][Self::tracee_mode]
+    /// until the next MODE.exec packet.
+    ///
+    /// The offset is applied to each buffer (each segment for [`decode_segments`]),
+    /// or to the whole stream for [`StreamDecoder`]. An offset equal to the buffer
+    /// length decodes nothing, while a larger offset results in
+    /// [`DecoderError::StartOffsetOutOfBounds`].
+    ///
+    /// By default, the start position is decided by [`sync`][Self::sync].
+    pub fn start_offset(&mut self, offset: usize) -> &mut Self {
+        self.start_offset = Some(offset);
+        self
+    }
+
+    /// Set whether the decoder will stop at the first TraceStop packet.
+    ///
+    /// Data after TraceStop is not guaranteed to be valid until the next PSB
+    /// packet. If set to `true`, the decoder will stop right after TraceStop
+    /// without decoding the remaining bytes, which avoids errors of trailing
+    /// garbage data.
+    ///
+    /// Default is `false`.
+    pub fn stop_at_trace_stop(&mut self, stop: bool) -> &mut Self {
+        self.stop_at_trace_stop = stop;
+        self
+    }
+
+    /// Set whether continuous PAD packets are reported at once via
+    /// [`HandlePacket::on_pad_run`] instead of [`HandlePacket::on_pad_packet`]
+    /// for each of them.
+    ///
+    /// Default is `false`.
+    pub fn coalesce_pad(&mut self, coalesce: bool) -> &mut Self {
+        self.coalesce_pad = coalesce;
+        self
+    }
+
+    /// Set the maximum number of packet handler errors to skip.
+    ///
+    /// By default, any error returned by the packet handler aborts the decoding.
+    /// For best-effort processing (e.g., a logger whose sink occasionally fails),
+    /// errors returned by packet handlers when handling packets can be skipped:
+    /// the rest of the erroneous packet is decoded without invoking the packet
+    /// handler (decoder states such as the tracee mode are still updated), and
+    /// decoding continues with the next packet. Once `budget` errors
+    /// have been skipped, the next error aborts the decoding as usual. Skipped
+    /// errors are logged if the `log_handler` feature is enabled, and counted in
+    /// [`DecodeSummary::skipped_handler_errors`].
+    ///
+    /// This only applies to [`DecoderError::PacketHandler`] returned when handling
+    /// packets. Errors of malformed packets, as well as errors returned by
+    /// [`HandlePacket::at_decode_begin`] and [`HandlePacket::on_sync`], always
+    /// abort the decoding. The budget is applied to each buffer (each segment for
+    /// [`decode_segments`]), or each [`StreamDecoder`].
+    ///
+    /// Default is `0`.
+    pub fn handler_error_budget(&mut self, budget: usize) -> &mut Self {
+        self.handler_error_budget = budget;
+        self
+    }
+
+    /// Set whether to validate that reserved bits of packets are cleared.
+    ///
+    /// In strict mode, a packet with any reserved bit set is rejected with
+    /// [`DecoderError::InvalidPacket`], which is useful for detecting corrupted
+    /// traces or decoding at wrong offsets. In lenient mode, reserved bits are
+    /// ignored, since future processors may define them.
+    ///
+    /// Payloads of PIP and VMCS packets have no reserved bits in the packet
+    /// layout, and are thus not checked.
+    ///
+    /// Besides, a packet that cannot appear in PSB+ before the PSBEND packet is
+    /// rejected with [`DecoderError::MissingPsbend`] in strict mode. In lenient
+    /// mode, [`HandlePacket::on_missing_psbend`] is invoked and decoding continues.
+    ///
+    /// Default is `false`.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
 }
 
 const PSB_BYTES: [u8; 16] = [
@@ -504,6 +922,11 @@ const PSB_BYTES: [u8; 16] = [
 
 /// Decode the given Intel PT buffer.
 ///
+/// [`HandlePacket::at_decode_begin`] is invoked before decoding, so handlers
+/// which reset their states there (such as
+/// [`PacketCounter`][packet_handler::packet_counter::PacketCounter]) only
+/// reflect the latest call.
+///
 /// Note that the Linux Perf tool records more than raw Intel PT packets,
 /// some sideband data is also recorded. As a result, you need to extract AUX data
 /// from the `perf.data` in order to use this method.
@@ -519,29 +942,1351 @@ pub fn decode<H: HandlePacket>(
     options: DecodeOptions,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
-    let DecodeOptions {
-        tracee_mode,
-        no_sync,
-    } = options;
+    packet_handler
+        .at_decode_begin()
+        .map_err(DecoderError::PacketHandler)?;
 
+    decode_segment(buf, options, packet_handler).into_result()
+}
+
+/// Decode the given Intel PT buffer, and return a summary of decoding.
+///
+/// This is the same as [`decode`], except that the returned [`DecodeSummary`] tells
+/// how many bytes are decoded and why the decoding stops.
+pub fn decode_with_summary<H: HandlePacket>(
+    buf: &[u8],
+    options: DecodeOptions,
+    packet_handler: &mut H,
+) -> DecodeSummary<H> {
+    if let Err(err) = packet_handler.at_decode_begin() {
+        return DecodeSummary {
+            bytes_consumed: 0,
+            stopped_at: StopReason::Error(DecoderError::PacketHandler(err)),
+            skipped_handler_errors: 0,
+        };
+    }
+
+    decode_segment(buf, options, packet_handler)
+}
+
+/// Decode the given Intel PT buffer, and report the decoding progress.
+///
+/// This is the same as [`decode`], except that `on_progress` is invoked with
+/// current position and buffer length each time at least `interval` bytes have
+/// been decoded since the last report, and once more with the buffer length when
+/// decoding reaches the end of buffer (including stopping at TraceStop). The
+/// reported positions are strictly increasing.
+pub fn decode_with_progress<H: HandlePacket>(
+    buf: &[u8],
+    options: DecodeOptions,
+    packet_handler: &mut H,
+    interval: NonZero<usize>,
+    mut on_progress: impl FnMut(usize, usize),
+) -> DecoderResult<(), H> {
     packet_handler
         .at_decode_begin()
         .map_err(DecoderError::PacketHandler)?;
 
-    let start_pos = if no_sync {
+    decode_segment_with_progress(
+        buf,
+        options,
+        packet_handler,
+        Some(interval),
+        &mut on_progress,
+    )
+    .into_result()
+}
+
+/// Validate the structure of the given Intel PT buffer without any user handler.
+///
+/// This runs the same decoding as [`decode_with_summary`] with an internal handler
+/// which only counts packets, so that the returned [`ValidationSummary`] tells
+/// whether the buffer is well-formed, how many packets it contains, and where the
+/// decoding stops. Since the handler is statically dispatched and almost does
+/// nothing, this is also useful to measure the throughput of decoding itself,
+/// separated from the cost of packet handlers.
+#[must_use]
+pub fn validate(buf: &[u8], options: DecodeOptions) -> ValidationSummary {
+    let mut packet_counter = PacketCounter::new();
+    let decode_summary = decode_with_summary(buf, options, &mut packet_counter);
+    ValidationSummary {
+        packet_count: packet_counter.packet_count(),
+        decode_summary,
+    }
+}
+
+/// Decode the given Intel PT buffers as consecutive segments of one trace.
+///
+/// This is useful for traces split into multiple buffers, such as AUXTRACE
+/// records in `perf.data`. Different from invoking [`decode`] for each buffer,
+/// [`HandlePacket::at_decode_begin`] is only invoked once, so the state of
+/// `packet_handler` is kept across segments. The [`DecodeOptions`] are applied
+/// to each segment, i.e., each segment is synced to its own first PSB packet
+/// by default.
+pub fn decode_segments<H: HandlePacket, B: AsRef<[u8]>>(
+    segments: impl IntoIterator<Item = B>,
+    options: DecodeOptions,
+    packet_handler: &mut H,
+) -> DecoderResult<(), H> {
+    packet_handler
+        .at_decode_begin()
+        .map_err(DecoderError::PacketHandler)?;
+
+    for segment in segments {
+        decode_segment(segment.as_ref(), options, packet_handler).into_result()?;
+    }
+
+    Ok(())
+}
+
+/// Decode a single buffer without invoking [`HandlePacket::at_decode_begin`]
+fn decode_segment<H: HandlePacket>(
+    buf: &[u8],
+    options: DecodeOptions,
+    packet_handler: &mut H,
+) -> DecodeSummary<H> {
+    decode_segment_with_progress(buf, options, packet_handler, None, &mut |_, _| {})
+}
+
+/// Decode a single buffer without invoking [`HandlePacket::at_decode_begin`],
+/// and report the progress by `on_progress` if `progress_interval` is given
+fn decode_segment_with_progress<H: HandlePacket>(
+    buf: &[u8],
+    options: DecodeOptions,
+    packet_handler: &mut H,
+    progress_interval: Option<NonZero<usize>>,
+    on_progress: &mut dyn FnMut(usize, usize),
+) -> DecodeSummary<H> {
+    let DecodeOptions {
+        tracee_mode,
+        no_sync,
+        stop_at_trace_stop,
+        coalesce_pad,
+        handler_error_budget,
+        strict,
+        start_offset,
+    } = options;
+
+    let start_pos = if let Some(start_offset) = start_offset {
+        if start_offset > buf.len() {
+            return DecodeSummary {
+                bytes_consumed: 0,
+                stopped_at: StopReason::Error(DecoderError::StartOffsetOutOfBounds {
+                    offset: start_offset,
+                    len: buf.len(),
+                }),
+                skipped_handler_errors: 0,
+            };
+        }
+        start_offset
+    } else if no_sync {
         0
     } else {
         let Some(start_pos) = memchr::memmem::find(buf, &PSB_BYTES) else {
-            return Err(DecoderError::NoPsb);
+            return DecodeSummary {
+                bytes_consumed: 0,
+                stopped_at: StopReason::Error(DecoderError::NoPsb),
+                skipped_handler_errors: 0,
+            };
         };
         start_pos
     };
 
     let mut context = DecoderContext {
         pos: start_pos,
+        packet_start: start_pos,
+        base_offset: 0,
         tracee_mode,
         packet_block: None,
+        in_psb: false,
+        psb_count: 0,
+        trace_stop_end: None,
+        stop_at_trace_stop,
+        coalesce_pad,
+        progress_interval,
+        next_progress_pos: progress_interval.map_or(usize::MAX, |interval| {
+            start_pos.saturating_add(interval.get())
+        }),
+        handler_error_budget,
+        skipped_handler_errors: 0,
+        strict,
     };
 
-    raw_packet_handler::level1::decode(buf, &mut context, packet_handler)
+    if start_offset.is_none()
+        && !no_sync
+        && let Err(err) = packet_handler.on_sync(&context, start_pos)
+    {
+        return DecodeSummary {
+            bytes_consumed: start_pos,
+            stopped_at: StopReason::Error(DecoderError::PacketHandler(err)),
+            skipped_handler_errors: 0,
+        };
+    }
+
+    match raw_packet_handler::level1::decode(buf, &mut context, packet_handler, on_progress) {
+        Ok(()) => match context.trace_stop_end {
+            Some(trace_stop_end) if context.stop_at_trace_stop || trace_stop_end == context.pos => {
+                DecodeSummary {
+                    bytes_consumed: trace_stop_end,
+                    stopped_at: StopReason::TraceStop,
+                    skipped_handler_errors: context.skipped_handler_errors,
+                }
+            }
+            _ => DecodeSummary {
+                bytes_consumed: context.pos,
+                stopped_at: StopReason::EndOfBuffer,
+                skipped_handler_errors: context.skipped_handler_errors,
+            },
+        },
+        Err(err) => DecodeSummary {
+            bytes_consumed: context.pos,
+            stopped_at: StopReason::Error(err),
+            skipped_handler_errors: context.skipped_handler_errors,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet_handler::nop::NopPacketHandler, test_utils::PSBEND_BYTES};
+
+    #[derive(Default)]
+    struct SyncRecorder {
+        psb_offset: Option<usize>,
+    }
+
+    impl HandlePacket for SyncRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_sync(
+            &mut self,
+            _context: &DecoderContext,
+            psb_offset: usize,
+        ) -> Result<(), Self::Error> {
+            self.psb_offset = Some(psb_offset);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct LongTntRecorder {
+        long_tnt: Option<(u64, u32)>,
+    }
+
+    impl HandlePacket for LongTntRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_long_tnt_packet(
+            &mut self,
+            _context: &DecoderContext,
+            packet_bytes: NonZero<u64>,
+            highest_bit: u32,
+        ) -> Result<(), Self::Error> {
+            assert!(self.long_tnt.is_none(), "Only one long TNT expected");
+            self.long_tnt = Some((packet_bytes.get(), highest_bit));
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct PadRecorder {
+        pad_packets: usize,
+        pad_runs: usize,
+        last_pad_run: Option<usize>,
+    }
+
+    impl HandlePacket for PadRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_pad_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+            self.pad_packets += 1;
+            Ok(())
+        }
+
+        fn on_pad_run(
+            &mut self,
+            _context: &DecoderContext,
+            count: usize,
+        ) -> Result<(), Self::Error> {
+            self.pad_runs += 1;
+            self.last_pad_run = Some(count);
+            Ok(())
+        }
+    }
+
+    const TRACE_STOP_BYTES: [u8; 2] = [0x02, 0x83];
+
+    #[test]
+    fn test_tracee_mode_bitness() {
+        for tracee_mode in [TraceeMode::Mode16, TraceeMode::Mode32, TraceeMode::Mode64] {
+            assert_eq!(
+                TraceeMode::try_from_bitness(tracee_mode.bitness()),
+                Some(tracee_mode)
+            );
+        }
+        assert_eq!(TraceeMode::try_from_bitness(8), None);
+    }
+
+    #[test]
+    fn test_tracee_mode_mask_address() {
+        assert_eq!(TraceeMode::Mode16.mask_address(0x1_2345), 0x2345);
+        assert_eq!(TraceeMode::Mode32.mask_address(0x1_2345_6789), 0x2345_6789);
+        assert_eq!(
+            TraceeMode::Mode64.mask_address(0x1_2345_6789),
+            0x1_2345_6789
+        );
+        // Canonical addresses are kept in 64-bit mode
+        assert_eq!(
+            TraceeMode::Mode64.mask_address(0xFFFF_8000_0000_1000),
+            0xFFFF_8000_0000_1000
+        );
+        assert_eq!(
+            TraceeMode::Mode32.mask_address(0xFFFF_8000_0000_1000),
+            0x1000
+        );
+    }
+
+    #[test]
+    fn test_summary_trace_stop() {
+        let mut buf = [0u8; 20];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..].copy_from_slice(&TRACE_STOP_BYTES);
+
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 20);
+        assert!(matches!(summary.stopped_at, StopReason::TraceStop));
+
+        // PAD after TraceStop
+        let mut buf = [0u8; 21];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&TRACE_STOP_BYTES);
+        buf[18..20].copy_from_slice(&PSBEND_BYTES);
+
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 21);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+    }
+
+    #[test]
+    fn test_stop_at_trace_stop() {
+        // Truncated TIP packet after TraceStop
+        let mut buf = [0u8; 22];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&TRACE_STOP_BYTES);
+        buf[18..].copy_from_slice(&[0xCD, 0x00, 0x10, 0x00]);
+
+        let mut options = DecodeOptions::default();
+        options.stop_at_trace_stop(true);
+        let summary = decode_with_summary(&buf, options, &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 18);
+        assert!(matches!(summary.stopped_at, StopReason::TraceStop));
+
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 18);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::UnexpectedEOF)
+        ));
+    }
+
+    #[derive(Default)]
+    struct MissingPsbendRecorder {
+        missing_psbend: Option<(u8, usize)>,
+        short_tnt_in_psb: Option<bool>,
+    }
+
+    impl HandlePacket for MissingPsbendRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_missing_psbend(
+            &mut self,
+            context: &DecoderContext,
+            byte: u8,
+        ) -> Result<(), Self::Error> {
+            assert!(
+                self.missing_psbend.is_none(),
+                "Only one missing PSBEND expected"
+            );
+            self.missing_psbend = Some((byte, context.packet_offset()));
+            Ok(())
+        }
+
+        fn on_short_tnt_packet(
+            &mut self,
+            context: &DecoderContext,
+            _packet_byte: NonZero<u8>,
+            _highest_bit: u32,
+        ) -> Result<(), Self::Error> {
+            self.short_tnt_in_psb = Some(context.is_in_psb());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_missing_psbend() {
+        let mut strict = DecodeOptions::default();
+        strict.strict(true);
+
+        // PSB+ without PSBEND, followed by PAD and short TNT
+        let mut buf = [0u8; 18];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[17] = 0b0000_0110;
+
+        let summary = decode_with_summary(&buf, strict, &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 17);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::MissingPsbend {
+                byte: 0b0000_0110,
+                offset: 17
+            })
+        ));
+
+        // Lenient mode reports the missing PSBEND and leaves PSB+
+        let mut recorder = MissingPsbendRecorder::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut recorder);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert_eq!(recorder.missing_psbend, Some((0b0000_0110, 17)));
+        assert_eq!(recorder.short_tnt_in_psb, Some(false));
+
+        // Offset is relative to the whole stream
+        let mut decoder = StreamDecoder::new(strict);
+        decoder.feed(&buf[..10], &mut NopPacketHandler).unwrap();
+        assert!(matches!(
+            decoder.feed(&buf[10..], &mut NopPacketHandler),
+            Err(DecoderError::MissingPsbend {
+                byte: 0b0000_0110,
+                offset: 17
+            })
+        ));
+
+        // Another PSB before PSBEND
+        let mut buf = [0u8; 34];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..32].copy_from_slice(&PSB_BYTES);
+        buf[32..].copy_from_slice(&PSBEND_BYTES);
+        let summary = decode_with_summary(&buf, strict, &mut NopPacketHandler);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::MissingPsbend {
+                byte: 0x82,
+                offset: 16
+            })
+        ));
+
+        // The new PSB starts a new PSB+ in lenient mode
+        let mut recorder = MissingPsbendRecorder::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut recorder);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert_eq!(recorder.missing_psbend, Some((0x82, 16)));
+
+        // TIP in PSB+ is rejected before its IP payload is decoded
+        let mut buf = [0u8; 19];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..].copy_from_slice(&[0x2D, 0x00, 0x10]);
+        let summary = decode_with_summary(&buf, strict, &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 16);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::MissingPsbend {
+                byte: 0x2D,
+                offset: 16
+            })
+        ));
+        let mut recorder = MissingPsbendRecorder::default();
+        decode(&buf, DecodeOptions::default(), &mut recorder).unwrap();
+        assert_eq!(recorder.missing_psbend, Some((0x2D, 16)));
+
+        // Short TNT after PSBEND is live execution
+        let mut buf = [0u8; 19];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18] = 0b0000_0110;
+        let mut recorder = MissingPsbendRecorder::default();
+        let summary = decode_with_summary(&buf, strict, &mut recorder);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert!(recorder.missing_psbend.is_none());
+    }
+
+    #[test]
+    fn test_summary_unexpected_eof() {
+        // TIP packet with 8-byte IP, but truncated
+        let mut buf = [0u8; 22];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..].copy_from_slice(&[0xCD, 0x00, 0x10, 0x00]);
+
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, 18);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::UnexpectedEOF)
+        ));
+    }
+
+    /// Decode a long TNT packet with given 6-byte payload after PSB+
+    fn decode_long_tnt(payload: u64) -> (DecodeSummary<LongTntRecorder>, LongTntRecorder) {
+        let mut buf = [0u8; 26];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..20].copy_from_slice(&[0x02, 0xA3]);
+        buf[20..].copy_from_slice(&payload.to_le_bytes()[..6]);
+
+        let mut handler = LongTntRecorder::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut handler);
+        (summary, handler)
+    }
+
+    #[test]
+    fn test_long_tnt_malformed() {
+        // All-zero payload, i.e., no stop bit
+        // Only stop bit, without any Taken/Not-taken bits
+        for payload in [0, 1] {
+            let (summary, handler) = decode_long_tnt(payload);
+            assert_eq!(summary.bytes_consumed, 18);
+            assert!(matches!(
+                summary.stopped_at,
+                StopReason::Error(DecoderError::InvalidPacket {
+                    byte: 0xA3,
+                    offset: 18
+                })
+            ));
+            assert!(handler.long_tnt.is_none());
+        }
+
+        // Truncated
+        let mut buf = [0u8; 24];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..20].copy_from_slice(&[0x02, 0xA3]);
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut NopPacketHandler);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::UnexpectedEOF)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_packet_position() {
+        for (packet, byte, offset) in [
+            // Unknown byte after PAD
+            (&[0x00, 0x05][..], 0x05, 19),
+            // Unknown level2 byte
+            (&[0x02, 0xFF][..], 0xFF, 18),
+        ] {
+            let mut buf = [0u8; 32];
+            buf[..16].copy_from_slice(&PSB_BYTES);
+            buf[16..18].copy_from_slice(&PSBEND_BYTES);
+            buf[18..(18 + packet.len())].copy_from_slice(packet);
+            let buf = &buf[..(18 + packet.len())];
+            let err = decode(buf, DecodeOptions::default(), &mut NopPacketHandler).unwrap_err();
+            let DecoderError::InvalidPacket {
+                byte: err_byte,
+                offset: err_offset,
+            } = err
+            else {
+                panic!("Unexpected error {err}");
+            };
+            assert_eq!((err_byte, err_offset), (byte, offset));
+        }
+    }
+
+    #[test]
+    fn test_ip_bytes_per_tracee_mode() {
+        for tracee_mode in [TraceeMode::Mode16, TraceeMode::Mode32, TraceeMode::Mode64] {
+            for ip_bytes in 0..=0b111u8 {
+                // TIP with 8-byte payload, the unused bytes are decoded as PADs
+                let mut buf = [0u8; 16 + 2 + 9];
+                buf[..16].copy_from_slice(&PSB_BYTES);
+                buf[16..18].copy_from_slice(&PSBEND_BYTES);
+                buf[18] = (ip_bytes << 5) | 0x0D;
+                let mut options = DecodeOptions::default();
+                options.tracee_mode(tracee_mode);
+                let res = decode(&buf, options, &mut NopPacketHandler);
+                if ip_bytes == 0b101 || ip_bytes == 0b111 {
+                    let Err(DecoderError::ReservedIpBytes {
+                        byte,
+                        ip_bytes: err_ip_bytes,
+                        offset,
+                    }) = res
+                    else {
+                        panic!("Reserved IPBytes {ip_bytes:#05b} accepted in {tracee_mode:?}");
+                    };
+                    assert_eq!((byte, err_ip_bytes, offset), (buf[18], ip_bytes, 18));
+                } else {
+                    assert!(res.is_ok(), "{ip_bytes:#05b} rejected in {tracee_mode:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_tnt_stop_bit() {
+        for stop_bit in 1..48 {
+            let (summary, handler) = decode_long_tnt(1 << stop_bit);
+            assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+            assert_eq!(handler.long_tnt, Some((1 << stop_bit, stop_bit - 1)));
+        }
+        let (_, handler) = decode_long_tnt(0xFFFF_FFFF_FFFF);
+        assert_eq!(handler.long_tnt, Some((0xFFFF_FFFF_FFFF, 46)));
+
+        // Arbitrary payloads never produce out-of-range highest bits
+        let mut payload = 0x1234_5678_9ABC_DEF0u64;
+        for _ in 0..1000 {
+            payload = payload
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            let payload = payload >> (payload % 64);
+            let (summary, handler) = decode_long_tnt(payload);
+            match handler.long_tnt {
+                None => assert!(matches!(
+                    summary.stopped_at,
+                    StopReason::Error(DecoderError::InvalidPacket { .. })
+                )),
+                Some((_, highest_bit)) => assert!(highest_bit <= 46),
+            }
+        }
+    }
+
+    #[test]
+    fn test_coalesce_pad() {
+        let mut buf = [0u8; 1020];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[1018..].copy_from_slice(&PSBEND_BYTES);
+
+        let mut handler = PadRecorder::default();
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        assert_eq!(handler.pad_packets, 1000);
+        assert_eq!(handler.pad_runs, 0);
+
+        let mut options = DecodeOptions::default();
+        options.coalesce_pad(true);
+        let mut handler = PadRecorder::default();
+        let summary = decode_with_summary(&buf, options, &mut handler);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert_eq!(summary.bytes_consumed, 1020);
+        assert_eq!(handler.pad_packets, 0);
+        assert_eq!(handler.pad_runs, 1);
+        assert_eq!(handler.last_pad_run, Some(1000));
+    }
+
+    #[test]
+    fn test_c_state_and_wake_reason() {
+        for encoding in 0..0xF {
+            assert_eq!(CState::from_encoding(encoding).number(), encoding + 1);
+        }
+        assert_eq!(CState::from_encoding(0xF), CState::C0);
+        assert_eq!(CState::from_encoding(0xF1), CState::C2);
+        assert_eq!(CState::from_encoding(0x5), CState::C6);
+
+        let wake_reasons = [
+            (0x1, WakeReason::INTERRUPT),
+            (0x2, WakeReason::TIMER_DEADLINE),
+            (0x4, WakeReason::STORE_TO_MONITORED_ADDRESS),
+            (0x8, WakeReason::HARDWARE_WAKE),
+            (0x3, WakeReason::INTERRUPT | WakeReason::TIMER_DEADLINE),
+            (0xF8, WakeReason::HARDWARE_WAKE),
+        ];
+        for (encoding, wake_reason) in wake_reasons {
+            assert_eq!(WakeReason::from_encoding(encoding), wake_reason);
+        }
+        let wake_reason = WakeReason::from_encoding(0x3);
+        assert!(wake_reason.contains(WakeReason::INTERRUPT));
+        assert!(!wake_reason.contains(WakeReason::HARDWARE_WAKE));
+        assert!(WakeReason::from_encoding(0x0).is_empty());
+        #[cfg(feature = "alloc")]
+        {
+            use alloc::string::ToString;
+
+            assert_eq!(wake_reason.to_string(), "Interrupt | TimerDeadline");
+            assert_eq!(WakeReason::default().to_string(), "None");
+        }
+    }
+
+    #[test]
+    fn test_evd_type() {
+        let evd_types = [
+            (0x0, EvdType::PageFaultAddress),
+            (0x1, EvdType::VmExitQualification),
+            (0x2, EvdType::VmExitReason),
+            (0x3, EvdType::Unknown(0x3)),
+            (0x3F, EvdType::Unknown(0x3F)),
+            (0xC1, EvdType::VmExitQualification),
+        ];
+        for (encoding, evd_type) in evd_types {
+            assert_eq!(EvdType::from_encoding(encoding), evd_type);
+        }
+    }
+
+    #[derive(Default)]
+    struct EvdRecorder {
+        evd: Option<(EvdType, u64)>,
+    }
+
+    impl HandlePacket for EvdRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_evd(
+            &mut self,
+            _context: &DecoderContext,
+            evd_type: EvdType,
+            payload: u64,
+        ) -> Result<(), Self::Error> {
+            self.evd = Some((evd_type, payload));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_evd_packet() {
+        let mut buf = [0u8; 16 + 2 + 11];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..21].copy_from_slice(&[0x02, 0x53, 0x00]);
+        buf[21..].copy_from_slice(&0x1234_5678_u64.to_le_bytes());
+
+        // Bit 5 of type is kept, while reserved bits 7:6 are cleared
+        for (type_byte, evd_type) in [
+            (0x02, EvdType::VmExitReason),
+            (0x20, EvdType::Unknown(0x20)),
+            (0xE1, EvdType::Unknown(0x21)),
+            (0x40, EvdType::PageFaultAddress),
+        ] {
+            buf[20] = type_byte;
+            let mut handler = EvdRecorder::default();
+            decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+            assert_eq!(handler.evd, Some((evd_type, 0x1234_5678)));
+        }
+    }
+
+    #[derive(Default)]
+    struct PowerRecorder {
+        mwait: Option<(CState, u8, u8)>,
+        pwre: Option<(bool, CState, u8)>,
+        pwrx: Option<(CState, CState, WakeReason)>,
+    }
+
+    impl HandlePacket for PowerRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_mwait(
+            &mut self,
+            _context: &DecoderContext,
+            target_c_state: CState,
+            target_sub_c_state: u8,
+            ext: u8,
+        ) -> Result<(), Self::Error> {
+            self.mwait = Some((target_c_state, target_sub_c_state, ext));
+            Ok(())
+        }
+
+        fn on_pwre(
+            &mut self,
+            _context: &DecoderContext,
+            hw: bool,
+            resolved_thread_c_state: CState,
+            resolved_thread_sub_c_state: u8,
+        ) -> Result<(), Self::Error> {
+            self.pwre = Some((hw, resolved_thread_c_state, resolved_thread_sub_c_state));
+            Ok(())
+        }
+
+        fn on_pwrx(
+            &mut self,
+            _context: &DecoderContext,
+            last_core_c_state: CState,
+            deepest_core_c_state: CState,
+            wake_reason: WakeReason,
+        ) -> Result<(), Self::Error> {
+            self.pwrx = Some((last_core_c_state, deepest_core_c_state, wake_reason));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_power_packets() {
+        let mut buf = [0u8; 16 + 2 + 10 + 4 + 7];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        // MWAIT: hints 0x21 (C3.1), reserved bytes are garbage, ext 0x2
+        buf[18..28].copy_from_slice(&[0x02, 0xC2, 0x21, 0xFF, 0xFF, 0xFF, 0x02, 0xFF, 0xFF, 0xFF]);
+        // PWRE: C6.2 initiated by hardware
+        buf[28..32].copy_from_slice(&[0x02, 0x22, 0x80, 0x52]);
+        // PWRX: last C1, deepest C6, timer deadline
+        buf[32..].copy_from_slice(&[0x02, 0xA2, 0x05, 0x02, 0x00, 0x00, 0x00]);
+
+        let mut handler = PowerRecorder::default();
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        assert_eq!(handler.mwait, Some((CState::C3, 0x1, 0x2)));
+        assert_eq!(handler.pwre, Some((true, CState::C6, 0x2)));
+        assert_eq!(
+            handler.pwrx,
+            Some((CState::C1, CState::C6, WakeReason::TIMER_DEADLINE))
+        );
+    }
+
+    #[test]
+    fn test_truncated_power_packets() {
+        let mut buf = [0u8; 16 + 2 + 7];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        // PWRX: last C2, deepest C3, interrupt
+        buf[18..].copy_from_slice(&[0x02, 0xA2, 0x12, 0x01, 0x00, 0x00, 0x00]);
+
+        let mut handler = PowerRecorder::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut handler);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert_eq!(summary.bytes_consumed, buf.len());
+        assert_eq!(
+            handler.pwrx,
+            Some((CState::C2, CState::C3, WakeReason::INTERRUPT))
+        );
+
+        // Truncated at any byte, including the reserved bytes
+        for len in 19..buf.len() {
+            let mut handler = PowerRecorder::default();
+            let summary = decode_with_summary(&buf[..len], DecodeOptions::default(), &mut handler);
+            assert!(
+                matches!(
+                    summary.stopped_at,
+                    StopReason::Error(DecoderError::UnexpectedEOF)
+                ),
+                "{len}"
+            );
+            assert_eq!(summary.bytes_consumed, 18);
+            assert_eq!(handler.pwrx, None);
+        }
+
+        // Truncated MWAIT
+        let mut buf = [0u8; 16 + 2 + 9];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..].copy_from_slice(&[0x02, 0xC2, 0x21, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00]);
+        let mut handler = PowerRecorder::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut handler);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::UnexpectedEOF)
+        ));
+        assert_eq!(handler.mwait, None);
+    }
+
+    #[test]
+    fn test_mnt_packet() {
+        let mut buf = [0u8; 16 + 2 + 11];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[18..].copy_from_slice(&[0x02, 0xC3, 0x88, 1, 2, 3, 4, 5, 6, 7, 8]);
+        decode(&buf, DecodeOptions::default(), &mut NopPacketHandler).unwrap();
+
+        // Truncated
+        let err = decode(&buf[..28], DecodeOptions::default(), &mut NopPacketHandler).unwrap_err();
+        assert!(matches!(err, DecoderError::UnexpectedEOF));
+
+        // Corrupted sub-header
+        buf[20] = 0x89;
+        let err = decode(&buf, DecodeOptions::default(), &mut NopPacketHandler).unwrap_err();
+        assert!(matches!(
+            err,
+            DecoderError::InvalidPacket {
+                byte: 0xC3,
+                offset: 18
+            }
+        ));
+    }
+
+    #[test]
+    fn test_progress() {
+        // PSB, PSBEND, 1000 short TNT packets, PSBEND
+        let mut buf = [0x04u8; 1020];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[1018..].copy_from_slice(&PSBEND_BYTES);
+
+        let mut report_count = 0;
+        let mut last_pos = 0;
+        decode_with_progress(
+            &buf,
+            DecodeOptions::default(),
+            &mut NopPacketHandler,
+            NonZero::new(100).unwrap(),
+            |pos, len| {
+                assert_eq!(len, 1020);
+                assert!(pos <= len);
+                assert!(pos > last_pos, "{last_pos} -> {pos}");
+                last_pos = pos;
+                report_count += 1;
+            },
+        )
+        .unwrap();
+        // Reports at 100, 200, ..., 1000, and the end of buffer
+        assert_eq!(report_count, 11);
+        assert_eq!(last_pos, 1020);
+    }
+
+    struct ModeOverrider {
+        mode_override: Option<TraceeMode>,
+        mode_changes: usize,
+        tip_modes: [Option<TraceeMode>; 2],
+        tip_count: usize,
+    }
+
+    impl HandlePacket for ModeOverrider {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_mode_exec_change(
+            &mut self,
+            _context: &DecoderContext,
+            _old_mode: TraceeMode,
+            _new_mode: TraceeMode,
+        ) -> Result<(), Self::Error> {
+            self.mode_changes += 1;
+            Ok(())
+        }
+
+        fn override_tracee_mode(
+            &mut self,
+            _context: &DecoderContext,
+        ) -> Result<Option<TraceeMode>, Self::Error> {
+            Ok(self.mode_override)
+        }
+
+        fn on_tip_packet(
+            &mut self,
+            context: &DecoderContext,
+            _ip_reconstruction_pattern: IpReconstructionPattern,
+        ) -> Result<(), Self::Error> {
+            self.tip_modes[self.tip_count] = Some(context.tracee_mode());
+            self.tip_count += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_override_tracee_mode() {
+        let mut buf = [0u8; 24];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        // MODE.exec: 64-bit
+        buf[16..18].copy_from_slice(&[0x99, 0x01]);
+        buf[18..20].copy_from_slice(&PSBEND_BYTES);
+        // TIP with IP suppressed
+        buf[20] = 0x0D;
+        // MODE.exec: 16-bit
+        buf[21..23].copy_from_slice(&[0x99, 0x00]);
+        buf[23] = 0x0D;
+
+        let mut handler = ModeOverrider {
+            mode_override: Some(TraceeMode::Mode32),
+            mode_changes: 0,
+            tip_modes: [None; 2],
+            tip_count: 0,
+        };
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        // The override takes precedence over MODE.exec in PSB+, and is
+        // replaced by the later MODE.exec
+        assert_eq!(
+            handler.tip_modes,
+            [Some(TraceeMode::Mode32), Some(TraceeMode::Mode16)]
+        );
+        assert_eq!(handler.mode_changes, 2);
+    }
+
+    #[test]
+    fn test_validate() {
+        // PSB, PSBEND, PAD, short TNT, and an invalid byte
+        let mut buf = [0u8; 21];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        buf[19] = 0b0000_0110;
+        buf[20] = 0xD9;
+
+        let mut counter = PacketCounter::new();
+        decode(&buf[..20], DecodeOptions::default(), &mut counter).unwrap();
+        let summary = validate(&buf[..20], DecodeOptions::default());
+        assert_eq!(summary.packet_count, counter.packet_count());
+        assert_eq!(summary.decode_summary.bytes_consumed, 20);
+        assert!(matches!(
+            summary.decode_summary.stopped_at,
+            StopReason::EndOfBuffer
+        ));
+
+        let summary = validate(&buf, DecodeOptions::default());
+        assert_eq!(summary.packet_count, counter.packet_count());
+        assert_eq!(summary.decode_summary.bytes_consumed, 20);
+        assert!(matches!(
+            summary.decode_summary.stopped_at,
+            StopReason::Error(DecoderError::InvalidPacket { byte: 0xD9, .. })
+        ));
+    }
+
+    #[test]
+    fn test_on_sync() {
+        let mut buf = [0xFFu8; 23];
+        buf[5..21].copy_from_slice(&PSB_BYTES);
+        buf[21..].copy_from_slice(&PSBEND_BYTES);
+
+        let mut handler = SyncRecorder::default();
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        assert_eq!(handler.psb_offset, Some(5));
+
+        let mut handler = SyncRecorder::default();
+        let mut options = DecodeOptions::default();
+        options.sync(false);
+        decode(&buf[5..], options, &mut handler).unwrap();
+        assert_eq!(handler.psb_offset, None);
+    }
+
+    /// Record PSB index of each PSB and PAD packet
+    #[derive(Default)]
+    struct PsbIndexRecorder {
+        psb_indices: [Option<usize>; 4],
+        psb_count: usize,
+        pad_indices: [Option<usize>; 4],
+        pad_count: usize,
+    }
+
+    impl HandlePacket for PsbIndexRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_psb_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+            self.psb_indices[self.psb_count] = context.psb_index();
+            self.psb_count += 1;
+            Ok(())
+        }
+
+        fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+            self.pad_indices[self.pad_count] = context.psb_index();
+            self.pad_count += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_psb_index() {
+        // PAD, then 3 times of PSB, PSBEND and PAD
+        let mut buf = [0u8; 1 + (16 + 2 + 1) * 3];
+        for psb in 0..3 {
+            let start = 1 + psb * (16 + 2 + 1);
+            buf[start..start + 16].copy_from_slice(&PSB_BYTES);
+            buf[start + 16..start + 18].copy_from_slice(&PSBEND_BYTES);
+        }
+
+        let mut handler = PsbIndexRecorder::default();
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        // The leading PAD is skipped by synchronization
+        assert_eq!(handler.psb_indices, [Some(0), Some(1), Some(2), None]);
+        assert_eq!(handler.pad_indices, [Some(0), Some(1), Some(2), None]);
+
+        let mut handler = PsbIndexRecorder::default();
+        let mut options = DecodeOptions::default();
+        options.start_offset(0);
+        decode(&buf, options, &mut handler).unwrap();
+        assert_eq!(handler.psb_indices, [Some(0), Some(1), Some(2), None]);
+        assert_eq!(handler.pad_indices, [None, Some(0), Some(1), Some(2)]);
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("PAD packet {0} is rejected")]
+    struct PadError(usize);
+
+    /// Count PAD packets, and fail on the 3rd, 5th and 7th of them
+    #[derive(Default)]
+    struct FlakyPadHandler {
+        pad_packets: usize,
+    }
+
+    impl HandlePacket for FlakyPadHandler {
+        type Error = PadError;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_pad_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+            self.pad_packets += 1;
+            if matches!(self.pad_packets, 3 | 5 | 7) {
+                return Err(PadError(self.pad_packets));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handler_error_budget() {
+        let mut buf = [0u8; 16 + 2 + 10];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+
+        // Abort at the first error by default
+        let mut handler = FlakyPadHandler::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut handler);
+        assert_eq!(summary.bytes_consumed, 18 + 2);
+        assert_eq!(summary.skipped_handler_errors, 0);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::PacketHandler(PadError(3)))
+        ));
+        assert_eq!(handler.pad_packets, 3);
+
+        // Continue until the budget is exhausted
+        let mut options = DecodeOptions::default();
+        options.handler_error_budget(2);
+        let mut handler = FlakyPadHandler::default();
+        let summary = decode_with_summary(&buf, options, &mut handler);
+        assert_eq!(summary.bytes_consumed, 18 + 6);
+        assert_eq!(summary.skipped_handler_errors, 2);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::PacketHandler(PadError(7)))
+        ));
+        assert_eq!(handler.pad_packets, 7);
+
+        // All packets are processed with enough budget
+        options.handler_error_budget(3);
+        let mut handler = FlakyPadHandler::default();
+        let summary = decode_with_summary(&buf, options, &mut handler);
+        assert_eq!(summary.skipped_handler_errors, 3);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert_eq!(handler.pad_packets, 10);
+
+        // Errors of malformed packets are not skipped
+        buf[27] = 0xD9;
+        let mut handler = FlakyPadHandler::default();
+        let summary = decode_with_summary(&buf, options, &mut handler);
+        assert_eq!(summary.bytes_consumed, 27);
+        assert!(matches!(
+            summary.stopped_at,
+            StopReason::Error(DecoderError::InvalidPacket { byte: 0xD9, .. })
+        ));
+        assert_eq!(handler.pad_packets, 9);
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("PSB packet is rejected")]
+    struct PsbError;
+
+    /// Record PSB index of each PAD packet, and fail on the first PSB packet
+    #[derive(Default)]
+    struct FlakyPsbHandler {
+        psb_packets: usize,
+        pad_indices: [Option<usize>; 3],
+        pad_count: usize,
+    }
+
+    impl HandlePacket for FlakyPsbHandler {
+        type Error = PsbError;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_psb_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+            self.psb_packets += 1;
+            if self.psb_packets == 1 {
+                return Err(PsbError);
+            }
+            Ok(())
+        }
+
+        fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+            self.pad_indices[self.pad_count] = context.psb_index();
+            self.pad_count += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handler_error_budget_at_psb() {
+        // 3 times of PSB, PSBEND and PAD
+        let mut buf = [0u8; (16 + 2 + 1) * 3];
+        for psb in 0..3 {
+            let start = psb * (16 + 2 + 1);
+            buf[start..start + 16].copy_from_slice(&PSB_BYTES);
+            buf[start + 16..start + 18].copy_from_slice(&PSBEND_BYTES);
+        }
+
+        let mut options = DecodeOptions::default();
+        options.handler_error_budget(1);
+        let mut handler = FlakyPsbHandler::default();
+        let summary = decode_with_summary(&buf, options, &mut handler);
+        assert_eq!(summary.skipped_handler_errors, 1);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        // The skipped PSB packet is only counted once
+        assert_eq!(handler.psb_packets, 3);
+        assert_eq!(handler.pad_indices, [Some(0), Some(1), Some(2)]);
+    }
+
+    /// Record tracee mode of the TIP packet, and fail on each mode change
+    #[derive(Default)]
+    struct FlakyModeHandler {
+        tip_mode: Option<TraceeMode>,
+    }
+
+    impl HandlePacket for FlakyModeHandler {
+        type Error = PsbError;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_mode_exec_change(
+            &mut self,
+            _context: &DecoderContext,
+            _old_mode: TraceeMode,
+            _new_mode: TraceeMode,
+        ) -> Result<(), Self::Error> {
+            Err(PsbError)
+        }
+
+        fn on_tip_packet(
+            &mut self,
+            context: &DecoderContext,
+            _ip_reconstruction_pattern: IpReconstructionPattern,
+        ) -> Result<(), Self::Error> {
+            self.tip_mode = Some(context.tracee_mode());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handler_error_budget_keeps_state() {
+        let mut buf = [0u8; 16 + 2 + 2 + 1];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        // MODE.exec: 32-bit, followed by TIP with IP suppressed
+        buf[18..20].copy_from_slice(&[0x99, 0x02]);
+        buf[20] = 0x0D;
+
+        let mut options = DecodeOptions::default();
+        options.handler_error_budget(1);
+        let mut handler = FlakyModeHandler::default();
+        let summary = decode_with_summary(&buf, options, &mut handler);
+        assert_eq!(summary.skipped_handler_errors, 1);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        // The tracee mode is still changed by the skipped MODE packet
+        assert_eq!(handler.tip_mode, Some(TraceeMode::Mode32));
+    }
+
+    #[derive(Default)]
+    struct CfeRecorder {
+        cfe: Option<(bool, u8, u8)>,
+    }
+
+    impl HandlePacket for CfeRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_cfe_packet(
+            &mut self,
+            _context: &DecoderContext,
+            ip_bit: bool,
+            r#type: u8,
+            vector: u8,
+        ) -> Result<(), Self::Error> {
+            self.cfe = Some((ip_bit, r#type, vector));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cfe_packet() {
+        let mut buf = [0u8; 16 + 2 + 4 + 1];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&PSBEND_BYTES);
+        // CFE: IP bit set, type 0x1 (interrupt), vector 0x20, followed by PAD
+        buf[18..22].copy_from_slice(&[0x02, 0x13, 0x81, 0x20]);
+
+        let mut handler = CfeRecorder::default();
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut handler);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+        assert_eq!(handler.cfe, Some((true, 0x1, 0x20)));
+    }
+
+    #[test]
+    fn test_strict_reserved_bits() {
+        let packets: [&[u8]; 9] = [
+            // CBR with reserved byte 3 set
+            &[0x02, 0x03, 0x20, 0x01],
+            // TMA with reserved bit 1 of byte 6 set
+            &[0x02, 0x73, 0x00, 0x00, 0x00, 0x00, 0x02],
+            // MODE.Exec with reserved bit 3 set
+            &[0x99, 0x09],
+            // MODE.TSX with reserved bit 2 set
+            &[0x99, 0x24],
+            // MWAIT with reserved byte 3 set
+            &[0x02, 0xC2, 0x21, 0xFF, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00],
+            // PWRE with reserved bit 0 of byte 2 set
+            &[0x02, 0x22, 0x01, 0x52],
+            // PWRX with reserved byte 4 set
+            &[0x02, 0xA2, 0x05, 0x02, 0x01, 0x00, 0x00],
+            // EVD with reserved bit 7 of byte 2 set
+            &[0x02, 0x53, 0x80, 0, 0, 0, 0, 0, 0, 0, 0],
+            // CFE with reserved bit 5 of byte 2 set
+            &[0x02, 0x13, 0x21, 0x20],
+        ];
+        let mut strict = DecodeOptions::default();
+        strict.strict(true);
+        for packet in packets {
+            let mut buf = PSB_BYTES.to_vec();
+            buf.extend_from_slice(&PSBEND_BYTES);
+            buf.extend_from_slice(packet);
+
+            let summary =
+                decode_with_summary(&buf, DecodeOptions::default(), &mut NopPacketHandler);
+            assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+            assert_eq!(summary.bytes_consumed, buf.len());
+
+            let summary = decode_with_summary(&buf, strict, &mut NopPacketHandler);
+            assert!(matches!(
+                summary.stopped_at,
+                StopReason::Error(DecoderError::InvalidPacket { offset: 18, .. })
+            ));
+            assert_eq!(summary.bytes_consumed, 18);
+        }
+
+        // Packets without reserved bits set pass in strict mode
+        let mut buf = PSB_BYTES.to_vec();
+        buf.extend_from_slice(&PSBEND_BYTES);
+        buf.extend_from_slice(&[0x02, 0x03, 0x20, 0x00, 0x99, 0x01, 0x02, 0x13, 0x81, 0x20]);
+        let summary = decode_with_summary(&buf, strict, &mut NopPacketHandler);
+        assert!(matches!(summary.stopped_at, StopReason::EndOfBuffer));
+    }
 }
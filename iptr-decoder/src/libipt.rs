@@ -0,0 +1,104 @@
+//! This module contains an adapter for Intel PT traces used by libipt tools.
+//!
+//! The `.pt` files consumed by libipt's `ptdump` and `ptxed`, including those
+//! extracted from `perf.data` by libipt's `perf-read-aux.bash` script, contain
+//! raw Intel PT data without any container header. Sideband information (e.g.,
+//! the `.sb` files produced by `perf-read-sideband.bash`) is always stored in
+//! separate files, and is not needed for decoding packets. Thus such files can
+//! be passed to [`decode`][crate::decode] as is, where leading bytes before the
+//! first PSB packet (e.g., of a wrapped AUX buffer) are skipped by
+//! synchronization.
+//!
+//! Use [`libipt_pt_data`] to get the raw Intel PT data of such files, which
+//! rejects files that are obviously not raw Intel PT data, instead of silently
+//! decoding them to nothing.
+
+use thiserror::Error;
+
+/// Magic of `perf.data` files
+const PERF_DATA_MAGIC: [u8; 8] = *b"PERFILE2";
+
+/// Container format of an Intel PT trace file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtContainer {
+    /// Raw Intel PT data without any container, as used by libipt tools
+    Raw,
+    /// `perf.data` file recorded by `perf record -e intel_pt//`, whose Intel PT
+    /// data is stored in AUXTRACE records
+    PerfData,
+}
+
+impl PtContainer {
+    /// Detect the container format of `buf`.
+    ///
+    /// Any data not recognized as another container is treated as raw Intel PT
+    /// data.
+    #[must_use]
+    pub fn detect(buf: &[u8]) -> Self {
+        if buf.starts_with(&PERF_DATA_MAGIC) {
+            Self::PerfData
+        } else {
+            Self::Raw
+        }
+    }
+}
+
+/// Error for [`libipt_pt_data`]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LibiptFormatError {
+    /// The file is not raw Intel PT data, but a container of given format
+    #[error("Not raw Intel PT data but a {0:?} container")]
+    UnsupportedContainer(PtContainer),
+}
+
+/// Get the raw Intel PT data in a `.pt` file used by libipt tools.
+///
+/// Since such files have no container header, the whole `buf` is returned for
+/// raw Intel PT data. For `perf.data` files, which should be read by
+/// `iptr-perf-pt-reader` instead, [`LibiptFormatError::UnsupportedContainer`]
+/// is returned.
+pub fn libipt_pt_data(buf: &[u8]) -> Result<&[u8], LibiptFormatError> {
+    match PtContainer::detect(buf) {
+        PtContainer::Raw => Ok(buf),
+        container @ PtContainer::PerfData => {
+            Err(LibiptFormatError::UnsupportedContainer(container))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DecodeOptions, PSB_BYTES, decode_with_summary, packet_handler::nop::NopPacketHandler,
+    };
+
+    #[test]
+    fn test_libipt_pt_data() {
+        // Tail of a wrapped AUX buffer, followed by PSB, PSBEND and PAD
+        let mut fixture = [0u8; 3 + 16 + 2 + 1];
+        fixture[..3].copy_from_slice(&[0x4D, 0x02, 0x00]);
+        fixture[3..19].copy_from_slice(&PSB_BYTES);
+        fixture[19..21].copy_from_slice(&[0x02, 0x23]);
+
+        assert_eq!(PtContainer::detect(&fixture), PtContainer::Raw);
+        let pt = libipt_pt_data(&fixture).unwrap();
+        assert_eq!(pt, fixture);
+        let summary = decode_with_summary(pt, DecodeOptions::default(), &mut NopPacketHandler);
+        assert_eq!(summary.bytes_consumed, fixture.len());
+
+        // Empty files are empty traces
+        assert_eq!(libipt_pt_data(&[]).unwrap(), &[] as &[u8]);
+
+        let mut perf_data = [0u8; 16];
+        perf_data[..8].copy_from_slice(b"PERFILE2");
+        assert_eq!(PtContainer::detect(&perf_data), PtContainer::PerfData);
+        assert!(matches!(
+            libipt_pt_data(&perf_data),
+            Err(LibiptFormatError::UnsupportedContainer(
+                PtContainer::PerfData
+            ))
+        ));
+    }
+}
@@ -0,0 +1,946 @@
+//! Handler for selecting packet handlers at runtime.
+
+use alloc::boxed::Box;
+use core::num::NonZero;
+
+use thiserror::Error;
+
+use crate::{DecoderContext, HandlePacket, IpReconstructionPattern, PtwPayload, TraceeMode};
+
+/// Error of [`DynHandlePacket`] and [`BoxedPacketHandler`], which boxes the error
+/// of the underlying packet handler.
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct BoxedError(Box<dyn core::error::Error + Send + Sync>);
+
+impl BoxedError {
+    /// Box the given error
+    #[must_use]
+    pub fn new(err: impl core::error::Error + Send + Sync + 'static) -> Self {
+        Self(Box::new(err))
+    }
+
+    /// Get the boxed error
+    #[must_use]
+    pub fn into_inner(self) -> Box<dyn core::error::Error + Send + Sync> {
+        self.0
+    }
+}
+
+/// Object-safe mirror of [`HandlePacket`], whose errors are boxed into [`BoxedError`].
+///
+/// This is implemented for every [`HandlePacket`] whose error is `Send + Sync + 'static`,
+/// and is used by [`BoxedPacketHandler`]. You should not need to implement this trait
+/// manually.
+pub trait DynHandlePacket {
+    /// Object-safe version of [`HandlePacket::at_decode_begin`]
+    fn at_decode_begin(&mut self) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_sync`]
+    fn on_sync(&mut self, context: &DecoderContext, psb_offset: usize) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_short_tnt_packet`]
+    fn on_short_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_byte: NonZero<u8>,
+        highest_bit: u32,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_long_tnt_packet`]
+    fn on_long_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_bytes: NonZero<u64>,
+        highest_bit: u32,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_tip_packet`]
+    fn on_tip_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_tip_pgd_packet`]
+    fn on_tip_pgd_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_tip_pge_packet`]
+    fn on_tip_pge_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_fup_packet`]
+    fn on_fup_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_pad_packet`]
+    fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_pad_run`]
+    fn on_pad_run(&mut self, context: &DecoderContext, count: usize) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_cyc_packet`]
+    fn on_cyc_packet(
+        &mut self,
+        context: &DecoderContext,
+        cyc_packet: &[u8],
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_mode_packet`]
+    fn on_mode_packet(
+        &mut self,
+        context: &DecoderContext,
+        leaf_id: u8,
+        mode: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_mode_exec_change`]
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::override_tracee_mode`]
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_mtc_packet`]
+    fn on_mtc_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc_payload: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_tsc_packet`]
+    fn on_tsc_packet(&mut self, context: &DecoderContext, tsc_value: u64)
+    -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_cbr_packet`]
+    fn on_cbr_packet(
+        &mut self,
+        context: &DecoderContext,
+        core_bus_ratio: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_tma_packet`]
+    fn on_tma_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc: u16,
+        fast_counter: u8,
+        fc8: bool,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_vmcs_packet`]
+    fn on_vmcs_packet(
+        &mut self,
+        context: &DecoderContext,
+        vmcs_pointer: u64,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_ovf_packet`]
+    fn on_ovf_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_psb_packet`]
+    fn on_psb_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_psbend_packet`]
+    fn on_psbend_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_missing_psbend`]
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_trace_stop_packet`]
+    fn on_trace_stop_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_pip_packet`]
+    fn on_pip_packet(
+        &mut self,
+        context: &DecoderContext,
+        cr3: u64,
+        rsvd_nr: bool,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_mnt_packet`]
+    fn on_mnt_packet(&mut self, context: &DecoderContext, payload: u64) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_ptw_packet`]
+    fn on_ptw_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        payload: PtwPayload,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_exstop_packet`]
+    fn on_exstop_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_mwait_packet`]
+    fn on_mwait_packet(
+        &mut self,
+        context: &DecoderContext,
+        mwait_hints: u8,
+        ext: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_pwre_packet`]
+    fn on_pwre_packet(
+        &mut self,
+        context: &DecoderContext,
+        hw: bool,
+        resolved_thread_c_state: u8,
+        resolved_thread_sub_c_state: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_pwrx_packet`]
+    fn on_pwrx_packet(
+        &mut self,
+        context: &DecoderContext,
+        last_core_c_state: u8,
+        deepest_core_c_state: u8,
+        wake_reason: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_evd_packet`]
+    fn on_evd_packet(
+        &mut self,
+        context: &DecoderContext,
+        r#type: u8,
+        payload: u64,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_cfe_packet`]
+    fn on_cfe_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        r#type: u8,
+        vector: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_bbp_packet`]
+    fn on_bbp_packet(
+        &mut self,
+        context: &DecoderContext,
+        sz_bit: bool,
+        r#type: u8,
+    ) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_bep_packet`]
+    fn on_bep_packet(&mut self, context: &DecoderContext, ip_bit: bool) -> Result<(), BoxedError>;
+
+    /// Object-safe version of [`HandlePacket::on_bip_packet`]
+    fn on_bip_packet(
+        &mut self,
+        context: &DecoderContext,
+        id: u8,
+        payload: &[u8],
+        bbp_type: u8,
+    ) -> Result<(), BoxedError>;
+}
+
+impl<H> DynHandlePacket for H
+where
+    H: HandlePacket,
+    H::Error: Send + Sync + 'static,
+{
+    fn at_decode_begin(&mut self) -> Result<(), BoxedError> {
+        HandlePacket::at_decode_begin(self).map_err(BoxedError::new)
+    }
+
+    fn on_sync(&mut self, context: &DecoderContext, psb_offset: usize) -> Result<(), BoxedError> {
+        HandlePacket::on_sync(self, context, psb_offset).map_err(BoxedError::new)
+    }
+
+    fn on_short_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_byte: NonZero<u8>,
+        highest_bit: u32,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_short_tnt_packet(self, context, packet_byte, highest_bit)
+            .map_err(BoxedError::new)
+    }
+
+    fn on_long_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_bytes: NonZero<u64>,
+        highest_bit: u32,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_long_tnt_packet(self, context, packet_bytes, highest_bit)
+            .map_err(BoxedError::new)
+    }
+
+    fn on_tip_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_tip_packet(self, context, ip_reconstruction_pattern)
+            .map_err(BoxedError::new)
+    }
+
+    fn on_tip_pgd_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_tip_pgd_packet(self, context, ip_reconstruction_pattern)
+            .map_err(BoxedError::new)
+    }
+
+    fn on_tip_pge_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_tip_pge_packet(self, context, ip_reconstruction_pattern)
+            .map_err(BoxedError::new)
+    }
+
+    fn on_fup_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_fup_packet(self, context, ip_reconstruction_pattern)
+            .map_err(BoxedError::new)
+    }
+
+    fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError> {
+        HandlePacket::on_pad_packet(self, context).map_err(BoxedError::new)
+    }
+
+    fn on_pad_run(&mut self, context: &DecoderContext, count: usize) -> Result<(), BoxedError> {
+        HandlePacket::on_pad_run(self, context, count).map_err(BoxedError::new)
+    }
+
+    fn on_cyc_packet(
+        &mut self,
+        context: &DecoderContext,
+        cyc_packet: &[u8],
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_cyc_packet(self, context, cyc_packet).map_err(BoxedError::new)
+    }
+
+    fn on_mode_packet(
+        &mut self,
+        context: &DecoderContext,
+        leaf_id: u8,
+        mode: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_mode_packet(self, context, leaf_id, mode).map_err(BoxedError::new)
+    }
+
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_mode_exec_change(self, context, old_mode, new_mode)
+            .map_err(BoxedError::new)
+    }
+
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, BoxedError> {
+        HandlePacket::override_tracee_mode(self, context).map_err(BoxedError::new)
+    }
+
+    fn on_mtc_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc_payload: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_mtc_packet(self, context, ctc_payload).map_err(BoxedError::new)
+    }
+
+    fn on_tsc_packet(
+        &mut self,
+        context: &DecoderContext,
+        tsc_value: u64,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_tsc_packet(self, context, tsc_value).map_err(BoxedError::new)
+    }
+
+    fn on_cbr_packet(
+        &mut self,
+        context: &DecoderContext,
+        core_bus_ratio: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_cbr_packet(self, context, core_bus_ratio).map_err(BoxedError::new)
+    }
+
+    fn on_tma_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc: u16,
+        fast_counter: u8,
+        fc8: bool,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_tma_packet(self, context, ctc, fast_counter, fc8).map_err(BoxedError::new)
+    }
+
+    fn on_vmcs_packet(
+        &mut self,
+        context: &DecoderContext,
+        vmcs_pointer: u64,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_vmcs_packet(self, context, vmcs_pointer).map_err(BoxedError::new)
+    }
+
+    fn on_ovf_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError> {
+        HandlePacket::on_ovf_packet(self, context).map_err(BoxedError::new)
+    }
+
+    fn on_psb_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError> {
+        HandlePacket::on_psb_packet(self, context).map_err(BoxedError::new)
+    }
+
+    fn on_psbend_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError> {
+        HandlePacket::on_psbend_packet(self, context).map_err(BoxedError::new)
+    }
+
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), BoxedError> {
+        HandlePacket::on_missing_psbend(self, context, byte).map_err(BoxedError::new)
+    }
+
+    fn on_trace_stop_packet(&mut self, context: &DecoderContext) -> Result<(), BoxedError> {
+        HandlePacket::on_trace_stop_packet(self, context).map_err(BoxedError::new)
+    }
+
+    fn on_pip_packet(
+        &mut self,
+        context: &DecoderContext,
+        cr3: u64,
+        rsvd_nr: bool,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_pip_packet(self, context, cr3, rsvd_nr).map_err(BoxedError::new)
+    }
+
+    fn on_mnt_packet(&mut self, context: &DecoderContext, payload: u64) -> Result<(), BoxedError> {
+        HandlePacket::on_mnt_packet(self, context, payload).map_err(BoxedError::new)
+    }
+
+    fn on_ptw_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        payload: PtwPayload,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_ptw_packet(self, context, ip_bit, payload).map_err(BoxedError::new)
+    }
+
+    fn on_exstop_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_exstop_packet(self, context, ip_bit).map_err(BoxedError::new)
+    }
+
+    fn on_mwait_packet(
+        &mut self,
+        context: &DecoderContext,
+        mwait_hints: u8,
+        ext: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_mwait_packet(self, context, mwait_hints, ext).map_err(BoxedError::new)
+    }
+
+    fn on_pwre_packet(
+        &mut self,
+        context: &DecoderContext,
+        hw: bool,
+        resolved_thread_c_state: u8,
+        resolved_thread_sub_c_state: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_pwre_packet(
+            self,
+            context,
+            hw,
+            resolved_thread_c_state,
+            resolved_thread_sub_c_state,
+        )
+        .map_err(BoxedError::new)
+    }
+
+    fn on_pwrx_packet(
+        &mut self,
+        context: &DecoderContext,
+        last_core_c_state: u8,
+        deepest_core_c_state: u8,
+        wake_reason: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_pwrx_packet(
+            self,
+            context,
+            last_core_c_state,
+            deepest_core_c_state,
+            wake_reason,
+        )
+        .map_err(BoxedError::new)
+    }
+
+    fn on_evd_packet(
+        &mut self,
+        context: &DecoderContext,
+        r#type: u8,
+        payload: u64,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_evd_packet(self, context, r#type, payload).map_err(BoxedError::new)
+    }
+
+    fn on_cfe_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        r#type: u8,
+        vector: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_cfe_packet(self, context, ip_bit, r#type, vector).map_err(BoxedError::new)
+    }
+
+    fn on_bbp_packet(
+        &mut self,
+        context: &DecoderContext,
+        sz_bit: bool,
+        r#type: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_bbp_packet(self, context, sz_bit, r#type).map_err(BoxedError::new)
+    }
+
+    fn on_bep_packet(&mut self, context: &DecoderContext, ip_bit: bool) -> Result<(), BoxedError> {
+        HandlePacket::on_bep_packet(self, context, ip_bit).map_err(BoxedError::new)
+    }
+
+    fn on_bip_packet(
+        &mut self,
+        context: &DecoderContext,
+        id: u8,
+        payload: &[u8],
+        bbp_type: u8,
+    ) -> Result<(), BoxedError> {
+        HandlePacket::on_bip_packet(self, context, id, payload, bbp_type).map_err(BoxedError::new)
+    }
+}
+
+/// A [`HandlePacket`] instance forwarding to a boxed packet handler.
+///
+/// Different from [`CombinedPacketHandler`][super::combined::CombinedPacketHandler],
+/// this uses dynamic dispatch, so that handlers of different types can be stored
+/// in the same type, e.g., when the handler is selected at runtime by command line
+/// arguments. Errors of the boxed handler are boxed into [`BoxedError`].
+pub struct BoxedPacketHandler<'a> {
+    handler: Box<dyn DynHandlePacket + 'a>,
+}
+
+impl<'a> BoxedPacketHandler<'a> {
+    /// Create a new [`BoxedPacketHandler`] by boxing `handler`
+    #[must_use]
+    pub fn new(handler: impl DynHandlePacket + 'a) -> Self {
+        Self {
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Consume the handler and get the boxed handler
+    #[must_use]
+    pub fn into_inner(self) -> Box<dyn DynHandlePacket + 'a> {
+        self.handler
+    }
+}
+
+impl HandlePacket for BoxedPacketHandler<'_> {
+    type Error = BoxedError;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.handler.at_decode_begin()
+    }
+
+    fn on_sync(&mut self, context: &DecoderContext, psb_offset: usize) -> Result<(), Self::Error> {
+        self.handler.on_sync(context, psb_offset)
+    }
+
+    fn on_short_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_byte: NonZero<u8>,
+        highest_bit: u32,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_short_tnt_packet(context, packet_byte, highest_bit)
+    }
+
+    fn on_long_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_bytes: NonZero<u64>,
+        highest_bit: u32,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_long_tnt_packet(context, packet_bytes, highest_bit)
+    }
+
+    fn on_tip_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_tip_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_tip_pgd_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_tip_pgd_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_tip_pge_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_tip_pge_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_fup_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_fup_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.handler.on_pad_packet(context)
+    }
+
+    fn on_pad_run(&mut self, context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        self.handler.on_pad_run(context, count)
+    }
+
+    fn on_cyc_packet(
+        &mut self,
+        context: &DecoderContext,
+        cyc_packet: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.handler.on_cyc_packet(context, cyc_packet)
+    }
+
+    fn on_mode_packet(
+        &mut self,
+        context: &DecoderContext,
+        leaf_id: u8,
+        mode: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_mode_packet(context, leaf_id, mode)
+    }
+
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_mode_exec_change(context, old_mode, new_mode)
+    }
+
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, Self::Error> {
+        self.handler.override_tracee_mode(context)
+    }
+
+    fn on_mtc_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc_payload: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_mtc_packet(context, ctc_payload)
+    }
+
+    fn on_tsc_packet(
+        &mut self,
+        context: &DecoderContext,
+        tsc_value: u64,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_tsc_packet(context, tsc_value)
+    }
+
+    fn on_cbr_packet(
+        &mut self,
+        context: &DecoderContext,
+        core_bus_ratio: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_cbr_packet(context, core_bus_ratio)
+    }
+
+    fn on_tma_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc: u16,
+        fast_counter: u8,
+        fc8: bool,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_tma_packet(context, ctc, fast_counter, fc8)
+    }
+
+    fn on_vmcs_packet(
+        &mut self,
+        context: &DecoderContext,
+        vmcs_pointer: u64,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_vmcs_packet(context, vmcs_pointer)
+    }
+
+    fn on_ovf_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.handler.on_ovf_packet(context)
+    }
+
+    fn on_psb_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.handler.on_psb_packet(context)
+    }
+
+    fn on_psbend_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.handler.on_psbend_packet(context)
+    }
+
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), Self::Error> {
+        self.handler.on_missing_psbend(context, byte)
+    }
+
+    fn on_trace_stop_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.handler.on_trace_stop_packet(context)
+    }
+
+    fn on_pip_packet(
+        &mut self,
+        context: &DecoderContext,
+        cr3: u64,
+        rsvd_nr: bool,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_pip_packet(context, cr3, rsvd_nr)
+    }
+
+    fn on_mnt_packet(&mut self, context: &DecoderContext, payload: u64) -> Result<(), Self::Error> {
+        self.handler.on_mnt_packet(context, payload)
+    }
+
+    fn on_ptw_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        payload: PtwPayload,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_ptw_packet(context, ip_bit, payload)
+    }
+
+    fn on_exstop_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_exstop_packet(context, ip_bit)
+    }
+
+    fn on_mwait_packet(
+        &mut self,
+        context: &DecoderContext,
+        mwait_hints: u8,
+        ext: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_mwait_packet(context, mwait_hints, ext)
+    }
+
+    fn on_pwre_packet(
+        &mut self,
+        context: &DecoderContext,
+        hw: bool,
+        resolved_thread_c_state: u8,
+        resolved_thread_sub_c_state: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_pwre_packet(
+            context,
+            hw,
+            resolved_thread_c_state,
+            resolved_thread_sub_c_state,
+        )
+    }
+
+    fn on_pwrx_packet(
+        &mut self,
+        context: &DecoderContext,
+        last_core_c_state: u8,
+        deepest_core_c_state: u8,
+        wake_reason: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_pwrx_packet(
+            context,
+            last_core_c_state,
+            deepest_core_c_state,
+            wake_reason,
+        )
+    }
+
+    fn on_evd_packet(
+        &mut self,
+        context: &DecoderContext,
+        r#type: u8,
+        payload: u64,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_evd_packet(context, r#type, payload)
+    }
+
+    fn on_cfe_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        r#type: u8,
+        vector: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_cfe_packet(context, ip_bit, r#type, vector)
+    }
+
+    fn on_bbp_packet(
+        &mut self,
+        context: &DecoderContext,
+        sz_bit: bool,
+        r#type: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_bbp_packet(context, sz_bit, r#type)
+    }
+
+    fn on_bep_packet(&mut self, context: &DecoderContext, ip_bit: bool) -> Result<(), Self::Error> {
+        self.handler.on_bep_packet(context, ip_bit)
+    }
+
+    fn on_bip_packet(
+        &mut self,
+        context: &DecoderContext,
+        id: u8,
+        payload: &[u8],
+        bbp_type: u8,
+    ) -> Result<(), Self::Error> {
+        self.handler.on_bip_packet(context, id, payload, bbp_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::ToString;
+
+    use super::*;
+    use crate::{DecodeOptions, decode, error::DecoderError};
+
+    /// `PSB, PSBEND, TIP 0x1000, TIP 0x1000`
+    fn trace() -> [u8; 36] {
+        let mut buf = [0u8; 36];
+        buf[..16].copy_from_slice(&[0x02, 0x82].repeat(8));
+        buf[16..18].copy_from_slice(&[0x02, 0x23]);
+        for tip in buf[18..].chunks_mut(9) {
+            tip[0] = 0xCD;
+            tip[1..].copy_from_slice(&0x1000u64.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Count TIP packets into borrowed counter
+    struct TipCounter<'a>(&'a mut usize);
+
+    impl HandlePacket for TipCounter<'_> {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_tip_packet(
+            &mut self,
+            _context: &DecoderContext,
+            _ip_reconstruction_pattern: IpReconstructionPattern,
+        ) -> Result<(), Self::Error> {
+            *self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Error)]
+    #[error("TIP is not allowed")]
+    struct TipError;
+
+    /// Fail on TIP packets
+    struct TipRejecter;
+
+    impl HandlePacket for TipRejecter {
+        type Error = TipError;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn on_tip_packet(
+            &mut self,
+            _context: &DecoderContext,
+            _ip_reconstruction_pattern: IpReconstructionPattern,
+        ) -> Result<(), Self::Error> {
+            Err(TipError)
+        }
+    }
+
+    #[test]
+    fn test_boxed_packet_handler() {
+        let trace = trace();
+        let mut tip_count = 0;
+        for reject in [false, true] {
+            // Selected at runtime
+            let mut handler = if reject {
+                BoxedPacketHandler::new(TipRejecter)
+            } else {
+                BoxedPacketHandler::new(TipCounter(&mut tip_count))
+            };
+            let res = decode(&trace, DecodeOptions::default(), &mut handler);
+            if reject {
+                let Err(DecoderError::PacketHandler(err)) = res else {
+                    panic!("Unexpected result");
+                };
+                assert_eq!(err.to_string(), "TIP is not allowed");
+                assert!(err.into_inner().downcast_ref::<TipError>().is_some());
+            } else {
+                res.unwrap();
+            }
+        }
+        assert_eq!(tip_count, 2);
+    }
+}
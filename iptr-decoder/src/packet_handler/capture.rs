@@ -0,0 +1,630 @@
+//! Handler for capturing the last packets before an error
+//!
+//! When decoding fails deep in a trace, the packets just before the failure are
+//! usually the most useful information for a bug report. [`DebugCaptureHandler`]
+//! wraps another handler, and keeps the last N packets in a ring buffer together
+//! with their offsets, which can be dumped after decoding:
+//!
+//! ```rust
+//! # use iptr_decoder::{DecodeOptions, packet_handler::{capture::DebugCaptureHandler, packet_counter::PacketCounter}};
+//! # let buf = [0u8; 0];
+//! # let custom_packet_handler = PacketCounter::default();
+//! // let custom_packet_handler = ...
+//! let mut handler = DebugCaptureHandler::new(custom_packet_handler, 16);
+//! if let Err(err) = iptr_decoder::decode(&buf, DecodeOptions::default(), &mut handler) {
+//!     eprintln!("{err}\nLast packets:\n{}", handler.dump());
+//! }
+//! ```
+//!
+//! This is a diagnostics utility which copies every packet, so it is not intended
+//! to be used in performance-critical decoding.
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::{fmt::Write, num::NonZero};
+
+use crate::{
+    DecoderContext, HandlePacket, IpReconstructionPattern, PtwPayload, TraceeMode,
+    packet_handler::log::Packet,
+};
+
+/// A packet captured by [`DebugCaptureHandler`]
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    /// Offset of the packet, see [`DecoderContext::packet_offset`]
+    offset: usize,
+    /// Captured packet, whose borrowed content is replaced by empty slices
+    packet: Packet<'static>,
+    /// Owned content of CYC and BIP packets
+    content: Vec<u8>,
+}
+
+/// Split a packet into a [`Packet<'static>`] and its owned content.
+///
+/// The first block lists the arms of packets with borrowed content, and the
+/// following variants (with their field names) are packets without borrowed
+/// content, which are rebuilt as is.
+macro_rules! split_content {
+    (
+        $packet:expr,
+        { $($borrowed_arms:tt)* },
+        $($variant:ident $({ $($field:ident),+ })? $(($tuple:ident))?),* $(,)?
+    ) => {
+        match $packet {
+            $($borrowed_arms)*
+            $(
+                Packet::$variant $({ $($field),+ })? $(($tuple))? => (
+                    Packet::$variant $({ $($field),+ })? $(($tuple))?,
+                    Vec::new(),
+                ),
+            )*
+        }
+    };
+}
+
+impl CapturedPacket {
+    /// Capture the packet at given offset
+    fn new(offset: usize, packet: Packet<'_>) -> Self {
+        let (packet, content) = split_content!(
+            packet,
+            {
+                Packet::Cyc { cyc_packet } => (Packet::Cyc { cyc_packet: &[] }, cyc_packet.to_vec()),
+                Packet::Bip {
+                    id,
+                    payload,
+                    bbp_type,
+                } => (
+                    Packet::Bip {
+                        id,
+                        payload: &[],
+                        bbp_type,
+                    },
+                    payload.to_vec(),
+                ),
+            },
+            ShortTnt { packet_byte, highest_bit },
+            LongTnt { packet_bytes, highest_bit },
+            Tip(pattern),
+            TipPgd(pattern),
+            TipPge(pattern),
+            Fup(pattern),
+            Pad,
+            PadRun { count },
+            Mode { leaf_id, mode },
+            Mtc { ctc_payload },
+            Tsc { tsc_value },
+            Cbr { core_bus_ratio },
+            Tma { ctc, fast_counter, fc8 },
+            Vmcs { vmcs_pointer },
+            Ovf,
+            Psb,
+            Psbend,
+            TraceStop,
+            Pip { cr3, rsvd_nr },
+            Mnt { payload },
+            Ptw { ip_bit, payload },
+            Exstop { ip_bit },
+            Mwait { mwait_hints, ext },
+            Pwre { hw, resolved_thread_c_state, resolved_thread_sub_c_state },
+            Pwrx { last_core_c_state, deepest_core_c_state, wake_reason },
+            Evd { r#type, payload },
+            Cfe { ip_bit, r#type, vector },
+            Bbp { sz_bit, r#type },
+            Bep { ip_bit },
+        );
+        Self {
+            offset,
+            packet,
+            content,
+        }
+    }
+
+    /// Get the offset of the packet, see [`DecoderContext::packet_offset`]
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the captured packet
+    #[must_use]
+    pub fn packet(&self) -> Packet<'_> {
+        match self.packet {
+            Packet::Cyc { .. } => Packet::Cyc {
+                cyc_packet: &self.content,
+            },
+            Packet::Bip { id, bbp_type, .. } => Packet::Bip {
+                id,
+                payload: &self.content,
+                bbp_type,
+            },
+            packet => packet,
+        }
+    }
+}
+
+/// A [`HandlePacket`] instance wrapping another handler, which keeps the last
+/// `capacity` packets for diagnostics.
+///
+/// Every packet is captured before it is passed to the wrapped handler, so if
+/// the wrapped handler fails, the failing packet is the last captured one. If
+/// the decoder itself fails (e.g., an invalid packet), the failing packet is not
+/// captured, and its offset is reported by the error or
+/// [`DecodeSummary`][crate::DecodeSummary]. The captured packets are cleared at
+/// [`at_decode_begin`][HandlePacket::at_decode_begin].
+pub struct DebugCaptureHandler<H: HandlePacket> {
+    /// Wrapped handler
+    handler: H,
+    /// Maximum number of captured packets
+    capacity: usize,
+    /// Captured packets, from the oldest to the newest
+    packets: VecDeque<CapturedPacket>,
+}
+
+impl<H: HandlePacket> DebugCaptureHandler<H> {
+    /// Create a new [`DebugCaptureHandler`] keeping the last `capacity` packets
+    #[must_use]
+    pub fn new(handler: H, capacity: usize) -> Self {
+        Self {
+            handler,
+            capacity,
+            packets: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Get shared reference to the wrapped handler
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Get unique reference to the wrapped handler
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Consume the handler and get the wrapped handler
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
+    /// Get the captured packets, from the oldest to the newest
+    pub fn captured_packets(&self) -> impl ExactSizeIterator<Item = &CapturedPacket> {
+        self.packets.iter()
+    }
+
+    /// Dump the captured packets, from the oldest to the newest.
+    ///
+    /// Each line is the offset of a packet followed by the packet formatted in
+    /// the same way as [`PacketHandlerRawLogger`][super::log::PacketHandlerRawLogger].
+    #[must_use]
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        for packet in &self.packets {
+            // Writing to string never fails
+            let _ = writeln!(dump, "{:#x}\t{}", packet.offset(), packet.packet());
+        }
+        dump
+    }
+
+    /// Capture the packet at current offset
+    fn capture(&mut self, context: &DecoderContext, packet: Packet<'_>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.packets.len() == self.capacity {
+            self.packets.pop_front();
+        }
+        self.packets
+            .push_back(CapturedPacket::new(context.packet_offset(), packet));
+    }
+}
+
+impl<H: HandlePacket> HandlePacket for DebugCaptureHandler<H> {
+    type Error = H::Error;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.packets.clear();
+        self.handler.at_decode_begin()
+    }
+
+    fn on_sync(&mut self, context: &DecoderContext, psb_offset: usize) -> Result<(), Self::Error> {
+        self.handler.on_sync(context, psb_offset)
+    }
+
+    fn on_short_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_byte: NonZero<u8>,
+        highest_bit: u32,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::ShortTnt {
+                packet_byte,
+                highest_bit,
+            },
+        );
+        self.handler
+            .on_short_tnt_packet(context, packet_byte, highest_bit)
+    }
+
+    fn on_long_tnt_packet(
+        &mut self,
+        context: &DecoderContext,
+        packet_bytes: NonZero<u64>,
+        highest_bit: u32,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::LongTnt {
+                packet_bytes,
+                highest_bit,
+            },
+        );
+        self.handler
+            .on_long_tnt_packet(context, packet_bytes, highest_bit)
+    }
+
+    fn on_tip_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Tip(ip_reconstruction_pattern));
+        self.handler
+            .on_tip_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_tip_pgd_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::TipPgd(ip_reconstruction_pattern));
+        self.handler
+            .on_tip_pgd_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_tip_pge_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::TipPge(ip_reconstruction_pattern));
+        self.handler
+            .on_tip_pge_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_fup_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Fup(ip_reconstruction_pattern));
+        self.handler
+            .on_fup_packet(context, ip_reconstruction_pattern)
+    }
+
+    fn on_pad_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Pad);
+        self.handler.on_pad_packet(context)
+    }
+
+    fn on_pad_run(&mut self, context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        self.capture(context, Packet::PadRun { count });
+        self.handler.on_pad_run(context, count)
+    }
+
+    fn on_cyc_packet(
+        &mut self,
+        context: &DecoderContext,
+        cyc_packet: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Cyc { cyc_packet });
+        self.handler.on_cyc_packet(context, cyc_packet)
+    }
+
+    fn on_mode_packet(
+        &mut self,
+        context: &DecoderContext,
+        leaf_id: u8,
+        mode: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Mode { leaf_id, mode });
+        self.handler.on_mode_packet(context, leaf_id, mode)
+    }
+
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        self.handler
+            .on_mode_exec_change(context, old_mode, new_mode)
+    }
+
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, Self::Error> {
+        self.handler.override_tracee_mode(context)
+    }
+
+    fn on_mtc_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc_payload: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Mtc { ctc_payload });
+        self.handler.on_mtc_packet(context, ctc_payload)
+    }
+
+    fn on_tsc_packet(
+        &mut self,
+        context: &DecoderContext,
+        tsc_value: u64,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Tsc { tsc_value });
+        self.handler.on_tsc_packet(context, tsc_value)
+    }
+
+    fn on_cbr_packet(
+        &mut self,
+        context: &DecoderContext,
+        core_bus_ratio: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Cbr { core_bus_ratio });
+        self.handler.on_cbr_packet(context, core_bus_ratio)
+    }
+
+    fn on_tma_packet(
+        &mut self,
+        context: &DecoderContext,
+        ctc: u16,
+        fast_counter: u8,
+        fc8: bool,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::Tma {
+                ctc,
+                fast_counter,
+                fc8,
+            },
+        );
+        self.handler.on_tma_packet(context, ctc, fast_counter, fc8)
+    }
+
+    fn on_vmcs_packet(
+        &mut self,
+        context: &DecoderContext,
+        vmcs_pointer: u64,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Vmcs { vmcs_pointer });
+        self.handler.on_vmcs_packet(context, vmcs_pointer)
+    }
+
+    fn on_ovf_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Ovf);
+        self.handler.on_ovf_packet(context)
+    }
+
+    fn on_psb_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Psb);
+        self.handler.on_psb_packet(context)
+    }
+
+    fn on_psbend_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Psbend);
+        self.handler.on_psbend_packet(context)
+    }
+
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), Self::Error> {
+        self.handler.on_missing_psbend(context, byte)
+    }
+
+    fn on_trace_stop_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        self.capture(context, Packet::TraceStop);
+        self.handler.on_trace_stop_packet(context)
+    }
+
+    fn on_pip_packet(
+        &mut self,
+        context: &DecoderContext,
+        cr3: u64,
+        rsvd_nr: bool,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Pip { cr3, rsvd_nr });
+        self.handler.on_pip_packet(context, cr3, rsvd_nr)
+    }
+
+    fn on_mnt_packet(&mut self, context: &DecoderContext, payload: u64) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Mnt { payload });
+        self.handler.on_mnt_packet(context, payload)
+    }
+
+    fn on_ptw_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        payload: PtwPayload,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Ptw { ip_bit, payload });
+        self.handler.on_ptw_packet(context, ip_bit, payload)
+    }
+
+    fn on_exstop_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Exstop { ip_bit });
+        self.handler.on_exstop_packet(context, ip_bit)
+    }
+
+    fn on_mwait_packet(
+        &mut self,
+        context: &DecoderContext,
+        mwait_hints: u8,
+        ext: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Mwait { mwait_hints, ext });
+        self.handler.on_mwait_packet(context, mwait_hints, ext)
+    }
+
+    fn on_pwre_packet(
+        &mut self,
+        context: &DecoderContext,
+        hw: bool,
+        resolved_thread_c_state: u8,
+        resolved_thread_sub_c_state: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::Pwre {
+                hw,
+                resolved_thread_c_state,
+                resolved_thread_sub_c_state,
+            },
+        );
+        self.handler.on_pwre_packet(
+            context,
+            hw,
+            resolved_thread_c_state,
+            resolved_thread_sub_c_state,
+        )
+    }
+
+    fn on_pwrx_packet(
+        &mut self,
+        context: &DecoderContext,
+        last_core_c_state: u8,
+        deepest_core_c_state: u8,
+        wake_reason: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::Pwrx {
+                last_core_c_state,
+                deepest_core_c_state,
+                wake_reason,
+            },
+        );
+        self.handler.on_pwrx_packet(
+            context,
+            last_core_c_state,
+            deepest_core_c_state,
+            wake_reason,
+        )
+    }
+
+    fn on_evd_packet(
+        &mut self,
+        context: &DecoderContext,
+        r#type: u8,
+        payload: u64,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Evd { r#type, payload });
+        self.handler.on_evd_packet(context, r#type, payload)
+    }
+
+    fn on_cfe_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_bit: bool,
+        r#type: u8,
+        vector: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::Cfe {
+                ip_bit,
+                r#type,
+                vector,
+            },
+        );
+        self.handler.on_cfe_packet(context, ip_bit, r#type, vector)
+    }
+
+    fn on_bbp_packet(
+        &mut self,
+        context: &DecoderContext,
+        sz_bit: bool,
+        r#type: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Bbp { sz_bit, r#type });
+        self.handler.on_bbp_packet(context, sz_bit, r#type)
+    }
+
+    fn on_bep_packet(&mut self, context: &DecoderContext, ip_bit: bool) -> Result<(), Self::Error> {
+        self.capture(context, Packet::Bep { ip_bit });
+        self.handler.on_bep_packet(context, ip_bit)
+    }
+
+    fn on_bip_packet(
+        &mut self,
+        context: &DecoderContext,
+        id: u8,
+        payload: &[u8],
+        bbp_type: u8,
+    ) -> Result<(), Self::Error> {
+        self.capture(
+            context,
+            Packet::Bip {
+                id,
+                payload,
+                bbp_type,
+            },
+        );
+        self.handler.on_bip_packet(context, id, payload, bbp_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DecodeOptions, PSB_BYTES, decode_with_summary,
+        packet_handler::packet_counter::PacketCounter,
+    };
+
+    #[test]
+    fn test_capture_before_error() {
+        let mut buf = [0u8; 16 + 2 + 3 + 2 + 1 + 1];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&[0x02, 0x23]);
+        // TIP with 2 bytes IP
+        buf[18..21].copy_from_slice(&[0x2D, 0x34, 0x12]);
+        // CYC with 1 more byte
+        buf[21..23].copy_from_slice(&[0b0000_0111, 0b0000_0100]);
+        // Short TNT
+        buf[23] = 0b0000_0110;
+        // Invalid packet
+        buf[24] = 0xD9;
+
+        let mut handler = DebugCaptureHandler::new(PacketCounter::new(), 3);
+        let summary = decode_with_summary(&buf, DecodeOptions::default(), &mut handler);
+        assert_eq!(summary.bytes_consumed, 24);
+        assert!(summary.into_result().is_err());
+        assert_eq!(handler.handler().packet_count(), 5);
+
+        let offsets = handler
+            .captured_packets()
+            .map(CapturedPacket::offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [18, 21, 23]);
+        assert_eq!(
+            handler.dump(),
+            alloc::format!(
+                "0x12\t{}\n0x15\t{}\n0x17\t{}\n",
+                Packet::Tip(IpReconstructionPattern::TwoBytesWithLastIp(0x1234)),
+                Packet::Cyc {
+                    cyc_packet: &[0b0000_0111, 0b0000_0100]
+                },
+                Packet::ShortTnt {
+                    packet_byte: NonZero::new(0b0000_0110).unwrap(),
+                    highest_bit: 1,
+                },
+            )
+        );
+    }
+}
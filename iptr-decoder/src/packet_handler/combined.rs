@@ -5,7 +5,7 @@ use core::{self as std, num::NonZero}; // workaround for `perfect_derive`
 use perfect_derive::perfect_derive;
 use thiserror::Error;
 
-use crate::{DecoderContext, HandlePacket, IpReconstructionPattern};
+use crate::{DecoderContext, HandlePacket, IpReconstructionPattern, TraceeMode};
 
 /// A [`HandlePacket`] instance for combining two sub handlers
 ///
@@ -95,6 +95,17 @@ where
         Ok(())
     }
 
+    fn on_sync(&mut self, context: &DecoderContext, psb_offset: usize) -> Result<(), Self::Error> {
+        self.handler1
+            .on_sync(context, psb_offset)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_sync(context, psb_offset)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
     fn on_short_tnt_packet(
         &mut self,
         context: &DecoderContext,
@@ -198,6 +209,17 @@ where
         Ok(())
     }
 
+    fn on_pad_run(&mut self, context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        self.handler1
+            .on_pad_run(context, count)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_pad_run(context, count)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
     fn on_cyc_packet(
         &mut self,
         context: &DecoderContext,
@@ -213,6 +235,40 @@ where
         Ok(())
     }
 
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        self.handler1
+            .on_mode_exec_change(context, old_mode, new_mode)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_mode_exec_change(context, old_mode, new_mode)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    /// The override of the second handler takes precedence if both handlers
+    /// override the tracee mode
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, Self::Error> {
+        let mode1 = self
+            .handler1
+            .override_tracee_mode(context)
+            .map_err(CombinedError::H1Error)?;
+        let mode2 = self
+            .handler2
+            .override_tracee_mode(context)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(mode2.or(mode1))
+    }
+
     fn on_mode_packet(
         &mut self,
         context: &DecoderContext,
@@ -339,6 +395,17 @@ where
         Ok(())
     }
 
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), Self::Error> {
+        self.handler1
+            .on_missing_psbend(context, byte)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_missing_psbend(context, byte)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
     fn on_trace_stop_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
         self.handler1
             .on_trace_stop_packet(context)
@@ -0,0 +1,514 @@
+//! Handler for counting packets per packet type
+
+use core::num::NonZero;
+
+use derive_more::Display;
+
+use crate::{DecoderContext, HandlePacket, IpReconstructionPattern};
+
+/// Type of Intel PT packets
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketType {
+    /// Short TNT packet
+    #[display("TNT.short")]
+    ShortTnt,
+    /// Long TNT packet
+    #[display("TNT.long")]
+    LongTnt,
+    /// TIP packet
+    #[display("TIP")]
+    Tip,
+    /// TIP.PGD packet
+    #[display("TIP.PGD")]
+    TipPgd,
+    /// TIP.PGE packet
+    #[display("TIP.PGE")]
+    TipPge,
+    /// FUP packet
+    #[display("FUP")]
+    Fup,
+    /// PAD packet
+    #[display("PAD")]
+    Pad,
+    /// CYC packet
+    #[display("CYC")]
+    Cyc,
+    /// MODE packet
+    #[display("MODE")]
+    Mode,
+    /// MTC packet
+    #[display("MTC")]
+    Mtc,
+    /// TSC packet
+    #[display("TSC")]
+    Tsc,
+    /// CBR packet
+    #[display("CBR")]
+    Cbr,
+    /// TMA packet
+    #[display("TMA")]
+    Tma,
+    /// VMCS packet
+    #[display("VMCS")]
+    Vmcs,
+    /// OVF packet
+    #[display("OVF")]
+    Ovf,
+    /// PSB packet
+    #[display("PSB")]
+    Psb,
+    /// PSBEND packet
+    #[display("PSBEND")]
+    Psbend,
+    /// TraceStop packet
+    #[display("TraceStop")]
+    TraceStop,
+    /// PIP packet
+    #[display("PIP")]
+    Pip,
+    /// MNT packet
+    #[display("MNT")]
+    Mnt,
+    /// PTW packet
+    #[display("PTW")]
+    Ptw,
+    /// EXSTOP packet
+    #[display("EXSTOP")]
+    Exstop,
+    /// MWAIT packet
+    #[display("MWAIT")]
+    Mwait,
+    /// PWRE packet
+    #[display("PWRE")]
+    Pwre,
+    /// PWRX packet
+    #[display("PWRX")]
+    Pwrx,
+    /// EVD packet
+    #[display("EVD")]
+    Evd,
+    /// CFE packet
+    #[display("CFE")]
+    Cfe,
+    /// BBP packet
+    #[display("BBP")]
+    Bbp,
+    /// BEP packet
+    #[display("BEP")]
+    Bep,
+    /// BIP packet
+    #[display("BIP")]
+    Bip,
+}
+
+impl PacketType {
+    /// All packet types, in the order of their discriminants
+    pub const ALL: [PacketType; 30] = [
+        Self::ShortTnt,
+        Self::LongTnt,
+        Self::Tip,
+        Self::TipPgd,
+        Self::TipPge,
+        Self::Fup,
+        Self::Pad,
+        Self::Cyc,
+        Self::Mode,
+        Self::Mtc,
+        Self::Tsc,
+        Self::Cbr,
+        Self::Tma,
+        Self::Vmcs,
+        Self::Ovf,
+        Self::Psb,
+        Self::Psbend,
+        Self::TraceStop,
+        Self::Pip,
+        Self::Mnt,
+        Self::Ptw,
+        Self::Exstop,
+        Self::Mwait,
+        Self::Pwre,
+        Self::Pwrx,
+        Self::Evd,
+        Self::Cfe,
+        Self::Bbp,
+        Self::Bep,
+        Self::Bip,
+    ];
+}
+
+/// A [`HandlePacket`] instance for counting Intel PT packets per packet type.
+///
+/// This is the fastest way to triage an unknown trace. Continuous PAD packets
+/// reported by [`HandlePacket::on_pad_run`] are counted one by one. The counts
+/// are reset at [`at_decode_begin`][HandlePacket::at_decode_begin].
+#[derive(Default)]
+pub struct PacketHistogram {
+    /// Packet counts indexed by [`PacketType`]
+    counts: [usize; PacketType::ALL.len()],
+}
+
+impl PacketHistogram {
+    /// Create a new [`PacketHistogram`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the count of given packet type
+    #[must_use]
+    pub fn count(&self, packet_type: PacketType) -> usize {
+        self.counts[packet_type as usize]
+    }
+
+    /// Get the count of all packets
+    #[must_use]
+    pub fn total_count(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Iterate over packet types that appear at least once, with their counts,
+    /// in the order of [`PacketType::ALL`]
+    pub fn iter(&self) -> impl Iterator<Item = (PacketType, usize)> {
+        PacketType::ALL
+            .into_iter()
+            .zip(self.counts)
+            .filter(|(_, count)| *count > 0)
+    }
+
+    /// Count a packet of given type
+    #[inline]
+    fn add(&mut self, packet_type: PacketType) {
+        self.counts[packet_type as usize] += 1;
+    }
+}
+
+impl HandlePacket for PacketHistogram {
+    // Will never fail
+    type Error = core::convert::Infallible;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.counts = [0; PacketType::ALL.len()];
+        Ok(())
+    }
+
+    fn on_short_tnt_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _packet_byte: NonZero<u8>,
+        _highest_bit: u32,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::ShortTnt);
+        Ok(())
+    }
+
+    fn on_long_tnt_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _packet_bytes: NonZero<u64>,
+        _highest_bit: u32,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::LongTnt);
+        Ok(())
+    }
+
+    fn on_tip_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Tip);
+        Ok(())
+    }
+
+    fn on_tip_pgd_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::TipPgd);
+        Ok(())
+    }
+
+    fn on_tip_pge_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::TipPge);
+        Ok(())
+    }
+
+    fn on_fup_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Fup);
+        Ok(())
+    }
+
+    fn on_pad_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.add(PacketType::Pad);
+        Ok(())
+    }
+
+    fn on_pad_run(&mut self, _context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        self.counts[PacketType::Pad as usize] += count;
+        Ok(())
+    }
+
+    fn on_cyc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _cyc_packet: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Cyc);
+        Ok(())
+    }
+
+    fn on_mode_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _leaf_id: u8,
+        _mode: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Mode);
+        Ok(())
+    }
+
+    fn on_mtc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ctc_payload: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Mtc);
+        Ok(())
+    }
+
+    fn on_tsc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _tsc_value: u64,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Tsc);
+        Ok(())
+    }
+
+    fn on_cbr_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _core_bus_ratio: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Cbr);
+        Ok(())
+    }
+
+    fn on_tma_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ctc: u16,
+        _fast_counter: u8,
+        _fc8: bool,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Tma);
+        Ok(())
+    }
+
+    fn on_vmcs_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _vmcs_pointer: u64,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Vmcs);
+        Ok(())
+    }
+
+    fn on_ovf_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.add(PacketType::Ovf);
+        Ok(())
+    }
+
+    fn on_psb_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.add(PacketType::Psb);
+        Ok(())
+    }
+
+    fn on_psbend_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.add(PacketType::Psbend);
+        Ok(())
+    }
+
+    fn on_trace_stop_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.add(PacketType::TraceStop);
+        Ok(())
+    }
+
+    fn on_pip_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _cr3: u64,
+        _rsvd_nr: bool,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Pip);
+        Ok(())
+    }
+
+    fn on_mnt_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _payload: u64,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Mnt);
+        Ok(())
+    }
+
+    fn on_ptw_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_bit: bool,
+        _payload: crate::PtwPayload,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Ptw);
+        Ok(())
+    }
+
+    fn on_exstop_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_bit: bool,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Exstop);
+        Ok(())
+    }
+
+    fn on_mwait_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _mwait_hints: u8,
+        _ext: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Mwait);
+        Ok(())
+    }
+
+    fn on_pwre_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _hw: bool,
+        _resolved_thread_c_state: u8,
+        _resolved_thread_sub_c_state: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Pwre);
+        Ok(())
+    }
+
+    fn on_pwrx_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _last_core_c_state: u8,
+        _deepest_core_c_state: u8,
+        _wake_reason: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Pwrx);
+        Ok(())
+    }
+
+    fn on_evd_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _type: u8,
+        _payload: u64,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Evd);
+        Ok(())
+    }
+
+    fn on_cfe_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_bit: bool,
+        _type: u8,
+        _vector: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Cfe);
+        Ok(())
+    }
+
+    fn on_bbp_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _sz_bit: bool,
+        _type: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Bbp);
+        Ok(())
+    }
+
+    fn on_bep_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _ip_bit: bool,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Bep);
+        Ok(())
+    }
+
+    fn on_bip_packet(
+        &mut self,
+        _context: &DecoderContext,
+        _id: u8,
+        _payload: &[u8],
+        _bbp_type: u8,
+    ) -> Result<(), Self::Error> {
+        self.add(PacketType::Bip);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecodeOptions, PSB_BYTES};
+
+    #[test]
+    fn test_packet_type_order() {
+        for (index, packet_type) in PacketType::ALL.into_iter().enumerate() {
+            assert_eq!(packet_type as usize, index);
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut buf = [0u8; 16 + 2 + 3 + 1 + 1 + 2];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&[0x02, 0x23]);
+        // TIP with 2 bytes IP
+        buf[18..21].copy_from_slice(&[0x2D, 0x34, 0x12]);
+        // Short TNT
+        buf[21] = 0b0000_0110;
+        // PAD, followed by a short TNT and a PAD
+        buf[23] = 0b0000_0110;
+
+        let mut histogram = PacketHistogram::new();
+        crate::decode(&buf, DecodeOptions::default(), &mut histogram).unwrap();
+        assert_eq!(histogram.count(PacketType::ShortTnt), 2);
+        assert_eq!(histogram.count(PacketType::Pad), 2);
+        assert_eq!(histogram.count(PacketType::Fup), 0);
+        assert_eq!(histogram.total_count(), 7);
+        assert!(histogram.iter().eq([
+            (PacketType::ShortTnt, 2),
+            (PacketType::Tip, 1),
+            (PacketType::Pad, 2),
+            (PacketType::Psb, 1),
+            (PacketType::Psbend, 1),
+        ]));
+
+        // Counts are the same with coalesced PAD packets
+        let mut options = DecodeOptions::default();
+        options.coalesce_pad(true);
+        crate::decode(&buf, options, &mut histogram).unwrap();
+        assert_eq!(histogram.count(PacketType::Pad), 2);
+        assert_eq!(histogram.total_count(), 7);
+    }
+}
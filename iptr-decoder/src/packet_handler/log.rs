@@ -13,10 +13,247 @@
 //! let handler = CombinedPacketHandler::new(handler1, handler2);
 //! // Use handler1 ...
 //! ```
+//!
+//! The formatting of each packet is also available as the [`Display`][core::fmt::Display]
+//! implementation of [`Packet`], so that tools can print packets in the same format.
 
-use core::{convert::Infallible, fmt::Write, num::NonZero};
+use core::{convert::Infallible, fmt, num::NonZero};
 
-use crate::{DecoderContext, HandlePacket, IpReconstructionPattern, PtwPayload};
+use crate::{DecoderContext, HandlePacket, IpReconstructionPattern, PtwPayload, TraceeMode};
+
+/// Intel PT packet with its decoded fields, used for formatting.
+///
+/// The fields are the same as the corresponding callbacks in [`HandlePacket`].
+/// Formatting a packet via [`Display`][core::fmt::Display] gives the same
+/// string as [`PacketHandlerRawLogger`] logs.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[expect(missing_docs)]
+pub enum Packet<'a> {
+    /// Short TNT packet
+    ShortTnt {
+        packet_byte: NonZero<u8>,
+        highest_bit: u32,
+    },
+    /// Long TNT packet
+    LongTnt {
+        packet_bytes: NonZero<u64>,
+        highest_bit: u32,
+    },
+    /// TIP packet
+    Tip(IpReconstructionPattern),
+    /// TIP.PGD packet
+    TipPgd(IpReconstructionPattern),
+    /// TIP.PGE packet
+    TipPge(IpReconstructionPattern),
+    /// FUP packet
+    Fup(IpReconstructionPattern),
+    /// PAD packet
+    Pad,
+    /// Run of continuous PAD packets
+    PadRun { count: usize },
+    /// CYC packet
+    Cyc { cyc_packet: &'a [u8] },
+    /// MODE packet
+    Mode { leaf_id: u8, mode: u8 },
+    /// MTC packet
+    Mtc { ctc_payload: u8 },
+    /// TSC packet
+    Tsc { tsc_value: u64 },
+    /// CBR packet
+    Cbr { core_bus_ratio: u8 },
+    /// TMA packet
+    Tma {
+        ctc: u16,
+        fast_counter: u8,
+        fc8: bool,
+    },
+    /// VMCS packet
+    Vmcs { vmcs_pointer: u64 },
+    /// OVF packet
+    Ovf,
+    /// PSB packet
+    Psb,
+    /// PSBEND packet
+    Psbend,
+    /// TraceStop packet
+    TraceStop,
+    /// PIP packet
+    Pip { cr3: u64, rsvd_nr: bool },
+    /// MNT packet
+    Mnt { payload: u64 },
+    /// PTW packet
+    Ptw { ip_bit: bool, payload: PtwPayload },
+    /// EXSTOP packet
+    Exstop { ip_bit: bool },
+    /// MWAIT packet
+    Mwait { mwait_hints: u8, ext: u8 },
+    /// PWRE packet
+    Pwre {
+        hw: bool,
+        resolved_thread_c_state: u8,
+        resolved_thread_sub_c_state: u8,
+    },
+    /// PWRX packet
+    Pwrx {
+        last_core_c_state: u8,
+        deepest_core_c_state: u8,
+        wake_reason: u8,
+    },
+    /// EVD packet
+    Evd { r#type: u8, payload: u64 },
+    /// CFE packet
+    Cfe {
+        ip_bit: bool,
+        r#type: u8,
+        vector: u8,
+    },
+    /// BBP packet
+    Bbp { sz_bit: bool, r#type: u8 },
+    /// BEP packet
+    Bep { ip_bit: bool },
+    /// BIP packet
+    Bip {
+        id: u8,
+        payload: &'a [u8],
+        bbp_type: u8,
+    },
+}
+
+impl fmt::Display for Packet<'_> {
+    #[expect(clippy::too_many_lines, clippy::enum_glob_use)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Packet::*;
+        match *self {
+            ShortTnt {
+                packet_byte,
+                highest_bit,
+            } => write!(
+                f,
+                "[Short TNT packet]\tpacket byte: {packet_byte:#010b}\thighest bit: {highest_bit}"
+            ),
+            LongTnt {
+                packet_bytes,
+                highest_bit,
+            } => write!(
+                f,
+                "[Long TNT packet]\tpacket bytes: {packet_bytes:#050b}\thighest bit: {highest_bit}"
+            ),
+            Tip(ip_reconstruction_pattern) => {
+                write!(
+                    f,
+                    "[TIP packet]\tip reconstruction: {ip_reconstruction_pattern}"
+                )
+            }
+            TipPgd(ip_reconstruction_pattern) => {
+                write!(
+                    f,
+                    "[TIP.PGD packet]\tip reconstruction: {ip_reconstruction_pattern}"
+                )
+            }
+            TipPge(ip_reconstruction_pattern) => {
+                write!(
+                    f,
+                    "[TIP.PGE packet]\tip reconstruction: {ip_reconstruction_pattern}"
+                )
+            }
+            Fup(ip_reconstruction_pattern) => {
+                write!(
+                    f,
+                    "[FUP packet]\tip reconstruction: {ip_reconstruction_pattern}"
+                )
+            }
+            Pad => write!(f, "[PAD packet]"),
+            PadRun { count } => write!(f, "[PAD packet]\tcount: {count}"),
+            Cyc { cyc_packet } => {
+                write!(f, "[CYC packet]\t")?;
+                for (index, byte) in cyc_packet.iter().enumerate() {
+                    if index != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{byte:#010b}")?;
+                }
+                Ok(())
+            }
+            Mode { leaf_id, mode } => {
+                write!(
+                    f,
+                    "[MODE packet]\tLeaf ID: {leaf_id:#05b}\tmode:{mode:#07b}"
+                )
+            }
+            Mtc { ctc_payload } => write!(f, "[MTC packet]\tCTC: {ctc_payload:#010b}"),
+            Tsc { tsc_value } => write!(f, "[TSC packet]\tTSC: {tsc_value:#066b}"),
+            Cbr { core_bus_ratio } => {
+                write!(f, "[CBR packet]\tCore:Bus Ratio: {core_bus_ratio:#010b}")
+            }
+            Tma {
+                ctc,
+                fast_counter,
+                fc8,
+            } => write!(
+                f,
+                "[TMA packet]\tCTC: {ctc:#018b}\tFast Counter: {fast_counter:#010b}\tFC8: {fc8}"
+            ),
+            Vmcs { vmcs_pointer } => write!(f, "[VMCS packet]\tVMCS Pointer: {vmcs_pointer:#x}"),
+            Ovf => write!(f, "[OVF packet]"),
+            Psb => write!(f, "[PSB packet]"),
+            Psbend => write!(f, "[PSBEND packet]"),
+            TraceStop => write!(f, "[TRACE STOP packet]"),
+            Pip { cr3, rsvd_nr } => write!(f, "[PIP packet]\tCR3: {cr3:#x}\tRSVD.NR: {rsvd_nr}"),
+            Mnt { payload } => write!(f, "[MNT packet]\tPayload: {payload:#x}"),
+            Ptw { ip_bit, payload } => {
+                write!(f, "[PTW packet]\tIP bit: {ip_bit}\tPayload: {payload}")
+            }
+            Exstop { ip_bit } => write!(f, "[EXSTOP packet]\tIP bit: {ip_bit}"),
+            Mwait { mwait_hints, ext } => write!(
+                f,
+                "[MWAIT packet]\tMWAIT hints: {mwait_hints:#010b}\tEXT: {ext:#04b}"
+            ),
+            Pwre {
+                hw,
+                resolved_thread_c_state,
+                resolved_thread_sub_c_state,
+            } => write!(
+                f,
+                "[PWRE packet]\tHW: {hw}\tResolved Thread C-State: {resolved_thread_c_state:#06b}\tResolved Thread Sub C-State: {resolved_thread_sub_c_state:#06b}"
+            ),
+            Pwrx {
+                last_core_c_state,
+                deepest_core_c_state,
+                wake_reason,
+            } => write!(
+                f,
+                "[PWRX packet]\tLast Core C-State: {last_core_c_state:#06b}\tDeepest Core C-State: {deepest_core_c_state:#06b}\tWake Reason: {wake_reason:#06b}"
+            ),
+            Evd { r#type, payload } => {
+                write!(f, "[EVD packet]\tType: {type:#08b}\tPayload: {payload:#x}")
+            }
+            Cfe {
+                ip_bit,
+                r#type,
+                vector,
+            } => write!(
+                f,
+                "[CFE packet]\tIP bit: {ip_bit}\tType: {type:#07b}\tVector: {vector:#010b}"
+            ),
+            Bbp { sz_bit, r#type } => {
+                write!(f, "[BBP packet]\tSZ bit: {sz_bit}\tType: {type:#07b}")
+            }
+            Bep { ip_bit } => write!(f, "[BEP packet]\tIP bit: {ip_bit}"),
+            Bip {
+                id,
+                payload,
+                bbp_type,
+            } => {
+                write!(f, "[BIP packet]\tID: {id:#07b}\tpayload: 0b")?;
+                for byte in payload {
+                    write!(f, "{byte:08b}")?;
+                }
+                write!(f, "\tbbp_type: {bbp_type:#07b}")
+            }
+        }
+    }
+}
 
 /// Handler for logging each packets
 ///
@@ -33,6 +270,11 @@ impl HandlePacket for PacketHandlerRawLogger {
         Ok(())
     }
 
+    fn on_sync(&mut self, _context: &DecoderContext, psb_offset: usize) -> Result<(), Self::Error> {
+        log::trace!("Synced to PSB at offset {psb_offset:#x}");
+        Ok(())
+    }
+
     fn on_short_tnt_packet(
         &mut self,
         _context: &DecoderContext,
@@ -40,7 +282,11 @@ impl HandlePacket for PacketHandlerRawLogger {
         highest_bit: u32,
     ) -> Result<(), Self::Error> {
         log::trace!(
-            "[Short TNT packet]\tpacket byte: {packet_byte:#010b}\thighest bit: {highest_bit}"
+            "{}",
+            Packet::ShortTnt {
+                packet_byte,
+                highest_bit
+            }
         );
         Ok(())
     }
@@ -52,7 +298,11 @@ impl HandlePacket for PacketHandlerRawLogger {
         highest_bit: u32,
     ) -> Result<(), Self::Error> {
         log::trace!(
-            "[Long TNT packet]\tpacket bytes: {packet_bytes:#050b}\thighest bit: {highest_bit}"
+            "{}",
+            Packet::LongTnt {
+                packet_bytes,
+                highest_bit
+            }
         );
         Ok(())
     }
@@ -62,7 +312,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
     ) -> Result<(), Self::Error> {
-        log::trace!("[TIP packet]\tip reconstruction: {ip_reconstruction_pattern}");
+        log::trace!("{}", Packet::Tip(ip_reconstruction_pattern));
         Ok(())
     }
 
@@ -71,7 +321,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
     ) -> Result<(), Self::Error> {
-        log::trace!("[TIP.PGD packet]\tip reconstruction: {ip_reconstruction_pattern}");
+        log::trace!("{}", Packet::TipPgd(ip_reconstruction_pattern));
         Ok(())
     }
 
@@ -80,7 +330,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
     ) -> Result<(), Self::Error> {
-        log::trace!("[TIP.PGE packet]\tip reconstruction: {ip_reconstruction_pattern}");
+        log::trace!("{}", Packet::TipPge(ip_reconstruction_pattern));
         Ok(())
     }
 
@@ -89,12 +339,17 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
     ) -> Result<(), Self::Error> {
-        log::trace!("[FUP packet]\tip reconstruction: {ip_reconstruction_pattern}");
+        log::trace!("{}", Packet::Fup(ip_reconstruction_pattern));
         Ok(())
     }
 
     fn on_pad_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
-        log::trace!("[PAD packet]");
+        log::trace!("{}", Packet::Pad);
+        Ok(())
+    }
+
+    fn on_pad_run(&mut self, _context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        log::trace!("{}", Packet::PadRun { count });
         Ok(())
     }
 
@@ -103,14 +358,17 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         cyc_packet: &[u8],
     ) -> Result<(), Self::Error> {
-        log::trace!(
-            "[CYC packet]\t{}",
-            cyc_packet
-                .iter()
-                .map(|byte| alloc::format!("{byte:#010b}"))
-                .collect::<alloc::vec::Vec<_>>()
-                .join(", ")
-        );
+        log::trace!("{}", Packet::Cyc { cyc_packet });
+        Ok(())
+    }
+
+    fn on_mode_exec_change(
+        &mut self,
+        _context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        log::trace!("[MODE.exec change]\t{old_mode:?} -> {new_mode:?}");
         Ok(())
     }
 
@@ -120,7 +378,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         leaf_id: u8,
         mode: u8,
     ) -> Result<(), Self::Error> {
-        log::trace!("[MODE packet]\tLeaf ID: {leaf_id:#05b}\tmode:{mode:#07b}");
+        log::trace!("{}", Packet::Mode { leaf_id, mode });
         Ok(())
     }
 
@@ -129,7 +387,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ctc_payload: u8,
     ) -> Result<(), Self::Error> {
-        log::trace!("[MTC packet]\tCTC: {ctc_payload:#010b}");
+        log::trace!("{}", Packet::Mtc { ctc_payload });
         Ok(())
     }
 
@@ -138,7 +396,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         tsc_value: u64,
     ) -> Result<(), Self::Error> {
-        log::trace!("[TSC packet]\tTSC: {tsc_value:#066b}");
+        log::trace!("{}", Packet::Tsc { tsc_value });
         Ok(())
     }
 
@@ -147,7 +405,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         core_bus_ratio: u8,
     ) -> Result<(), Self::Error> {
-        log::trace!("[CBR packet]\tCore:Bus Ratio: {core_bus_ratio:#010b}");
+        log::trace!("{}", Packet::Cbr { core_bus_ratio });
         Ok(())
     }
 
@@ -159,7 +417,12 @@ impl HandlePacket for PacketHandlerRawLogger {
         fc8: bool,
     ) -> Result<(), Self::Error> {
         log::trace!(
-            "[TMA packet]\tCTC: {ctc:#018b}\tFast Counter: {fast_counter:#010b}\tFC8: {fc8}"
+            "{}",
+            Packet::Tma {
+                ctc,
+                fast_counter,
+                fc8
+            }
         );
         Ok(())
     }
@@ -169,27 +432,35 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         vmcs_pointer: u64,
     ) -> Result<(), Self::Error> {
-        log::trace!("[VMCS packet]\tVMCS Pointer: {vmcs_pointer:#x}");
+        log::trace!("{}", Packet::Vmcs { vmcs_pointer });
         Ok(())
     }
 
     fn on_ovf_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
-        log::trace!("[OVF packet]");
+        log::trace!("{}", Packet::Ovf);
         Ok(())
     }
 
     fn on_psb_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
-        log::trace!("[PSB packet]");
+        log::trace!("{}", Packet::Psb);
         Ok(())
     }
 
     fn on_psbend_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
-        log::trace!("[PSBEND packet]");
+        log::trace!("{}", Packet::Psbend);
+        Ok(())
+    }
+
+    fn on_missing_psbend(&mut self, context: &DecoderContext, byte: u8) -> Result<(), Self::Error> {
+        log::warn!(
+            "Missing PSBEND before packet {byte:#04x} at offset {:#x}",
+            context.packet_offset()
+        );
         Ok(())
     }
 
     fn on_trace_stop_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
-        log::trace!("[TRACE STOP packet]");
+        log::trace!("{}", Packet::TraceStop);
         Ok(())
     }
 
@@ -199,7 +470,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         cr3: u64,
         rsvd_nr: bool,
     ) -> Result<(), Self::Error> {
-        log::trace!("[PIP packet]\tCR3: {cr3:#x}\tRSVD.NR: {rsvd_nr}");
+        log::trace!("{}", Packet::Pip { cr3, rsvd_nr });
         Ok(())
     }
 
@@ -208,7 +479,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         payload: u64,
     ) -> Result<(), Self::Error> {
-        log::trace!("[MNT packet]\tPayload: {payload:#x}");
+        log::trace!("{}", Packet::Mnt { payload });
         Ok(())
     }
 
@@ -218,7 +489,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         ip_bit: bool,
         payload: PtwPayload,
     ) -> Result<(), Self::Error> {
-        log::trace!("[PTW packet]\tIP bit: {ip_bit}\tPayload: {payload}");
+        log::trace!("{}", Packet::Ptw { ip_bit, payload });
         Ok(())
     }
 
@@ -227,7 +498,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ip_bit: bool,
     ) -> Result<(), Self::Error> {
-        log::trace!("[EXSTOP packet]\tIP bit: {ip_bit}");
+        log::trace!("{}", Packet::Exstop { ip_bit });
         Ok(())
     }
 
@@ -237,7 +508,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         mwait_hints: u8,
         ext: u8,
     ) -> Result<(), Self::Error> {
-        log::trace!("[MWAIT packet]\tMWAIT hints: {mwait_hints:#010b}\tEXT: {ext:#04b}");
+        log::trace!("{}", Packet::Mwait { mwait_hints, ext });
         Ok(())
     }
 
@@ -249,7 +520,12 @@ impl HandlePacket for PacketHandlerRawLogger {
         resolved_thread_sub_c_state: u8,
     ) -> Result<(), Self::Error> {
         log::trace!(
-            "[PWRE packet]\tHW: {hw}\tResolved Thread C-State: {resolved_thread_c_state:#06b}\tResolved Thread Sub C-State: {resolved_thread_sub_c_state:#06b}"
+            "{}",
+            Packet::Pwre {
+                hw,
+                resolved_thread_c_state,
+                resolved_thread_sub_c_state
+            }
         );
         Ok(())
     }
@@ -262,7 +538,12 @@ impl HandlePacket for PacketHandlerRawLogger {
         wake_reason: u8,
     ) -> Result<(), Self::Error> {
         log::trace!(
-            "[PWRX packet]\tLast Core C-State: {last_core_c_state:#06b}\tDeepest Core C-State: {deepest_core_c_state:#06b}\tWake Reason: {wake_reason:#06b}"
+            "{}",
+            Packet::Pwrx {
+                last_core_c_state,
+                deepest_core_c_state,
+                wake_reason
+            }
         );
         Ok(())
     }
@@ -273,7 +554,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         r#type: u8,
         payload: u64,
     ) -> Result<(), Self::Error> {
-        log::trace!("[EVD packet]\tType: {type:#07b}\tPayload: {payload:#x}");
+        log::trace!("{}", Packet::Evd { r#type, payload });
         Ok(())
     }
 
@@ -284,7 +565,14 @@ impl HandlePacket for PacketHandlerRawLogger {
         r#type: u8,
         vector: u8,
     ) -> Result<(), Self::Error> {
-        log::trace!("[CFE packet]\tIP bit: {ip_bit}\tType: {type:#07b}\tVector: {vector:#010b}");
+        log::trace!(
+            "{}",
+            Packet::Cfe {
+                ip_bit,
+                r#type,
+                vector
+            }
+        );
         Ok(())
     }
 
@@ -294,7 +582,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         sz_bit: bool,
         r#type: u8,
     ) -> Result<(), Self::Error> {
-        log::trace!("[BBP packet]\tSZ bit: {sz_bit}\tType: {type:#07b}");
+        log::trace!("{}", Packet::Bbp { sz_bit, r#type });
         Ok(())
     }
 
@@ -303,7 +591,7 @@ impl HandlePacket for PacketHandlerRawLogger {
         _context: &DecoderContext,
         ip_bit: bool,
     ) -> Result<(), Self::Error> {
-        log::trace!("[BEP packet]\tIP bit: {ip_bit}");
+        log::trace!("{}", Packet::Bep { ip_bit });
         Ok(())
     }
 
@@ -315,14 +603,70 @@ impl HandlePacket for PacketHandlerRawLogger {
         bbp_type: u8,
     ) -> Result<(), Self::Error> {
         log::trace!(
-            "[BIP packet]\tID: {id:#07b}\tpayload: {}\tbbp_type: {bbp_type:#07b}",
-            payload
-                .iter()
-                .fold(alloc::string::String::from("0b"), |mut output, byte| {
-                    let _ = core::write!(output, "{byte:08b}");
-                    output
-                })
+            "{}",
+            Packet::Bip {
+                id,
+                payload,
+                bbp_type
+            }
         );
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn test_packet_display() {
+        let packet = Packet::ShortTnt {
+            packet_byte: NonZero::new(0b0000_1010).unwrap(),
+            highest_bit: 2,
+        };
+        assert_eq!(
+            packet.to_string(),
+            "[Short TNT packet]\tpacket byte: 0b00001010\thighest bit: 2"
+        );
+        let packet = Packet::Tip(IpReconstructionPattern::TwoBytesWithLastIp(0x1234));
+        assert_eq!(
+            packet.to_string(),
+            alloc::format!(
+                "[TIP packet]\tip reconstruction: {}",
+                IpReconstructionPattern::TwoBytesWithLastIp(0x1234)
+            )
+        );
+        assert_eq!(Packet::Psbend.to_string(), "[PSBEND packet]");
+        let packet = Packet::Cyc {
+            cyc_packet: &[0b11, 0b101],
+        };
+        assert_eq!(packet.to_string(), "[CYC packet]\t0b00000011, 0b00000101");
+        let packet = Packet::Pip {
+            cr3: 0x1000,
+            rsvd_nr: true,
+        };
+        assert_eq!(
+            packet.to_string(),
+            "[PIP packet]\tCR3: 0x1000\tRSVD.NR: true"
+        );
+        let packet = Packet::Evd {
+            r#type: 0b10,
+            payload: 0xFF,
+        };
+        assert_eq!(
+            packet.to_string(),
+            "[EVD packet]\tType: 0b000010\tPayload: 0xff"
+        );
+        let packet = Packet::Bip {
+            id: 0b1,
+            payload: &[0xFF, 0x01],
+            bbp_type: 0b11,
+        };
+        assert_eq!(
+            packet.to_string(),
+            "[BIP packet]\tID: 0b00001\tpayload: 0b1111111100000001\tbbp_type: 0b00011"
+        );
+    }
+}
@@ -1,7 +1,18 @@
 //! This module contains serveral convenient structs
 //! that implments [`HandlePacket`][crate::HandlePacket].
 
+#[cfg(feature = "alloc")]
+pub mod boxed;
+#[cfg(feature = "log_handler")]
+pub mod capture;
 pub mod combined;
+pub mod histogram;
 #[cfg(feature = "log_handler")]
 pub mod log;
+pub mod mode_split;
+pub mod nop;
 pub mod packet_counter;
+#[cfg(feature = "alloc")]
+pub mod power;
+#[cfg(feature = "alloc")]
+pub mod timeline;
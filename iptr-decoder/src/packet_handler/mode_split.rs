@@ -0,0 +1,373 @@
+//! Handler for dispatching packets to sub handlers based on tracee mode.
+
+use core::{self as std, num::NonZero}; // workaround for `perfect_derive`
+
+use perfect_derive::perfect_derive;
+use thiserror::Error;
+
+use crate::{DecoderContext, HandlePacket, IpReconstructionPattern, TraceeMode};
+
+/// A [`HandlePacket`] instance for dispatching packets to different sub
+/// handlers based on current tracee mode
+///
+/// Packets bound to the executed code (e.g., TNT) are only forwarded to the
+/// sub handler matching [`context.tracee_mode()`][DecoderContext::tracee_mode],
+/// which is useful when a trace mixes 64-bit code and 32-bit compatibility code.
+///
+/// Mode-independent packets, i.e., PSB, PSBEND, PIP, VMCS, timing packets (TSC,
+/// MTC, CYC, TMA and CBR), OVF, and IP packets (TIP, TIP.PGE, TIP.PGD and FUP),
+/// are forwarded to all sub handlers, as well as [`on_sync`][HandlePacket::on_sync]
+/// and [`at_decode_begin`][HandlePacket::at_decode_begin]. Otherwise the sub
+/// handlers of inactive modes would have stale paging and timing state, and stale
+/// LastIP for IP compression. Sub handlers can check
+/// [`context.tracee_mode()`][DecoderContext::tracee_mode] to ignore IP packets of
+/// other modes. [`on_mode_exec_change`][HandlePacket::on_mode_exec_change] is
+/// forwarded to the sub handlers of both the old mode and the new mode.
+pub struct ModeSplitHandler<H64, H32, H16>
+where
+    H64: HandlePacket,
+    H32: HandlePacket,
+    H16: HandlePacket,
+{
+    handler64: H64,
+    handler32: H32,
+    handler16: H16,
+}
+
+impl<H64, H32, H16> ModeSplitHandler<H64, H32, H16>
+where
+    H64: HandlePacket,
+    H32: HandlePacket,
+    H16: HandlePacket,
+{
+    /// Create a new [`ModeSplitHandler`]
+    #[must_use]
+    pub fn new(handler64: H64, handler32: H32, handler16: H16) -> Self {
+        Self {
+            handler64,
+            handler32,
+            handler16,
+        }
+    }
+
+    /// Consume the handler and get the original three handlers
+    pub fn into_inner(self) -> (H64, H32, H16) {
+        (self.handler64, self.handler32, self.handler16)
+    }
+
+    /// Get shared reference to the handler for 64-bit mode
+    pub fn handler64(&self) -> &H64 {
+        &self.handler64
+    }
+
+    /// Get unique reference to the handler for 64-bit mode
+    pub fn handler64_mut(&mut self) -> &mut H64 {
+        &mut self.handler64
+    }
+
+    /// Get shared reference to the handler for 32-bit mode
+    pub fn handler32(&self) -> &H32 {
+        &self.handler32
+    }
+
+    /// Get unique reference to the handler for 32-bit mode
+    pub fn handler32_mut(&mut self) -> &mut H32 {
+        &mut self.handler32
+    }
+
+    /// Get shared reference to the handler for 16-bit mode
+    pub fn handler16(&self) -> &H16 {
+        &self.handler16
+    }
+
+    /// Get unique reference to the handler for 16-bit mode
+    pub fn handler16_mut(&mut self) -> &mut H16 {
+        &mut self.handler16
+    }
+}
+
+/// Error for [`ModeSplitHandler`]
+#[derive(Error)]
+#[perfect_derive(Debug)]
+pub enum ModeSplitError<H64, H32, H16>
+where
+    H64: HandlePacket,
+    H32: HandlePacket,
+    H16: HandlePacket,
+{
+    /// Error of the handler for 64-bit mode
+    #[error(transparent)]
+    Mode64Error(H64::Error),
+    /// Error of the handler for 32-bit mode
+    #[error(transparent)]
+    Mode32Error(H32::Error),
+    /// Error of the handler for 16-bit mode
+    #[error(transparent)]
+    Mode16Error(H16::Error),
+}
+
+/// Forward a callback to the sub handler of current tracee mode
+macro_rules! forward_to_current {
+    ($self:ident, $context:ident, $method:ident($($arg:ident),*)) => {
+        match $context.tracee_mode() {
+            TraceeMode::Mode64 => $self
+                .handler64
+                .$method($context, $($arg),*)
+                .map_err(ModeSplitError::Mode64Error),
+            TraceeMode::Mode32 => $self
+                .handler32
+                .$method($context, $($arg),*)
+                .map_err(ModeSplitError::Mode32Error),
+            TraceeMode::Mode16 => $self
+                .handler16
+                .$method($context, $($arg),*)
+                .map_err(ModeSplitError::Mode16Error),
+        }
+    };
+}
+
+/// Forward a callback to all sub handlers
+macro_rules! forward_to_all {
+    ($self:ident, $context:ident, $method:ident($($arg:ident),*)) => {{
+        $self
+            .handler64
+            .$method($context, $($arg),*)
+            .map_err(ModeSplitError::Mode64Error)?;
+        $self
+            .handler32
+            .$method($context, $($arg),*)
+            .map_err(ModeSplitError::Mode32Error)?;
+        $self
+            .handler16
+            .$method($context, $($arg),*)
+            .map_err(ModeSplitError::Mode16Error)?;
+        Ok(())
+    }};
+}
+
+/// Implement packet callbacks by forwarding with `forward_to_current` or `forward_to_all`
+macro_rules! forward_callbacks {
+    ($forward:ident: $(fn $method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method(
+                &mut self,
+                context: &DecoderContext,
+                $($arg: $ty),*
+            ) -> Result<(), Self::Error> {
+                $forward!(self, context, $method($($arg),*))
+            }
+        )*
+    };
+}
+
+impl<H64, H32, H16> HandlePacket for ModeSplitHandler<H64, H32, H16>
+where
+    H64: HandlePacket,
+    H32: HandlePacket,
+    H16: HandlePacket,
+    ModeSplitError<H64, H32, H16>: core::error::Error,
+{
+    type Error = ModeSplitError<H64, H32, H16>;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.handler64
+            .at_decode_begin()
+            .map_err(ModeSplitError::Mode64Error)?;
+        self.handler32
+            .at_decode_begin()
+            .map_err(ModeSplitError::Mode32Error)?;
+        self.handler16
+            .at_decode_begin()
+            .map_err(ModeSplitError::Mode16Error)?;
+        Ok(())
+    }
+
+    // Mode-independent packets, which update the decoding state (e.g., LastIP,
+    // timing and paging) shared by all tracee modes
+    forward_callbacks! {
+        forward_to_all:
+        fn on_sync(psb_offset: usize);
+        fn on_tip_packet(ip_reconstruction_pattern: IpReconstructionPattern);
+        fn on_tip_pgd_packet(ip_reconstruction_pattern: IpReconstructionPattern);
+        fn on_tip_pge_packet(ip_reconstruction_pattern: IpReconstructionPattern);
+        fn on_fup_packet(ip_reconstruction_pattern: IpReconstructionPattern);
+        fn on_cyc_packet(cyc_packet: &[u8]);
+        fn on_mtc_packet(ctc_payload: u8);
+        fn on_tsc_packet(tsc_value: u64);
+        fn on_cbr_packet(core_bus_ratio: u8);
+        fn on_tma_packet(ctc: u16, fast_counter: u8, fc8: bool);
+        fn on_vmcs_packet(vmcs_pointer: u64);
+        fn on_ovf_packet();
+        fn on_psb_packet();
+        fn on_psbend_packet();
+        fn on_missing_psbend(byte: u8);
+        fn on_pip_packet(cr3: u64, rsvd_nr: bool);
+    }
+
+    forward_callbacks! {
+        forward_to_current:
+        fn on_short_tnt_packet(packet_byte: NonZero<u8>, highest_bit: u32);
+        fn on_long_tnt_packet(packet_bytes: NonZero<u64>, highest_bit: u32);
+        fn on_pad_packet();
+        fn on_pad_run(count: usize);
+        fn on_mode_packet(leaf_id: u8, mode: u8);
+        fn on_trace_stop_packet();
+        fn on_mnt_packet(payload: u64);
+        fn on_ptw_packet(ip_bit: bool, payload: crate::PtwPayload);
+        fn on_exstop_packet(ip_bit: bool);
+        fn on_mwait_packet(mwait_hints: u8, ext: u8);
+        fn on_pwre_packet(hw: bool, resolved_thread_c_state: u8, resolved_thread_sub_c_state: u8);
+        fn on_pwrx_packet(last_core_c_state: u8, deepest_core_c_state: u8, wake_reason: u8);
+        fn on_evd_packet(r#type: u8, payload: u64);
+        fn on_cfe_packet(ip_bit: bool, r#type: u8, vector: u8);
+        fn on_bbp_packet(sz_bit: bool, r#type: u8);
+        fn on_bep_packet(ip_bit: bool);
+        fn on_bip_packet(id: u8, payload: &[u8], bbp_type: u8);
+    }
+
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        old_mode: TraceeMode,
+        new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        // Both the handler being left and the handler being entered are notified
+        for mode in [old_mode, new_mode] {
+            match mode {
+                TraceeMode::Mode64 => self
+                    .handler64
+                    .on_mode_exec_change(context, old_mode, new_mode)
+                    .map_err(ModeSplitError::Mode64Error)?,
+                TraceeMode::Mode32 => self
+                    .handler32
+                    .on_mode_exec_change(context, old_mode, new_mode)
+                    .map_err(ModeSplitError::Mode32Error)?,
+                TraceeMode::Mode16 => self
+                    .handler16
+                    .on_mode_exec_change(context, old_mode, new_mode)
+                    .map_err(ModeSplitError::Mode16Error)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only the handler of current tracee mode is asked
+    fn override_tracee_mode(
+        &mut self,
+        context: &DecoderContext,
+    ) -> Result<Option<TraceeMode>, Self::Error> {
+        forward_to_current!(self, context, override_tracee_mode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecodeOptions, PSB_BYTES, decode, packet_handler::packet_counter::PacketCounter};
+
+    #[test]
+    fn test_mode_split() {
+        const TIP: [u8; 9] = [0xCD, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        const MODE_EXEC_32: [u8; 2] = [0x99, 0x02];
+        const MODE_EXEC_64: [u8; 2] = [0x99, 0x01];
+
+        let parts: [&[u8]; 8] = [
+            &PSB_BYTES,
+            &[0x02, 0x23],
+            &TIP,
+            &MODE_EXEC_32,
+            &TIP,
+            &TIP,
+            &MODE_EXEC_64,
+            &TIP,
+        ];
+        let mut buf = [0u8; 58];
+        let mut pos = 0;
+        for part in parts {
+            buf[pos..pos + part.len()].copy_from_slice(part);
+            pos += part.len();
+        }
+        assert_eq!(pos, buf.len());
+
+        let mut handler = ModeSplitHandler::new(
+            PacketCounter::new(),
+            PacketCounter::new(),
+            PacketCounter::new(),
+        );
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        // PSB, PSBEND and TIPs are broadcast, plus MODE after switching back
+        assert_eq!(handler.handler64().packet_count(), 7);
+        // PSB, PSBEND and TIPs are broadcast, plus MODE
+        assert_eq!(handler.handler32().packet_count(), 7);
+        // PSB, PSBEND and TIPs are broadcast
+        assert_eq!(handler.handler16().packet_count(), 6);
+    }
+
+    /// Reconstruct IPs of TIP packets
+    #[derive(Default)]
+    struct TipRecorder {
+        last_ip: u64,
+    }
+
+    impl HandlePacket for TipRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            self.last_ip = 0;
+            Ok(())
+        }
+
+        fn on_tip_packet(
+            &mut self,
+            _context: &DecoderContext,
+            ip_reconstruction_pattern: IpReconstructionPattern,
+        ) -> Result<(), Self::Error> {
+            crate::utils::reconstruct_ip_and_update_last(
+                &mut self.last_ip,
+                ip_reconstruction_pattern,
+            );
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mode_split_last_ip() {
+        const TIP_FULL: [u8; 9] = [0xCD, 0x00, 0x10, 0x56, 0x34, 0x12, 0x7F, 0x00, 0x00];
+        // Update lower 4 bytes
+        const TIP_4_BYTES: [u8; 5] = [0x4D, 0x00, 0x20, 0x00, 0x00];
+        // Update lower 2 bytes
+        const TIP_2_BYTES: [u8; 3] = [0x2D, 0x00, 0x30];
+        const MODE_EXEC_32: [u8; 2] = [0x99, 0x02];
+        const MODE_EXEC_64: [u8; 2] = [0x99, 0x01];
+
+        let parts: [&[u8]; 7] = [
+            &PSB_BYTES,
+            &[0x02, 0x23],
+            &TIP_FULL,
+            &MODE_EXEC_32,
+            &TIP_4_BYTES,
+            &MODE_EXEC_64,
+            &TIP_2_BYTES,
+        ];
+        let mut buf = [0u8; 39];
+        let mut pos = 0;
+        for part in parts {
+            buf[pos..pos + part.len()].copy_from_slice(part);
+            pos += part.len();
+        }
+        assert_eq!(pos, buf.len());
+
+        let mut handler = ModeSplitHandler::new(
+            TipRecorder::default(),
+            TipRecorder::default(),
+            TipRecorder::default(),
+        );
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        // The TIP in 32-bit mode is also used by the 64-bit handler as the
+        // reference of IP compression
+        assert_eq!(handler.handler64().last_ip, 0x7F12_0000_3000);
+        assert_eq!(handler.handler32().last_ip, 0x7F12_0000_3000);
+        assert_eq!(handler.handler16().last_ip, 0x7F12_0000_3000);
+    }
+}
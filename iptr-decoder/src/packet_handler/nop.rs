@@ -0,0 +1,20 @@
+//! Handler which does nothing
+
+use crate::HandlePacket;
+
+/// A [`HandlePacket`] instance which ignores all packets.
+///
+/// This is useful for only checking whether a trace can be decoded, e.g., with
+/// [`decode_with_summary`][crate::decode_with_summary], or as a placeholder in
+/// [`CombinedPacketHandler`][super::combined::CombinedPacketHandler].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NopPacketHandler;
+
+impl HandlePacket for NopPacketHandler {
+    // Will never fail
+    type Error = core::convert::Infallible;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -7,7 +7,10 @@ use crate::{DecoderContext, HandlePacket, IpReconstructionPattern};
 /// A [`HandlePacket`] instance for counting Intel PT packets
 #[derive(Default)]
 pub struct PacketCounter {
+    /// Packet count since last reset
     packet_count: usize,
+    /// Packet count before last reset
+    previous_count: usize,
 }
 
 impl PacketCounter {
@@ -17,11 +20,37 @@ impl PacketCounter {
         Self::default()
     }
 
-    /// Get the total packet count
+    /// Get the packet count since last reset.
+    ///
+    /// The count is reset at [`at_decode_begin`][HandlePacket::at_decode_begin]
+    /// and [`take_count`][Self::take_count]. Since every call of
+    /// [`decode`][crate::decode] invokes `at_decode_begin`, this is the
+    /// packet count of the latest decoding, while the counts of all decodings
+    /// are accumulated in [`total_count`][Self::total_count].
     #[must_use]
     pub fn packet_count(&self) -> usize {
         self.packet_count
     }
+
+    /// Get the packet count since last reset, and reset the count
+    pub fn take_count(&mut self) -> usize {
+        let packet_count = self.packet_count;
+        self.reset_count();
+        packet_count
+    }
+
+    /// Get the total packet count during the lifetime of this counter,
+    /// which is never reset
+    #[must_use]
+    pub fn total_count(&self) -> usize {
+        self.previous_count + self.packet_count
+    }
+
+    /// Reset the packet count, and accumulate it into the total count
+    fn reset_count(&mut self) {
+        self.previous_count += self.packet_count;
+        self.packet_count = 0;
+    }
 }
 
 impl HandlePacket for PacketCounter {
@@ -29,7 +58,7 @@ impl HandlePacket for PacketCounter {
     type Error = core::convert::Infallible;
 
     fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
-        self.packet_count = 0;
+        self.reset_count();
         Ok(())
     }
 
@@ -101,6 +130,12 @@ impl HandlePacket for PacketCounter {
         Ok(())
     }
 
+    fn on_pad_run(&mut self, _context: &DecoderContext, count: usize) -> Result<(), Self::Error> {
+        self.packet_count += count;
+
+        Ok(())
+    }
+
     fn on_cyc_packet(
         &mut self,
         _context: &DecoderContext,
@@ -331,3 +366,42 @@ impl HandlePacket for PacketCounter {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecodeOptions, PSB_BYTES, decode};
+
+    #[test]
+    fn test_take_count() {
+        // PSB, PSBEND, PAD, short TNT
+        let mut buf = [0u8; 20];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&[0x02, 0x23]);
+        buf[19] = 0b0000_0110;
+
+        let mut counter = PacketCounter::new();
+        decode(&buf, DecodeOptions::default(), &mut counter).unwrap();
+        assert_eq!(counter.take_count(), 4);
+        assert_eq!(counter.take_count(), 0);
+        decode(&buf[..18], DecodeOptions::default(), &mut counter).unwrap();
+        assert_eq!(counter.take_count(), 2);
+        assert_eq!(counter.total_count(), 6);
+    }
+
+    #[test]
+    fn test_reset_per_decode() {
+        // PSB, PSBEND, short TNT
+        let mut buf = [0u8; 19];
+        buf[..16].copy_from_slice(&PSB_BYTES);
+        buf[16..18].copy_from_slice(&[0x02, 0x23]);
+        buf[18] = 0b0000_0110;
+
+        let mut counter = PacketCounter::new();
+        decode(&buf, DecodeOptions::default(), &mut counter).unwrap();
+        assert_eq!(counter.packet_count(), 3);
+        decode(&buf, DecodeOptions::default(), &mut counter).unwrap();
+        assert_eq!(counter.packet_count(), 3);
+        assert_eq!(counter.total_count(), 6);
+    }
+}
@@ -0,0 +1,251 @@
+//! Handler for reconstructing C-state residency intervals from power events
+
+use alloc::vec::Vec;
+
+use crate::{
+    CState, DecoderContext, HandlePacket, WakeReason,
+    utils::{PtTimingConfig, TimeTracker},
+};
+
+/// An interval during which the thread stays in a C-state deeper than C0,
+/// built by [`PowerEventHandler`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerInterval {
+    /// Estimated TSC of the PWRE packet entering the C-state
+    pub start_tsc: u64,
+    /// Estimated TSC of the PWRX packet (or the next PWRE packet) leaving the
+    /// C-state
+    pub end_tsc: u64,
+    /// Resolved thread C-state given by the PWRE packet
+    pub c_state: CState,
+    /// Core frequency in MHz before entering the C-state, calculated from the
+    /// last CBR packet. [`None`] if no CBR packet is met yet.
+    pub frequency_mhz: Option<u32>,
+}
+
+/// A [`HandlePacket`] instance for reconstructing the intervals of C-states and
+/// the core frequency from CBR, PWRE and PWRX packets.
+///
+/// An interval starts at a PWRE packet and ends at the next PWRX packet. If another
+/// PWRE packet comes first (i.e., the C-state changes without waking up), the
+/// interval ends there and a new interval starts. A PWRX packet without a
+/// preceding PWRE packet (e.g., tracing starts during a C-state) is ignored, and
+/// the pending interval is dropped at OVF packets since the timing is lost.
+///
+/// Timestamps and the core frequency are tracked by [`TimeTracker`]. The bus
+/// clock frequency is not recorded in the trace and can be set by
+/// [`bus_clock_mhz`][Self::bus_clock_mhz].
+pub struct PowerEventHandler {
+    /// Tracker of TSC and core frequency
+    time_tracker: TimeTracker,
+    /// Estimated TSC, resolved thread C-state and core frequency of the pending
+    /// PWRE packet
+    pending_pwre: Option<(u64, CState, Option<u32>)>,
+    /// Reconstructed intervals
+    intervals: Vec<PowerInterval>,
+}
+
+impl PowerEventHandler {
+    /// Create a new [`PowerEventHandler`] with given timing configuration
+    #[must_use]
+    pub fn new(timing_config: PtTimingConfig) -> Self {
+        Self {
+            time_tracker: TimeTracker::with_timing_config(timing_config),
+            pending_pwre: None,
+            intervals: Vec::new(),
+        }
+    }
+
+    /// Set bus clock frequency in MHz. Default is 100.
+    pub fn bus_clock_mhz(&mut self, bus_clock_mhz: u32) -> &mut Self {
+        self.time_tracker.bus_clock_mhz(bus_clock_mhz);
+        self
+    }
+
+    /// Get the reconstructed intervals, ordered by occurrence in the trace.
+    ///
+    /// The intervals are cleared at [`at_decode_begin`][HandlePacket::at_decode_begin].
+    #[must_use]
+    pub fn intervals(&self) -> &[PowerInterval] {
+        &self.intervals
+    }
+
+    /// Consume the handler and get the reconstructed intervals
+    #[must_use]
+    pub fn into_intervals(self) -> Vec<PowerInterval> {
+        self.intervals
+    }
+
+    /// Get current core frequency in MHz
+    #[must_use]
+    pub fn frequency_mhz(&self) -> Option<u32> {
+        self.time_tracker.frequency_mhz()
+    }
+
+    /// End the pending interval at current estimated TSC
+    fn end_pending_interval(&mut self) {
+        let Some((start_tsc, c_state, frequency_mhz)) = self.pending_pwre.take() else {
+            return;
+        };
+        self.intervals.push(PowerInterval {
+            start_tsc,
+            end_tsc: self.time_tracker.estimated_tsc(),
+            c_state,
+            frequency_mhz,
+        });
+    }
+}
+
+impl Default for PowerEventHandler {
+    fn default() -> Self {
+        Self::new(PtTimingConfig::default())
+    }
+}
+
+impl HandlePacket for PowerEventHandler {
+    // Will never fail
+    type Error = core::convert::Infallible;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.time_tracker.reset();
+        self.pending_pwre = None;
+        self.intervals.clear();
+        Ok(())
+    }
+
+    fn on_ovf_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.pending_pwre = None;
+        Ok(())
+    }
+
+    fn on_tsc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        tsc_value: u64,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_tsc(tsc_value);
+        Ok(())
+    }
+
+    fn on_tma_packet(
+        &mut self,
+        _context: &DecoderContext,
+        ctc: u16,
+        _fast_counter: u8,
+        _fc8: bool,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_tma(ctc);
+        Ok(())
+    }
+
+    fn on_mtc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        ctc_payload: u8,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_mtc(ctc_payload);
+        Ok(())
+    }
+
+    fn on_cbr_packet(
+        &mut self,
+        _context: &DecoderContext,
+        core_bus_ratio: u8,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_cbr(core_bus_ratio);
+        Ok(())
+    }
+
+    fn on_pwre(
+        &mut self,
+        _context: &DecoderContext,
+        _hw: bool,
+        resolved_thread_c_state: CState,
+        _resolved_thread_sub_c_state: u8,
+    ) -> Result<(), Self::Error> {
+        self.end_pending_interval();
+        self.pending_pwre = Some((
+            self.time_tracker.estimated_tsc(),
+            resolved_thread_c_state,
+            self.frequency_mhz(),
+        ));
+        Ok(())
+    }
+
+    fn on_pwrx(
+        &mut self,
+        _context: &DecoderContext,
+        _last_core_c_state: CState,
+        _deepest_core_c_state: CState,
+        _wake_reason: WakeReason,
+    ) -> Result<(), Self::Error> {
+        self.end_pending_interval();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DecodeOptions, PSB_BYTES, decode,
+        test_utils::{PSBEND_BYTES, cbr_packet, tsc_packet},
+    };
+
+    #[test]
+    fn test_power_intervals() {
+        let mut buf = Vec::new();
+        buf.extend(PSB_BYTES);
+        buf.extend(tsc_packet(0x1000));
+        buf.extend(cbr_packet(24));
+        buf.extend(PSBEND_BYTES);
+        // PWRE: C6.0
+        buf.extend([0x02, 0x22, 0x00, 0x50]);
+        // EXSTOP
+        buf.extend([0x02, 0x62]);
+        buf.extend(tsc_packet(0x2000));
+        // Frequency changes during C6
+        buf.extend(cbr_packet(16));
+        // PWRX: last C6, deepest C6, interrupt
+        buf.extend([0x02, 0xA2, 0x55, 0x01, 0x00, 0x00, 0x00]);
+        buf.extend(cbr_packet(8));
+        buf.extend(tsc_packet(0x3000));
+        // PWRE: C1.0
+        buf.extend([0x02, 0x22, 0x00, 0x00]);
+        buf.extend(tsc_packet(0x3400));
+        // PWRE: C3.1
+        buf.extend([0x02, 0x22, 0x00, 0x21]);
+        buf.extend(tsc_packet(0x3800));
+        // PWRX: last C3, deepest C3, timer deadline
+        buf.extend([0x02, 0xA2, 0x33, 0x02, 0x00, 0x00, 0x00]);
+        // PWRX without PWRE
+        buf.extend([0x02, 0xA2, 0x33, 0x02, 0x00, 0x00, 0x00]);
+
+        let mut handler = PowerEventHandler::default();
+        handler.bus_clock_mhz(133);
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        assert_eq!(
+            handler.intervals(),
+            [
+                PowerInterval {
+                    start_tsc: 0x1000,
+                    end_tsc: 0x2000,
+                    c_state: CState::C6,
+                    frequency_mhz: Some(24 * 133),
+                },
+                PowerInterval {
+                    start_tsc: 0x3000,
+                    end_tsc: 0x3400,
+                    c_state: CState::C1,
+                    frequency_mhz: Some(8 * 133),
+                },
+                PowerInterval {
+                    start_tsc: 0x3400,
+                    end_tsc: 0x3800,
+                    c_state: CState::C3,
+                    frequency_mhz: Some(8 * 133),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Handler for building a timeline of control flow and power events
+
+use alloc::vec::Vec;
+
+use derive_more::Display;
+
+use crate::{
+    CState, DecoderContext, HandlePacket, IpReconstructionPattern, WakeReason,
+    utils::{PtTimingConfig, TimeTracker, reconstruct_ip_and_update_last, reconstruct_ip_in_mode},
+};
+
+/// Event in the timeline built by [`TimelineHandler`]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineEvent {
+    /// TIP packet, with the branch target if it is not out of context
+    #[display("TIP({_0:x?})")]
+    Tip(Option<u64>),
+    /// TIP.PGE packet, with the address where tracing is enabled if it is
+    /// not out of context
+    #[display("TIP.PGE({_0:x?})")]
+    TipPge(Option<u64>),
+    /// TIP.PGD packet, with the address where tracing is disabled if it is
+    /// not out of context
+    #[display("TIP.PGD({_0:x?})")]
+    TipPgd(Option<u64>),
+    /// FUP packet outside PSB+, with the source address of asynchronous event
+    /// if it is not out of context
+    #[display("FUP({_0:x?})")]
+    Fup(Option<u64>),
+    /// EXSTOP packet
+    #[display("EXSTOP(ip_bit={ip_bit})")]
+    Exstop {
+        /// Whether a FUP packet follows
+        ip_bit: bool,
+    },
+    /// MWAIT packet
+    #[display("MWAIT(target={target_c_state}.{target_sub_c_state:#x}, ext={ext:#x})")]
+    Mwait {
+        /// Target C-state in MWAIT hints
+        target_c_state: CState,
+        /// Target sub C-state in MWAIT hints
+        target_sub_c_state: u8,
+        /// `EXT[1:0]`
+        ext: u8,
+    },
+    /// PWRE packet
+    #[display("PWRE(hw={hw}, c_state={resolved_thread_c_state}.{resolved_thread_sub_c_state:#x})")]
+    Pwre {
+        /// Whether the C-state entry is initiated by hardware
+        hw: bool,
+        /// Resolved Thread C-State
+        resolved_thread_c_state: CState,
+        /// Resolved Thread Sub C-State
+        resolved_thread_sub_c_state: u8,
+    },
+    /// PWRX packet
+    #[display(
+        "PWRX(last={last_core_c_state}, deepest={deepest_core_c_state}, wake_reason={wake_reason})"
+    )]
+    Pwrx {
+        /// Last Core C-State
+        last_core_c_state: CState,
+        /// Deepest Core C-State
+        deepest_core_c_state: CState,
+        /// Wake Reason
+        wake_reason: WakeReason,
+    },
+}
+
+/// A [`HandlePacket`] instance for building a timeline of control flow
+/// transitions and power events.
+///
+/// Each event is paired with the estimated TSC at which the event happens. The
+/// TSC is taken from TSC packets, and interpolated by MTC packets following a TMA
+/// packet according to the [`PtTimingConfig`]. CYC packets are not used. The
+/// estimated TSC is guaranteed to be monotonic, i.e., if a TSC packet is behind
+/// the interpolated TSC, the interpolated TSC is kept until time catches up.
+/// Events before the first TSC packet have estimated TSC 0.
+pub struct TimelineHandler {
+    /// Tracker of TSC
+    time_tracker: TimeTracker,
+    /// Last IP for reconstructing IPs
+    last_ip: u64,
+    /// Timeline events
+    timeline: Vec<(u64, TimelineEvent)>,
+}
+
+impl TimelineHandler {
+    /// Create a new [`TimelineHandler`] with given timing configuration
+    #[must_use]
+    pub fn new(timing_config: PtTimingConfig) -> Self {
+        Self {
+            time_tracker: TimeTracker::with_timing_config(timing_config),
+            last_ip: 0,
+            timeline: Vec::new(),
+        }
+    }
+
+    /// Get the timeline as `(estimated_tsc, event)` pairs, ordered by
+    /// occurrence in the trace.
+    ///
+    /// The timeline is cleared at [`at_decode_begin`][HandlePacket::at_decode_begin].
+    #[must_use]
+    pub fn timeline(&self) -> &[(u64, TimelineEvent)] {
+        &self.timeline
+    }
+
+    /// Consume the handler and get the timeline
+    #[must_use]
+    pub fn into_timeline(self) -> Vec<(u64, TimelineEvent)> {
+        self.timeline
+    }
+
+    /// Get current estimated TSC
+    #[must_use]
+    pub fn estimated_tsc(&self) -> u64 {
+        self.time_tracker.estimated_tsc()
+    }
+
+    /// Reconstruct the IP in current tracee mode and push a control flow event
+    fn push_ip_event(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+        event: fn(Option<u64>) -> TimelineEvent,
+    ) {
+        let ip = reconstruct_ip_in_mode(
+            &mut self.last_ip,
+            ip_reconstruction_pattern,
+            context.tracee_mode(),
+        );
+        self.push_event(event(ip));
+    }
+
+    /// Push an event at current estimated TSC
+    fn push_event(&mut self, event: TimelineEvent) {
+        self.timeline.push((self.estimated_tsc(), event));
+    }
+}
+
+impl Default for TimelineHandler {
+    fn default() -> Self {
+        Self::new(PtTimingConfig::default())
+    }
+}
+
+impl HandlePacket for TimelineHandler {
+    // Will never fail
+    type Error = core::convert::Infallible;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.time_tracker.reset();
+        self.last_ip = 0;
+        self.timeline.clear();
+        Ok(())
+    }
+
+    fn on_psb_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.last_ip = 0;
+        Ok(())
+    }
+
+    fn on_tsc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        tsc_value: u64,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_tsc(tsc_value);
+        Ok(())
+    }
+
+    fn on_tma_packet(
+        &mut self,
+        _context: &DecoderContext,
+        ctc: u16,
+        _fast_counter: u8,
+        _fc8: bool,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_tma(ctc);
+        Ok(())
+    }
+
+    fn on_mtc_packet(
+        &mut self,
+        _context: &DecoderContext,
+        ctc_payload: u8,
+    ) -> Result<(), Self::Error> {
+        self.time_tracker.on_mtc(ctc_payload);
+        Ok(())
+    }
+
+    fn on_tip_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.push_ip_event(context, ip_reconstruction_pattern, TimelineEvent::Tip);
+        Ok(())
+    }
+
+    fn on_tip_pge_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.push_ip_event(context, ip_reconstruction_pattern, TimelineEvent::TipPge);
+        Ok(())
+    }
+
+    fn on_tip_pgd_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        self.push_ip_event(context, ip_reconstruction_pattern, TimelineEvent::TipPgd);
+        Ok(())
+    }
+
+    fn on_fup_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+    ) -> Result<(), Self::Error> {
+        if context.is_in_psb() {
+            // Status-only FUP, which only updates the last IP
+            reconstruct_ip_and_update_last(&mut self.last_ip, ip_reconstruction_pattern);
+        } else {
+            self.push_ip_event(context, ip_reconstruction_pattern, TimelineEvent::Fup);
+        }
+        Ok(())
+    }
+
+    fn on_exstop_packet(
+        &mut self,
+        _context: &DecoderContext,
+        ip_bit: bool,
+    ) -> Result<(), Self::Error> {
+        self.push_event(TimelineEvent::Exstop { ip_bit });
+        Ok(())
+    }
+
+    fn on_mwait(
+        &mut self,
+        _context: &DecoderContext,
+        target_c_state: CState,
+        target_sub_c_state: u8,
+        ext: u8,
+    ) -> Result<(), Self::Error> {
+        self.push_event(TimelineEvent::Mwait {
+            target_c_state,
+            target_sub_c_state,
+            ext,
+        });
+        Ok(())
+    }
+
+    fn on_pwre(
+        &mut self,
+        _context: &DecoderContext,
+        hw: bool,
+        resolved_thread_c_state: CState,
+        resolved_thread_sub_c_state: u8,
+    ) -> Result<(), Self::Error> {
+        self.push_event(TimelineEvent::Pwre {
+            hw,
+            resolved_thread_c_state,
+            resolved_thread_sub_c_state,
+        });
+        Ok(())
+    }
+
+    fn on_pwrx(
+        &mut self,
+        _context: &DecoderContext,
+        last_core_c_state: CState,
+        deepest_core_c_state: CState,
+        wake_reason: WakeReason,
+    ) -> Result<(), Self::Error> {
+        self.push_event(TimelineEvent::Pwrx {
+            last_core_c_state,
+            deepest_core_c_state,
+            wake_reason,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DecodeOptions, PSB_BYTES, decode,
+        test_utils::{PSBEND_BYTES, tsc_packet},
+    };
+
+    #[test]
+    fn test_power_event_timeline() {
+        let mut buf = Vec::new();
+        buf.extend(PSB_BYTES);
+        buf.extend(tsc_packet(0x1000));
+        buf.extend(PSBEND_BYTES);
+        // PWRE: HW, C3.1
+        buf.extend([0x02, 0x22, 0x80, 0x21]);
+        // EXSTOP
+        buf.extend([0x02, 0x62]);
+        buf.extend(tsc_packet(0x1800));
+        // PWRX: last C3, deepest C4, interrupt
+        buf.extend([0x02, 0xA2, 0x23, 0x01, 0x00, 0x00, 0x00]);
+        // Stale TSC does not move time backwards
+        buf.extend(tsc_packet(0x1700));
+        // TIP.PGE 0x1000
+        buf.extend([0x31, 0x00, 0x10]);
+
+        let mut handler = TimelineHandler::default();
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        assert_eq!(
+            handler.timeline(),
+            [
+                (
+                    0x1000,
+                    TimelineEvent::Pwre {
+                        hw: true,
+                        resolved_thread_c_state: CState::C3,
+                        resolved_thread_sub_c_state: 0x1,
+                    }
+                ),
+                (0x1000, TimelineEvent::Exstop { ip_bit: false }),
+                (
+                    0x1800,
+                    TimelineEvent::Pwrx {
+                        last_core_c_state: CState::C3,
+                        deepest_core_c_state: CState::C4,
+                        wake_reason: WakeReason::INTERRUPT,
+                    }
+                ),
+                (0x1800, TimelineEvent::TipPge(Some(0x1000))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mtc_interpolation() {
+        let timing_config = PtTimingConfig::from_tsc_ctc_ratio(2, 1, 3).unwrap();
+        let mut buf = Vec::new();
+        buf.extend(PSB_BYTES);
+        buf.extend(tsc_packet(0x1_0000));
+        // TMA: CTC 0x7F4
+        buf.extend([0x02, 0x73, 0xF4, 0x07, 0x00, 0x00, 0x00]);
+        buf.extend(PSBEND_BYTES);
+        // MTC: CTC[10:3] = 0xFF, i.e., CTC 0x7F8
+        buf.extend([0x59, 0xFF]);
+        // PWRE
+        buf.extend([0x02, 0x22, 0x00, 0x10]);
+        // MTC: CTC[10:3] = 0x01 (wrapped), i.e., CTC 0x808
+        buf.extend([0x59, 0x01]);
+        // PWRX
+        buf.extend([0x02, 0xA2, 0x11, 0x02, 0x00, 0x00, 0x00]);
+
+        let mut handler = TimelineHandler::new(timing_config);
+        decode(&buf, DecodeOptions::default(), &mut handler).unwrap();
+        let timeline = handler.into_timeline();
+        assert_eq!(
+            timeline.iter().map(|(tsc, _)| *tsc).collect::<Vec<_>>(),
+            [0x1_0000 + 2 * 0x4, 0x1_0000 + 2 * 0x14]
+        );
+    }
+}
@@ -5,7 +5,7 @@ use derive_more::Display;
 use crate::{
     DecoderContext, HandlePacket, TraceeMode,
     error::{DecoderError, DecoderResult},
-    raw_packet_handler::{RawPacketHandler, RawPacketHandlers},
+    raw_packet_handler::{RawPacketHandler, RawPacketHandlers, leave_psb_if_missing_psbend},
 };
 
 impl<H: HandlePacket> RawPacketHandlers<H> {
@@ -75,12 +75,20 @@ fn handle_pad_packet<H: HandlePacket>(
 ) -> DecoderResult<(), H> {
     let packet_length = 1;
 
+    if context.coalesce_pad {
+        let count = buf.get(context.pos..).map_or(0, |buf| {
+            buf.iter().take_while(|byte| **byte == 0b0000_0000).count()
+        });
+        let result = packet_handler.on_pad_run(context, count);
+        context.pos += count * packet_length;
+        return result.map_err(DecoderError::PacketHandler);
+    }
+
     loop {
-        packet_handler
-            .on_pad_packet(context)
-            .map_err(DecoderError::PacketHandler)?;
+        let result = packet_handler.on_pad_packet(context);
 
         context.pos += packet_length;
+        result.map_err(DecoderError::PacketHandler)?;
         let Some(byte) = buf.get(context.pos) else {
             break;
         };
@@ -88,6 +96,7 @@ fn handle_pad_packet<H: HandlePacket>(
             break;
         }
         // Fast path for continuous PAD packet
+        context.packet_start = context.pos;
     }
 
     Ok(())
@@ -105,7 +114,10 @@ fn handle_short_tnt_packet<H: HandlePacket>(
 
         if (byte & 0b0000_0111) != 0b0000_0100 {
             // BIP's first byte is end with 100
-            return Err(DecoderError::InvalidPacket);
+            return Err(DecoderError::InvalidPacket {
+                byte,
+                offset: context.pos,
+            });
         }
 
         let packet_length = packet_block.size.size() + 1;
@@ -116,13 +128,11 @@ fn handle_short_tnt_packet<H: HandlePacket>(
         else {
             return Err(DecoderError::UnexpectedEOF);
         };
-        packet_handler
-            .on_bip_packet(context, id, bytes, packet_block.r#type)
-            .map_err(DecoderError::PacketHandler)?;
+        let result = packet_handler.on_bip_packet(context, id, bytes, packet_block.r#type);
 
         context.pos += packet_length;
 
-        return Ok(());
+        return result.map_err(DecoderError::PacketHandler);
     }
 
     // SAFETY: byte will never be zero
@@ -132,17 +142,17 @@ fn handle_short_tnt_packet<H: HandlePacket>(
     // The 0b00000000 is PAD packet, so leading zeros will never be 8, so no need
     // to check the trailing 1
     debug_assert!(byte.leading_zeros() <= 6, "Unexpected short TNT packet!");
+    let missing_psbend = leave_psb_if_missing_psbend(context, byte.get(), packet_handler)?;
 
     let packet_length = 1;
 
     let highest_bit = 6 - byte.leading_zeros();
-    packet_handler
-        .on_short_tnt_packet(context, byte, highest_bit)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = missing_psbend
+        .and_then(|()| packet_handler.on_short_tnt_packet(context, byte, highest_bit));
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -152,17 +162,16 @@ fn handle_tip_packet<H: HandlePacket>(
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
+    let missing_psbend = leave_psb_if_missing_psbend(context, byte, packet_handler)?;
     context.pos += 1; // Header
 
     let ip_bytes = byte >> 5;
     // SAFETY: ip_bytes is not greater than 0b111
     let ip_reconstruction_pattern = unsafe { ip_reconstruction(buf, ip_bytes, context)? };
 
-    packet_handler
-        .on_tip_packet(context, ip_reconstruction_pattern)
-        .map_err(DecoderError::PacketHandler)?;
-
-    Ok(())
+    missing_psbend
+        .and_then(|()| packet_handler.on_tip_packet(context, ip_reconstruction_pattern))
+        .map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -172,17 +181,16 @@ fn handle_tip_pgd_packet<H: HandlePacket>(
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
+    let missing_psbend = leave_psb_if_missing_psbend(context, byte, packet_handler)?;
     context.pos += 1; // Header
 
     let ip_bytes = byte >> 5;
     // SAFETY: ip_bytes is not greater than 0b111
     let ip_reconstruction_pattern = unsafe { ip_reconstruction(buf, ip_bytes, context)? };
 
-    packet_handler
-        .on_tip_pgd_packet(context, ip_reconstruction_pattern)
-        .map_err(DecoderError::PacketHandler)?;
-
-    Ok(())
+    missing_psbend
+        .and_then(|()| packet_handler.on_tip_pgd_packet(context, ip_reconstruction_pattern))
+        .map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -192,17 +200,16 @@ fn handle_tip_pge_packet<H: HandlePacket>(
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
+    let missing_psbend = leave_psb_if_missing_psbend(context, byte, packet_handler)?;
     context.pos += 1; // Header
 
     let ip_bytes = byte >> 5;
     // SAFETY: ip_bytes is not greater than 0b111
     let ip_reconstruction_pattern = unsafe { ip_reconstruction(buf, ip_bytes, context)? };
 
-    packet_handler
-        .on_tip_pge_packet(context, ip_reconstruction_pattern)
-        .map_err(DecoderError::PacketHandler)?;
-
-    Ok(())
+    missing_psbend
+        .and_then(|()| packet_handler.on_tip_pge_packet(context, ip_reconstruction_pattern))
+        .map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -228,7 +235,26 @@ fn handle_fup_packet<H: HandlePacket>(
 /// Pattern for IP reconstruction
 ///
 /// You can use utility function [`reconstruct_ip_and_update_last`][crate::utils::reconstruct_ip_and_update_last]
-/// to use this enumerate.
+/// to use this enumerate, or [`reconstruct_ip_in_mode`][crate::utils::reconstruct_ip_in_mode]
+/// to also wrap the IP to the address width of current tracee mode.
+///
+/// The pattern is determined by `IPBytes` (bits 7:5 of the header byte) as
+/// below. According to the Intel SDM, the IP compression is independent of the
+/// tracee mode, so the same patterns are accepted in 16-bit, 32-bit and 64-bit
+/// modes (e.g., an 8-byte IP payload in 32-bit mode simply has its upper 4
+/// bytes cleared). The reserved `IPBytes` are rejected with
+/// [`DecoderError::ReservedIpBytes`] in all modes.
+///
+/// | `IPBytes` | Pattern                                        |
+/// |-----------|------------------------------------------------|
+/// | `0b000`   | [`OutOfContext`][Self::OutOfContext]           |
+/// | `0b001`   | [`TwoBytesWithLastIp`][Self::TwoBytesWithLastIp]   |
+/// | `0b010`   | [`FourBytesWithLastIp`][Self::FourBytesWithLastIp] |
+/// | `0b011`   | [`SixBytesExtended`][Self::SixBytesExtended]   |
+/// | `0b100`   | [`SixBytesWithLastIp`][Self::SixBytesWithLastIp]   |
+/// | `0b101`   | Reserved                                       |
+/// | `0b110`   | [`EightBytes`][Self::EightBytes]               |
+/// | `0b111`   | Reserved                                       |
 #[derive(Debug, Display, Clone, Copy)]
 pub enum IpReconstructionPattern {
     /// None, IP is out of context
@@ -332,7 +358,13 @@ unsafe fn ip_reconstruction<H: HandlePacket>(
             IpReconstructionPattern::EightBytes(ip_payload)
         }
         0b101 | 0b111 => {
-            return Err(DecoderError::InvalidPacket);
+            // pos has been updated by the header
+            let offset = context.pos - 1;
+            return Err(DecoderError::ReservedIpBytes {
+                byte: buf[offset],
+                ip_bytes,
+                offset,
+            });
         }
         _ => {
             // SAFETY: ip_bytes should be no greater than than 0b111
@@ -368,13 +400,12 @@ fn handle_cyc_packet<H: HandlePacket>(
 
     // SAFETY: All bytes are accessed before, end_pos is exclusive
     debug_assert!(buf.len() >= end_pos, "Unexpected");
-    packet_handler
-        .on_cyc_packet(context, unsafe { buf.get_unchecked(context.pos..end_pos) })
-        .map_err(DecoderError::PacketHandler)?;
+    let result =
+        packet_handler.on_cyc_packet(context, unsafe { buf.get_unchecked(context.pos..end_pos) });
 
     context.pos = end_pos;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -395,13 +426,11 @@ fn handle_tsc_packet<H: HandlePacket>(
     let tsc_bytes = [*byte1, *byte2, *byte3, *byte4, *byte5, *byte6, *byte7, 0];
     let tsc_value = u64::from_le_bytes(tsc_bytes);
 
-    packet_handler
-        .on_tsc_packet(context, tsc_value)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_tsc_packet(context, tsc_value);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -418,19 +447,17 @@ fn handle_mtc_packet<H: HandlePacket>(
     };
     let ctc_payload = *byte;
 
-    packet_handler
-        .on_mtc_packet(context, ctc_payload)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_mtc_packet(context, ctc_payload);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_mode_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    header: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
@@ -443,33 +470,54 @@ fn handle_mode_packet<H: HandlePacket>(
     let leaf_id = (byte & 0b1110_0000) >> 5;
     let mode = byte & 0b0001_1111;
 
+    // Bits 4:3 of MODE.Exec and bits 4:2 of MODE.TSX are reserved
+    let reserved_mask = match leaf_id {
+        0b000 => 0b0001_1000,
+        0b001 => 0b0001_1100,
+        _ => 0,
+    };
+    if context.strict && mode & reserved_mask != 0 {
+        return Err(DecoderError::InvalidPacket {
+            byte: header,
+            offset: context.pos,
+        });
+    }
+
+    let mut result = Ok(());
     if leaf_id == 0b000 {
         // MODE.exec packet
-        match mode & 0b0000_0011 {
-            0b00 => context.tracee_mode = TraceeMode::Mode16,
-            0b01 => context.tracee_mode = TraceeMode::Mode64,
-            0b10 => context.tracee_mode = TraceeMode::Mode32,
-            _ => {}
+        let new_mode = match mode & 0b0000_0011 {
+            0b00 => Some(TraceeMode::Mode16),
+            0b01 => Some(TraceeMode::Mode64),
+            0b10 => Some(TraceeMode::Mode32),
+            _ => None,
+        };
+        if let Some(new_mode) = new_mode
+            && new_mode != context.tracee_mode
+        {
+            result = packet_handler.on_mode_exec_change(context, context.tracee_mode, new_mode);
+            context.tracee_mode = new_mode;
         }
     }
 
-    packet_handler
-        .on_mode_packet(context, leaf_id, mode)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = result.and_then(|()| packet_handler.on_mode_packet(context, leaf_id, mode));
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_wrong_packet<H: HandlePacket>(
     _buf: &[u8],
-    _byte: u8,
-    _context: &mut DecoderContext,
+    byte: u8,
+    context: &mut DecoderContext,
     _packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
-    Err(DecoderError::InvalidPacket)
+    Err(DecoderError::InvalidPacket {
+        byte,
+        offset: context.pos,
+    })
 }
 
 #[inline]
@@ -495,18 +543,46 @@ macro_rules! h {
     };
 }
 
+/// Decode packets in `buf` from `context.pos`, and report the progress by
+/// `on_progress` if the progress interval is set in `context`
 pub fn decode<H: HandlePacket>(
     buf: &[u8],
     context: &mut DecoderContext,
     packet_handler: &mut H,
+    on_progress: &mut dyn FnMut(usize, usize),
 ) -> DecoderResult<(), H> {
-    loop {
-        let Some(byte) = buf.get(context.pos) else {
-            break;
-        };
+    while let Some(byte) = buf.get(context.pos) {
+        if context.pos >= context.next_progress_pos {
+            context.report_progress(buf.len(), on_progress);
+        }
         let byte = *byte;
+        context.packet_start = context.pos;
         // Note that context.pos has not been updated before calling dispatch functions
-        h!(byte, buf, context, packet_handler: 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79,80,81,82,83,84,85,86,87,88,89,90,91,92,93,94,95,96,97,98,99,100,101,102,103,104,105,106,107,108,109,110,111,112,113,114,115,116,117,118,119,120,121,122,123,124,125,126,127,128,129,130,131,132,133,134,135,136,137,138,139,140,141,142,143,144,145,146,147,148,149,150,151,152,153,154,155,156,157,158,159,160,161,162,163,164,165,166,167,168,169,170,171,172,173,174,175,176,177,178,179,180,181,182,183,184,185,186,187,188,189,190,191,192,193,194,195,196,197,198,199,200,201,202,203,204,205,206,207,208,209,210,211,212,213,214,215,216,217,218,219,220,221,222,223,224,225,226,227,228,229,230,231,232,233,234,235,236,237,238,239,240,241,242,243,244,245,246,247,248,249,250,251,252,253,254,255)?;
+        if let Err(err) = h!(byte, buf, context, packet_handler: 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79,80,81,82,83,84,85,86,87,88,89,90,91,92,93,94,95,96,97,98,99,100,101,102,103,104,105,106,107,108,109,110,111,112,113,114,115,116,117,118,119,120,121,122,123,124,125,126,127,128,129,130,131,132,133,134,135,136,137,138,139,140,141,142,143,144,145,146,147,148,149,150,151,152,153,154,155,156,157,158,159,160,161,162,163,164,165,166,167,168,169,170,171,172,173,174,175,176,177,178,179,180,181,182,183,184,185,186,187,188,189,190,191,192,193,194,195,196,197,198,199,200,201,202,203,204,205,206,207,208,209,210,211,212,213,214,215,216,217,218,219,220,221,222,223,224,225,226,227,228,229,230,231,232,233,234,235,236,237,238,239,240,241,242,243,244,245,246,247,248,249,250,251,252,253,254,255)
+        {
+            // Note that the start may be moved by continuous PAD packets.
+            let packet_start = context.packet_start;
+            if matches!(err, DecoderError::PacketHandler(_))
+                && context.skipped_handler_errors < context.handler_error_budget
+            {
+                // The erroneous packet has been fully decoded, and the position
+                // has been moved to the next packet.
+                context.skipped_handler_errors += 1;
+                #[cfg(feature = "log_handler")]
+                if let DecoderError::PacketHandler(handler_err) = &err {
+                    log::warn!(
+                        "Skip packet handler error at offset {packet_start:#x}: {handler_err}"
+                    );
+                }
+                continue;
+            }
+            // Make sure the position is at the start of the erroneous packet
+            context.pos = packet_start;
+            return Err(err);
+        }
+    }
+    if context.progress_interval.is_some() {
+        context.report_progress(buf.len(), on_progress);
     }
 
     Ok(())
@@ -1,31 +1,57 @@
-use core::{hint::unreachable_unchecked, num::NonZero};
+use core::{
+    fmt,
+    hint::unreachable_unchecked,
+    num::NonZero,
+    ops::{BitOr, BitOrAssign},
+};
 
 use derive_more::Display;
 
 use crate::{
     DecoderContext, HandlePacket, PacketBlockInformation, PacketBlockSize,
     error::{DecoderError, DecoderResult},
+    raw_packet_handler::leave_psb_if_missing_psbend,
 };
 
+/// Check that reserved bits of the packet are zero in strict mode, see
+/// [`DecodeOptions::strict`][crate::DecodeOptions::strict].
+///
+/// `byte` is the second header byte, and `has_reserved_bits` is whether any
+/// reserved bit is set.
+#[inline]
+fn check_reserved_bits<H: HandlePacket>(
+    context: &DecoderContext,
+    byte: u8,
+    has_reserved_bits: bool,
+) -> DecoderResult<(), H> {
+    if context.strict && has_reserved_bits {
+        return Err(DecoderError::InvalidPacket {
+            byte,
+            offset: context.pos,
+        });
+    }
+    Ok(())
+}
+
 #[inline]
 fn handle_cbr_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
     let packet_length = 4;
 
-    let Some(core_bus_ratio) = buf.get(context.pos + 2) else {
+    // Byte 3 is reserved
+    let Some([core_bus_ratio, reserved]) = buf.get((context.pos + 2)..(context.pos + 4)) else {
         return Err(DecoderError::UnexpectedEOF);
     };
-    packet_handler
-        .on_cbr_packet(context, *core_bus_ratio)
-        .map_err(DecoderError::PacketHandler)?;
+    check_reserved_bits(context, byte, *reserved != 0)?;
+    let result = packet_handler.on_cbr_packet(context, *core_bus_ratio);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -47,13 +73,11 @@ fn handle_pip_packet<H: HandlePacket>(
     let byte2 = *byte2 & 0b1111_1110; // Clear lowest bit
     let cr3 = u64::from_le_bytes([byte2, *byte3, *byte4, *byte5, *byte6, *byte7, 0, 0]) << 5;
 
-    packet_handler
-        .on_pip_packet(context, cr3, rsvd_nr)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_pip_packet(context, cr3, rsvd_nr);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[expect(clippy::unreadable_literal)]
@@ -62,7 +86,7 @@ const PSB: u128 = 0x82028202820282028202820282028202;
 #[inline]
 fn handle_psb_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
@@ -76,16 +100,22 @@ fn handle_psb_packet<H: HandlePacket>(
     };
     let psb = u128::from_le_bytes(*bytes);
     if psb != PSB {
-        return Err(DecoderError::InvalidPacket);
+        return Err(DecoderError::InvalidPacket {
+            byte,
+            offset: context.pos,
+        });
     }
+    let missing_psbend = leave_psb_if_missing_psbend(context, byte, packet_handler)?;
 
-    packet_handler
-        .on_psb_packet(context)
-        .map_err(DecoderError::PacketHandler)?;
+    // The PSB packet is counted before invoking the handler, so that the handler
+    // can get index of current PSB packet
+    context.psb_count += 1;
+    let result = missing_psbend.and_then(|()| packet_handler.on_psb_packet(context));
 
+    context.in_psb = true;
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -97,38 +127,51 @@ fn handle_psbend_packet<H: HandlePacket>(
 ) -> DecoderResult<(), H> {
     let packet_length = 2;
 
-    packet_handler
-        .on_psbend_packet(context)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_psbend_packet(context);
 
+    context.in_psb = false;
     context.pos += packet_length;
+    result.map_err(DecoderError::PacketHandler)?;
+
+    if let Some(new_mode) = packet_handler
+        .override_tracee_mode(context)
+        .map_err(DecoderError::PacketHandler)?
+        && new_mode != context.tracee_mode
+    {
+        let result = packet_handler.on_mode_exec_change(context, context.tracee_mode, new_mode);
+        context.tracee_mode = new_mode;
+        result.map_err(DecoderError::PacketHandler)?;
+    }
 
     Ok(())
 }
 
 #[inline]
 fn handle_trace_stop_packet<H: HandlePacket>(
-    _buf: &[u8],
+    buf: &[u8],
     _byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
     let packet_length = 2;
 
-    packet_handler
-        .on_trace_stop_packet(context)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_trace_stop_packet(context);
 
     context.pos += packet_length;
+    context.trace_stop_end = Some(context.pos);
+    if context.stop_at_trace_stop {
+        // Move to the end so that the decoding loop exits without
+        // extra checks for each packet
+        context.pos = buf.len();
+    }
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
-#[expect(clippy::int_plus_one)]
 fn handle_long_tnt_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
@@ -140,29 +183,28 @@ fn handle_long_tnt_packet<H: HandlePacket>(
     else {
         return Err(DecoderError::UnexpectedEOF);
     };
-    let packet = u64::from_le_bytes(*bytes);
-    let leading_zeros = packet.leading_zeros();
-    if leading_zeros == 64 - 16 {
-        // There is no trailing 1
-        return Err(DecoderError::InvalidPacket);
+    let packet_bytes = u64::from_le_bytes(*bytes) >> 16;
+    // The highest set bit of payload is the stop bit, and there should
+    // be at least one Taken/Not-taken bit below it
+    if packet_bytes <= 1 {
+        return Err(DecoderError::InvalidPacket {
+            byte,
+            offset: context.pos,
+        });
     }
-    let packet_bytes = packet >> 16;
-    // SAFETY: Trailing 1 guarantees the nonzero
+    let missing_psbend = leave_psb_if_missing_psbend(context, byte, packet_handler)?;
+    // SAFETY: Checked above
     let packet_bytes = unsafe { NonZero::new_unchecked(packet_bytes) };
+    // Payload is 48 bits and at least 2, so leading zeros is in 16..=62,
+    // and the highest bit is in 0..=46
+    let highest_bit = 62 - packet_bytes.leading_zeros();
 
-    // Leading zeros must <= 64-16. And we have checked it is not equal
-    // to 64-16, so it <= 64 - 16 -1
-    debug_assert!(leading_zeros <= 64 - 16 - 1, "Unexpected");
-    let highest_bit = 46u32.wrapping_sub(leading_zeros); // (63-index) - (trailing 1) - (16 length of header)
-    debug_assert!(highest_bit <= 46 || highest_bit == u32::MAX, "Unexpected");
-
-    packet_handler
-        .on_long_tnt_packet(context, packet_bytes, highest_bit)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = missing_psbend
+        .and_then(|()| packet_handler.on_long_tnt_packet(context, packet_bytes, highest_bit));
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -182,13 +224,11 @@ fn handle_vmcs_packet<H: HandlePacket>(
     };
     let vmcs_pointer = u64::from_le_bytes([*byte2, *byte3, *byte4, *byte5, *byte6, 0, 0, 0]) << 12;
 
-    packet_handler
-        .on_vmcs_packet(context, vmcs_pointer)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_vmcs_packet(context, vmcs_pointer);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -200,20 +240,18 @@ fn handle_ovf_packet<H: HandlePacket>(
 ) -> DecoderResult<(), H> {
     let packet_length = 2;
 
-    packet_handler
-        .on_ovf_packet(context)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_ovf_packet(context);
 
     context.packet_block = None;
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_mnt_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
@@ -237,47 +275,50 @@ fn handle_mnt_packet<H: HandlePacket>(
     else {
         return Err(DecoderError::UnexpectedEOF);
     };
+    // The third header byte is checked after the whole packet is available, so that
+    // a truncated packet is always reported as EOF
     if *byte2 != 0b1000_1000 {
-        return Err(DecoderError::UnexpectedEOF);
+        return Err(DecoderError::InvalidPacket {
+            byte,
+            offset: context.pos,
+        });
     }
     let payload = u64::from_le_bytes([
         *byte3, *byte4, *byte5, *byte6, *byte7, *byte8, *byte9, *byte10,
     ]);
 
-    packet_handler
-        .on_mnt_packet(context, payload)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_mnt_packet(context, payload);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_tma_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
     let packet_length = 7;
 
-    let Some([byte2, byte3, _byte4, byte5, byte6]) = buf.get((context.pos + 2)..(context.pos + 7))
+    let Some([byte2, byte3, byte4, byte5, byte6]) = buf.get((context.pos + 2)..(context.pos + 7))
     else {
         return Err(DecoderError::UnexpectedEOF);
     };
+    // Byte 4 and bits 7:1 of byte 6 are reserved
+    check_reserved_bits(context, byte, *byte4 != 0 || *byte6 & 0b1111_1110 != 0)?;
 
     let ctc = u16::from_le_bytes([*byte2, *byte3]);
     let fast_counter = *byte5;
     let fc8 = *byte6 % 2 != 0;
 
-    packet_handler
-        .on_tma_packet(context, ctc, fast_counter, fc8)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_tma_packet(context, ctc, fast_counter, fc8);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 /// Payload for PTW packet
@@ -291,6 +332,177 @@ pub enum PtwPayload {
     EightBytes(u64),
 }
 
+/// Processor C-state in MWAIT, PWRE and PWRX packets
+///
+/// The C-states are encoded in the same way as the target C-state of MWAIT
+/// hints (`EAX[7:4]`), i.e., `0x0` is C1, `0x1` is C2, ..., and `0xF` is C0.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[expect(missing_docs)]
+pub enum CState {
+    C0,
+    C1,
+    C2,
+    C3,
+    C4,
+    C5,
+    C6,
+    C7,
+    C8,
+    C9,
+    C10,
+    C11,
+    C12,
+    C13,
+    C14,
+    C15,
+}
+
+impl CState {
+    /// All C-states, indexed by the number of C-state
+    const ALL: [CState; 16] = [
+        Self::C0,
+        Self::C1,
+        Self::C2,
+        Self::C3,
+        Self::C4,
+        Self::C5,
+        Self::C6,
+        Self::C7,
+        Self::C8,
+        Self::C9,
+        Self::C10,
+        Self::C11,
+        Self::C12,
+        Self::C13,
+        Self::C14,
+        Self::C15,
+    ];
+
+    /// Decode the 4-bit C-state encoding. Upper 4 bits of `encoding` are ignored.
+    #[must_use]
+    pub fn from_encoding(encoding: u8) -> Self {
+        Self::ALL[usize::from(encoding.wrapping_add(1) & 0b1111)]
+    }
+
+    /// Get the number of C-state, e.g., 6 for C6
+    #[must_use]
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Wake reason bitmask in PWRX packet
+///
+/// More than one bit may be set if several wake conditions happened at the
+/// same time, so individual reasons should be tested with
+/// [`contains`][WakeReason::contains].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct WakeReason(u8);
+
+impl WakeReason {
+    /// Wake due to external interrupt received
+    pub const INTERRUPT: Self = Self(0b0001);
+    /// Wake due to timer expiration, such as UMWAIT/TPAUSE TSC-quanta
+    pub const TIMER_DEADLINE: Self = Self(0b0010);
+    /// Wake due to store to the address armed by MONITOR
+    pub const STORE_TO_MONITORED_ADDRESS: Self = Self(0b0100);
+    /// Wake due to hardware autonomous condition, such as HDC
+    pub const HARDWARE_WAKE: Self = Self(0b1000);
+
+    /// Names of all known bits, used for formatting
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::INTERRUPT, "Interrupt"),
+        (Self::TIMER_DEADLINE, "TimerDeadline"),
+        (Self::STORE_TO_MONITORED_ADDRESS, "StoreToMonitoredAddress"),
+        (Self::HARDWARE_WAKE, "HardwareWake"),
+    ];
+
+    /// Decode the 4-bit wake reason. Upper 4 bits of `encoding` are ignored.
+    #[must_use]
+    pub const fn from_encoding(encoding: u8) -> Self {
+        Self(encoding & 0b1111)
+    }
+
+    /// Get the raw 4-bit encoding
+    #[must_use]
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether no wake reason is reported
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all bits of `other` are set
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for WakeReason {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for WakeReason {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Display for WakeReason {
+    /// Format as names of set bits joined by `|`, e.g., `Interrupt | TimerDeadline`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("None");
+        }
+        let mut first = true;
+        for (reason, name) in Self::NAMES {
+            if self.contains(reason) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Type of event data in EVD packet
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EvdType {
+    /// Linear address of page fault (CR2), following a CFE packet of page fault
+    PageFaultAddress,
+    /// VM exit qualification, following a CFE packet of VM exit
+    VmExitQualification,
+    /// VM exit reason, following a CFE packet of VM exit
+    VmExitReason,
+    /// Unknown or reserved encoding
+    #[display("Unknown({_0:#x})")]
+    Unknown(u8),
+}
+
+impl EvdType {
+    /// Decode the 6-bit EVD type. Upper 2 bits of `encoding` are ignored.
+    #[must_use]
+    pub fn from_encoding(encoding: u8) -> Self {
+        match encoding & 0b0011_1111 {
+            0b00_0000 => Self::PageFaultAddress,
+            0b00_0001 => Self::VmExitQualification,
+            0b00_0010 => Self::VmExitReason,
+            encoding => Self::Unknown(encoding),
+        }
+    }
+}
+
 #[inline]
 fn handle_ptw_packet<H: HandlePacket>(
     buf: &[u8],
@@ -329,7 +541,10 @@ fn handle_ptw_packet<H: HandlePacket>(
             PtwPayload::EightBytes(payload)
         }
         0b10 | 0b11 => {
-            return Err(DecoderError::InvalidPacket);
+            return Err(DecoderError::InvalidPacket {
+                byte,
+                offset: context.pos,
+            });
         }
         _ => {
             // SAFETY: payload_bytes <= 0b11
@@ -339,13 +554,11 @@ fn handle_ptw_packet<H: HandlePacket>(
         }
     };
 
-    packet_handler
-        .on_ptw_packet(context, ip_bit, payload)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_ptw_packet(context, ip_bit, payload);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -359,45 +572,54 @@ fn handle_exstop_packet<H: HandlePacket>(
 
     let ip_bit = (byte & 0b1000_0000) != 0;
 
-    packet_handler
-        .on_exstop_packet(context, ip_bit)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_exstop_packet(context, ip_bit);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_mwait_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
     let packet_length = 10;
 
-    let Some(mwait_hints) = buf.get(context.pos + 2) else {
-        return Err(DecoderError::UnexpectedEOF);
-    };
-    let Some(ext) = buf.get(context.pos + 2) else {
+    let Some([mwait_hints, byte3, byte4, byte5, ext, byte7, byte8, byte9]) =
+        buf.get((context.pos + 2)..(context.pos + packet_length))
+    else {
         return Err(DecoderError::UnexpectedEOF);
     };
+    // Only MWAIT hints in byte 2 and EXT in bits 1:0 of byte 6 are defined
+    check_reserved_bits(
+        context,
+        byte,
+        [
+            *byte3,
+            *byte4,
+            *byte5,
+            *ext & 0b1111_1100,
+            *byte7,
+            *byte8,
+            *byte9,
+        ] != [0; 7],
+    )?;
     let ext = *ext & 0b0000_0011;
 
-    packet_handler
-        .on_mwait_packet(context, *mwait_hints, ext)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_mwait_packet(context, *mwait_hints, ext);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_pwre_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
@@ -406,52 +628,59 @@ fn handle_pwre_packet<H: HandlePacket>(
     let Some([byte2, byte3]) = buf.get((context.pos + 2)..(context.pos + 4)) else {
         return Err(DecoderError::UnexpectedEOF);
     };
+    // Bits 6:0 of byte 2 are reserved
+    check_reserved_bits(context, byte, *byte2 & 0b0111_1111 != 0)?;
     let hw = (*byte2 & 0b1000_0000) != 0;
     let resolved_thread_c_state = (*byte3 & 0b1111_0000) >> 4;
     let resolved_thread_sub_c_state = *byte3 & 0b0000_1111;
 
-    packet_handler
-        .on_pwre_packet(
-            context,
-            hw,
-            resolved_thread_c_state,
-            resolved_thread_sub_c_state,
-        )
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_pwre_packet(
+        context,
+        hw,
+        resolved_thread_c_state,
+        resolved_thread_sub_c_state,
+    );
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_pwrx_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
     let packet_length = 7;
 
-    let Some([byte2, byte3]) = buf.get((context.pos + 2)..(context.pos + 4)) else {
+    // Byte 2 contains Last Core C-State and Deepest Core C-State, byte 3 contains
+    // Wake Reason, and bytes 4 to 6 are reserved
+    let Some([byte2, byte3, byte4, byte5, byte6]) =
+        buf.get((context.pos + 2)..(context.pos + packet_length))
+    else {
         return Err(DecoderError::UnexpectedEOF);
     };
+    check_reserved_bits(
+        context,
+        byte,
+        *byte3 & 0b1111_0000 != 0 || [*byte4, *byte5, *byte6] != [0; 3],
+    )?;
     let last_core_c_state = (*byte2 & 0b1111_0000) >> 4;
     let deepest_core_c_state = *byte2 & 0b0000_1111;
     let wake_reason = *byte3 & 0b0000_1111;
 
-    packet_handler
-        .on_pwrx_packet(
-            context,
-            last_core_c_state,
-            deepest_core_c_state,
-            wake_reason,
-        )
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_pwrx_packet(
+        context,
+        last_core_c_state,
+        deepest_core_c_state,
+        wake_reason,
+    );
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -461,27 +690,27 @@ fn handle_cfe_packet<H: HandlePacket>(
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
-    let packet_length = 2;
+    let packet_length = 4;
 
-    let ip_bit = (byte & 0b1000_0000) != 0;
-    let r#type = byte & 0b0001_1111;
-    let Some(vector) = buf.get(context.pos + 3) else {
+    let Some([byte2, vector]) = buf.get((context.pos + 2)..(context.pos + 4)) else {
         return Err(DecoderError::UnexpectedEOF);
     };
+    // Bits 6:5 of byte 2 are reserved
+    check_reserved_bits(context, byte, *byte2 & 0b0110_0000 != 0)?;
+    let ip_bit = (*byte2 & 0b1000_0000) != 0;
+    let r#type = *byte2 & 0b0001_1111;
 
-    packet_handler
-        .on_cfe_packet(context, ip_bit, r#type, *vector)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_cfe_packet(context, ip_bit, r#type, *vector);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_evd_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
@@ -503,43 +732,43 @@ fn handle_evd_packet<H: HandlePacket>(
     else {
         return Err(DecoderError::UnexpectedEOF);
     };
-    let r#type = byte2 & 0b001_1111;
+    // Bits 7:6 of byte 2 are reserved
+    check_reserved_bits(context, byte, *byte2 & 0b1100_0000 != 0)?;
+    let r#type = byte2 & 0b0011_1111;
     let payload = u64::from_le_bytes([
         *byte3, *byte4, *byte5, *byte6, *byte7, *byte8, *byte9, *byte10,
     ]);
 
-    packet_handler
-        .on_evd_packet(context, r#type, payload)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_evd_packet(context, r#type, payload);
 
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
 fn handle_bbp_packet<H: HandlePacket>(
     buf: &[u8],
-    _byte: u8,
+    byte: u8,
     context: &mut DecoderContext,
     packet_handler: &mut H,
 ) -> DecoderResult<(), H> {
     let packet_length = 3;
 
-    let Some(byte) = buf.get(context.pos + 2) else {
+    let Some(byte2) = buf.get(context.pos + 2) else {
         return Err(DecoderError::UnexpectedEOF);
     };
-    let sz_bit = (*byte & 0b1000_0000) != 0;
+    // Bits 6:5 of byte 2 are reserved
+    check_reserved_bits(context, byte, *byte2 & 0b0110_0000 != 0)?;
+    let sz_bit = (*byte2 & 0b1000_0000) != 0;
     let size = PacketBlockSize::from_sz_bit(sz_bit);
-    let r#type = *byte & 0b0001_1111;
-    packet_handler
-        .on_bbp_packet(context, sz_bit, r#type)
-        .map_err(DecoderError::PacketHandler)?;
+    let r#type = *byte2 & 0b0001_1111;
+    let result = packet_handler.on_bbp_packet(context, sz_bit, r#type);
 
     context.packet_block = Some(PacketBlockInformation { size, r#type });
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -552,14 +781,12 @@ fn handle_bep_packet<H: HandlePacket>(
     let packet_length = 2;
 
     let ip_bit = (byte & 0b1000_0000) != 0;
-    packet_handler
-        .on_bep_packet(context, ip_bit)
-        .map_err(DecoderError::PacketHandler)?;
+    let result = packet_handler.on_bep_packet(context, ip_bit);
 
     context.packet_block = None;
     context.pos += packet_length;
 
-    Ok(())
+    result.map_err(DecoderError::PacketHandler)
 }
 
 #[inline]
@@ -637,7 +864,10 @@ pub fn decode<H: HandlePacket>(
             handle_mnt_packet(buf, byte, context, packet_handler)?;
         }
         _ => {
-            return Err(DecoderError::InvalidPacket);
+            return Err(DecoderError::InvalidPacket {
+                byte,
+                offset: context.pos,
+            });
         }
     }
 
@@ -1,6 +1,9 @@
 use core::marker::PhantomData;
 
-use crate::{DecoderContext, HandlePacket, error::DecoderResult};
+use crate::{
+    DecoderContext, HandlePacket,
+    error::{DecoderError, DecoderResult},
+};
 
 pub mod level1;
 pub mod level2;
@@ -12,6 +15,39 @@ type RawPacketHandler<H> = fn(
     packet_handler: &mut H,
 ) -> DecoderResult<(), H>;
 
+/// Leave PSB+ if the PSBEND packet is missing.
+///
+/// PSB+ only contains status packets, so packets like TNT, TIP and PSB mean the
+/// PSBEND packet is missing. `byte` is the header byte (the second header byte for
+/// packets starting with `0x02`).
+///
+/// This is an error in strict mode. Otherwise, the packet handler is notified and
+/// the decoder leaves PSB+. This should be invoked before any state change made
+/// by the packet.
+///
+/// The result of the packet handler is returned as the inner result, so that the
+/// caller can finish decoding the packet before reporting it. The packet handler
+/// should not be invoked for the packet if the inner result is an error.
+#[inline]
+fn leave_psb_if_missing_psbend<H: HandlePacket>(
+    context: &mut DecoderContext,
+    byte: u8,
+    packet_handler: &mut H,
+) -> DecoderResult<Result<(), H::Error>, H> {
+    if !context.in_psb {
+        return Ok(Ok(()));
+    }
+    if context.strict {
+        return Err(DecoderError::MissingPsbend {
+            byte,
+            offset: context.packet_start,
+        });
+    }
+    let result = packet_handler.on_missing_psbend(context, byte);
+    context.in_psb = false;
+    Ok(result)
+}
+
 pub struct RawPacketHandlers<H: HandlePacket> {
     phantom: PhantomData<H>,
 }
@@ -0,0 +1,633 @@
+//! This module contains the streaming decoder for incremental buffer chunks
+
+use crate::{
+    DecodeOptions, DecoderContext, HandlePacket, PSB_BYTES,
+    error::{DecoderError, DecoderResult},
+    raw_packet_handler,
+};
+
+/// Maximum length of a packet that can be split across chunks.
+///
+/// All Intel PT packets are no longer than 16 bytes (PSB), except CYC packets
+/// whose length is not bounded by the specification, but is far below this in
+/// practice.
+const MAX_CARRY_LEN: usize = 32;
+
+/// Status of [`StreamDecoder`]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StreamStatus {
+    /// [`HandlePacket::at_decode_begin`] is not invoked yet
+    NotStarted,
+    /// Syncing forward for the first PSB packet
+    Syncing,
+    /// Decoding packets
+    Decoding,
+    /// Decoding stopped at a TraceStop packet
+    Stopped,
+}
+
+/// Outcome of decoding a single buffer
+enum BufferOutcome {
+    /// All packets are decoded, and the next packet starts at the given
+    /// position, which may exceed the buffer length
+    Decoded(usize),
+    /// The packet starting at the given position is incomplete
+    Incomplete(usize),
+    /// Decoding stopped at a TraceStop packet
+    Stopped,
+}
+
+/// Stateful decoder for Intel PT data that arrives in chunks, e.g., when
+/// tracing live.
+///
+/// Feed the chunks in order by [`feed`][Self::feed]. Packets split across chunk
+/// boundaries are buffered and decoded when the rest arrives, so the packet
+/// handler observes the same packets as decoding the concatenated buffer with
+/// [`decode`][crate::decode], and [`DecoderContext::packet_offset`] is the offset
+/// in the whole stream. The only exception is that if
+/// [`DecodeOptions::coalesce_pad`] is set, a run of PAD packets split across
+/// chunks is reported by multiple [`HandlePacket::on_pad_run`] invocations.
+/// Call [`finish`][Self::finish] after the last chunk to check that no
+/// incomplete packet is left.
+pub struct StreamDecoder {
+    /// Decoder context across chunks. Its position is relative to the
+    /// buffer being decoded.
+    context: DecoderContext,
+    /// Current status
+    status: StreamStatus,
+    /// Whether to sync forward for the first PSB packet
+    sync: bool,
+    /// Trailing bytes of previous chunks, which is either an incomplete packet,
+    /// or a possibly partial PSB packet when syncing
+    carry: [u8; MAX_CARRY_LEN],
+    /// Length of valid bytes in [`carry`][Self::carry]
+    carry_len: usize,
+    /// Number of bytes at the beginning of next chunk that belong to the
+    /// last decoded packet
+    skip_len: usize,
+    /// Total length of chunks fed before
+    fed_len: usize,
+    /// Offset in the stream to start decoding at, see
+    /// [`DecodeOptions::start_offset`]
+    start_offset: Option<usize>,
+}
+
+impl StreamDecoder {
+    /// Create a new streaming decoder.
+    ///
+    /// [`HandlePacket::at_decode_begin`] is invoked when feeding the first chunk.
+    #[must_use]
+    pub fn new(options: DecodeOptions) -> Self {
+        let DecodeOptions {
+            tracee_mode,
+            no_sync,
+            stop_at_trace_stop,
+            coalesce_pad,
+            handler_error_budget,
+            strict,
+            start_offset,
+        } = options;
+        Self {
+            context: DecoderContext {
+                pos: 0,
+                packet_start: 0,
+                base_offset: 0,
+                tracee_mode,
+                packet_block: None,
+                in_psb: false,
+                psb_count: 0,
+                trace_stop_end: None,
+                stop_at_trace_stop,
+                coalesce_pad,
+                progress_interval: None,
+                next_progress_pos: usize::MAX,
+                handler_error_budget,
+                skipped_handler_errors: 0,
+                strict,
+            },
+            status: StreamStatus::NotStarted,
+            sync: !no_sync && start_offset.is_none(),
+            carry: [0; MAX_CARRY_LEN],
+            carry_len: 0,
+            // Bytes before the start offset are skipped as if they belong to a packet
+            skip_len: start_offset.unwrap_or(0),
+            fed_len: 0,
+            start_offset,
+        }
+    }
+
+    /// Feed the next chunk of Intel PT data.
+    ///
+    /// If the decoding has stopped at a TraceStop packet (see
+    /// [`DecodeOptions::stop_at_trace_stop`]), the chunk is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as [`decode`][crate::decode], except that incomplete
+    /// packets at the end of chunk are not treated as [`DecoderError::UnexpectedEOF`].
+    /// [`DecoderError::InvalidPacket`] is returned if an incomplete packet is longer
+    /// than 32 bytes. The decoder should not be fed anymore after errors.
+    pub fn feed<H: HandlePacket>(
+        &mut self,
+        chunk: &[u8],
+        packet_handler: &mut H,
+    ) -> DecoderResult<(), H> {
+        if self.status == StreamStatus::NotStarted {
+            packet_handler
+                .at_decode_begin()
+                .map_err(DecoderError::PacketHandler)?;
+            self.status = if self.sync {
+                StreamStatus::Syncing
+            } else {
+                StreamStatus::Decoding
+            };
+        }
+        let fed_len = self.fed_len;
+        self.fed_len += chunk.len();
+
+        let skip_len = self.skip_len.min(chunk.len());
+        self.skip_len -= skip_len;
+        let chunk = &chunk[skip_len..];
+        let chunk_offset = fed_len + skip_len;
+
+        let mut start_pos = 0;
+        if self.status == StreamStatus::Syncing {
+            let Some(pos) = self.sync(chunk, fed_len, packet_handler)? else {
+                return Ok(());
+            };
+            start_pos = pos;
+        }
+        if self.status == StreamStatus::Stopped {
+            return Ok(());
+        }
+
+        if self.carry_len > 0 {
+            // Complete the carried packet with the leading bytes of this chunk
+            let carry_len = self.carry_len;
+            let extra_len = chunk.len().min(MAX_CARRY_LEN);
+            let mut buf = [0u8; MAX_CARRY_LEN * 2];
+            buf[..carry_len].copy_from_slice(&self.carry[..carry_len]);
+            buf[carry_len..(carry_len + extra_len)].copy_from_slice(&chunk[..extra_len]);
+            let buf = &buf[..(carry_len + extra_len)];
+            let buf_offset = chunk_offset - carry_len;
+            self.carry_len = 0;
+            match self.decode_buffer(buf, buf_offset, 0, packet_handler)? {
+                BufferOutcome::Decoded(pos) => start_pos = pos - carry_len,
+                BufferOutcome::Incomplete(pos) if pos >= carry_len => {
+                    start_pos = pos - carry_len;
+                }
+                BufferOutcome::Incomplete(pos) => {
+                    if extra_len < chunk.len() {
+                        return Err(DecoderError::InvalidPacket {
+                            byte: buf[pos],
+                            offset: buf_offset + pos,
+                        });
+                    }
+                    // The whole chunk is still not enough
+                    return self.save_carry(&buf[pos..], buf_offset + pos);
+                }
+                BufferOutcome::Stopped => return Ok(()),
+            }
+        }
+
+        if start_pos >= chunk.len() {
+            // Bytes to skip may be left if the whole chunk is skipped
+            self.skip_len += start_pos - chunk.len();
+            return Ok(());
+        }
+        match self.decode_buffer(chunk, chunk_offset, start_pos, packet_handler)? {
+            BufferOutcome::Decoded(pos) => {
+                self.skip_len = pos - chunk.len();
+                Ok(())
+            }
+            BufferOutcome::Incomplete(pos) => self.save_carry(&chunk[pos..], chunk_offset + pos),
+            BufferOutcome::Stopped => Ok(()),
+        }
+    }
+
+    /// Get the number of packet handler errors skipped so far, see
+    /// [`DecodeOptions::handler_error_budget`].
+    #[must_use]
+    pub fn skipped_handler_errors(&self) -> usize {
+        self.context.skipped_handler_errors
+    }
+
+    /// Finish decoding after all chunks are fed.
+    ///
+    /// # Errors
+    ///
+    /// Return [`DecoderError::NoPsb`] if no PSB packet is found when syncing,
+    /// [`DecoderError::StartOffsetOutOfBounds`] if the stream is shorter than the
+    /// start offset, and [`DecoderError::UnexpectedEOF`] if the last packet is
+    /// incomplete.
+    pub fn finish<H: HandlePacket>(self) -> DecoderResult<(), H> {
+        if let Some(start_offset) = self.start_offset
+            && start_offset > self.fed_len
+        {
+            return Err(DecoderError::StartOffsetOutOfBounds {
+                offset: start_offset,
+                len: self.fed_len,
+            });
+        }
+        match self.status {
+            StreamStatus::Syncing => Err(DecoderError::NoPsb),
+            StreamStatus::NotStarted if self.sync => Err(DecoderError::NoPsb),
+            StreamStatus::Decoding if self.carry_len > 0 => Err(DecoderError::UnexpectedEOF),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sync forward for the first PSB packet in carried bytes and `chunk`.
+    ///
+    /// Return the position in `chunk` to start decoding, or [`None`] if
+    /// the PSB packet is not found yet.
+    fn sync<H: HandlePacket>(
+        &mut self,
+        chunk: &[u8],
+        fed_len: usize,
+        packet_handler: &mut H,
+    ) -> DecoderResult<Option<usize>, H> {
+        let carry_len = self.carry_len;
+        // Find PSB packet crossing the boundary of carried bytes and chunk
+        let mut buf = [0u8; MAX_CARRY_LEN * 2];
+        let extra_len = chunk.len().min(PSB_BYTES.len());
+        buf[..carry_len].copy_from_slice(&self.carry[..carry_len]);
+        buf[carry_len..(carry_len + extra_len)].copy_from_slice(&chunk[..extra_len]);
+        let psb_pos = match memchr::memmem::find(&buf[..(carry_len + extra_len)], &PSB_BYTES) {
+            Some(pos) if pos < carry_len => {
+                // Keep the partial PSB packet as carried bytes
+                self.carry.copy_within(pos..carry_len, 0);
+                self.carry_len = carry_len - pos;
+                Some((fed_len - carry_len + pos, 0))
+            }
+            _ => memchr::memmem::find(chunk, &PSB_BYTES).map(|pos| {
+                self.carry_len = 0;
+                (fed_len + pos, pos)
+            }),
+        };
+        let Some((psb_offset, start_pos)) = psb_pos else {
+            // Keep the trailing bytes which may be the start of PSB packet
+            let keep_len = (PSB_BYTES.len() - 1).min(carry_len + chunk.len());
+            let mut buf = [0u8; MAX_CARRY_LEN * 2];
+            buf[..carry_len].copy_from_slice(&self.carry[..carry_len]);
+            let chunk_keep_len = chunk.len().min(keep_len);
+            buf[carry_len..(carry_len + chunk_keep_len)]
+                .copy_from_slice(&chunk[(chunk.len() - chunk_keep_len)..]);
+            let buf = &buf[..(carry_len + chunk_keep_len)];
+            self.carry[..keep_len].copy_from_slice(&buf[(buf.len() - keep_len)..]);
+            self.carry_len = keep_len;
+            return Ok(None);
+        };
+        self.status = StreamStatus::Decoding;
+        packet_handler
+            .on_sync(&self.context, psb_offset)
+            .map_err(DecoderError::PacketHandler)?;
+
+        Ok(Some(start_pos))
+    }
+
+    /// Decode packets in `buf` from `start_pos`, where `buf_offset` is the offset
+    /// of `buf` in the whole stream
+    fn decode_buffer<H: HandlePacket>(
+        &mut self,
+        buf: &[u8],
+        buf_offset: usize,
+        start_pos: usize,
+        packet_handler: &mut H,
+    ) -> DecoderResult<BufferOutcome, H> {
+        self.context.pos = start_pos;
+        self.context.base_offset = buf_offset;
+        self.context.trace_stop_end = None;
+        match raw_packet_handler::level1::decode(
+            buf,
+            &mut self.context,
+            packet_handler,
+            &mut |_, _| {},
+        ) {
+            Ok(()) if self.context.stop_at_trace_stop && self.context.trace_stop_end.is_some() => {
+                self.status = StreamStatus::Stopped;
+                Ok(BufferOutcome::Stopped)
+            }
+            Ok(()) => Ok(BufferOutcome::Decoded(self.context.pos)),
+            Err(DecoderError::UnexpectedEOF) => Ok(BufferOutcome::Incomplete(self.context.pos)),
+            Err(DecoderError::InvalidPacket { byte, offset }) => Err(DecoderError::InvalidPacket {
+                byte,
+                offset: buf_offset + offset,
+            }),
+            Err(DecoderError::MissingPsbend { byte, offset }) => Err(DecoderError::MissingPsbend {
+                byte,
+                offset: buf_offset + offset,
+            }),
+            Err(DecoderError::ReservedIpBytes {
+                byte,
+                ip_bytes,
+                offset,
+            }) => Err(DecoderError::ReservedIpBytes {
+                byte,
+                ip_bytes,
+                offset: buf_offset + offset,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Save the incomplete packet `bytes` at `offset` of the whole stream as carried bytes
+    fn save_carry<H: HandlePacket>(&mut self, bytes: &[u8], offset: usize) -> DecoderResult<(), H> {
+        if bytes.len() >= MAX_CARRY_LEN {
+            return Err(DecoderError::InvalidPacket {
+                byte: bytes[0],
+                offset,
+            });
+        }
+        self.carry[..bytes.len()].copy_from_slice(bytes);
+        self.carry_len = bytes.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::num::NonZero;
+    use std::{format, string::String, vec::Vec};
+
+    use super::*;
+    use crate::{IpReconstructionPattern, decode};
+
+    /// Record all handled packets in text
+    #[derive(Default)]
+    struct PacketRecorder {
+        packets: Vec<String>,
+        /// All `(offset, count)` of PAD runs
+        pad_runs: Vec<(usize, usize)>,
+    }
+
+    impl HandlePacket for PacketRecorder {
+        type Error = core::convert::Infallible;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            self.packets.clear();
+            self.pad_runs.clear();
+            Ok(())
+        }
+
+        fn on_pad_run(
+            &mut self,
+            context: &DecoderContext,
+            count: usize,
+        ) -> Result<(), Self::Error> {
+            self.pad_runs.push((context.packet_offset(), count));
+            Ok(())
+        }
+
+        fn on_sync(
+            &mut self,
+            _context: &DecoderContext,
+            psb_offset: usize,
+        ) -> Result<(), Self::Error> {
+            self.packets.push(format!("SYNC {psb_offset}"));
+            Ok(())
+        }
+
+        fn on_psb_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+            self.packets.push(String::from("PSB"));
+            Ok(())
+        }
+
+        fn on_psbend_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+            self.packets.push(String::from("PSBEND"));
+            Ok(())
+        }
+
+        fn on_short_tnt_packet(
+            &mut self,
+            _context: &DecoderContext,
+            packet_byte: NonZero<u8>,
+            highest_bit: u32,
+        ) -> Result<(), Self::Error> {
+            self.packets
+                .push(format!("TNT {packet_byte:#x} {highest_bit}"));
+            Ok(())
+        }
+
+        fn on_tip_packet(
+            &mut self,
+            _context: &DecoderContext,
+            ip_reconstruction_pattern: IpReconstructionPattern,
+        ) -> Result<(), Self::Error> {
+            self.packets
+                .push(format!("TIP {ip_reconstruction_pattern}"));
+            Ok(())
+        }
+
+        fn on_cyc_packet(
+            &mut self,
+            _context: &DecoderContext,
+            cyc_packet: &[u8],
+        ) -> Result<(), Self::Error> {
+            self.packets.push(format!("CYC {cyc_packet:?}"));
+            Ok(())
+        }
+
+        fn on_pip_packet(
+            &mut self,
+            _context: &DecoderContext,
+            cr3: u64,
+            rsvd_nr: bool,
+        ) -> Result<(), Self::Error> {
+            self.packets.push(format!("PIP {cr3:#x} {rsvd_nr}"));
+            Ok(())
+        }
+    }
+
+    fn trace() -> Vec<u8> {
+        let mut trace = Vec::new();
+        // Leading garbage, containing a partial PSB packet
+        trace.extend_from_slice(&[0xFF, 0x02, 0x82, 0x02, 0x82, 0x00]);
+        trace.extend_from_slice(&PSB_BYTES);
+        // PIP
+        trace.extend_from_slice(&[0x02, 0x43, 0x20, 0x30, 0x40, 0x50, 0x60, 0x00]);
+        // PSBEND
+        trace.extend_from_slice(&[0x02, 0x23]);
+        // TIP with 8-byte IP
+        trace.extend_from_slice(&[0xCD, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        // Short TNT
+        trace.extend_from_slice(&[0b0000_1010, 0b0101_0110]);
+        // CYC with 2 extension bytes
+        trace.extend_from_slice(&[0b1010_0111, 0b0000_0011, 0b0000_0010]);
+        // PAD
+        trace.extend_from_slice(&[0x00, 0x00]);
+        // TIP with 2-byte IP
+        trace.extend_from_slice(&[0x2D, 0x34, 0x12]);
+        trace
+    }
+
+    fn decode_stream<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Vec<String> {
+        let mut handler = PacketRecorder::default();
+        let mut decoder = StreamDecoder::new(DecodeOptions::default());
+        for chunk in chunks {
+            decoder.feed(chunk, &mut handler).unwrap();
+        }
+        decoder.finish::<PacketRecorder>().unwrap();
+        handler.packets
+    }
+
+    #[test]
+    fn test_stream_decode() {
+        let trace = trace();
+        let mut handler = PacketRecorder::default();
+        decode(&trace, DecodeOptions::default(), &mut handler).unwrap();
+        let expected = handler.packets;
+        assert_eq!(expected.first().map(String::as_str), Some("SYNC 6"));
+        assert_eq!(expected.len(), 9);
+
+        for split_pos in 0..=trace.len() {
+            let (left, right) = trace.split_at(split_pos);
+            assert_eq!(
+                decode_stream([left, right]),
+                expected,
+                "Split at {split_pos}"
+            );
+        }
+        for chunk_size in 1..=17 {
+            assert_eq!(
+                decode_stream(trace.chunks(chunk_size)),
+                expected,
+                "Chunk size {chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_stream_coalesce_pad() {
+        let trace = trace();
+        let mut options = DecodeOptions::default();
+        options.coalesce_pad(true);
+        let mut handler = PacketRecorder::default();
+        decode(&trace, options, &mut handler).unwrap();
+        let expected = handler.packets;
+        let pad_offset = 6 + 16 + 8 + 2 + 9 + 2 + 3;
+        assert_eq!(handler.pad_runs, [(pad_offset, 2)]);
+
+        for chunk_size in 1..=17 {
+            let mut handler = PacketRecorder::default();
+            let mut decoder = StreamDecoder::new(options);
+            for chunk in trace.chunks(chunk_size) {
+                decoder.feed(chunk, &mut handler).unwrap();
+            }
+            decoder.finish::<PacketRecorder>().unwrap();
+            assert_eq!(handler.packets, expected, "Chunk size {chunk_size}");
+            // The PAD run may be split across chunks, with offsets in the stream
+            let mut next_offset = pad_offset;
+            for &(offset, count) in &handler.pad_runs {
+                assert_eq!(offset, next_offset, "Chunk size {chunk_size}");
+                next_offset += count;
+            }
+            assert_eq!(next_offset, pad_offset + 2, "Chunk size {chunk_size}");
+        }
+        let mut handler = PacketRecorder::default();
+        let mut decoder = StreamDecoder::new(options);
+        let (left, right) = trace.split_at(pad_offset + 1);
+        decoder.feed(left, &mut handler).unwrap();
+        decoder.feed(right, &mut handler).unwrap();
+        assert_eq!(handler.pad_runs, [(pad_offset, 1), (pad_offset + 1, 1)]);
+    }
+
+    #[test]
+    fn test_stream_decode_incomplete() {
+        let trace = trace();
+        let mut handler = PacketRecorder::default();
+        let mut decoder = StreamDecoder::new(DecodeOptions::default());
+        decoder
+            .feed(&trace[..(trace.len() - 1)], &mut handler)
+            .unwrap();
+        assert!(matches!(
+            decoder.finish::<PacketRecorder>(),
+            Err(DecoderError::UnexpectedEOF)
+        ));
+
+        let decoder = StreamDecoder::new(DecodeOptions::default());
+        assert!(matches!(
+            decoder.finish::<PacketRecorder>(),
+            Err(DecoderError::NoPsb)
+        ));
+    }
+
+    #[test]
+    fn test_stream_invalid_packet_offset() {
+        let mut trace = trace();
+        // PAD and unknown byte
+        trace.extend([0x00, 0x05]);
+        for chunk_size in 1..=17 {
+            let mut handler = PacketRecorder::default();
+            let mut decoder = StreamDecoder::new(DecodeOptions::default());
+            let err = trace
+                .chunks(chunk_size)
+                .find_map(|chunk| decoder.feed(chunk, &mut handler).err())
+                .unwrap();
+            let DecoderError::InvalidPacket { byte, offset } = err else {
+                panic!("Unexpected error {err}");
+            };
+            assert_eq!(byte, 0x05, "Chunk size {chunk_size}");
+            assert_eq!(offset, trace.len() - 1, "Chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_start_offset() {
+        let mut trace = trace();
+        let mid = trace.len();
+        // Another PSB+ followed by short TNT
+        trace.extend_from_slice(&PSB_BYTES);
+        trace.extend_from_slice(&[0x02, 0x23, 0b0000_0110]);
+
+        let decode_from = |options: DecodeOptions, buf: &[u8]| {
+            let mut handler = PacketRecorder::default();
+            decode(buf, options, &mut handler).unwrap();
+            handler.packets
+        };
+        let mut no_sync = DecodeOptions::default();
+        no_sync.sync(false);
+
+        // Start at the PSB in the middle, without syncing
+        let mut options = DecodeOptions::default();
+        options.start_offset(mid);
+        let expected = decode_from(no_sync, &trace[mid..]);
+        assert_eq!(expected, ["PSB", "PSBEND", "TNT 0x6 1"]);
+        assert_eq!(decode_from(options, &trace), expected);
+
+        // Start at the TIP after the first PSB+, which syncing would skip
+        let tip_offset = 6 + 16 + 8 + 2;
+        options.start_offset(tip_offset);
+        let expected = decode_from(no_sync, &trace[tip_offset..]);
+        assert_eq!(expected[0], "TIP EightBytes(0x1000)");
+        assert_eq!(decode_from(options, &trace), expected);
+        for chunk_size in 1..=17 {
+            let mut handler = PacketRecorder::default();
+            let mut decoder = StreamDecoder::new(options);
+            for chunk in trace.chunks(chunk_size) {
+                decoder.feed(chunk, &mut handler).unwrap();
+            }
+            decoder.finish::<PacketRecorder>().unwrap();
+            assert_eq!(handler.packets, expected, "Chunk size {chunk_size}");
+        }
+
+        // Start offset is validated against the buffer length
+        options.start_offset(trace.len());
+        assert!(decode_from(options, &trace).is_empty());
+        options.start_offset(trace.len() + 1);
+        let mut handler = PacketRecorder::default();
+        assert!(matches!(
+            decode(&trace, options, &mut handler),
+            Err(DecoderError::StartOffsetOutOfBounds { offset, len })
+                if offset == trace.len() + 1 && len == trace.len()
+        ));
+        let mut decoder = StreamDecoder::new(options);
+        decoder.feed(&trace, &mut handler).unwrap();
+        assert!(matches!(
+            decoder.finish::<PacketRecorder>(),
+            Err(DecoderError::StartOffsetOutOfBounds { .. })
+        ));
+    }
+}
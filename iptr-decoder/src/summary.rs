@@ -0,0 +1,58 @@
+//! This module contains the summary of decoding
+
+use core as std; // workaround for `perfect_derive`
+
+use perfect_derive::perfect_derive;
+
+use crate::{HandlePacket, error::DecoderError, packet_handler::packet_counter::PacketCounter};
+
+/// Reason why the decoding stops
+#[perfect_derive(Debug)]
+pub enum StopReason<H: HandlePacket> {
+    /// All bytes in the buffer are decoded
+    EndOfBuffer,
+    /// The decoding stops at a TraceStop packet, see
+    /// [`DecodeOptions::stop_at_trace_stop`][crate::DecodeOptions::stop_at_trace_stop].
+    ///
+    /// If the decoder continues after TraceStop packets, this means all bytes
+    /// in the buffer are decoded, and the last packet is a TraceStop packet.
+    TraceStop,
+    /// Error occurred during decoding
+    Error(DecoderError<H>),
+}
+
+/// Summary of decoding, returned by [`decode_with_summary`][crate::decode_with_summary]
+#[perfect_derive(Debug)]
+pub struct DecodeSummary<H: HandlePacket> {
+    /// Number of bytes consumed in the buffer.
+    ///
+    /// This includes the bytes skipped when syncing forward for the first PSB
+    /// packet. If the decoding stops due to an error, this is the start position
+    /// of the packet causing the error.
+    pub bytes_consumed: usize,
+    /// Reason why the decoding stops
+    pub stopped_at: StopReason<H>,
+    /// Number of packet handler errors skipped, see
+    /// [`DecodeOptions::handler_error_budget`][crate::DecodeOptions::handler_error_budget]
+    pub skipped_handler_errors: usize,
+}
+
+/// Summary of validation, returned by [`validate`][crate::validate]
+#[derive(Debug)]
+pub struct ValidationSummary {
+    /// Number of packets decoded, including those before the error if the
+    /// decoding stops due to an error
+    pub packet_count: usize,
+    /// Summary of decoding
+    pub decode_summary: DecodeSummary<PacketCounter>,
+}
+
+impl<H: HandlePacket> DecodeSummary<H> {
+    /// Convert to a [`Result`], discarding the summary information
+    pub fn into_result(self) -> Result<(), DecoderError<H>> {
+        match self.stopped_at {
+            StopReason::EndOfBuffer | StopReason::TraceStop => Ok(()),
+            StopReason::Error(err) => Err(err),
+        }
+    }
+}
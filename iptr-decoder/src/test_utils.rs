@@ -0,0 +1,18 @@
+//! Helpers for building Intel PT packets in tests
+
+/// PSBEND packet
+pub(crate) const PSBEND_BYTES: [u8; 2] = [0x02, 0x23];
+
+/// TSC packet with given value
+#[cfg(feature = "alloc")]
+pub(crate) fn tsc_packet(value: u64) -> [u8; 8] {
+    let mut packet = [0x19; 8];
+    packet[1..].copy_from_slice(&value.to_le_bytes()[..7]);
+    packet
+}
+
+/// CBR packet with given core:bus ratio
+#[cfg(feature = "alloc")]
+pub(crate) fn cbr_packet(core_bus_ratio: u8) -> [u8; 4] {
+    [0x02, 0x03, core_bus_ratio, 0x00]
+}
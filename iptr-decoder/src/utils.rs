@@ -1,12 +1,16 @@
 //! Utility functions for dealing with extracted values in PT packets.
 
-use crate::IpReconstructionPattern;
+use crate::{IpReconstructionPattern, TraceeMode};
 
 /// Follow the `ip_reconstruction_pattern` to update the `last_ip`.
 ///
 /// This function will return `true` if the `last_ip` is updated. When this function
 /// returns false, it means the target of FUP or TIP is out of context, according to
 /// the Intel manual.
+///
+/// For [`SixBytesExtended`][IpReconstructionPattern::SixBytesExtended], bit 47 of
+/// the payload is sign-extended regardless of `last_ip`, so kernel addresses are
+/// reconstructed in their high-half canonical form.
 #[expect(
     clippy::cast_sign_loss,
     clippy::cast_possible_wrap,
@@ -32,3 +36,499 @@ pub fn reconstruct_ip_and_update_last(
 
     true
 }
+
+/// Follow the `ip_reconstruction_pattern` to update the `last_ip`, and return the
+/// reconstructed IP wrapped to the address width of `tracee_mode`, see
+/// [`TraceeMode::mask_address`].
+///
+/// `last_ip` itself is not wrapped, since the IP compression is independent of
+/// the tracee mode and `last_ip` is the reference of following compressed IPs.
+/// Returns [`None`] if the IP is out of context, in which case `last_ip` is not
+/// updated.
+pub fn reconstruct_ip_in_mode(
+    last_ip: &mut u64,
+    ip_reconstruction_pattern: IpReconstructionPattern,
+    tracee_mode: TraceeMode,
+) -> Option<u64> {
+    reconstruct_ip_and_update_last(last_ip, ip_reconstruction_pattern)
+        .then(|| tracee_mode.mask_address(*last_ip))
+}
+
+/// Linear address width with 4-level paging
+pub const CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING: u8 = 48;
+
+/// Linear address width with 5-level paging (LA57)
+pub const CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING: u8 = 57;
+
+/// Whether `address` is canonical for linear address width `width`, i.e., bits
+/// `63:width` are all equal to bit `width - 1`.
+///
+/// The width is [`CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING`] on most systems, and
+/// [`CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING`] if 5-level paging is enabled.
+/// IPs reconstructed from PT packets in 64-bit mode should always be canonical,
+/// so a non-canonical IP indicates trace corruption or an IP reconstruction bug.
+///
+/// # Panics
+///
+/// Panics if `width` is not in `1..=64`.
+#[must_use]
+#[expect(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+pub fn is_canonical_address(address: u64, width: u8) -> bool {
+    assert!(
+        (1..=64).contains(&width),
+        "Invalid linear address width {width}"
+    );
+    let shift = 64 - u32::from(width);
+    (((address << shift) as i64) >> shift) as u64 == address
+}
+
+/// Timing configuration of the traced CPU, used to convert timing packets.
+///
+/// These values are not recorded in the trace itself:
+///
+/// * The TSC/CTC ratio is `CPUID.(EAX=15H):EBX / CPUID.(EAX=15H):EAX`. On Linux,
+///   this is also recorded in the `AUXTRACE_INFO` of `perf.data`, which can be
+///   retrieved by `iptr-perf-pt-reader`.
+/// * The MTC frequency is the `MTCFreq` field (bits 17:14) of `IA32_RTIT_CTL` MSR
+///   configured when tracing, i.e., the `mtc_period` config of perf. An MTC packet
+///   is emitted every `2^mtc_freq_ratio` CTC cycles.
+///
+/// The configuration can only be created by [`from_tsc_ctc_ratio`][Self::from_tsc_ctc_ratio]
+/// or [`Default`], so the values are always valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtTimingConfig {
+    /// Numerator of TSC/CTC ratio, never zero
+    tsc_ctc_num: u32,
+    /// Denominator of TSC/CTC ratio, never zero
+    tsc_ctc_den: u32,
+    /// Encoded MTC frequency, i.e., `MTCFreq` field of `IA32_RTIT_CTL`, at most
+    /// [`MAX_MTC_FREQ_RATIO`][Self::MAX_MTC_FREQ_RATIO]
+    mtc_freq_ratio: u8,
+}
+
+impl Default for PtTimingConfig {
+    /// The TSC/CTC ratio defaults to 1, and the MTC frequency defaults to 3,
+    /// which is the default `mtc_period` of perf.
+    fn default() -> Self {
+        Self {
+            tsc_ctc_num: 1,
+            tsc_ctc_den: 1,
+            mtc_freq_ratio: 3,
+        }
+    }
+}
+
+impl PtTimingConfig {
+    /// Maximum encoded MTC frequency, since `MTCFreq` is a 4-bit field
+    pub const MAX_MTC_FREQ_RATIO: u8 = 15;
+
+    /// Create timing configuration from the TSC/CTC ratio `tsc_ctc_num / tsc_ctc_den`
+    /// and the encoded MTC frequency.
+    ///
+    /// Returns [`None`] if the numerator or denominator is zero or does not fit
+    /// in 32 bits, i.e., the ratio is not valid, or the MTC frequency is larger
+    /// than [`MAX_MTC_FREQ_RATIO`][Self::MAX_MTC_FREQ_RATIO].
+    #[must_use]
+    pub fn from_tsc_ctc_ratio(
+        tsc_ctc_num: u64,
+        tsc_ctc_den: u64,
+        mtc_freq_ratio: u8,
+    ) -> Option<Self> {
+        let tsc_ctc_num = u32::try_from(tsc_ctc_num).ok()?;
+        let tsc_ctc_den = u32::try_from(tsc_ctc_den).ok()?;
+        if tsc_ctc_num == 0 || tsc_ctc_den == 0 || mtc_freq_ratio > Self::MAX_MTC_FREQ_RATIO {
+            return None;
+        }
+        Some(Self {
+            tsc_ctc_num,
+            tsc_ctc_den,
+            mtc_freq_ratio,
+        })
+    }
+
+    /// Get numerator of TSC/CTC ratio
+    #[must_use]
+    pub fn tsc_ctc_num(&self) -> u32 {
+        self.tsc_ctc_num
+    }
+
+    /// Get denominator of TSC/CTC ratio
+    #[must_use]
+    pub fn tsc_ctc_den(&self) -> u32 {
+        self.tsc_ctc_den
+    }
+
+    /// Get encoded MTC frequency
+    #[must_use]
+    pub fn mtc_freq_ratio(&self) -> u8 {
+        self.mtc_freq_ratio
+    }
+
+    /// Convert a CTC delta into a TSC delta, rounding down
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn ctc_to_tsc(&self, ctc_delta: u64) -> u64 {
+        (u128::from(ctc_delta) * u128::from(self.tsc_ctc_num) / u128::from(self.tsc_ctc_den)) as u64
+    }
+
+    /// Convert a delta of MTC packets into a CTC delta
+    #[must_use]
+    pub fn mtc_to_ctc(&self, mtc_delta: u64) -> u64 {
+        mtc_delta << self.mtc_freq_ratio
+    }
+}
+
+/// Default bus clock frequency in MHz, which is 100 MHz on most recent Intel
+/// processors
+const DEFAULT_BUS_CLOCK_MHZ: u32 = 100;
+
+/// Extract the cycle counter from the total content of a CYC packet, e.g.,
+/// given by [`on_cyc_packet`][crate::HandlePacket::on_cyc_packet].
+///
+/// The first byte holds `CycCounter[4:0]` in bits 7:3, and each following byte
+/// holds the next 7 bits in bits 7:1, as long as the `Exp` bit of the previous
+/// byte is set. Bits beyond 64 are ignored.
+#[must_use]
+pub fn cyc_counter(cyc_packet: &[u8]) -> u64 {
+    let Some((first, rest)) = cyc_packet.split_first() else {
+        return 0;
+    };
+    let mut counter = u64::from(first >> 3);
+    let mut shift = 5;
+    for byte in rest {
+        if shift >= u64::BITS {
+            break;
+        }
+        counter |= u64::from(byte >> 1) << shift;
+        shift += 7;
+    }
+    counter
+}
+
+/// Tracker of the time of the traced CPU from timing packets.
+///
+/// Two kinds of time are tracked:
+///
+/// * The TSC, which is taken from TSC packets, and interpolated by MTC packets
+///   following a TMA packet according to the [`PtTimingConfig`]. The estimated
+///   TSC is kept monotonic, i.e., if a TSC packet is behind the interpolated TSC,
+///   the interpolated TSC is kept until time catches up.
+/// * The wall-clock time elapsed between CYC packets. A CYC packet holds the
+///   number of core clock cycles since the previous CYC packet. When `CYCThresh`
+///   of `IA32_RTIT_CTL` is set, CYC packets are only emitted once the counter
+///   reaches `2^(CYCThresh-1)`, but the counter is not truncated, so the deltas
+///   are still exact. The core clock frequency is the core:bus ratio of the last
+///   CBR packet multiplied by the bus clock frequency, which is not recorded in
+///   the trace and can be set by [`bus_clock_mhz`][Self::bus_clock_mhz].
+///
+/// Timing packets should be fed in the order they occur in the trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeTracker {
+    /// Timing configuration of the traced CPU
+    timing_config: PtTimingConfig,
+    /// Bus clock frequency in MHz
+    bus_clock_mhz: u32,
+    /// Core:bus ratio of the last CBR packet
+    core_bus_ratio: Option<u8>,
+    /// TSC value of the last TSC packet
+    tsc_base: u64,
+    /// CTC value at the last TMA packet, if a TMA packet follows the last TSC
+    /// packet
+    ctc_base: Option<u64>,
+    /// Accumulated CTC value, updated by MTC packets
+    ctc: u64,
+    /// Current estimated TSC
+    estimated_tsc: u64,
+}
+
+impl Default for TimeTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeTracker {
+    /// Create a new tracker with default timing configuration, bus clock
+    /// frequency 100 MHz, estimated TSC 0, and no CBR packet met yet
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_timing_config(PtTimingConfig::default())
+    }
+
+    /// Create a new tracker with given timing configuration. See [`new`][Self::new]
+    /// for other defaults.
+    #[must_use]
+    pub fn with_timing_config(timing_config: PtTimingConfig) -> Self {
+        Self {
+            timing_config,
+            bus_clock_mhz: DEFAULT_BUS_CLOCK_MHZ,
+            core_bus_ratio: None,
+            tsc_base: 0,
+            ctc_base: None,
+            ctc: 0,
+            estimated_tsc: 0,
+        }
+    }
+
+    /// Set bus clock frequency in MHz. Default is 100.
+    pub fn bus_clock_mhz(&mut self, bus_clock_mhz: u32) -> &mut Self {
+        self.bus_clock_mhz = bus_clock_mhz;
+        self
+    }
+
+    /// Reset the estimated TSC to 0 and forget the core:bus ratio, e.g., at
+    /// decode begin. The timing configuration and bus clock frequency are kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            bus_clock_mhz: self.bus_clock_mhz,
+            ..Self::with_timing_config(self.timing_config)
+        };
+    }
+
+    /// Get current estimated TSC
+    #[must_use]
+    pub fn estimated_tsc(&self) -> u64 {
+        self.estimated_tsc
+    }
+
+    /// Get current core frequency in MHz, i.e., the core:bus ratio of the last
+    /// CBR packet multiplied by the bus clock frequency.
+    ///
+    /// Returns [`None`] if no CBR packet is met yet, or the frequency overflows.
+    #[must_use]
+    pub fn frequency_mhz(&self) -> Option<u32> {
+        u32::from(self.core_bus_ratio?).checked_mul(self.bus_clock_mhz)
+    }
+
+    /// Update with TSC packet
+    pub fn on_tsc(&mut self, tsc_value: u64) {
+        self.tsc_base = tsc_value;
+        self.ctc_base = None;
+        self.update_estimated_tsc(tsc_value);
+    }
+
+    /// Update with TMA packet
+    pub fn on_tma(&mut self, ctc: u16) {
+        self.ctc = u64::from(ctc);
+        self.ctc_base = Some(self.ctc);
+    }
+
+    /// Update with MTC packet
+    pub fn on_mtc(&mut self, ctc_payload: u8) {
+        let Some(ctc_base) = self.ctc_base else {
+            // Cannot be related to TSC without TMA
+            return;
+        };
+        // MTC packet contains CTC[N+7:N], and lower bits are cleared when emitted.
+        // N is at most 15, so the shift never overflows.
+        let ctc_mask = (1u64 << (self.timing_config.mtc_freq_ratio() + 8)) - 1;
+        let mtc_ctc = self.timing_config.mtc_to_ctc(u64::from(ctc_payload));
+        let ctc_delta = mtc_ctc.wrapping_sub(self.ctc) & ctc_mask;
+        self.ctc += ctc_delta;
+        let tsc = self
+            .tsc_base
+            .saturating_add(self.timing_config.ctc_to_tsc(self.ctc - ctc_base));
+        self.update_estimated_tsc(tsc);
+    }
+
+    /// Update with CBR packet
+    pub fn on_cbr(&mut self, core_bus_ratio: u8) {
+        self.core_bus_ratio = Some(core_bus_ratio);
+    }
+
+    /// Update with CYC packet, and get the nanoseconds elapsed since the previous
+    /// CYC packet. See [`cyc_to_ns`][Self::cyc_to_ns].
+    #[must_use]
+    pub fn on_cyc(&mut self, cyc_packet: &[u8]) -> Option<u64> {
+        self.cyc_to_ns(cyc_counter(cyc_packet))
+    }
+
+    /// Convert a CYC delta into nanoseconds under the core:bus ratio of the last
+    /// CBR packet, rounding down.
+    ///
+    /// Returns [`None`] if no CBR packet is met yet, or the core clock frequency
+    /// is zero.
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn cyc_to_ns(&self, cyc_delta: u64) -> Option<u64> {
+        let core_clock_mhz = u128::from(self.core_bus_ratio?) * u128::from(self.bus_clock_mhz);
+        if core_clock_mhz == 0 {
+            return None;
+        }
+        Some((u128::from(cyc_delta) * 1000 / core_clock_mhz) as u64)
+    }
+
+    /// Update the estimated TSC, keeping it monotonic
+    fn update_estimated_tsc(&mut self, tsc: u64) {
+        self.estimated_tsc = self.estimated_tsc.max(tsc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_config() {
+        let config = PtTimingConfig::from_tsc_ctc_ratio(0xB4, 2, 3).unwrap();
+        assert_eq!(config.tsc_ctc_num(), 0xB4);
+        assert_eq!(config.tsc_ctc_den(), 2);
+        assert_eq!(config.mtc_freq_ratio(), 3);
+        assert_eq!(config.ctc_to_tsc(0x10), 0x10 * 90);
+        assert_eq!(config.mtc_to_ctc(2), 16);
+        assert_eq!(config.ctc_to_tsc(config.mtc_to_ctc(1)), 720);
+        assert_eq!(PtTimingConfig::default().ctc_to_tsc(0x10), 0x10);
+
+        // Invalid ratios
+        assert!(PtTimingConfig::from_tsc_ctc_ratio(0xB4, 0, 3).is_none());
+        assert!(PtTimingConfig::from_tsc_ctc_ratio(1 << 32, 2, 3).is_none());
+        assert!(PtTimingConfig::from_tsc_ctc_ratio(0xB4, 2, 16).is_none());
+        assert!(PtTimingConfig::from_tsc_ctc_ratio(0xB4, 2, u8::MAX).is_none());
+    }
+
+    #[test]
+    fn test_time_tracker() {
+        // 3200 cycles, with 5 bits in the first byte and 7 bits in the second
+        let cyc_packet = [0b0000_0111, 0b1100_1000];
+        assert_eq!(cyc_counter(&cyc_packet), 3200);
+        assert_eq!(cyc_counter(&[0b1111_1011]), 0x1F);
+        assert_eq!(cyc_counter(&[0xFF; 16]), u64::MAX);
+
+        let mut tracker = TimeTracker::new();
+        // Core clock is unknown before CBR
+        assert_eq!(tracker.on_cyc(&cyc_packet), None);
+        // 32 * 100 MHz = 3.2 GHz
+        tracker.on_cbr(32);
+        assert_eq!(tracker.on_cyc(&cyc_packet), Some(1000));
+        // 16 * 100 MHz = 1.6 GHz
+        tracker.on_cbr(16);
+        assert_eq!(tracker.on_cyc(&cyc_packet), Some(2000));
+        // 16 * 133 MHz = 2.128 GHz
+        tracker.bus_clock_mhz(133);
+        assert_eq!(tracker.cyc_to_ns(2128), Some(1000));
+        assert_eq!(tracker.cyc_to_ns(2127), Some(999));
+
+        assert_eq!(tracker.frequency_mhz(), Some(16 * 133));
+
+        tracker.on_cbr(0);
+        assert_eq!(tracker.cyc_to_ns(3200), None);
+        tracker.reset();
+        assert_eq!(tracker.cyc_to_ns(3200), None);
+        assert_eq!(tracker.frequency_mhz(), None);
+        // Bus clock frequency is kept after reset
+        tracker.on_cbr(16);
+        assert_eq!(tracker.cyc_to_ns(2128), Some(1000));
+        tracker.bus_clock_mhz(u32::MAX);
+        assert_eq!(tracker.frequency_mhz(), None);
+    }
+
+    #[test]
+    fn test_time_tracker_tsc() {
+        let mut tracker =
+            TimeTracker::with_timing_config(PtTimingConfig::from_tsc_ctc_ratio(2, 1, 3).unwrap());
+        // MTC before TMA is ignored
+        tracker.on_mtc(0x10);
+        assert_eq!(tracker.estimated_tsc(), 0);
+        tracker.on_tsc(0x1_0000);
+        tracker.on_tma(0x7F4);
+        // CTC 0x7F8
+        tracker.on_mtc(0xFF);
+        assert_eq!(tracker.estimated_tsc(), 0x1_0000 + 2 * 0x4);
+        // CTC 0x808, wrapped
+        tracker.on_mtc(0x01);
+        assert_eq!(tracker.estimated_tsc(), 0x1_0000 + 2 * 0x14);
+        // Stale TSC does not move time backwards
+        tracker.on_tsc(0x1_0010);
+        assert_eq!(tracker.estimated_tsc(), 0x1_0000 + 2 * 0x14);
+        tracker.reset();
+        assert_eq!(tracker.estimated_tsc(), 0);
+    }
+
+    #[test]
+    fn test_is_canonical_address() {
+        let width = CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING;
+        assert!(is_canonical_address(0, width));
+        assert!(is_canonical_address(0x0000_7FFF_FFFF_FFFF, width));
+        assert!(is_canonical_address(0xFFFF_8000_0000_0000, width));
+        assert!(is_canonical_address(u64::MAX, width));
+        assert!(!is_canonical_address(0x0000_8000_0000_0000, width));
+        assert!(!is_canonical_address(0xFFFF_7FFF_FFFF_FFFF, width));
+        assert!(!is_canonical_address(0x8000_0000_0000_1000, width));
+
+        let width = CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING;
+        assert!(is_canonical_address(0x0000_8000_0000_0000, width));
+        assert!(is_canonical_address(0x00FF_FFFF_FFFF_FFFF, width));
+        assert!(is_canonical_address(0xFF00_0000_0000_0000, width));
+        assert!(!is_canonical_address(0x0100_0000_0000_0000, width));
+
+        assert!(is_canonical_address(0x8000_0000_0000_1000, 64));
+    }
+
+    #[test]
+    fn test_reconstruct_ip_in_mode() {
+        let mut last_ip = 0;
+        assert_eq!(
+            reconstruct_ip_in_mode(
+                &mut last_ip,
+                IpReconstructionPattern::EightBytes(0x1_0000_1000),
+                TraceeMode::Mode32
+            ),
+            Some(0x1000)
+        );
+        // Last IP is kept unwrapped for following compressed IPs
+        assert_eq!(last_ip, 0x1_0000_1000);
+        assert_eq!(
+            reconstruct_ip_in_mode(
+                &mut last_ip,
+                IpReconstructionPattern::TwoBytesWithLastIp(0x2000),
+                TraceeMode::Mode64
+            ),
+            Some(0x1_0000_2000)
+        );
+        assert_eq!(
+            reconstruct_ip_in_mode(
+                &mut last_ip,
+                IpReconstructionPattern::OutOfContext,
+                TraceeMode::Mode16
+            ),
+            None
+        );
+        assert_eq!(last_ip, 0x1_0000_2000);
+    }
+
+    #[test]
+    fn test_reconstruct_six_bytes_extended() {
+        // Bit 47 set, sign-extended to a high-half canonical address
+        let mut last_ip = 0x0000_7FFF_1234_5678;
+        assert!(reconstruct_ip_and_update_last(
+            &mut last_ip,
+            IpReconstructionPattern::SixBytesExtended(0x8000_0000_1000)
+        ));
+        assert_eq!(last_ip, 0xFFFF_8000_0000_1000);
+        // Later compressed IPs reuse the sign-extended upper bytes
+        assert!(reconstruct_ip_and_update_last(
+            &mut last_ip,
+            IpReconstructionPattern::TwoBytesWithLastIp(0x2000)
+        ));
+        assert_eq!(last_ip, 0xFFFF_8000_0000_2000);
+
+        // Bit 47 clear, upper bytes of last IP are discarded
+        assert!(reconstruct_ip_and_update_last(
+            &mut last_ip,
+            IpReconstructionPattern::SixBytesExtended(0x10)
+        ));
+        assert_eq!(last_ip, 0x10);
+        assert!(reconstruct_ip_and_update_last(
+            &mut last_ip,
+            IpReconstructionPattern::SixBytesExtended(0x7FFF_FFFF_FFFF)
+        ));
+        assert_eq!(last_ip, 0x0000_7FFF_FFFF_FFFF);
+
+        // Out of context never touches last IP
+        assert!(!reconstruct_ip_and_update_last(
+            &mut last_ip,
+            IpReconstructionPattern::OutOfContext
+        ));
+        assert_eq!(last_ip, 0x0000_7FFF_FFFF_FFFF);
+    }
+}
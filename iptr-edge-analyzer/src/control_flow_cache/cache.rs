@@ -4,6 +4,9 @@ use zerocopy::{ByteHash, Immutable, IntoBytes};
 
 use hashbrown::HashMap;
 
+#[cfg(feature = "more_diagnose")]
+use crate::diagnose::{CacheDump, CacheEntry};
+
 /// Key structure for the 8bit cache hash map.
 #[derive(PartialEq, Eq, Clone, Copy, Immutable, IntoBytes)]
 #[repr(C, packed)]
@@ -17,8 +20,10 @@ derive_hash_fast::derive_hash_fast_zerocopy!(ControlFlowSequence8);
 
 /// Compact representation of trailing bits
 ///
-/// The lower 8 bits are trailing bits itself (from MSB to LSB),
-/// The upper 8 bits are number of bits
+/// The lower 8 bits are trailing bits itself (from MSB to LSB, i.e., the oldest
+/// bit is bit 7, and the unused lower bits are cleared), the upper 8 bits are
+/// number of bits. Since there are at most 7 trailing bits, both parts never
+/// overlap.
 #[derive(Clone, Copy, PartialEq, Eq, ByteHash, Immutable, IntoBytes)]
 #[repr(C, packed)]
 pub struct TrailingBits(u16);
@@ -26,16 +31,22 @@ pub struct TrailingBits(u16);
 impl TrailingBits {
     /// Create a new trailing bits
     ///
-    /// The bits shall never exceed 7 bits
+    /// `remain_tnt_buffer` holds the trailing bits in its upper `remain_bits`
+    /// bits (from MSB to LSB), and the other bits are ignored. The bits shall
+    /// never exceed 7 bits.
     #[expect(clippy::cast_possible_truncation)]
     pub fn new(remain_tnt_buffer: u32, remain_bits: u32) -> Self {
-        debug_assert_eq!(
-            remain_tnt_buffer & 0x00FF_FFFF,
-            0,
-            "remain tnt buffer exceeds 8 bits"
-        );
         debug_assert!(remain_bits < 8, "remain bits >= 8");
-        Self((remain_bits as u16) | ((remain_tnt_buffer >> 16) as u16))
+        let bits = remain_tnt_buffer & !(u32::MAX >> remain_bits);
+        Self(((remain_bits as u16) << 8) | ((bits >> 24) as u16))
+    }
+
+    /// Get the trailing bits in the lower bits, and the number of bits
+    #[cfg(feature = "more_diagnose")]
+    fn unpack(self) -> (u32, u32) {
+        let Self(packed) = self;
+        let bit_count = u32::from(packed >> 8);
+        (u32::from(packed & 0xFF) >> (8 - bit_count), bit_count)
     }
 }
 
@@ -62,6 +73,7 @@ struct ControlFlowSequence32 {
 derive_hash_fast::derive_hash_fast_zerocopy!(ControlFlowSequence32);
 
 /// Value structure for the cache hash map
+#[derive(Clone)]
 pub struct CachableInformation<D> {
     /// User defined data for [`HandleControlFlow`][crate::HandleControlFlow]
     pub user_data: D,
@@ -135,6 +147,53 @@ impl<D> ControlFlowCacheManager<D> {
         )
     }
 
+    /// Dump all cache entries, see [`CacheDump`]
+    #[cfg(feature = "more_diagnose")]
+    pub fn dump(&self) -> CacheDump {
+        let mut trailing_bits = self
+            .cache_trailing_bits
+            .iter()
+            .map(|(key, info)| {
+                let (tnt_bits, bit_count) = key.trailing_bits.unpack();
+                CacheEntry {
+                    start_bb: key.start_bb,
+                    tnt_bits,
+                    bit_count,
+                    new_bb: info.new_bb,
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut cache8 = self
+            .cache8
+            .iter()
+            .map(|(key, info)| CacheEntry {
+                start_bb: key.start_bb,
+                tnt_bits: u32::from(key.cached_tnts[0]),
+                bit_count: u8::BITS,
+                new_bb: info.new_bb,
+            })
+            .collect::<Vec<_>>();
+        let mut cache32 = self
+            .cache32
+            .iter()
+            .map(|(key, info)| CacheEntry {
+                start_bb: key.start_bb,
+                // The last byte holds the oldest bits
+                tnt_bits: u32::from_le_bytes(key.cached_tnts),
+                bit_count: u32::BITS,
+                new_bb: info.new_bb,
+            })
+            .collect::<Vec<_>>();
+        trailing_bits.sort_unstable();
+        cache8.sort_unstable();
+        cache32.sort_unstable();
+        CacheDump {
+            trailing_bits,
+            cache8,
+            cache32,
+        }
+    }
+
     /// Get cached information for 8 bits TNTs
     pub fn get_byte(&self, start_bb: u64, byte: u8) -> Option<&CachableInformation<D>> {
         self.cache8.get(&ControlFlowSequence8 {
@@ -201,3 +260,34 @@ impl<D> ControlFlowCacheManager<D> {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trailing_bits_packing() {
+        let mut packed_set = std::collections::HashSet::new();
+        for remain_bits in 1..=7u32 {
+            for value in 0..(1u32 << remain_bits) {
+                let remain_tnt_buffer = value << (32 - remain_bits);
+                let TrailingBits(packed) = TrailingBits::new(remain_tnt_buffer, remain_bits);
+                // Unpack
+                assert_eq!(u32::from(packed >> 8), remain_bits);
+                assert_eq!(u32::from(packed & 0xFF) >> (8 - remain_bits), value);
+                assert!(
+                    packed_set.insert(packed),
+                    "Collision at {value:#b}/{remain_bits}"
+                );
+                // Bits after trailing bits are ignored
+                let dirty_buffer = remain_tnt_buffer | (u32::MAX >> remain_bits);
+                let TrailingBits(dirty_packed) = TrailingBits::new(dirty_buffer, remain_bits);
+                assert_eq!(dirty_packed, packed, "{value:#b}/{remain_bits}");
+            }
+        }
+        assert_eq!(
+            packed_set.len(),
+            (1..=7).map(|bits| 1 << bits).sum::<usize>()
+        );
+    }
+}
@@ -2,7 +2,7 @@
 
 #[cfg(feature = "cache")]
 mod cache;
-use std::mem::MaybeUninit;
+use std::{mem::MaybeUninit, num::NonZero};
 
 #[cfg(feature = "cache")]
 pub use cache::ControlFlowCacheManager;
@@ -14,8 +14,9 @@ use self::cache::{CachableInformation, TrailingBits};
 #[cfg(feature = "cache")]
 use crate::error::AnalyzerError;
 use crate::{
-    EdgeAnalyzer, HandleControlFlow, PreTipStatus, ReadMemory, TntProceed, error::AnalyzerResult,
-    tnt_buffer::TntBuffer,
+    EdgeAnalyzer, HandleControlFlow, PreTipStatus, ReadMemory, TntProceed,
+    error::AnalyzerResult,
+    tnt_buffer::{TntBuffer, split_long_tnt},
 };
 
 #[cfg(feature = "cache")]
@@ -30,10 +31,60 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
     fn mark_deferred_tip(&mut self, remain_tnt_buffer: TntBuffer) -> AnalyzerResult<(), H, R> {
         self.tnt_buffer_manager.prepend_buf(remain_tnt_buffer)?;
         self.pre_tip_status = PreTipStatus::PendingIndirect;
+        #[cfg(feature = "more_diagnose")]
+        {
+            self.deferred_tip_count += 1;
+            self.update_max_pending_tnt_bits();
+        }
 
         Ok(())
     }
 
+    /// Check that the hit cache `cached_info` is the same as `recomputed_info`,
+    /// which is inserted by processing the same TNT bits from `start_bb` without
+    /// querying the cache again.
+    ///
+    /// `tnt_bits` holds the cached TNT bits in its lower `bit_count` bits, where
+    /// the oldest bit is the most significant one. `tnt_proceed` is the result of
+    /// the uncached processing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache diverges from the uncached path.
+    #[cfg(feature = "cache_self_check")]
+    fn check_cache_hit(
+        &self,
+        start_bb: u64,
+        tnt_bits: u32,
+        bit_count: u32,
+        cached_info: &CachableInformation<Option<CachedKey<H>>>,
+        tnt_proceed: TntProceed,
+        recomputed_info: Option<&CachableInformation<Option<CachedKey<H>>>>,
+    ) {
+        let (TntProceed::Continue, Some(recomputed_info)) = (tnt_proceed, recomputed_info) else {
+            panic!(
+                "Cache diverges at start_bb {start_bb:#x} with {bit_count} TNT bits \
+                 {tnt_bits:#b}: the uncached path waits for a deferred TIP"
+            );
+        };
+        assert_eq!(
+            cached_info.new_bb, recomputed_info.new_bb,
+            "Cache diverges at start_bb {start_bb:#x} with {bit_count} TNT bits {tnt_bits:#b}: \
+             different new basic blocks"
+        );
+        let is_same_cache = match (&cached_info.user_data, &recomputed_info.user_data) {
+            (Some(cached_key), Some(recomputed_key)) => {
+                self.handler.is_same_cache(cached_key, recomputed_key)
+            }
+            (cached_key, recomputed_key) => cached_key.is_none() && recomputed_key.is_none(),
+        };
+        assert!(
+            is_same_cache,
+            "Cache diverges at start_bb {start_bb:#x} with {bit_count} TNT bits {tnt_bits:#b}: \
+             different cached keys"
+        );
+    }
+
     /// Process a TNT buffer may or may not be full.
     ///
     /// Note that this function may re-inject tnt buffer into [`tnt_buffer_manager`][Self::tnt_buffer_manager] if
@@ -109,6 +160,61 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
         Ok(())
     }
 
+    /// Process all full TNT buffers in [`tnt_buffer_manager`][Self::tnt_buffer_manager].
+    ///
+    /// TNT bits after a deferred TIP cannot be processed before the TIP is met,
+    /// so this stops once a deferred TIP is detected.
+    pub(crate) fn handle_full_tnt_buffers(
+        &mut self,
+        context: &DecoderContext,
+        last_bb_ref: &mut u64,
+    ) -> AnalyzerResult<(), H, R> {
+        while !matches!(self.pre_tip_status, PreTipStatus::PendingIndirect)
+            && let Some(full_tnt_buffer) = self.tnt_buffer_manager.take_full()
+        {
+            self.handle_full_tnt_buffer(context, last_bb_ref, full_tnt_buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// A fast path for long TNT packets if there are no pending TNT bits.
+    ///
+    /// The TNT bits are processed in chunks directly, without being accumulated in
+    /// [`tnt_buffer_manager`][Self::tnt_buffer_manager]. If a deferred TIP is detected,
+    /// the remaining TNT bits are re-injected as done by
+    /// [`handle_full_tnt_buffer`][Self::handle_full_tnt_buffer].
+    ///
+    /// Returns `false` without doing anything if the fast path is not applicable, i.e.,
+    /// there are pending TNT bits, or there are less than 32 TNT bits in the packet.
+    pub(crate) fn handle_long_tnt_directly(
+        &mut self,
+        context: &DecoderContext,
+        last_bb_ref: &mut u64,
+        long_tnt_packet: NonZero<u64>,
+    ) -> AnalyzerResult<bool, H, R> {
+        if !self.tnt_buffer_manager.is_empty() {
+            return Ok(false);
+        }
+        let Some((full_tnt_buffer, remain_tnt_buffer)) = split_long_tnt(long_tnt_packet) else {
+            return Ok(false);
+        };
+        let tnt_proceed =
+            self.handle_tnt_buffer32(context, last_bb_ref, full_tnt_buffer.get_array_dword())?;
+        if let TntProceed::Break {
+            processed_bit_count,
+        } = tnt_proceed
+        {
+            // The remaining bits come after the unprocessed bits in the full buffer
+            self.tnt_buffer_manager.prepend_buf(remain_tnt_buffer)?;
+            self.mark_deferred_tip(full_tnt_buffer.remove_first_n_bits(processed_bit_count))?;
+            return Ok(true);
+        }
+        self.handle_maybe_full_tnt_buffer(context, last_bb_ref, remain_tnt_buffer)?;
+
+        Ok(true)
+    }
+
     /// Handle 32 Tnt bits stored in `tnt_buffer`.
     ///
     /// The behavior and return value is much like [`handle_tnt_buffer8`][Self::handle_tnt_buffer8],
@@ -126,15 +232,49 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
             {
                 self.cache_32bit_hit_count += 1;
             }
-            *last_bb_ref = cached_info.new_bb;
-            if let Some(cached_key) = &cached_info.user_data {
-                self.handler
-                    .on_reused_cache(cached_key, cached_info.new_bb)
-                    .map_err(AnalyzerError::ControlFlowHandler)?;
+            // Process the TNT bits again without cache, and check the results
+            #[cfg(feature = "cache_self_check")]
+            {
+                let cached_info = cached_info.clone();
+                let start_bb = *last_bb_ref;
+                let tnt_proceed = self.handle_tnt_buffer32_without_querying_cache(
+                    context,
+                    last_bb_ref,
+                    tnt_buffer,
+                )?;
+                self.check_cache_hit(
+                    start_bb,
+                    u32::from_le_bytes(tnt_buffer),
+                    u32::BITS,
+                    &cached_info,
+                    tnt_proceed,
+                    self.cache_manager.get_dword(start_bb, tnt_buffer),
+                );
+                return Ok(tnt_proceed);
             }
+            #[cfg(not(feature = "cache_self_check"))]
+            {
+                *last_bb_ref = cached_info.new_bb;
+                if let Some(cached_key) = &cached_info.user_data {
+                    self.handler
+                        .on_reused_cache(cached_key, cached_info.new_bb)
+                        .map_err(AnalyzerError::ControlFlowHandler)?;
+                }
 
-            return Ok(TntProceed::Continue);
+                return Ok(TntProceed::Continue);
+            }
         }
+        self.handle_tnt_buffer32_without_querying_cache(context, last_bb_ref, tnt_buffer)
+    }
+
+    /// Handle 32 TNT bits stored in `tnt_buffer` without querying the 32-bit
+    /// cache, see [`handle_tnt_buffer32`][Self::handle_tnt_buffer32].
+    fn handle_tnt_buffer32_without_querying_cache(
+        &mut self,
+        context: &DecoderContext,
+        last_bb_ref: &mut u64,
+        tnt_buffer: [u8; 4],
+    ) -> AnalyzerResult<TntProceed, H, R> {
         let start_bb = *last_bb_ref;
         let mut cached_keys = [const { MaybeUninit::uninit() }; 4];
         let [b0, b1, b2, b3] = tnt_buffer;
@@ -240,17 +380,48 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
             {
                 self.cache_8bit_hit_count += 1;
             }
-            *last_bb_ref = cached_info.new_bb;
-            if let Some(cached_key) = &cached_info.user_data {
-                self.handler
-                    .on_reused_cache(cached_key, cached_info.new_bb)
-                    .map_err(AnalyzerError::ControlFlowHandler)?;
+            // Process the TNT bits again without cache, and check the results
+            #[cfg(feature = "cache_self_check")]
+            {
+                let cached_info = cached_info.clone();
+                let start_bb = *last_bb_ref;
+                let (cached_key, tnt_proceed) =
+                    self.handle_tnt_buffer8_without_querying_cache(context, last_bb_ref, tnt_bits)?;
+                self.check_cache_hit(
+                    start_bb,
+                    u32::from(tnt_bits),
+                    u8::BITS,
+                    &cached_info,
+                    tnt_proceed,
+                    self.cache_manager.get_byte(start_bb, tnt_bits),
+                );
+                return Ok((cached_key, tnt_proceed));
             }
+            #[cfg(not(feature = "cache_self_check"))]
+            {
+                *last_bb_ref = cached_info.new_bb;
+                if let Some(cached_key) = &cached_info.user_data {
+                    self.handler
+                        .on_reused_cache(cached_key, cached_info.new_bb)
+                        .map_err(AnalyzerError::ControlFlowHandler)?;
+                }
 
-            // TODO: The clone can be optimized using the `entry` API
-            // and `Cow` structure.
-            return Ok((cached_info.user_data.clone(), TntProceed::Continue));
+                // TODO: The clone can be optimized using the `entry` API
+                // and `Cow` structure.
+                return Ok((cached_info.user_data.clone(), TntProceed::Continue));
+            }
         }
+        self.handle_tnt_buffer8_without_querying_cache(context, last_bb_ref, tnt_bits)
+    }
+
+    /// Handle 8 TNT bits stored in `tnt_bits` without querying the 8-bit cache,
+    /// see [`handle_tnt_buffer8`][Self::handle_tnt_buffer8].
+    fn handle_tnt_buffer8_without_querying_cache(
+        &mut self,
+        context: &DecoderContext,
+        last_bb_ref: &mut u64,
+        tnt_bits: u8,
+    ) -> AnalyzerResult<(Option<CachedKey<H>>, TntProceed), H, R> {
         #[cfg(feature = "cache")]
         self.handler
             .clear_current_cache()
@@ -303,7 +474,7 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
         &mut self,
         context: &DecoderContext,
         last_bb_ref: &mut u64,
-        mut remain_tnt_buffer: u32,
+        remain_tnt_buffer: u32,
         remain_bits: u32,
     ) -> AnalyzerResult<TntProceed, H, R> {
         debug_assert!((1..=7).contains(&remain_bits), "Unexpected remain bits");
@@ -318,15 +489,59 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
             {
                 self.cache_trailing_bits_hit_count += 1;
             }
-            *last_bb_ref = cached_info.new_bb;
-            if let Some(cached_key) = &cached_info.user_data {
-                self.handler
-                    .on_reused_cache(cached_key, cached_info.new_bb)
-                    .map_err(AnalyzerError::ControlFlowHandler)?;
+            // Process the TNT bits again without cache, and check the results
+            #[cfg(feature = "cache_self_check")]
+            {
+                let cached_info = cached_info.clone();
+                let start_bb = *last_bb_ref;
+                let tnt_proceed = self.handle_tnt_buffer_trailing_bits_without_querying_cache(
+                    context,
+                    last_bb_ref,
+                    remain_tnt_buffer,
+                    remain_bits,
+                )?;
+                self.check_cache_hit(
+                    start_bb,
+                    remain_tnt_buffer >> (u32::BITS - remain_bits),
+                    remain_bits,
+                    &cached_info,
+                    tnt_proceed,
+                    self.cache_manager
+                        .get_trailing_bits(start_bb, trailing_bits),
+                );
+                return Ok(tnt_proceed);
             }
+            #[cfg(not(feature = "cache_self_check"))]
+            {
+                *last_bb_ref = cached_info.new_bb;
+                if let Some(cached_key) = &cached_info.user_data {
+                    self.handler
+                        .on_reused_cache(cached_key, cached_info.new_bb)
+                        .map_err(AnalyzerError::ControlFlowHandler)?;
+                }
 
-            return Ok(TntProceed::Continue);
+                return Ok(TntProceed::Continue);
+            }
         }
+        self.handle_tnt_buffer_trailing_bits_without_querying_cache(
+            context,
+            last_bb_ref,
+            remain_tnt_buffer,
+            remain_bits,
+        )
+    }
+
+    /// Handle trailing TNT bits without querying the trailing bits cache, see
+    /// [`handle_tnt_buffer_trailing_bits`][Self::handle_tnt_buffer_trailing_bits].
+    fn handle_tnt_buffer_trailing_bits_without_querying_cache(
+        &mut self,
+        context: &DecoderContext,
+        last_bb_ref: &mut u64,
+        mut remain_tnt_buffer: u32,
+        remain_bits: u32,
+    ) -> AnalyzerResult<TntProceed, H, R> {
+        #[cfg(feature = "cache")]
+        let trailing_bits = TrailingBits::new(remain_tnt_buffer, remain_bits);
         #[cfg(feature = "cache")]
         self.handler
             .clear_current_cache()
@@ -386,3 +601,58 @@ fn update_cached_key<H: HandleControlFlow, R: ReadMemory>(
         .map_err(AnalyzerError::ControlFlowHandler)?;
     Ok(())
 }
+
+#[cfg(all(test, feature = "cache_self_check"))]
+mod tests {
+    use iptr_decoder::DecodeOptions;
+
+    use super::*;
+    use crate::test_utils::{loop_analyzer, loop_trace};
+
+    #[test]
+    fn test_cache_self_check() {
+        let trace = loop_trace();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let blocks = analyzer.handler().blocks.clone();
+        // All cache hits are checked against the uncached path
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.handler().blocks, blocks);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cache diverges at start_bb 0x1000 with 32 TNT bits")]
+    fn test_cache_self_check_corruption() {
+        let trace = loop_trace();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        // Inject a wrong new basic block for 32 taken bits
+        analyzer.cache_manager.insert_dword(
+            0x1000,
+            [0xFF; 4],
+            CachableInformation {
+                user_data: None,
+                new_bb: 0x1002,
+            },
+        );
+        let _ = iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer);
+    }
+
+    #[test]
+    #[should_panic(expected = "different cached keys")]
+    fn test_cache_self_check_cached_key_corruption() {
+        let trace = loop_trace();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        // Inject a wrong cached key with the correct new basic block
+        analyzer.cache_manager.insert_byte(
+            0x1000,
+            0xFF,
+            CachableInformation {
+                user_data: Some(Vec::new()),
+                new_bb: 0x1000,
+            },
+        );
+        let _ = iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer);
+    }
+}
@@ -0,0 +1,157 @@
+//! This module contains a control flow handler building a weighted call graph.
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{ControlFlowTransitionKind, HandleControlFlow};
+
+/// [`HandleControlFlow`] implementor for building a call graph, whose edges are
+/// weighted by the number of times each call is executed.
+///
+/// Different from the CFG graph which contains intra-procedural edges between
+/// basic blocks, each edge of the call graph is from the basic block terminated
+/// by `CALL` (the caller block) to the call target (the callee block). Both
+/// direct and indirect calls are recorded.
+///
+/// Calls are reported by [`on_call`][HandleControlFlow::on_call], which is
+/// only invoked in non-cache mode, so this handler is not available with the
+/// `cache` feature.
+#[derive(Default)]
+pub struct CallGraphHandler {
+    /// Key: (caller block, callee block), Value: number of calls
+    calls: HashMap<(u64, u64), usize>,
+}
+
+impl CallGraphHandler {
+    /// Create a new [`CallGraphHandler`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the number of calls, keyed by (caller block, callee block)
+    #[must_use]
+    pub fn calls(&self) -> &HashMap<(u64, u64), usize> {
+        &self.calls
+    }
+
+    /// Consume the handler and get the number of calls, keyed by
+    /// (caller block, callee block)
+    #[must_use]
+    pub fn into_calls(self) -> HashMap<(u64, u64), usize> {
+        self.calls
+    }
+
+    /// Render the call graph in Graphviz DOT format.
+    ///
+    /// Nodes are named by the addresses of basic blocks, and edges are labeled
+    /// by the number of calls. Edges are sorted by addresses, so the output is
+    /// deterministic.
+    #[must_use]
+    pub fn to_dot(&self) -> String {
+        let mut calls = self.calls.iter().collect::<Vec<_>>();
+        calls.sort_unstable();
+        let mut dot = String::from("digraph call_graph {\n");
+        for ((caller_block, callee_block), count) in calls {
+            // Writing to string never fails
+            let _ = writeln!(
+                dot,
+                "    \"{caller_block:#x}\" -> \"{callee_block:#x}\" [label=\"{count}\"];"
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl HandleControlFlow for CallGraphHandler {
+    type Error = std::convert::Infallible;
+    const NEEDS_INDIRECT_SOURCE: bool = true;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[inline]
+    fn on_new_block(
+        &mut self,
+        _block_addr: u64,
+        _transition_kind: ControlFlowTransitionKind,
+        _cache: bool,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_call(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        *self.calls.entry((src_block, dst_block)).or_default() += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iptr_decoder::DecodeOptions;
+
+    use super::*;
+    use crate::{
+        EdgeAnalyzer,
+        test_utils::{PtBuilder, SliceMemoryReader},
+    };
+
+    #[test]
+    fn test_nested_calls() {
+        /// ```plaintext
+        /// 0x1000: call 0x1010
+        /// 0x1005: call rax
+        /// 0x1007: jmp rbx
+        /// 0x1010: call 0x1020
+        /// 0x1015: ret
+        /// 0x1020: ret
+        /// ```
+        const CODE: [u8; 33] = [
+            0xE8, 0x0B, 0x00, 0x00, 0x00, 0xFF, 0xD0, 0xFF, 0xE3, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+            0xCC, 0xCC, 0xE8, 0x0B, 0x00, 0x00, 0x00, 0xC3, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+            0xCC, 0xCC, 0xCC, 0xCC, 0xC3,
+        ];
+        /// ```plaintext
+        /// 0x2000: call 0x1020
+        /// 0x2005: ret
+        /// ```
+        const INDIRECT_CALLEE_CODE: [u8; 6] = [0xE8, 0x1B, 0xF0, 0xFF, 0xFF, 0xC3];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .tip(0x1015)
+            .tip(0x1005)
+            .tip(0x2000)
+            .tip(0x2005)
+            .tip(0x1007)
+            .tip(0x1000)
+            .tip(0x1015)
+            .build();
+        let reader = SliceMemoryReader::new(&[(0x1000, &CODE), (0x2000, &INDIRECT_CALLEE_CODE)]);
+        let mut analyzer = EdgeAnalyzer::new(CallGraphHandler::new(), reader);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        let handler = analyzer.handler();
+        assert_eq!(
+            *handler.calls(),
+            HashMap::from([
+                ((0x1000, 0x1010), 2),
+                ((0x1010, 0x1020), 2),
+                ((0x1005, 0x2000), 1),
+                ((0x2000, 0x1020), 1),
+            ])
+        );
+        assert_eq!(
+            handler.to_dot(),
+            "digraph call_graph {\n\
+             \x20   \"0x1000\" -> \"0x1010\" [label=\"2\"];\n\
+             \x20   \"0x1005\" -> \"0x2000\" [label=\"1\"];\n\
+             \x20   \"0x1010\" -> \"0x1020\" [label=\"2\"];\n\
+             \x20   \"0x2000\" -> \"0x1020\" [label=\"1\"];\n\
+             }\n"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 //! This module contains combined control flow handler logics.
 
+#[cfg(not(feature = "cache"))]
+use crate::BlockInstruction;
 use crate::HandleControlFlow;
 
 use perfect_derive::perfect_derive;
@@ -77,6 +79,7 @@ where
 
     #[cfg(feature = "cache")]
     type CachedKey = (Option<H1::CachedKey>, Option<H2::CachedKey>);
+    const NEEDS_INDIRECT_SOURCE: bool = H1::NEEDS_INDIRECT_SOURCE || H2::NEEDS_INDIRECT_SOURCE;
 
     fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
         self.handler1
@@ -105,6 +108,111 @@ where
         Ok(())
     }
 
+    fn on_edge(
+        &mut self,
+        from: u64,
+        to: u64,
+        transition_kind: super::ControlFlowTransitionKind,
+        cache: bool,
+    ) -> Result<(), Self::Error> {
+        self.handler1
+            .on_edge(from, to, transition_kind, cache)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_edge(from, to, transition_kind, cache)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    fn on_async_event(&mut self, block_addr: u64, event_ip: u64) -> Result<(), Self::Error> {
+        self.handler1
+            .on_async_event(block_addr, event_ip)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_async_event(block_addr, event_ip)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    fn on_psb_region(&mut self, psb_index: usize) -> Result<(), Self::Error> {
+        self.handler1
+            .on_psb_region(psb_index)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_psb_region(psb_index)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    fn on_missing_endbr(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        self.handler1
+            .on_missing_endbr(src_block, dst_block)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_missing_endbr(src_block, dst_block)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    fn on_indirect_edge(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        self.handler1
+            .on_indirect_edge(src_block, dst_block)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_indirect_edge(src_block, dst_block)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    fn on_call(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        self.handler1
+            .on_call(src_block, dst_block)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_call(src_block, dst_block)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    fn on_return(
+        &mut self,
+        ret_block: u64,
+        expected: Option<u64>,
+        actual: u64,
+    ) -> Result<(), Self::Error> {
+        self.handler1
+            .on_return(ret_block, expected, actual)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_return(ret_block, expected, actual)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "cache"))]
+    fn on_block_instructions(
+        &mut self,
+        block_addr: u64,
+        instructions: &[BlockInstruction],
+        undecodable: bool,
+    ) -> Result<(), Self::Error> {
+        self.handler1
+            .on_block_instructions(block_addr, instructions, undecodable)
+            .map_err(CombinedError::H1Error)?;
+        self.handler2
+            .on_block_instructions(block_addr, instructions, undecodable)
+            .map_err(CombinedError::H2Error)?;
+
+        Ok(())
+    }
+
     #[cfg(feature = "cache")]
     fn cache_prev_cached_key(
         &mut self,
@@ -164,6 +272,23 @@ where
         Ok(())
     }
 
+    #[cfg(feature = "cache_self_check")]
+    fn is_same_cache(
+        &self,
+        (cached_key1, cached_key2): &Self::CachedKey,
+        (other1, other2): &Self::CachedKey,
+    ) -> bool {
+        let is_same1 = match (cached_key1, other1) {
+            (Some(cached_key), Some(other)) => self.handler1.is_same_cache(cached_key, other),
+            (cached_key, other) => cached_key.is_none() && other.is_none(),
+        };
+        let is_same2 = match (cached_key2, other2) {
+            (Some(cached_key), Some(other)) => self.handler2.is_same_cache(cached_key, other),
+            (cached_key, other) => cached_key.is_none() && other.is_none(),
+        };
+        is_same1 && is_same2
+    }
+
     /// In combined control flow handler, this function is special since
     /// it is possible that one of the two handlers wants to clear
     /// all caches while the other does not.
@@ -1,7 +1,8 @@
 //! This module contains fuzz bitmap control flow handler logics.
 
+use std::num::NonZero;
 #[cfg(feature = "cache")]
-use std::{num::NonZero, ops::Range};
+use std::ops::Range;
 
 use crate::{ControlFlowTransitionKind, HandleControlFlow};
 
@@ -28,13 +29,96 @@ pub struct FuzzBitmapControlFlowHandler<M: AsRef<[u8]> + AsMut<[u8]>> {
     bitmap_entries_arena: Vec<CompactBitmapEntry>,
     /// The fuzzing bitmap needed to be maintained.
     fuzzing_bitmap: M,
-    /// Range of valid instruction addresses, if given.
+    /// Ranges of valid instruction addresses, if given.
     ///
-    /// For instruction out of the range, it will not update
-    /// hit count of fuzz bitmap.
+    /// For instruction out of the ranges, it will not update
+    /// hit count of fuzz bitmap. The ranges are sorted by start address
+    /// and merged if overlapping, so that they can be binary searched.
     filter_range: Option<Box<[(u64, u64)]>>,
     /// Previous location used to calculating fuzzing bitmap index.
     prev_loc: u64,
+    /// Policy of calculating fuzzing bitmap index from edges.
+    edge_hash_policy: EdgeHashPolicy,
+    /// Mask for calculating bitmap index if the bitmap size is a power of two,
+    /// which avoids the division of taking modulo.
+    bitmap_index_mask: Option<u64>,
+    /// Number of edges recorded into fuzzing bitmap since decode begin
+    block_count: u64,
+    /// Checkpoint callback and its interval in recorded edges
+    checkpoint: Option<(CheckpointCallback, NonZero<u64>)>,
+    /// Block count at or after which the checkpoint callback is invoked next time
+    next_checkpoint: u64,
+    /// Reporter of new edges, if a new edge callback is set
+    new_edge: Option<NewEdgeReporter>,
+}
+
+/// Callback for checkpointing fuzzing bitmap, see
+/// [`FuzzBitmapControlFlowHandler::on_checkpoint`].
+///
+/// The arguments are current block count and the fuzzing bitmap.
+pub type CheckpointCallback = Box<dyn FnMut(u64, &[u8]) + Send>;
+
+/// Callback for new edges in fuzzing bitmap, see
+/// [`FuzzBitmapControlFlowHandler::on_new_edge`].
+///
+/// The argument is the bitmap index of the new edge.
+pub type NewEdgeCallback = Box<dyn FnMut(usize) + Send>;
+
+/// New edge callback and the bitmap indices already reported since decode begin
+struct NewEdgeReporter {
+    /// The new edge callback
+    callback: NewEdgeCallback,
+    /// Bitset of bitmap indices already reported since decode begin.
+    ///
+    /// This is tracked separately instead of relying on the hit counts in
+    /// fuzzing bitmap, since hit counts wrap around to zero.
+    reported: Box<[u64]>,
+}
+
+impl NewEdgeReporter {
+    /// Invoke the callback if `bitmap_index` is not reported since decode begin
+    #[cold]
+    fn report(&mut self, bitmap_index: usize) {
+        let (word, bit) = (bitmap_index / 64, 1u64 << (bitmap_index % 64));
+        debug_assert!(word < self.reported.len(), "Unexpected OOB");
+        let reported = unsafe { self.reported.get_unchecked_mut(word) };
+        if *reported & bit == 0 {
+            *reported |= bit;
+            (self.callback)(bitmap_index);
+        }
+    }
+}
+
+/// Policy of hashing an edge `(prev_loc, cur_loc)` into an index of fuzzing
+/// bitmap, where `prev_loc` and `cur_loc` are addresses of basic blocks.
+///
+/// The hashed value is taken modulo the bitmap size to get the final index.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgeHashPolicy {
+    /// `(prev_loc >> 1) ^ cur_loc`, the classic AFL scheme
+    #[default]
+    Afl,
+    /// `prev_loc ^ cur_loc`, without shifting `prev_loc`, so that A->B and
+    /// B->A are not distinguished
+    NoShift,
+    /// `((prev_loc >> 1) ^ cur_loc) * 0x9E37_79B9_7F4A_7C15 >> 32` (wrapping
+    /// multiplication), which spreads nearby addresses across the bitmap
+    Multiplicative,
+}
+
+impl EdgeHashPolicy {
+    /// Hash the edge `(prev_loc, cur_loc)` before taking modulo of bitmap size
+    #[inline]
+    #[must_use]
+    pub fn hash(self, prev_loc: u64, cur_loc: u64) -> u64 {
+        match self {
+            Self::Afl => (prev_loc >> 1) ^ cur_loc,
+            Self::NoShift => prev_loc ^ cur_loc,
+            Self::Multiplicative => {
+                ((prev_loc >> 1) ^ cur_loc).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32
+            }
+        }
+    }
 }
 
 /// Initial size of [`per_cache_recorded_bitmap_indices`][FuzzBitmapControlFlowHandler::per_cache_recorded_bitmap_indices].
@@ -57,9 +141,15 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> FuzzBitmapControlFlowHandler<M> {
     /// as `fuzzing_bitmap`. If you want to give range restrictions, pass `filter_range`,
     /// or you could just pass a [`None`] here to indicate that there is no
     /// range restrictions.
+    ///
+    /// `filter_range` is a list of `(start, end)` inclusive address ranges in any order,
+    /// e.g., the IP filter ranges of Intel PT, or the ranges of several hot functions.
+    /// A basic block is in range if its address is in any of the ranges.
     pub fn new(fuzzing_bitmap: M, filter_range: Option<&[(u64, u64)]>) -> Self {
-        #[cfg(feature = "cache")]
         let bitmap_size = fuzzing_bitmap.as_ref().len();
+        let bitmap_index_mask = bitmap_size
+            .is_power_of_two()
+            .then_some(bitmap_size as u64 - 1);
         #[cfg(feature = "cache")]
         let mut bitmap_entries_arena = Vec::with_capacity(INITIAL_BITMAP_ENTRIES_ARENA_SIZE);
         #[cfg(feature = "cache")]
@@ -71,41 +161,137 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> FuzzBitmapControlFlowHandler<M> {
             per_cache_bitmap: vec![0u8; bitmap_size].into_boxed_slice(),
             #[cfg(feature = "cache")]
             bitmap_entries_arena,
-            filter_range: filter_range.map(Box::from),
+            filter_range: filter_range.map(normalize_ranges),
             fuzzing_bitmap,
             prev_loc: 0,
+            edge_hash_policy: EdgeHashPolicy::default(),
+            bitmap_index_mask,
+            block_count: 0,
+            checkpoint: None,
+            next_checkpoint: u64::MAX,
+            new_edge: None,
+        }
+    }
+
+    /// Invoke `callback` every `interval` recorded edges with the current block
+    /// count and fuzzing bitmap, e.g., to copy the bitmap out for a coverage-growth
+    /// curve of a long trace.
+    ///
+    /// See [`block_count`][Self::block_count] for how blocks are counted. With the
+    /// `cache` feature, edges of a reused cache are recorded at once, so the
+    /// callback may be invoked past the exact interval.
+    pub fn on_checkpoint(
+        &mut self,
+        interval: NonZero<u64>,
+        callback: impl FnMut(u64, &[u8]) + Send + 'static,
+    ) -> &mut Self {
+        self.checkpoint = Some((Box::new(callback), interval));
+        self.next_checkpoint = self.block_count.saturating_add(interval.get());
+        self
+    }
+
+    /// Invoke `callback` with the bitmap index whenever the hit count of that index
+    /// transitions from zero to non-zero, i.e., a new edge is found, so that fuzzers
+    /// can react to new coverage without diffing the whole bitmap after each run.
+    ///
+    /// The fuzzing bitmap should be cleared before each run as usual. Each new
+    /// edge is reported exactly once per run (i.e., since decode begin), including
+    /// edges introduced by reused caches, even if its hit count wraps around.
+    pub fn on_new_edge(&mut self, callback: impl FnMut(usize) + Send + 'static) -> &mut Self {
+        let bitmap_size = self.fuzzing_bitmap.as_ref().len();
+        self.new_edge = Some(NewEdgeReporter {
+            callback: Box::new(callback),
+            reported: vec![0u64; bitmap_size.div_ceil(64)].into_boxed_slice(),
+        });
+        self
+    }
+
+    /// Get the number of edges recorded into the fuzzing bitmap since decode begin,
+    /// i.e., the number of executed basic blocks in the filter range.
+    ///
+    /// With the `cache` feature, edges of reused caches are counted by their hit
+    /// counts, which may wrap around for edges hit more than 255 times in a cache.
+    #[must_use]
+    pub fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    /// Get the fuzzing bitmap
+    #[must_use]
+    pub fn bitmap(&self) -> &[u8] {
+        self.fuzzing_bitmap.as_ref()
+    }
+
+    /// Count `count` recorded edges, and invoke the checkpoint callback if reached
+    #[inline]
+    fn count_blocks(&mut self, count: u64) {
+        self.block_count += count;
+        if self.block_count >= self.next_checkpoint {
+            self.checkpoint();
+        }
+    }
+
+    /// Invoke the checkpoint callback, and schedule the next checkpoint
+    #[cold]
+    fn checkpoint(&mut self) {
+        if let Some((callback, interval)) = &mut self.checkpoint {
+            callback(self.block_count, self.fuzzing_bitmap.as_ref());
+            self.next_checkpoint = self.block_count.saturating_add(interval.get());
         }
     }
 
+    /// Set the policy of hashing edges into fuzzing bitmap indices.
+    /// Default is [`EdgeHashPolicy::Afl`].
+    ///
+    /// This should be set before decoding, since cached bitmap entries are
+    /// calculated with the previous policy.
+    pub fn edge_hash_policy(&mut self, edge_hash_policy: EdgeHashPolicy) -> &mut Self {
+        self.edge_hash_policy = edge_hash_policy;
+        self
+    }
+
     #[inline]
     fn is_addr_in_filter_range(&self, address: u64) -> bool {
         let Some(filter_range) = &self.filter_range else {
             return true;
         };
-        for (start, end) in filter_range {
-            if (*start..=*end).contains(&address) {
-                return true;
-            }
-        }
-        false
+        // Ranges are sorted and disjoint, so only the last range starting
+        // at or before `address` may contain it
+        let index = filter_range.partition_point(|(start, _)| *start <= address);
+        index > 0 && address <= filter_range[index - 1].1
     }
 
-    /// Get fuzz bitmap size as a modulus for calculating bitmap index
-    fn bitmap_size_modulus(&self) -> u64 {
-        self.fuzzing_bitmap.as_ref().len() as u64
+    /// Calculate bitmap index from hashed edge.
+    ///
+    /// This takes the hash modulo bitmap size, which is done by masking if
+    /// bitmap size is a power of two.
+    #[inline]
+    #[expect(clippy::cast_possible_truncation)]
+    fn bitmap_index(&self, hash: u64) -> usize {
+        match self.bitmap_index_mask {
+            Some(mask) => (hash & mask) as usize,
+            None => (hash % self.fuzzing_bitmap.as_ref().len() as u64) as usize,
+        }
     }
 
     /// Update [`prev_loc`][FuzzBitmapControlFlowHandler::prev_loc] and calculate bitmap index
-    #[expect(clippy::cast_possible_truncation)]
     fn on_new_loc(&mut self, new_loc: u64) -> usize {
-        let bitmap_index = self.prev_loc ^ new_loc;
+        let hash = self.edge_hash_policy.hash(self.prev_loc, new_loc);
         self.set_new_loc(new_loc);
-        (bitmap_index % self.bitmap_size_modulus()) as usize
+        self.bitmap_index(hash)
     }
 
     /// Set [`prev_loc`][FuzzBitmapControlFlowHandler::prev_loc] without calculating bitmap index
     fn set_new_loc(&mut self, new_loc: u64) {
-        self.prev_loc = new_loc >> 1;
+        self.prev_loc = new_loc;
+    }
+
+    /// Bucket the hit counts in the fuzzing bitmap in place, see [`classify_counts`].
+    ///
+    /// This should be invoked after decoding, since the bucketed counts can no longer
+    /// be incremented correctly.
+    pub fn classify_counts(&mut self) {
+        classify_counts(self.fuzzing_bitmap.as_mut());
     }
 
     /// Get diagnose information
@@ -117,6 +303,27 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> FuzzBitmapControlFlowHandler<M> {
     }
 }
 
+/// Sort `ranges` by start address, and merge overlapping or adjacent ranges.
+/// Empty ranges (start greater than end) are dropped.
+fn normalize_ranges(ranges: &[(u64, u64)]) -> Box<[(u64, u64)]> {
+    let mut ranges = ranges
+        .iter()
+        .copied()
+        .filter(|(start, end)| start <= end)
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged.into_boxed_slice()
+}
+
 /// Diagnostic information for [`FuzzBitmapControlFlowHandler`].
 ///
 /// This struct can be retrieved from [`FuzzBitmapControlFlowHandler::diagnose`]
@@ -133,6 +340,13 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> HandleControlFlow for FuzzBitmapControlFlowHa
 
     fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
         self.prev_loc = 0;
+        self.block_count = 0;
+        if let Some((_, interval)) = self.checkpoint {
+            self.next_checkpoint = interval.get();
+        }
+        if let Some(new_edge) = &mut self.new_edge {
+            new_edge.reported.fill(0);
+        }
         #[cfg(feature = "cache")]
         self.clear_current_cache();
         Ok(())
@@ -153,7 +367,7 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> HandleControlFlow for FuzzBitmapControlFlowHa
             return Ok(());
         }
         match transition_kind {
-            ConditionalBranch | Indirect | DirectJump | DirectCall => {
+            ConditionalBranch | Indirect | FarTransfer(_) | DirectJump | DirectCall => {
                 let bitmap_index = self.on_new_loc(block_addr);
                 debug_assert!(
                     bitmap_index < self.fuzzing_bitmap.as_ref().len(),
@@ -161,6 +375,11 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> HandleControlFlow for FuzzBitmapControlFlowHa
                 );
                 let count = unsafe { self.fuzzing_bitmap.as_mut().get_unchecked_mut(bitmap_index) };
                 *count = count.wrapping_add(1);
+                if *count == 1
+                    && let Some(new_edge) = &mut self.new_edge
+                {
+                    new_edge.report(bitmap_index);
+                }
                 #[cfg(feature = "cache")]
                 if cache {
                     // SAFETY: bitmap index is caculated by modulo
@@ -175,6 +394,7 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> HandleControlFlow for FuzzBitmapControlFlowHa
                 }
                 #[cfg(not(feature = "cache"))]
                 let _ = cache;
+                self.count_blocks(1);
             }
             NewBlock => {
                 self.set_new_loc(block_addr);
@@ -269,6 +489,7 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> HandleControlFlow for FuzzBitmapControlFlowHa
             "Unexpected OOB"
         );
         let bitmap_entries = unsafe { self.bitmap_entries_arena.get_unchecked(entries_range) };
+        let mut block_count = 0;
         // FIXME: This loop should be unrolled, but there is a bug in LLVM: https://github.com/rust-lang/rust/issues/150647
         for bitmap_entry in bitmap_entries {
             let bitmap_index = bitmap_entry.bitmap_index();
@@ -277,19 +498,33 @@ impl<M: AsRef<[u8]> + AsMut<[u8]>> HandleControlFlow for FuzzBitmapControlFlowHa
                 "Unexpected OOB"
             );
             let count = unsafe { self.fuzzing_bitmap.as_mut().get_unchecked_mut(bitmap_index) };
+            let is_new_edge = *count == 0 && bitmap_entry.bitmap_count() != 0;
             *count = count.wrapping_add(bitmap_entry.bitmap_count());
+            block_count += bitmap_entry.bitmap_count() as u64;
+            if is_new_edge && let Some(new_edge) = &mut self.new_edge {
+                new_edge.report(bitmap_index);
+            }
         }
         self.set_new_loc(new_bb);
+        self.count_blocks(block_count);
 
         Ok(())
     }
 
+    #[cfg(feature = "cache_self_check")]
+    fn is_same_cache(&self, cached_key: &Self::CachedKey, other: &Self::CachedKey) -> bool {
+        self.bitmap_entries_arena[cached_key.to_range()]
+            == self.bitmap_entries_arena[other.to_range()]
+    }
+
     #[cfg(feature = "cache")]
     fn should_clear_all_cache(&mut self) -> Result<bool, Self::Error> {
         if self.bitmap_entries_arena.len() < BITMAP_ENTRIES_ARENA_MAX_SIZE {
             return Ok(false);
         }
         self.bitmap_entries_arena.clear();
+        // Keep the dummy element so that cached keys are still non-zero
+        self.bitmap_entries_arena.push(DUMMY_BITMAP_ENTRY);
 
         Ok(true)
     }
@@ -302,7 +537,7 @@ const DUMMY_BITMAP_ENTRY: CompactBitmapEntry = CompactBitmapEntry { value: 0 };
 
 /// Compact representation of a (pos, count) pair used for fuzzing bitmap
 #[cfg(feature = "cache")]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 struct CompactBitmapEntry {
     /// The actual value.
     ///
@@ -355,3 +590,335 @@ impl PerCacheBitmapEntries {
         (self.start.get() as usize)..(self.end.get() as usize)
     }
 }
+
+/// Lookup table for classifying hit counts into AFL-style buckets
+const COUNT_CLASS_LOOKUP: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut count = 1;
+    while count < 256 {
+        table[count] = match count {
+            1 => 1,
+            2 => 2,
+            3 => 4,
+            4..=7 => 8,
+            8..=15 => 16,
+            16..=31 => 32,
+            32..=127 => 64,
+            _ => 128,
+        };
+        count += 1;
+    }
+    table
+};
+
+/// Bucket the hit counts in `bitmap` in place as AFL's `classify_counts` does.
+///
+/// The hit counts are mapped to power-of-two classes: 1 to 1, 2 to 2, 3 to 4,
+/// 4-7 to 8, 8-15 to 16, 16-31 to 32, 32-127 to 64, and 128-255 to 128. This makes
+/// the bitmap directly comparable to AFL-style fuzzers' bitmaps.
+///
+/// Note that bucketing is not idempotent (e.g., 3 is mapped to 4, which is then
+/// mapped to 8), so this should be applied to raw hit counts only once. Since
+/// [`has_new_bits`] buckets the hit counts by itself, pass raw hit counts to it.
+pub fn classify_counts(bitmap: &mut [u8]) {
+    for count in bitmap {
+        *count = COUNT_CLASS_LOOKUP[*count as usize];
+    }
+}
+
+/// Check whether the fuzzing bitmap `new` has new coverage compared to `virgin`,
+/// and update `virgin` accordingly.
+///
+/// This mirrors AFL's `has_new_bits` with classified hit counts: the hit counts in
+/// `new` are bucketed as [`classify_counts`] does, and a bucket not
+/// seen before in `virgin` is treated as new coverage, either a new edge or a new
+/// hit count. `virgin` should be initialized with all `0xFF` before the first call,
+/// and is updated by clearing the bits of seen buckets. `new` is not modified.
+///
+/// If the two bitmaps have different lengths, only the common prefix is compared.
+pub fn has_new_bits(new: &[u8], virgin: &mut [u8]) -> bool {
+    let mut has_new = false;
+    for (count, virgin) in new.iter().zip(virgin.iter_mut()) {
+        let class = COUNT_CLASS_LOOKUP[*count as usize];
+        if class & *virgin != 0 {
+            *virgin &= !class;
+            has_new = true;
+        }
+    }
+    has_new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn test_has_new_bits() {
+        let mut virgin = [0xFFu8; 4];
+
+        // No new bits for empty bitmap
+        assert!(!has_new_bits(&[0, 0, 0, 0], &mut virgin));
+        assert_eq!(virgin, [0xFF; 4]);
+
+        // New edge
+        assert!(has_new_bits(&[0, 1, 0, 0], &mut virgin));
+        assert_eq!(virgin, [0xFF, 0xFE, 0xFF, 0xFF]);
+        assert!(!has_new_bits(&[0, 1, 0, 0], &mut virgin));
+
+        // New hit count bucket
+        assert!(has_new_bits(&[0, 5, 0, 0], &mut virgin));
+        assert_eq!(virgin, [0xFF, 0xF6, 0xFF, 0xFF]);
+        // Same bucket as 5
+        assert!(!has_new_bits(&[0, 7, 0, 0], &mut virgin));
+        assert!(!has_new_bits(&[0, 4, 0, 0], &mut virgin));
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        use std::sync::{Arc, Mutex};
+
+        let checkpoints = Arc::new(Mutex::new(Vec::new()));
+        let mut handler = FuzzBitmapControlFlowHandler::new(vec![0u8; 0x100], None);
+        let recorded = Arc::clone(&checkpoints);
+        handler.on_checkpoint(NonZero::new(3).unwrap(), move |block_count, bitmap| {
+            let coverage = bitmap.iter().filter(|count| **count != 0).count();
+            recorded.lock().unwrap().push((block_count, coverage));
+        });
+        handler.on_new_edge(|_| {});
+        // The handler can still be sent to other threads
+        assert_send(&handler);
+        handler.at_decode_begin().unwrap();
+
+        // A loop executed repeatedly after reaching new blocks
+        let blocks = [0x10, 0x20, 0x30, 0x40, 0x10, 0x20, 0x30, 0x40, 0x10, 0x20];
+        for block_addr in blocks {
+            handler
+                .on_new_block(
+                    block_addr,
+                    ControlFlowTransitionKind::ConditionalBranch,
+                    false,
+                )
+                .unwrap();
+        }
+        assert_eq!(handler.block_count(), 10);
+        let checkpoints = checkpoints.lock().unwrap();
+        assert_eq!(
+            checkpoints
+                .iter()
+                .map(|(count, _)| *count)
+                .collect::<Vec<_>>(),
+            [3, 6, 9]
+        );
+        assert!(
+            checkpoints
+                .windows(2)
+                .all(|window| window[0].1 <= window[1].1)
+        );
+        assert_eq!(checkpoints.first().unwrap().1, 3);
+        assert_eq!(
+            checkpoints.last().unwrap().1,
+            handler.bitmap().iter().filter(|count| **count != 0).count()
+        );
+
+        // Block count is reset at decode begin
+        handler.at_decode_begin().unwrap();
+        assert_eq!(handler.block_count(), 0);
+    }
+
+    #[test]
+    fn test_new_edge() {
+        use std::sync::{Arc, Mutex};
+
+        let new_edges = Arc::new(Mutex::new(Vec::new()));
+        let mut handler = FuzzBitmapControlFlowHandler::new(vec![0u8; 0x100], None);
+        let recorded = Arc::clone(&new_edges);
+        handler.on_new_edge(move |bitmap_index| recorded.lock().unwrap().push(bitmap_index));
+        handler.at_decode_begin().unwrap();
+
+        let covered_indices = |bitmap: &[u8]| {
+            bitmap
+                .iter()
+                .enumerate()
+                .filter_map(|(index, count)| (*count != 0).then_some(index))
+                .collect::<Vec<_>>()
+        };
+
+        // A loop executed repeatedly, each edge is only new at the first hit
+        let blocks = [0x10, 0x20, 0x30, 0x10, 0x20, 0x30, 0x10, 0x20, 0x30];
+        for block_addr in blocks {
+            handler
+                .on_new_block(
+                    block_addr,
+                    ControlFlowTransitionKind::ConditionalBranch,
+                    true,
+                )
+                .unwrap();
+        }
+        let mut reported = new_edges.lock().unwrap().clone();
+        assert_eq!(reported.len(), 4);
+        reported.sort_unstable();
+        reported.dedup();
+        assert_eq!(reported, covered_indices(handler.bitmap()));
+
+        // Edges replayed from cache in a new run are reported once as well, even
+        // if hit counts wrap around
+        #[cfg(feature = "cache")]
+        {
+            let cached_key = handler.take_cache().unwrap().unwrap();
+            handler.fuzzing_bitmap.fill(0);
+            new_edges.lock().unwrap().clear();
+            handler.at_decode_begin().unwrap();
+            for _ in 0..257 {
+                handler.on_reused_cache(&cached_key, 0x30).unwrap();
+            }
+            let mut reported = new_edges.lock().unwrap().clone();
+            assert_eq!(reported.len(), 4);
+            reported.sort_unstable();
+            assert_eq!(reported, covered_indices(handler.bitmap()));
+        }
+    }
+
+    #[test]
+    fn test_new_edge_wrapping_count() {
+        use std::sync::{Arc, Mutex};
+
+        let new_edges = Arc::new(Mutex::new(Vec::new()));
+        let mut handler = FuzzBitmapControlFlowHandler::new(vec![0u8; 0x100], None);
+        let recorded = Arc::clone(&new_edges);
+        handler.on_new_edge(move |bitmap_index| recorded.lock().unwrap().push(bitmap_index));
+        handler.at_decode_begin().unwrap();
+        handler
+            .on_new_block(0x10, ControlFlowTransitionKind::NewBlock, false)
+            .unwrap();
+
+        // Self loop hit 257 times, whose hit count wraps around to 1
+        for _ in 0..257 {
+            handler
+                .on_new_block(0x10, ControlFlowTransitionKind::ConditionalBranch, false)
+                .unwrap();
+        }
+        let self_loop_index = (0x10 >> 1) ^ 0x10;
+        assert_eq!(handler.bitmap()[self_loop_index], 1);
+        assert_eq!(*new_edges.lock().unwrap(), [self_loop_index]);
+
+        // Reported again in a new run
+        handler.fuzzing_bitmap.fill(0);
+        handler.at_decode_begin().unwrap();
+        handler
+            .on_new_block(0x10, ControlFlowTransitionKind::NewBlock, false)
+            .unwrap();
+        handler
+            .on_new_block(0x10, ControlFlowTransitionKind::ConditionalBranch, false)
+            .unwrap();
+        assert_eq!(
+            *new_edges.lock().unwrap(),
+            [self_loop_index, self_loop_index]
+        );
+    }
+
+    #[test]
+    fn test_multiple_filter_ranges() {
+        let mut handler = FuzzBitmapControlFlowHandler::new(
+            vec![0u8; 0x10000],
+            Some(&[(0x3000, 0x3FFF), (0x1000, 0x1FFF)]),
+        );
+        handler.at_decode_begin().unwrap();
+        for (block_addr, in_range) in [
+            (0x1800, true),
+            (0x2800, false),
+            (0x3000, true),
+            (0x4000, false),
+            (0x0FFF, false),
+            (0x1FFF, true),
+            (0x3FFF, true),
+        ] {
+            assert_eq!(
+                handler.is_addr_in_filter_range(block_addr),
+                in_range,
+                "{block_addr:#x}"
+            );
+            let block_count = handler.block_count();
+            handler
+                .on_new_block(block_addr, ControlFlowTransitionKind::DirectJump, false)
+                .unwrap();
+            assert_eq!(handler.block_count(), block_count + u64::from(in_range));
+            // Blocks out of range reset the previous location
+            assert_eq!(handler.prev_loc, if in_range { block_addr } else { 0 });
+        }
+        assert_eq!(
+            handler
+                .bitmap()
+                .iter()
+                .map(|count| *count as u64)
+                .sum::<u64>(),
+            4
+        );
+
+        // Overlapping and adjacent ranges are merged, and empty ranges are dropped
+        assert_eq!(
+            &*normalize_ranges(&[
+                (0x20, 0x2F),
+                (0x10, 0x1F),
+                (0x18, 0x24),
+                (0x40, 0x3F),
+                (0x50, 0x5F)
+            ]),
+            [(0x10, 0x2F), (0x50, 0x5F)]
+        );
+        let handler = FuzzBitmapControlFlowHandler::new(vec![0u8; 0x10], Some(&[]));
+        assert!(!handler.is_addr_in_filter_range(0));
+    }
+
+    #[test]
+    fn test_classify_counts() {
+        let mut bitmap = [0, 1, 2, 3, 4, 7, 8, 15, 16, 31, 32, 127, 128, 255];
+        let mut handler = FuzzBitmapControlFlowHandler::new(bitmap.as_mut_slice(), None);
+        handler.classify_counts();
+        assert_eq!(bitmap, [0, 1, 2, 4, 8, 8, 16, 16, 32, 32, 64, 64, 128, 128]);
+    }
+
+    #[test]
+    fn test_edge_hash_policy() {
+        for (policy, expected_index) in [
+            (EdgeHashPolicy::Afl, 0x0000_5000 ^ 0x0000_3000),
+            (EdgeHashPolicy::NoShift, 0x0000_A000 ^ 0x0000_3000),
+            (EdgeHashPolicy::Multiplicative, 0x8FBB),
+        ] {
+            let mut bitmap = vec![0u8; 0x10000];
+            let mut handler = FuzzBitmapControlFlowHandler::new(bitmap.as_mut_slice(), None);
+            handler.edge_hash_policy(policy);
+            handler
+                .on_new_block(0xA000, ControlFlowTransitionKind::NewBlock, false)
+                .unwrap();
+            handler
+                .on_new_block(0x3000, ControlFlowTransitionKind::ConditionalBranch, false)
+                .unwrap();
+            assert_eq!(
+                bitmap.iter().position(|count| *count != 0),
+                Some(expected_index),
+                "{policy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_masked_bitmap_index() {
+        let mut bitmap = vec![0u8; 0x10000];
+        let handler = FuzzBitmapControlFlowHandler::new(bitmap.as_mut_slice(), None);
+        assert_eq!(handler.bitmap_index_mask, Some(0xFFFF));
+        let mut hash = 0x1234_5678_9ABC_DEF0u64;
+        for _ in 0..0x1000 {
+            assert_eq!(handler.bitmap_index(hash) as u64, hash % 0x10000);
+            hash = hash.wrapping_mul(0x9E37_79B9_7F4A_7C15).wrapping_add(1);
+        }
+
+        // Non-power-of-two sizes fall back to modulo
+        let mut bitmap = vec![0u8; 0xFFFF];
+        let handler = FuzzBitmapControlFlowHandler::new(bitmap.as_mut_slice(), None);
+        assert_eq!(handler.bitmap_index_mask, None);
+        assert_eq!(handler.bitmap_index(0xFFFF), 0);
+        assert_eq!(handler.bitmap_index(0x1_0000), 1);
+    }
+}
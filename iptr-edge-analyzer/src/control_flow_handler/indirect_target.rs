@@ -0,0 +1,136 @@
+//! This module contains a control flow handler recording targets of indirect branches.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{ControlFlowTransitionKind, HandleControlFlow};
+
+/// [`HandleControlFlow`] implementor for recording observed targets of indirect
+/// branches, which is useful for CFI and forward-edge analysis.
+///
+/// Only indirect jumps and indirect calls whose source basic block is known are
+/// recorded, while returns and far transfers are not, see
+/// [`on_indirect_edge`][HandleControlFlow::on_indirect_edge].
+#[derive(Default)]
+pub struct IndirectTargetHandler {
+    /// Key: address of basic block terminated by indirect branch,
+    /// Value: observed target addresses
+    targets: HashMap<u64, HashSet<u64>>,
+}
+
+impl IndirectTargetHandler {
+    /// Create a new [`IndirectTargetHandler`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get observed targets, keyed by the address of basic block terminated by
+    /// indirect branch
+    #[must_use]
+    pub fn targets(&self) -> &HashMap<u64, HashSet<u64>> {
+        &self.targets
+    }
+
+    /// Consume the handler and get observed targets
+    #[must_use]
+    pub fn into_targets(self) -> HashMap<u64, HashSet<u64>> {
+        self.targets
+    }
+}
+
+impl HandleControlFlow for IndirectTargetHandler {
+    type Error = std::convert::Infallible;
+    // Indirect edges are never folded into cache
+    #[cfg(feature = "cache")]
+    type CachedKey = ();
+    const NEEDS_INDIRECT_SOURCE: bool = true;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[inline]
+    fn on_new_block(
+        &mut self,
+        _block_addr: u64,
+        _transition_kind: ControlFlowTransitionKind,
+        _cache: bool,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_indirect_edge(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        self.targets.entry(src_block).or_default().insert(dst_block);
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    fn cache_prev_cached_key(&mut self, _cached_key: Self::CachedKey) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    fn take_cache(&mut self) -> Result<Option<Self::CachedKey>, Self::Error> {
+        Ok(None)
+    }
+
+    #[cfg(feature = "cache")]
+    fn clear_current_cache(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    fn on_reused_cache(
+        &mut self,
+        _cached_key: &Self::CachedKey,
+        _new_bb: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iptr_decoder::DecodeOptions;
+
+    use super::*;
+    use crate::{
+        EdgeAnalyzer,
+        test_utils::{PtBuilder, SliceMemoryReader},
+    };
+
+    #[test]
+    fn test_indirect_call_targets() {
+        /// ```plaintext
+        /// 0x1000: call rax
+        /// 0x1002: jmp 0x1000
+        /// ```
+        const CALLER_CODE: [u8; 4] = [0xFF, 0xD0, 0xEB, 0xFC];
+        /// `ret`
+        const CALLEE_CODE: [u8; 1] = [0xC3];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .tip(0x2000)
+            .tip(0x1002)
+            .tip(0x3000)
+            .tip(0x1002)
+            .build();
+        let reader = SliceMemoryReader::new(&[
+            (0x1000, &CALLER_CODE),
+            (0x2000, &CALLEE_CODE),
+            (0x3000, &CALLEE_CODE),
+        ]);
+        let mut analyzer = EdgeAnalyzer::new(IndirectTargetHandler::new(), reader);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        // Returns from callees are not forward edges
+        let targets = analyzer.handler().targets();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[&0x1000], HashSet::from([0x2000, 0x3000]));
+        assert!(!targets.contains_key(&0x2000));
+        assert!(!targets.contains_key(&0x3000));
+    }
+}
@@ -9,6 +9,7 @@ pub struct LogControlFlowHandler {}
 impl HandleControlFlow for LogControlFlowHandler {
     // Log does not produce high-level errors
     type Error = std::convert::Infallible;
+    const NEEDS_INDIRECT_SOURCE: bool = true;
 
     fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
         Ok(())
@@ -23,4 +24,45 @@ impl HandleControlFlow for LogControlFlowHandler {
         log::trace!("Block {block_addr:#x} encountered via {transition_kind}");
         Ok(())
     }
+
+    fn on_async_event(&mut self, block_addr: u64, event_ip: u64) -> Result<(), Self::Error> {
+        log::trace!("Asynchronous event at {event_ip:#x} in block {block_addr:#x}");
+        Ok(())
+    }
+
+    fn on_psb_region(&mut self, psb_index: usize) -> Result<(), Self::Error> {
+        log::trace!("PSB region #{psb_index}");
+        Ok(())
+    }
+
+    fn on_indirect_edge(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        log::trace!("Indirect edge {src_block:#x} -> {dst_block:#x}");
+        Ok(())
+    }
+
+    fn on_missing_endbr(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        log::trace!("Indirect branch target {dst_block:#x} without ENDBR in block {src_block:#x}");
+        Ok(())
+    }
+
+    fn on_call(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        log::trace!("Call {src_block:#x} -> {dst_block:#x}");
+        Ok(())
+    }
+
+    fn on_return(
+        &mut self,
+        ret_block: u64,
+        expected: Option<u64>,
+        actual: u64,
+    ) -> Result<(), Self::Error> {
+        match expected {
+            Some(expected) if expected != actual => log::trace!(
+                "Mismatched return in block {ret_block:#x}: expected {expected:#x}, actual {actual:#x}"
+            ),
+            Some(_) => {}
+            None => log::trace!("Return without call in block {ret_block:#x} to {actual:#x}"),
+        }
+        Ok(())
+    }
 }
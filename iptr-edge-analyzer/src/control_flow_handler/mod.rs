@@ -9,14 +9,22 @@
 
 use derive_more::Display;
 
+#[cfg(not(feature = "cache"))]
+use crate::BlockInstruction;
+
+#[cfg(not(feature = "cache"))]
+pub mod call_graph;
 pub mod combined;
 #[cfg(feature = "fuzz_bitmap")]
 pub mod fuzz_bitmap;
+pub mod indirect_target;
 #[cfg(all(not(feature = "cache"), feature = "log_control_flow_handler"))]
 pub mod log;
+#[cfg(not(feature = "cache"))]
+pub mod return_validator;
 
 /// Kind of control flow transitions
-#[derive(Debug, Display, Clone, Copy)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
 pub enum ControlFlowTransitionKind {
     /// Conditional Jcc
     ConditionalBranch,
@@ -26,6 +34,9 @@ pub enum ControlFlowTransitionKind {
     DirectCall,
     /// Indirect transition, including `RET`.
     Indirect,
+    /// Far transfer, e.g., `SYSCALL` and `INT n`.
+    #[display("FarTransfer({_0})")]
+    FarTransfer(FarTransferKind),
     /// New block
     ///
     /// Basic blocks that cannot be categorized into
@@ -33,6 +44,38 @@ pub enum ControlFlowTransitionKind {
     NewBlock,
 }
 
+/// Kind of far transfer instructions, which change control flow other than
+/// near JMP, CALL and RET
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FarTransferKind {
+    /// `SYSCALL`
+    Syscall,
+    /// `SYSENTER`
+    Sysenter,
+    /// `SYSRET` or `SYSEXIT`
+    SystemReturn,
+    /// `INT n`, `INT1`, `INT3` or `INTO`, with the interrupt vector
+    #[display("Interrupt({_0:#x})")]
+    Interrupt(u8),
+    /// `IRET`
+    InterruptReturn,
+    /// Far `CALL`
+    FarCall,
+    /// Far `JMP`
+    FarJump,
+    /// Far `RET`
+    FarReturn,
+    /// Instructions that always raise exceptions, e.g., `UD2`
+    Exception,
+    /// Basic block that cannot be decoded, which is treated as a far transfer
+    /// since [`skip_undecodable_blocks`][crate::EdgeAnalyzer::skip_undecodable_blocks]
+    /// is set
+    Undecodable,
+    /// Other instructions that change control flow
+    Other,
+}
+
 /// Control flow handler used for [`EdgeAnalyzer`][crate::EdgeAnalyzer]
 ///
 /// There are several implementors provided in this crate, such as
@@ -78,6 +121,23 @@ pub trait HandleControlFlow {
     /// of re-parsing all TNT bits.
     #[cfg(feature = "cache")]
     type CachedKey: Clone;
+    /// Whether the handler needs the source basic blocks of indirect transitions.
+    ///
+    /// If a TIP packet is not preceded by TNT bits, the source of the indirect branch
+    /// is found by following direct JMPs and CALLs from the previous basic block,
+    /// which requires resolving CFG nodes and reading memory. The followed
+    /// transitions are reported by [`on_edge`][HandleControlFlow::on_edge] as the
+    /// same as those followed after TNT bits.
+    ///
+    /// This should be `true` if [`on_edge`][HandleControlFlow::on_edge],
+    /// [`on_indirect_edge`][HandleControlFlow::on_indirect_edge],
+    /// [`on_call`][HandleControlFlow::on_call] or [`on_return`][HandleControlFlow::on_return]
+    /// is overridden. If this is `false` and the source is not needed by the edge
+    /// analyzer itself (e.g., [`check_endbr`][crate::EdgeAnalyzer::check_endbr]),
+    /// the direct transitions are not followed, and the target is only reported by
+    /// [`on_new_block`][HandleControlFlow::on_new_block] with
+    /// [`ControlFlowTransitionKind::Indirect`]. Default is `false`.
+    const NEEDS_INDIRECT_SOURCE: bool = false;
 
     /// Callback at begin of decoding.
     ///
@@ -111,6 +171,164 @@ pub trait HandleControlFlow {
         cache: bool,
     ) -> Result<(), Self::Error>;
 
+    /// Callback when a control flow transition from a known basic block is met.
+    ///
+    /// `from` is the address of the basic block where the transition starts, and
+    /// `to`, `transition_kind` and `cache` are the same as
+    /// [`on_new_block`][HandleControlFlow::on_new_block]. Handlers that need real
+    /// edges instead of blocks can override this method. For transitions
+    /// without a known source (e.g., [`ControlFlowTransitionKind::NewBlock`]),
+    /// only [`on_new_block`][HandleControlFlow::on_new_block] is invoked.
+    ///
+    /// In cache mode, this function is only invoked for non-cached transitions,
+    /// just like [`on_new_block`][HandleControlFlow::on_new_block]. Transitions
+    /// folded into cache are NOT reported again by this function when the cache
+    /// is reused; only [`on_reused_cache`][HandleControlFlow::on_reused_cache] is
+    /// invoked, so handlers that need every edge should fold the edges into their
+    /// cached keys. The source of the next reported transition after reusing a
+    /// cache is the `new_bb` given there.
+    ///
+    /// By default this invokes [`on_new_block`][HandleControlFlow::on_new_block]
+    /// with `to`.
+    #[inline]
+    fn on_edge(
+        &mut self,
+        from: u64,
+        to: u64,
+        transition_kind: ControlFlowTransitionKind,
+        cache: bool,
+    ) -> Result<(), Self::Error> {
+        let _ = from;
+        self.on_new_block(to, transition_kind, cache)
+    }
+
+    /// Callback when an asynchronous event (e.g., interrupt or exception) is met,
+    /// i.e., a FUP packet is bound by a subsequent TIP packet.
+    ///
+    /// `block_addr` is the address of the basic block in which the event occurs, and
+    /// `event_ip` is the address of the instruction at which the event occurs. The
+    /// resumption address will then be reported by [`on_new_block`][HandleControlFlow::on_new_block]
+    /// with [`ControlFlowTransitionKind::NewBlock`].
+    ///
+    /// This is not invoked if the basic block of the event is unknown. By default this is a nop.
+    #[expect(unused)]
+    fn on_async_event(&mut self, block_addr: u64, event_ip: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when a PSB packet is met, which starts a new PSB region.
+    ///
+    /// `psb_index` is the zero-based index of the PSB packet in the decoded
+    /// buffer, see [`DecoderContext::psb_index`][iptr_decoder::DecoderContext::psb_index].
+    /// All basic blocks and edges reported after this belong to this PSB region
+    /// until the next invocation, which can be used to tag them, e.g., for
+    /// replaying only the region between two PSB packets. Pending TNT bits are
+    /// dropped at PSB packets, so no control flow crosses PSB regions.
+    #[expect(unused)]
+    fn on_psb_region(&mut self, psb_index: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when an indirect jump or indirect call from a known basic block is met.
+    ///
+    /// `src_block` is the address of the basic block terminated by the indirect
+    /// branch, and `dst_block` is the branch target. Only forward edges are reported,
+    /// i.e., `RET` (see [`on_return`][HandleControlFlow::on_return]) and far transfers
+    /// are not. Note that `src_block` may not have been reported by
+    /// [`on_new_block`][HandleControlFlow::on_new_block] if it is reached by direct
+    /// transitions from the previous indirect branch target. This is invoked before
+    /// [`on_new_block`][HandleControlFlow::on_new_block] with
+    /// [`ControlFlowTransitionKind::Indirect`] for `dst_block`, and is never folded
+    /// into cache.
+    ///
+    /// This is not invoked if the source basic block is unknown. By default this is a nop.
+    #[expect(unused)]
+    fn on_indirect_edge(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when the target of an indirect jump or indirect call does not
+    /// start with `ENDBR64`/`ENDBR32`, which indicates a potential control-flow
+    /// hijack or trace corruption under Indirect Branch Tracking of Intel CET.
+    ///
+    /// `src_block` is the address of the basic block terminated by the indirect
+    /// branch, and `dst_block` is the branch target. This is only invoked if
+    /// [`check_endbr`][crate::EdgeAnalyzer::check_endbr] is set, before
+    /// [`on_indirect_edge`][HandleControlFlow::on_indirect_edge]. By default this is a nop.
+    #[expect(unused)]
+    fn on_missing_endbr(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when a `CALL` (either direct or indirect) from a known basic block
+    /// is met.
+    ///
+    /// `src_block` is the address of the basic block terminated by `CALL` (the
+    /// caller block), and `dst_block` is the call target (the callee block). This is invoked whenever the return address
+    /// is pushed into the callstack, including direct calls that are followed when
+    /// looking for the source of an indirect branch, see
+    /// [`NEEDS_INDIRECT_SOURCE`][HandleControlFlow::NEEDS_INDIRECT_SOURCE].
+    ///
+    /// The callstack is only maintained in non-cache mode, so this is never
+    /// invoked in cache mode. By default this is a nop.
+    #[expect(unused)]
+    fn on_call(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when a `RET` from a known basic block is met.
+    ///
+    /// `ret_block` is the address of the basic block terminated by `RET`, `expected` is
+    /// the return address pushed by the corresponding CALL, and `actual` is the
+    /// return target given by the TIP packet. `expected` is [`None`] if the
+    /// corresponding CALL is not traced, e.g., it happens before tracing is
+    /// enabled or is lost due to OVF. This is invoked after
+    /// [`on_indirect_edge`][HandleControlFlow::on_indirect_edge].
+    ///
+    /// The callstack is only maintained in non-cache mode, so this is never
+    /// invoked in cache mode. Compressed returns are not supported by the
+    /// [`EdgeAnalyzer`][crate::EdgeAnalyzer]. By default this is a nop.
+    #[expect(unused)]
+    fn on_return(
+        &mut self,
+        ret_block: u64,
+        expected: Option<u64>,
+        actual: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when a basic block is executed, with all its instructions.
+    ///
+    /// `instructions` are the instructions of basic block at `block_addr` in
+    /// order, including the terminator, so that concatenating the instructions of
+    /// all invocations reconstructs the executed instruction stream. This is only
+    /// invoked if [`record_instructions`][crate::EdgeAnalyzer::record_instructions]
+    /// is set, right after the basic block is reported by
+    /// [`on_new_block`][HandleControlFlow::on_new_block] or
+    /// [`on_edge`][HandleControlFlow::on_edge], and also for basic blocks reached
+    /// by direct transitions that are only followed when looking for the source of
+    /// an indirect branch. Note that if an asynchronous event occurs in the middle
+    /// of a basic block, the instructions after the event are still included.
+    ///
+    /// `undecodable` is set if the basic block cannot be fully decoded (see
+    /// [`skip_undecodable_blocks`][crate::EdgeAnalyzer::skip_undecodable_blocks]),
+    /// in which case `instructions` only contain the instructions before the
+    /// undecodable one.
+    ///
+    /// The executed basic blocks are only reported in non-cache mode, so this is
+    /// only available in non-cache mode. By default this is a nop.
+    #[cfg(not(feature = "cache"))]
+    #[expect(unused)]
+    fn on_block_instructions(
+        &mut self,
+        block_addr: u64,
+        instructions: &[BlockInstruction],
+        undecodable: bool,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Merge a previous cached key into cache
     ///
     /// When analyzing TNT packets, the cache manager maintains two kinds of cache: 8bits cache
@@ -151,6 +369,20 @@ pub trait HandleControlFlow {
         new_bb: u64,
     ) -> Result<(), Self::Error>;
 
+    /// Check whether reusing `cached_key` and `other` have the same impact.
+    ///
+    /// This is used by the `cache_self_check` feature, where each hit cache is
+    /// compared with the cache generated by processing the same TNT bits without
+    /// cache again.
+    ///
+    /// The default implementation treats any two cached keys as the same, so
+    /// only divergence of the new basic block is detected.
+    #[cfg(feature = "cache_self_check")]
+    #[expect(unused)]
+    fn is_same_cache(&self, cached_key: &Self::CachedKey, other: &Self::CachedKey) -> bool {
+        true
+    }
+
     /// Callback to check whether all caches in
     /// [`EdgeAnalyzer`][crate::EdgeAnalyzer] should be cleared.
     ///
@@ -161,7 +393,16 @@ pub trait HandleControlFlow {
     /// should clear its own caches as well. All previous cached keys
     /// are guaranteed to be invalid.
     ///
-    /// This function will be invoked at decode begin.
+    /// Apart from memory pressure, a handler may also want to request
+    /// this when the cached TNT sequences no longer describe the traced
+    /// program, for example when the tracee has self-modifying code, or
+    /// when the handler knows that the address space has been switched
+    /// (CR3 change).
+    ///
+    /// This function will be invoked at decode begin and at each PSB packet.
+    /// The default implementation never requests clearing.
     #[cfg(feature = "cache")]
-    fn should_clear_all_cache(&mut self) -> Result<bool, Self::Error>;
+    fn should_clear_all_cache(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
 }
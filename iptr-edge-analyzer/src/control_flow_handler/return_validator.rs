@@ -0,0 +1,182 @@
+//! This module contains a control flow handler validating return targets
+//! against the callstack.
+
+use crate::{ControlFlowTransitionKind, HandleControlFlow};
+
+/// A `RET` whose target does not match the return address pushed by the
+/// corresponding CALL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReturnMismatch {
+    /// Address of basic block terminated by `RET`
+    pub ret_block: u64,
+    /// Return address pushed by the corresponding CALL
+    pub expected: u64,
+    /// Actual return target
+    pub actual: u64,
+}
+
+/// [`HandleControlFlow`] implementor for detecting returns whose targets do not
+/// match the callstack, which is useful for detecting stack pivoting and
+/// ROP-style control flow.
+///
+/// Returns are reported by [`on_return`][HandleControlFlow::on_return], which
+/// is only invoked in non-cache mode, so this handler is not available with the
+/// `cache` feature.
+#[derive(Default)]
+pub struct ReturnValidatorHandler {
+    /// Whether to record every mismatch site
+    record_mismatches: bool,
+    /// Number of mismatched returns
+    mismatch_count: usize,
+    /// Number of returns whose corresponding CALL is not traced
+    unknown_return_count: usize,
+    /// Recorded mismatches, only filled if `record_mismatches` is set
+    mismatches: Vec<ReturnMismatch>,
+}
+
+impl ReturnValidatorHandler {
+    /// Create a new [`ReturnValidatorHandler`] that only counts mismatches
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to record every mismatch site, which can be retrieved by
+    /// [`mismatches`][Self::mismatches]. Default is `false`.
+    pub fn record_mismatches(&mut self, record: bool) -> &mut Self {
+        self.record_mismatches = record;
+        self
+    }
+
+    /// Get the number of returns whose target does not match the callstack
+    #[must_use]
+    pub fn mismatch_count(&self) -> usize {
+        self.mismatch_count
+    }
+
+    /// Get the number of returns whose corresponding CALL is not traced, e.g.,
+    /// returning from the function where tracing is enabled
+    #[must_use]
+    pub fn unknown_return_count(&self) -> usize {
+        self.unknown_return_count
+    }
+
+    /// Get recorded mismatch sites.
+    ///
+    /// This is always empty unless [`record_mismatches`][Self::record_mismatches]
+    /// is set.
+    #[must_use]
+    pub fn mismatches(&self) -> &[ReturnMismatch] {
+        &self.mismatches
+    }
+}
+
+impl HandleControlFlow for ReturnValidatorHandler {
+    type Error = std::convert::Infallible;
+    const NEEDS_INDIRECT_SOURCE: bool = true;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.mismatch_count = 0;
+        self.unknown_return_count = 0;
+        self.mismatches.clear();
+        Ok(())
+    }
+
+    #[inline]
+    fn on_new_block(
+        &mut self,
+        _block_addr: u64,
+        _transition_kind: ControlFlowTransitionKind,
+        _cache: bool,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn on_return(
+        &mut self,
+        ret_block: u64,
+        expected: Option<u64>,
+        actual: u64,
+    ) -> Result<(), Self::Error> {
+        let Some(expected) = expected else {
+            self.unknown_return_count += 1;
+            return Ok(());
+        };
+        if expected == actual {
+            return Ok(());
+        }
+        self.mismatch_count += 1;
+        if self.record_mismatches {
+            self.mismatches.push(ReturnMismatch {
+                ret_block,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iptr_decoder::DecodeOptions;
+
+    use super::*;
+    use crate::{
+        EdgeAnalyzer,
+        test_utils::{PtBuilder, SliceMemoryReader},
+    };
+
+    #[test]
+    fn test_pivoted_return() {
+        /// ```plaintext
+        /// 0x1000: call 0x1010
+        /// 0x1005: jmp rax
+        /// 0x1010: ret
+        /// ```
+        const CODE: [u8; 17] = [
+            0xE8, 0x0B, 0x00, 0x00, 0x00, 0xFF, 0xE0, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+            0xCC, 0xCC, 0xC3,
+        ];
+        /// `ret`
+        const GADGET_CODE: [u8; 1] = [0xC3];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            // Normal return
+            .tip(0x1005)
+            .tip(0x1000)
+            // Pivoted return
+            .tip(0x2000)
+            // Return without call
+            .tip(0x3000)
+            .build();
+        let reader = SliceMemoryReader::new(&[(0x1000, &CODE), (0x2000, &GADGET_CODE)]);
+        let mut handler = ReturnValidatorHandler::new();
+        handler.record_mismatches(true);
+        let mut analyzer = EdgeAnalyzer::new(handler, reader);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        let handler = analyzer.handler();
+        assert_eq!(handler.mismatch_count(), 1);
+        assert_eq!(handler.unknown_return_count(), 1);
+        assert_eq!(
+            handler.mismatches(),
+            [ReturnMismatch {
+                ret_block: 0x1010,
+                expected: 0x1005,
+                actual: 0x2000,
+            }]
+        );
+
+        // No return address is kept without callstack
+        let (_, reader) = analyzer.into_handler_and_reader();
+        let mut analyzer = EdgeAnalyzer::new(ReturnValidatorHandler::new(), reader);
+        analyzer.max_callstack_depth(0);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.handler().mismatch_count(), 0);
+        assert_eq!(analyzer.handler().unknown_return_count(), 3);
+    }
+}
@@ -8,6 +8,23 @@ use crate::{EdgeAnalyzer, HandleControlFlow, ReadMemory};
 pub struct DiagnosticInformation {
     /// Size of CFG graph, i.e., number of nodes
     pub cfg_size: usize,
+    /// Count of OVF packets, i.e., how many times the trace data is lost
+    pub ovf_count: usize,
+    /// Count of pending TNT bits discarded when recovering from OVF
+    pub ovf_discarded_tnt_bit_count: usize,
+    /// Count of basic blocks that cannot be decoded and are treated as far
+    /// transfers, see [`EdgeAnalyzer::skip_undecodable_blocks`]
+    pub undecodable_block_count: usize,
+    /// Count of indirect branch targets not starting with `ENDBR64`/`ENDBR32`,
+    /// see [`EdgeAnalyzer::check_endbr`]
+    pub missing_endbr_count: usize,
+    /// Count of deferred TIPs, i.e., how many times the remaining TNT bits
+    /// are re-injected to wait for a TIP packet
+    #[cfg(feature = "more_diagnose")]
+    pub deferred_tip_count: usize,
+    /// Maximum number of pending TNT bits observed
+    #[cfg(feature = "more_diagnose")]
+    pub max_pending_tnt_bits: u32,
     /// Size of trailing bits cache, i.e., number of entries
     #[cfg(feature = "cache")]
     pub cache_trailing_bits_size: usize,
@@ -31,6 +48,35 @@ pub struct DiagnosticInformation {
     pub cache_missed_bit_count: usize,
 }
 
+/// An entry of control flow cache, see [`EdgeAnalyzer::dump_cache`]
+#[cfg(all(feature = "cache", feature = "more_diagnose"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CacheEntry {
+    /// Address of basic block where the TNT bits start
+    pub start_bb: u64,
+    /// The cached TNT bits in the lower [`bit_count`][Self::bit_count] bits,
+    /// where the oldest bit is the most significant one
+    pub tnt_bits: u32,
+    /// Number of cached TNT bits
+    pub bit_count: u32,
+    /// Address of basic block after processing the TNT bits
+    pub new_bb: u64,
+}
+
+/// All entries of control flow caches, see [`EdgeAnalyzer::dump_cache`].
+///
+/// Entries of each cache tier are sorted.
+#[cfg(all(feature = "cache", feature = "more_diagnose"))]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheDump {
+    /// Entries of trailing bits cache, each with 1 to 7 TNT bits
+    pub trailing_bits: Vec<CacheEntry>,
+    /// Entries of 8bit cache
+    pub cache8: Vec<CacheEntry>,
+    /// Entries of 32bit cache
+    pub cache32: Vec<CacheEntry>,
+}
+
 impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
     /// Get diagnostic information
     #[must_use]
@@ -41,6 +87,14 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
 
         DiagnosticInformation {
             cfg_size,
+            ovf_count: self.ovf_count,
+            missing_endbr_count: self.missing_endbr_count,
+            ovf_discarded_tnt_bit_count: self.ovf_discarded_tnt_bit_count,
+            undecodable_block_count: self.static_analyzer.undecodable_block_count(),
+            #[cfg(feature = "more_diagnose")]
+            deferred_tip_count: self.deferred_tip_count,
+            #[cfg(feature = "more_diagnose")]
+            max_pending_tnt_bits: self.max_pending_tnt_bits,
             #[cfg(feature = "cache")]
             cache_trailing_bits_size,
             #[cfg(feature = "cache")]
@@ -57,4 +111,23 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
             cache_missed_bit_count: self.cache_missed_bit_count,
         }
     }
+
+    /// Dump all entries of the control flow caches.
+    ///
+    /// This is useful for debugging the caches, e.g., comparing the dumped
+    /// entries with the edges reported when the `cache` feature is disabled, to
+    /// find out which cache entry causes a divergence.
+    #[cfg(all(feature = "cache", feature = "more_diagnose"))]
+    #[must_use]
+    pub fn dump_cache(&self) -> CacheDump {
+        self.cache_manager.dump()
+    }
+
+    /// Update the maximum number of pending TNT bits with current pending bits
+    #[cfg(feature = "more_diagnose")]
+    pub(crate) fn update_max_pending_tnt_bits(&mut self) {
+        self.max_pending_tnt_bits = self
+            .max_pending_tnt_bits
+            .max(self.tnt_buffer_manager.pending_bits());
+    }
 }
@@ -17,8 +17,40 @@ pub enum AnalyzerError<H: HandleControlFlow, R: ReadMemory> {
     #[error("Memory reader error")]
     MemoryReader(#[source] R::Error),
     /// Instructions non-decodable by iced-x86
-    #[error("Invalid instruction")]
-    InvalidInstruction,
+    #[error("Invalid instruction at {insn_addr:#x}")]
+    InvalidInstruction {
+        /// Address of the instruction that cannot be decoded
+        insn_addr: u64,
+    },
+    /// Basic block in unmapped memory, which is likely caused by trace
+    /// corruption (e.g., an indirect branch to a bogus target)
+    #[error("Basic block at unmapped address {address:#x}")]
+    UnmappedAddress {
+        /// Address of the basic block
+        address: u64,
+    },
+    /// Basic block starting exactly at the end of a mapped memory region, so
+    /// that there is no instruction to decode
+    #[error("Basic block at the end of memory region {address:#x}")]
+    BlockAtRegionEnd {
+        /// Address of the basic block
+        address: u64,
+    },
+    /// Basic block not in the pre-populated CFG graph, see
+    /// [`EdgeAnalyzer::prepopulated_cfg_only`][crate::EdgeAnalyzer::prepopulated_cfg_only]
+    #[error("Basic block {address:#x} is not in the pre-populated CFG graph")]
+    BlockNotInPrepopulatedCfg {
+        /// Address of the basic block
+        address: u64,
+    },
+    /// Non-canonical IP reconstructed in 64-bit mode, which is likely caused by
+    /// trace corruption or an IP reconstruction bug, see
+    /// [`EdgeAnalyzer::check_canonical_address`][crate::EdgeAnalyzer::check_canonical_address]
+    #[error("Non-canonical address {address:#x}")]
+    NonCanonicalAddress {
+        /// The reconstructed IP
+        address: u64,
+    },
     /// Corrupted callstack, will affect the behavior
     /// of return compression
     #[error("The self-maintained callstack is corrupted")]
@@ -7,19 +7,36 @@ pub mod control_flow_handler;
 mod diagnose;
 pub mod error;
 pub mod memory_reader;
+#[cfg(feature = "perf_memory_reader")]
+pub mod perf;
 mod static_analyzer;
+#[cfg(test)]
+mod test_utils;
 mod tnt_buffer;
 
+#[cfg(not(feature = "cache"))]
+use std::collections::VecDeque;
 use std::num::NonZero;
 
-use iptr_decoder::{DecoderContext, HandlePacket, IpReconstructionPattern};
+use hashbrown::HashSet;
+use iptr_decoder::{DecoderContext, HandlePacket, IpReconstructionPattern, TraceeMode};
 
 #[cfg(feature = "cache")]
 use crate::control_flow_cache::ControlFlowCacheManager;
+#[cfg(all(feature = "cache", feature = "more_diagnose"))]
+pub use crate::diagnose::{CacheDump, CacheEntry};
+#[cfg(feature = "perf_memory_reader")]
+pub use crate::perf::{PerfAnalyzeError, PerfAnalyzeOptions, analyze_perf_data};
+#[cfg(feature = "serde")]
+pub use crate::static_analyzer::CfgSnapshot;
 pub use crate::{
-    control_flow_handler::{ControlFlowTransitionKind, HandleControlFlow},
+    control_flow_handler::{ControlFlowTransitionKind, FarTransferKind, HandleControlFlow},
     diagnose::DiagnosticInformation,
     memory_reader::ReadMemory,
+    static_analyzer::{
+        BlockInstruction, CfgMetrics, CfgNode, CfgTerminator, CrossReadPolicy, MIN_BLOCK_READ_SIZE,
+        branch_false_target,
+    },
 };
 use crate::{
     error::{AnalyzerError, AnalyzerResult},
@@ -57,16 +74,54 @@ enum PreTipStatus {
     /// There is a FUP packet before this packet. So there must be
     /// a TIP or TIP.PGD packet.
     PendingFup,
+    /// There is a FUP packet before this packet, which
+    /// binds to the instruction where an asynchronous event occurs.
+    ///
+    /// If followed by a TIP packet, it is an asynchronous event, and the
+    /// TIP packet is the resumption address. Otherwise, this is the
+    /// same as [`PendingFup`][PreTipStatus::PendingFup].
+    PendingAsyncEvent {
+        /// Address of the instruction where event occurs
+        event_ip: u64,
+    },
     /// There is an OVF packet before this packet. So there must be
     /// a FUP, TIP or TIP.PGE packet.
+    ///
+    /// The OVF may be followed by PSB+ before the binding FUP or TIP.PGE,
+    /// so this status is kept across PSB packets.
     PendingOvf,
 }
 
+/// Decoding states of an [`EdgeAnalyzer`] saved by
+/// [`resume_context`][EdgeAnalyzer::resume_context], which can be used to resume
+/// decoding a trace split into multiple buffers.
+///
+/// See [`resume_with`][EdgeAnalyzer::resume_with] for details.
+#[derive(Clone)]
+pub struct ResumeContext {
+    /// Saved [`EdgeAnalyzer::last_ip`]
+    last_ip: u64,
+    /// Saved [`EdgeAnalyzer::last_bb`]
+    last_bb: Option<NonZero<u64>>,
+    /// Saved [`EdgeAnalyzer::pre_tip_status`]
+    pre_tip_status: PreTipStatus,
+    /// Saved [`EdgeAnalyzer::tnt_buffer_manager`]
+    tnt_buffer_manager: TntBufferManager,
+    /// Saved [`EdgeAnalyzer::tnt_tracee_mode`]
+    tnt_tracee_mode: TraceeMode,
+    /// Saved [`EdgeAnalyzer::in_untraced_region`]
+    in_untraced_region: bool,
+    /// Saved [`EdgeAnalyzer::callstack`]
+    #[cfg(not(feature = "cache"))]
+    callstack: VecDeque<u64>,
+}
+
 /// An edge analyzer that implements [`HandlePacket`] trait.
 ///
 /// The analyzer will trace the control flow during the Intel PT packets, and invoke
 /// corresponding callbacks in the given control flow handler that implements
 /// [`HandleControlFlow`].
+#[expect(clippy::struct_excessive_bools)]
 pub struct EdgeAnalyzer<H: HandleControlFlow, R: ReadMemory> {
     /// IP-reconstruction-specific field.
     ///
@@ -89,11 +144,62 @@ pub struct EdgeAnalyzer<H: HandleControlFlow, R: ReadMemory> {
     pre_tip_status: PreTipStatus,
     /// Buffering the TNT bits for better cache.
     tnt_buffer_manager: TntBufferManager,
+    /// Tracee mode of the last TNT packet, used to process the
+    /// pending TNT bits in [`flush`][Self::flush].
+    tnt_tracee_mode: TraceeMode,
     /// Caches used to speed up TNT bits resolution without querying the CFG.
     #[cfg(feature = "cache")]
     cache_manager: ControlFlowCacheManager<Option<H::CachedKey>>,
     /// CFG node maintainer
     static_analyzer: StaticControlFlowAnalyzer,
+    /// CR3 value of the last PIP packet.
+    ///
+    /// This is kept across decodings, since the CFG graph and caches are
+    /// kept as well.
+    last_cr3: Option<u64>,
+    /// VMCS pointer of the last VMCS packet.
+    ///
+    /// This is kept across decodings, same as [`last_cr3`][Self::last_cr3].
+    last_vmcs: Option<u64>,
+    /// Whether to clear the CFG graph and caches when CR3 or VMCS pointer changes
+    clear_cache_on_address_space_change: bool,
+    /// Whether to treat out-of-context TIP packets as trace gaps instead of errors
+    out_of_context_tip_as_gap: bool,
+    /// Whether to check indirect branch targets start with ENDBR64 or ENDBR32
+    check_endbr: bool,
+    /// Linear address width to check reconstructed IPs are canonical
+    /// for in 64-bit mode, `None` if not checking
+    canonical_address_width: Option<u8>,
+    /// Whether the control flow is in an untraced region entered by an
+    /// out-of-context TIP packet, where block tracking is suspended until
+    /// the next TIP.PGE packet.
+    in_untraced_region: bool,
+    /// Whether to clear the caches when tracee mode changes
+    #[cfg(feature = "cache")]
+    clear_cache_on_mode_change: bool,
+    /// Decoding states to be restored at the next [`reset`][Self::reset]
+    resume_context: Option<ResumeContext>,
+    /// Return addresses of CALLs that are not yet returned.
+    ///
+    /// This is only maintained in non-cache mode, since CALLs folded into
+    /// caches are not replayed.
+    #[cfg(not(feature = "cache"))]
+    callstack: VecDeque<u64>,
+    /// Maximum depth of [`callstack`][Self::callstack]
+    #[cfg(not(feature = "cache"))]
+    max_callstack_depth: usize,
+    /// Count of OVF packets
+    ovf_count: usize,
+    /// Count of TNT bits discarded due to OVF
+    ovf_discarded_tnt_bit_count: usize,
+    /// Count of indirect branch targets not starting with ENDBR64 or ENDBR32
+    missing_endbr_count: usize,
+    /// Diagnose-related metrics
+    #[cfg(feature = "more_diagnose")]
+    deferred_tip_count: usize,
+    /// Diagnose-related metrics
+    #[cfg(feature = "more_diagnose")]
+    max_pending_tnt_bits: u32,
     /// Diagnose-related metrics
     #[cfg(all(feature = "cache", feature = "more_diagnose"))]
     cache_trailing_bits_hit_count: usize,
@@ -121,9 +227,31 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
             last_bb: None,
             pre_tip_status: PreTipStatus::Normal,
             tnt_buffer_manager: TntBufferManager::new(),
+            tnt_tracee_mode: TraceeMode::Mode64,
             #[cfg(feature = "cache")]
             cache_manager: ControlFlowCacheManager::new(),
             static_analyzer: StaticControlFlowAnalyzer::new(),
+            last_cr3: None,
+            last_vmcs: None,
+            clear_cache_on_address_space_change: false,
+            out_of_context_tip_as_gap: false,
+            check_endbr: false,
+            canonical_address_width: None,
+            in_untraced_region: false,
+            #[cfg(feature = "cache")]
+            clear_cache_on_mode_change: false,
+            resume_context: None,
+            #[cfg(not(feature = "cache"))]
+            callstack: VecDeque::new(),
+            #[cfg(not(feature = "cache"))]
+            max_callstack_depth: 1024,
+            ovf_count: 0,
+            ovf_discarded_tnt_bit_count: 0,
+            missing_endbr_count: 0,
+            #[cfg(feature = "more_diagnose")]
+            deferred_tip_count: 0,
+            #[cfg(feature = "more_diagnose")]
+            max_pending_tnt_bits: 0,
             #[cfg(all(feature = "cache", feature = "more_diagnose"))]
             cache_32bit_hit_count: 0,
             #[cfg(all(feature = "cache", feature = "more_diagnose"))]
@@ -152,20 +280,499 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
         &self.reader
     }
 
+    /// Reset the decoding state, so that the analyzer can be reused for
+    /// another independent trace.
+    ///
+    /// This will also invoke [`at_decode_begin`][HandleControlFlow::at_decode_begin]
+    /// of the control flow handler and [`at_decode_begin`][ReadMemory::at_decode_begin]
+    /// of the memory reader. The CFG graph and caches are kept.
+    ///
+    /// This is automatically invoked by [`iptr_decoder::decode`]. You only need to
+    /// call this manually if the packets of independent traces are fed by other means.
+    ///
+    /// If a [`ResumeContext`] is given by [`resume_with`][Self::resume_with], the
+    /// decoding states are restored from it after resetting.
+    pub fn reset(&mut self) -> AnalyzerResult<(), H, R> {
+        self.last_ip = 0;
+        self.last_bb = None;
+        self.pre_tip_status = PreTipStatus::Normal;
+        self.tnt_buffer_manager.clear();
+        self.in_untraced_region = false;
+        #[cfg(not(feature = "cache"))]
+        self.callstack.clear();
+        self.handler
+            .at_decode_begin()
+            .map_err(AnalyzerError::ControlFlowHandler)?;
+        self.reader
+            .at_decode_begin()
+            .map_err(AnalyzerError::MemoryReader)?;
+        #[cfg(feature = "cache")]
+        self.clear_all_cache_if_needed()?;
+        self.ovf_count = 0;
+        self.ovf_discarded_tnt_bit_count = 0;
+        self.missing_endbr_count = 0;
+        #[cfg(feature = "more_diagnose")]
+        {
+            self.deferred_tip_count = 0;
+            self.max_pending_tnt_bits = 0;
+        }
+        #[cfg(all(feature = "cache", feature = "more_diagnose"))]
+        {
+            self.cache_32bit_hit_count = 0;
+            self.cache_8bit_hit_count = 0;
+            self.cache_trailing_bits_hit_count = 0;
+            self.cache_missed_bit_count = 0;
+        }
+        if let Some(resume_context) = self.resume_context.take() {
+            let ResumeContext {
+                last_ip,
+                last_bb,
+                pre_tip_status,
+                tnt_buffer_manager,
+                tnt_tracee_mode,
+                in_untraced_region,
+                #[cfg(not(feature = "cache"))]
+                callstack,
+            } = resume_context;
+            self.last_ip = last_ip;
+            self.last_bb = last_bb;
+            self.pre_tip_status = pre_tip_status;
+            self.tnt_buffer_manager = tnt_buffer_manager;
+            self.tnt_tracee_mode = tnt_tracee_mode;
+            self.in_untraced_region = in_untraced_region;
+            #[cfg(not(feature = "cache"))]
+            {
+                self.callstack = callstack;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save current decoding states, including the last IP used for IP
+    /// reconstruction, the last basic block and pending TNT bits.
+    ///
+    /// Use [`resume_with`][Self::resume_with] to restore the states.
+    #[must_use]
+    pub fn resume_context(&self) -> ResumeContext {
+        ResumeContext {
+            last_ip: self.last_ip,
+            last_bb: self.last_bb,
+            pre_tip_status: self.pre_tip_status,
+            tnt_buffer_manager: self.tnt_buffer_manager.clone(),
+            tnt_tracee_mode: self.tnt_tracee_mode,
+            in_untraced_region: self.in_untraced_region,
+            #[cfg(not(feature = "cache"))]
+            callstack: self.callstack.clone(),
+        }
+    }
+
+    /// Restore the decoding states from `resume_context` at the next
+    /// [`reset`][Self::reset], i.e., at the beginning of next decoding.
+    ///
+    /// This is useful when a trace is split into multiple buffers (e.g., AUXTRACE
+    /// records in `perf.data`) and they have to be decoded by separate calls of
+    /// [`iptr_decoder::decode`]: save the context after decoding a buffer, and
+    /// resume with it before decoding the next one, so that compressed IPs and pending
+    /// TNT bits right after the buffer boundary are handled as if the buffers were
+    /// decoded at once. The control flow handler and memory reader are still reset.
+    ///
+    /// This is only safe if the next buffer exactly continues the previous one, and
+    /// the next buffer is decoded without syncing to PSB (see
+    /// [`DecodeOptions::sync`][iptr_decoder::DecodeOptions::sync]) with the tracee mode
+    /// at the boundary. If a PSB packet is at the boundary, there is no need to resume,
+    /// since the last IP is reset by PSB.
+    pub fn resume_with(&mut self, resume_context: ResumeContext) -> &mut Self {
+        self.resume_context = Some(resume_context);
+        self
+    }
+
+    /// Get the number of TNT bits that are received but not yet processed.
+    ///
+    /// TNT bits are buffered for better cache, and are only processed when the
+    /// buffer is full, or when a packet that requires the control flow to be
+    /// resolved (e.g., TIP) is met.
+    #[must_use]
+    pub fn pending_tnt_bits(&self) -> u32 {
+        self.tnt_buffer_manager.pending_bits()
+    }
+
+    /// Process all pending TNT bits.
+    ///
+    /// If the trace ends without a final TIP packet (e.g., the trace is truncated),
+    /// the trailing TNT bits are left pending after decoding, and the control flows
+    /// they indicate are not reported. Leftover TNT bits at the end of trace are only
+    /// processed by an explicit flush, which should be invoked after decoding if they
+    /// are needed.
+    ///
+    /// If the TNT bits reach an indirect branch, the remaining bits are kept pending
+    /// since they belong to the control flow after the missing TIP packet.
+    pub fn flush(&mut self) -> AnalyzerResult<(), H, R> {
+        let context = DecoderContext::new(self.tnt_tracee_mode);
+        self.process_all_pending_tnts(&context)
+    }
+
+    /// Set whether a TIP packet with out-of-context target is treated as a trace
+    /// gap instead of an error. Default is `false`.
+    ///
+    /// With filtering, a TIP packet may legitimately jump to a target outside of
+    /// the traced ranges. When this is set, such TIP packets make the analyzer
+    /// enter an untraced region, where all control flows are ignored until the
+    /// next TIP.PGE packet. Otherwise, [`AnalyzerError::InvalidPacket`] is
+    /// returned.
+    pub fn out_of_context_tip_as_gap(&mut self, as_gap: bool) -> &mut Self {
+        self.out_of_context_tip_as_gap = as_gap;
+        self
+    }
+
+    /// Set whether to check that targets of indirect jumps and indirect calls
+    /// start with `ENDBR64` (or `ENDBR32` in 32-bit and 16-bit modes). Default
+    /// is `false`.
+    ///
+    /// With Indirect Branch Tracking of Intel CET, every legitimate target of
+    /// indirect jumps and indirect calls begins with `ENDBR64`/`ENDBR32`. When
+    /// this is set, each target given by a TIP packet is checked, and those not
+    /// beginning with `ENDBR64`/`ENDBR32` are reported by
+    /// [`HandleControlFlow::on_missing_endbr`] and counted by
+    /// [`DiagnosticInformation::missing_endbr_count`], which indicates a
+    /// potential control-flow hijack or trace corruption. This is only
+    /// meaningful for binaries compiled with IBT enabled.
+    ///
+    /// Returns, far transfers and indirect branches with the `NOTRACK` prefix
+    /// (e.g., jump tables) are not checked, since they are not tracked by IBT.
+    /// Targets that cannot be read are not reported.
+    pub fn check_endbr(&mut self, check: bool) -> &mut Self {
+        self.check_endbr = check;
+        self
+    }
+
+    /// Set the linear address width used to check that every IP reconstructed
+    /// from TIP, TIP.PGE, TIP.PGD and FUP packets is canonical in 64-bit mode,
+    /// or `None` to disable the check. Default is `None`.
+    ///
+    /// The width is usually
+    /// [`CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING`][iptr_decoder::utils::CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING],
+    /// or [`CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING`][iptr_decoder::utils::CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING]
+    /// if the traced system enables 5-level paging.
+    ///
+    /// When this is set, a non-canonical IP aborts the decoding with
+    /// [`AnalyzerError::NonCanonicalAddress`], which catches trace corruption
+    /// and IP reconstruction bugs early instead of failing later when reading
+    /// memory. IPs in 16-bit and 32-bit modes are not checked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` is not in `1..=64`.
+    pub fn check_canonical_address(&mut self, width: Option<u8>) -> &mut Self {
+        if let Some(width) = width {
+            assert!(
+                (1..=64).contains(&width),
+                "Invalid linear address width {width}"
+            );
+        }
+        self.canonical_address_width = width;
+        self
+    }
+
+    /// Set maximum depth of the callstack maintained for
+    /// [`on_call`][HandleControlFlow::on_call] and [`on_return`][HandleControlFlow::on_return].
+    /// Default is `1024`.
+    ///
+    /// Traces with unbounded recursion, or CALLs whose returns are never traced
+    /// (e.g., `longjmp` or CALLs used to get the current IP), would otherwise grow
+    /// the callstack without limit. If the depth is exceeded, the oldest return
+    /// address is dropped, and the corresponding return is reported with an
+    /// unknown expected target. This is only available in non-cache mode.
+    #[cfg(not(feature = "cache"))]
+    pub fn max_callstack_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_callstack_depth = depth;
+        let excess = self.callstack.len().saturating_sub(depth);
+        self.callstack.drain(..excess);
+        self
+    }
+
+    /// Set whether to continue decoding past basic blocks that cannot be decoded.
+    /// Default is `false`.
+    ///
+    /// If the traced binary does not exactly match the memory (e.g., a small region
+    /// is patched), resolving some basic blocks may fail with
+    /// [`AnalyzerError::InvalidInstruction`], which stops decoding. When this is set,
+    /// such basic blocks are instead treated as opaque far transfers of
+    /// [`FarTransferKind::Undecodable`], whose targets are given by the next TIP
+    /// packet, just like indirect branches. This trades accuracy for resilience: the
+    /// control flow inside these blocks is lost, and TNT bits produced by them may be
+    /// misattributed. The number of such blocks is reported in
+    /// [`DiagnosticInformation::undecodable_block_count`].
+    pub fn skip_undecodable_blocks(&mut self, skip: bool) -> &mut Self {
+        self.static_analyzer.set_skip_undecodable_blocks(skip);
+        self
+    }
+
+    /// Set whether to record observed targets of indirect branches. Default is
+    /// `false`.
+    ///
+    /// When set, each time a TIP packet gives the target of a basic block
+    /// terminated by an indirect jump or indirect call, the target is recorded,
+    /// which can be retrieved by [`indirect_targets`][Self::indirect_targets]
+    /// and [`iter_indirect_targets`][Self::iter_indirect_targets]. This turns
+    /// the statically unknown targets into dynamically resolved edges, which is
+    /// useful for building a complete CFG. The recorded targets are cleared
+    /// together with the CFG graph, e.g., when the address space changes.
+    pub fn record_indirect_targets(&mut self, record: bool) -> &mut Self {
+        self.static_analyzer.set_record_indirect_targets(record);
+        self
+    }
+
+    /// Set whether to report the instructions of each executed basic block by
+    /// [`HandleControlFlow::on_block_instructions`]. Default is `false`.
+    ///
+    /// This reconstructs the literal executed instruction stream, e.g., for a
+    /// disassembly-style trace view. The instructions of each basic block are
+    /// decoded once when it is resolved and kept in memory, so that executing the
+    /// same basic block again does not decode it again. Note that this disables
+    /// [`fast_classify`][Self::fast_classify] for newly resolved basic blocks,
+    /// since all instructions need to be decoded.
+    #[cfg(not(feature = "cache"))]
+    pub fn record_instructions(&mut self, record: bool) -> &mut Self {
+        self.static_analyzer.set_record_instructions(record);
+        self
+    }
+
+    /// Get observed targets of the indirect branch terminating the basic block
+    /// at `block_addr` under `tracee_mode`, or `None` if no target is observed.
+    ///
+    /// Targets are only recorded if enabled by
+    /// [`record_indirect_targets`][Self::record_indirect_targets].
+    #[must_use]
+    pub fn indirect_targets(
+        &self,
+        tracee_mode: TraceeMode,
+        block_addr: u64,
+    ) -> Option<&HashSet<u64>> {
+        self.static_analyzer
+            .indirect_targets(tracee_mode, block_addr)
+    }
+
+    /// Iterate over all observed indirect targets, keyed by the tracee mode and
+    /// the address of basic block terminated by indirect branch, see
+    /// [`indirect_targets`][Self::indirect_targets].
+    pub fn iter_indirect_targets(&self) -> impl Iterator<Item = (TraceeMode, u64, &HashSet<u64>)> {
+        self.static_analyzer.iter_indirect_targets()
+    }
+
+    /// Calculate simple metrics of the current CFG graph, e.g., the number of
+    /// basic blocks and loop back-edges.
+    ///
+    /// Observed indirect targets are only counted if enabled by
+    /// [`record_indirect_targets`][Self::record_indirect_targets].
+    #[must_use]
+    pub fn cfg_metrics(&self) -> CfgMetrics {
+        self.static_analyzer.metrics()
+    }
+
+    /// Whether the analyzer is currently in an untraced region, see
+    /// [`out_of_context_tip_as_gap`][Self::out_of_context_tip_as_gap].
+    #[must_use]
+    pub fn is_in_untraced_region(&self) -> bool {
+        self.in_untraced_region
+    }
+
+    /// Set whether the CFG graph and caches are cleared when CR3 or VMCS
+    /// pointer changes. Default is `false`.
+    ///
+    /// The CFG graph and caches are keyed by virtual addresses, which may mean
+    /// different codes in different address spaces. Set this to `true` if the
+    /// traced address spaces map different codes at the same addresses.
+    ///
+    /// Clearing has its own costs. Besides the CFG graph and caches, the
+    /// callstack and the last basic block are dropped as well, so codes shared
+    /// by all address spaces (e.g., the kernel) are resolved again after every
+    /// switch, and TNT bits after a PIP packet that are not preceded by a TIP
+    /// packet cannot be resolved and are lost.
+    pub fn clear_cache_on_address_space_change(&mut self, clear: bool) -> &mut Self {
+        self.clear_cache_on_address_space_change = clear;
+        self
+    }
+
+    /// Set whether the caches are cleared when tracee mode is changed by MODE.exec
+    /// packet. Default is `false`.
+    ///
+    /// The same bytes may be decoded as different instructions in 64-bit, 32-bit
+    /// and 16-bit modes. The CFG graph is maintained separately for each mode, but
+    /// the caches are not. If the traced code at the same address may be executed
+    /// in different modes, this should be set to `true`.
+    #[cfg(feature = "cache")]
+    pub fn clear_cache_on_mode_change(&mut self, clear: bool) -> &mut Self {
+        self.clear_cache_on_mode_change = clear;
+        self
+    }
+
+    /// Get a snapshot of the current CFG graph.
+    ///
+    /// The snapshot can be serialized, and loaded by
+    /// [`load_cfg_snapshot`][Self::load_cfg_snapshot] in later runs to skip
+    /// resolving the instructions again.
+    #[cfg(feature = "serde")]
+    #[must_use]
+    pub fn cfg_snapshot(&self) -> CfgSnapshot {
+        self.static_analyzer.save_snapshot()
+    }
+
+    /// Load a CFG snapshot retrieved by [`cfg_snapshot`][Self::cfg_snapshot].
+    ///
+    /// The snapshot is not validated against the memory reader. It is the user's
+    /// responsibility to make sure the traced binaries are not changed since the
+    /// snapshot is taken, otherwise the analyzed control flow would be wrong. All
+    /// caches will be cleared.
+    #[cfg(feature = "serde")]
+    pub fn load_cfg_snapshot(&mut self, snapshot: CfgSnapshot) {
+        self.static_analyzer.load_snapshot(snapshot);
+        #[cfg(feature = "cache")]
+        self.cache_manager.clear_all_cache();
+    }
+
+    /// Set whether to only use the pre-populated CFG graph without reading any
+    /// memory. Default is `false`.
+    ///
+    /// When set, basic blocks are only looked up in the CFG graph, e.g., loaded
+    /// by `load_cfg_snapshot` with the `serde` feature or resolved in previous
+    /// decodings, and [`AnalyzerError::BlockNotInPrepopulatedCfg`] is returned
+    /// for missing ones instead of reading memory. Together with
+    /// [`NullMemoryReader`][crate::memory_reader::null::NullMemoryReader], this
+    /// replays traces covered by the CFG graph without the traced binaries.
+    ///
+    /// Note that the CFG graph is cleared when the address space changes if
+    /// enabled by [`clear_cache_on_address_space_change`][Self::clear_cache_on_address_space_change].
+    pub fn prepopulated_cfg_only(&mut self, prepopulated_only: bool) -> &mut Self {
+        self.static_analyzer
+            .set_prepopulated_only(prepopulated_only);
+        self
+    }
+
+    /// Resolve the terminator of the basic block starting at `block_addr` in
+    /// `tracee_mode`, without decoding any packets.
+    ///
+    /// This is useful for tools that only need the static CFG information,
+    /// such as disassemblers. The memory is read by the memory reader of this
+    /// analyzer, and the resolved block is inserted into the CFG graph, so
+    /// later decoding will not resolve it again.
+    pub fn resolve_block(
+        &mut self,
+        tracee_mode: TraceeMode,
+        block_addr: u64,
+    ) -> AnalyzerResult<CfgTerminator, H, R> {
+        let cfg_node = self.static_analyzer.resolve::<H, R>(
+            &mut self.reader,
+            tracee_mode,
+            tracee_mode.mask_address(block_addr),
+        )?;
+        Ok(cfg_node.terminator)
+    }
+
+    /// Set options passed to the iced-x86 decoder when resolving instructions,
+    /// which should be a combination of `iced_x86::DecoderOptions` constants.
+    /// Default is `iced_x86::DecoderOptions::NONE`.
+    ///
+    /// This is useful when the traced code uses encodings that are only
+    /// decoded validly under specific options. This should be set before
+    /// decoding, since the already resolved CFG nodes will not be affected.
+    pub fn iced_options(&mut self, iced_options: u32) -> &mut Self {
+        self.static_analyzer.set_iced_options(iced_options);
+        self
+    }
+
+    /// Set maximum size of each memory read when resolving instructions.
+    /// Default is 4096, and values less than [`MIN_BLOCK_READ_SIZE`] are treated
+    /// as [`MIN_BLOCK_READ_SIZE`].
+    ///
+    /// Instructions are resolved by reading memory in chunks of this size, or to
+    /// the end of the memory region if [`readable_len`][ReadMemory::readable_len]
+    /// is smaller. For memory readers where each read is expensive (e.g., backed by
+    /// network or core dumps) and basic blocks are short, a smaller size avoids
+    /// reading unused bytes.
+    pub fn block_read_size(&mut self, block_read_size: usize) -> &mut Self {
+        self.static_analyzer.set_block_read_size(block_read_size);
+        self
+    }
+
+    /// Set whether to skip common non-control-flow instructions by their opcode
+    /// bytes when resolving instructions. Default is `false`.
+    ///
+    /// Decoding every instruction by iced-x86 dominates the cost of resolving
+    /// new basic blocks. If enabled, instructions such as MOV, PUSH, POP and
+    /// arithmetic instructions without legacy prefixes are recognized by their
+    /// opcode and ModRM bytes, and only other instructions are decoded by
+    /// iced-x86. The resolved CFG nodes are the same, since any instruction that
+    /// is not definitely recognized is still decoded by iced-x86. This has no
+    /// effect in 16-bit mode.
+    pub fn fast_classify(&mut self, fast_classify: bool) -> &mut Self {
+        self.static_analyzer.set_fast_classify(fast_classify);
+        self
+    }
+
+    /// Set policy of handling instructions split across memory reads.
+    /// Default is [`CrossReadPolicy::Scratch`].
+    pub fn cross_read_policy(&mut self, cross_read_policy: CrossReadPolicy) -> &mut Self {
+        self.static_analyzer
+            .set_cross_read_policy(cross_read_policy);
+        self
+    }
+
+    /// Clear all caches in [`cache_manager`][Self::cache_manager] if either the
+    /// control flow handler requires so, or the caches are too large.
+    ///
+    /// This should only be invoked when there is no pending TNT bits, i.e., no
+    /// cached keys are in use.
+    #[cfg(feature = "cache")]
+    fn clear_all_cache_if_needed(&mut self) -> AnalyzerResult<(), H, R> {
+        if self
+            .handler
+            .should_clear_all_cache()
+            .map_err(AnalyzerError::ControlFlowHandler)?
+            || self.cache_manager.should_clear_all_cache()
+        {
+            self.cache_manager.clear_all_cache();
+        }
+
+        Ok(())
+    }
+
+    /// Clear the pending TNT bits when recovering from OVF, since
+    /// they cannot be bound to the new IP.
+    fn discard_tnt_buffer_after_ovf(&mut self) {
+        self.ovf_discarded_tnt_bit_count += self.tnt_buffer_manager.pending_bits() as usize;
+        self.tnt_buffer_manager.clear();
+    }
+
     /// Perform IP reconstruction and update the `last_ip` field,
-    /// returns the full-width IP address
+    /// returns the IP address wrapped to the address width of current tracee mode.
+    ///
+    /// The `last_ip` field itself is not wrapped, since it is used as the
+    /// reference of IP compression. If [`check_canonical_address`][Self::check_canonical_address]
+    /// is set, IPs in 64-bit mode that are non-canonical for the configured
+    /// width are rejected.
     fn reconstruct_ip_and_update_last(
         &mut self,
+        context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
-    ) -> Option<u64> {
-        if !iptr_decoder::utils::reconstruct_ip_and_update_last(
+    ) -> AnalyzerResult<Option<u64>, H, R> {
+        let tracee_mode = context.tracee_mode();
+        let Some(ip) = iptr_decoder::utils::reconstruct_ip_in_mode(
             &mut self.last_ip,
             ip_reconstruction_pattern,
-        ) {
-            return None;
+            tracee_mode,
+        ) else {
+            return Ok(None);
+        };
+        if tracee_mode == TraceeMode::Mode64
+            && self
+                .canonical_address_width
+                .is_some_and(|width| !iptr_decoder::utils::is_canonical_address(ip, width))
+        {
+            return Err(AnalyzerError::NonCanonicalAddress { address: ip });
         }
 
-        Some(self.last_ip)
+        Ok(Some(ip))
     }
 
     /// Process the given TNT bit, querying the CFG graph without
@@ -178,7 +785,8 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
     #[expect(
         clippy::enum_glob_use,
         clippy::items_after_statements,
-        clippy::needless_continue
+        clippy::needless_continue,
+        clippy::too_many_lines
     )]
     fn process_tnt_bit_without_querying_cache(
         &mut self,
@@ -205,33 +813,67 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
                         tnt_proceed = TntProceed::Continue;
                         break 'cfg_traverse;
                     }
-                    let r#false = r#false as u64 | (r#true & 0xFFFF_FFFF_0000_0000);
+                    let r#false = static_analyzer::branch_false_target(
+                        r#true,
+                        r#false,
+                        context.tracee_mode(),
+                    );
+                    let from = last_bb;
                     last_bb = if is_taken { r#true } else { r#false };
                     self.handler
-                        .on_new_block(last_bb, ControlFlowTransitionKind::ConditionalBranch, true)
+                        .on_edge(
+                            from,
+                            last_bb,
+                            ControlFlowTransitionKind::ConditionalBranch,
+                            true,
+                        )
                         .map_err(AnalyzerError::ControlFlowHandler)?;
+                    #[cfg(not(feature = "cache"))]
+                    self.report_block_instructions(context, last_bb)?;
                     tnt_bit_processed = true;
                     // Continue to eat all direct goto and direct call (useful for last bit before TIP)
                     continue 'cfg_traverse;
                 }
                 DirectGoto { target } => {
+                    let from = last_bb;
                     last_bb = target;
                     self.handler
-                        .on_new_block(last_bb, ControlFlowTransitionKind::DirectJump, true)
+                        .on_edge(from, last_bb, ControlFlowTransitionKind::DirectJump, true)
                         .map_err(AnalyzerError::ControlFlowHandler)?;
+                    #[cfg(not(feature = "cache"))]
+                    self.report_block_instructions(context, last_bb)?;
                     continue 'cfg_traverse;
                 }
-                DirectCall { target } => {
+                DirectCall {
+                    target,
+                    return_address,
+                } => {
+                    #[cfg(not(feature = "cache"))]
+                    {
+                        self.push_return_address(return_address);
+                        self.handler
+                            .on_call(last_bb, target)
+                            .map_err(AnalyzerError::ControlFlowHandler)?;
+                    }
+                    #[cfg(feature = "cache")]
+                    let _ = return_address;
+                    let from = last_bb;
                     last_bb = target;
                     self.handler
-                        .on_new_block(last_bb, ControlFlowTransitionKind::DirectCall, true)
+                        .on_edge(from, last_bb, ControlFlowTransitionKind::DirectCall, true)
                         .map_err(AnalyzerError::ControlFlowHandler)?;
+                    #[cfg(not(feature = "cache"))]
+                    self.report_block_instructions(context, last_bb)?;
                     continue 'cfg_traverse;
                 }
-                IndirectGoto
-                | IndirectCall
+                IndirectGoto { notrack: _ }
+                | IndirectCall {
+                    return_address: _,
+                    notrack: _,
+                }
                 | FarTransfers {
                     next_instruction: _,
+                    kind: _,
                 } => {
                     if tnt_bit_processed {
                         tnt_proceed = TntProceed::Continue;
@@ -270,106 +912,381 @@ impl<H: HandleControlFlow, R: ReadMemory> EdgeAnalyzer<H, R> {
         // There are already some valid TNT packets here since
         // last_bb is not uninitialized
         let mut last_bb = last_bb.get();
-        // Clear the pending tnt buffers.
-        let tnt_buffer = self.tnt_buffer_manager.take();
-        let res = self.handle_maybe_full_tnt_buffer(context, &mut last_bb, tnt_buffer);
+        // Clear the pending tnt buffers, until a deferred TIP is detected.
+        let mut res = Ok(());
+        while !self.tnt_buffer_manager.is_empty()
+            && !matches!(self.pre_tip_status, PreTipStatus::PendingIndirect)
+        {
+            let tnt_buffer = self.tnt_buffer_manager.take();
+            res = self.handle_maybe_full_tnt_buffer(context, &mut last_bb, tnt_buffer);
+            if res.is_err() {
+                break;
+            }
+        }
         self.last_bb = NonZero::new(last_bb);
         res
     }
 
-    /// Handle TIP or TIP.PGD since TIP.PGD can replace TIP packets if
-    /// the destination goes out of ranges.
-    #[expect(clippy::redundant_else)]
-    fn handle_tip_or_tip_pgd_packet(
+    /// Whether the source of indirect transitions is needed, either by the
+    /// control flow handler (see [`HandleControlFlow::NEEDS_INDIRECT_SOURCE`]) or
+    /// by the edge analyzer itself.
+    fn needs_indirect_source(&self) -> bool {
+        #[cfg(not(feature = "cache"))]
+        let recording_instructions = self.static_analyzer.is_recording_instructions();
+        #[cfg(feature = "cache")]
+        let recording_instructions = false;
+        H::NEEDS_INDIRECT_SOURCE
+            || self.check_endbr
+            || self.static_analyzer.is_recording_indirect_targets()
+            || recording_instructions
+    }
+
+    /// Follow direct JMPs and CALLs starting from `block_addr`, and return the
+    /// address of the first basic block with other terminators.
+    ///
+    /// This is used to find the source of indirect branches. The followed
+    /// transitions are reported by [`on_edge`][HandleControlFlow::on_edge] as
+    /// processing TNT bits does, but never folded into cache. If there are too
+    /// many direct transitions (e.g., an infinite loop), the last reached block
+    /// is returned. If a reached block cannot be resolved (e.g., it is unmapped
+    /// or undecodable), the source is unknown and [`None`] is returned.
+    ///
+    /// Return addresses of the followed CALLs are pushed into the callstack, and
+    /// the CALLs are reported by [`on_call`][HandleControlFlow::on_call].
+    fn follow_direct_transitions(
         &mut self,
         context: &DecoderContext,
-        ip_reconstruction_pattern: IpReconstructionPattern,
-        is_pgd: bool,
+        block_addr: u64,
+    ) -> AnalyzerResult<Option<u64>, H, R> {
+        /// Max number of direct transitions to follow
+        const MAX_DIRECT_TRANSITIONS: usize = 0x100;
+
+        let mut block_addr = block_addr;
+        for _ in 0..MAX_DIRECT_TRANSITIONS {
+            let Ok(cfg_node) = self.static_analyzer.resolve::<H, R>(
+                &mut self.reader,
+                context.tracee_mode(),
+                block_addr,
+            ) else {
+                return Ok(None);
+            };
+            let from = block_addr;
+            let transition_kind = match cfg_node.terminator {
+                static_analyzer::CfgTerminator::DirectGoto { target } => {
+                    block_addr = target;
+                    ControlFlowTransitionKind::DirectJump
+                }
+                static_analyzer::CfgTerminator::DirectCall {
+                    target,
+                    return_address,
+                } => {
+                    #[cfg(not(feature = "cache"))]
+                    {
+                        self.push_return_address(return_address);
+                        self.handler
+                            .on_call(from, target)
+                            .map_err(AnalyzerError::ControlFlowHandler)?;
+                    }
+                    #[cfg(feature = "cache")]
+                    let _ = return_address;
+                    block_addr = target;
+                    ControlFlowTransitionKind::DirectCall
+                }
+                _ => break,
+            };
+            self.handler
+                .on_edge(from, block_addr, transition_kind, false)
+                .map_err(AnalyzerError::ControlFlowHandler)?;
+            #[cfg(not(feature = "cache"))]
+            self.report_block_instructions(context, block_addr)?;
+        }
+        Ok(Some(block_addr))
+    }
+
+    /// Report the instructions of executed basic block at `block_addr` to the
+    /// control flow handler if enabled, see
+    /// [`record_instructions`][Self::record_instructions].
+    #[cfg(not(feature = "cache"))]
+    fn report_block_instructions(
+        &mut self,
+        context: &DecoderContext,
+        block_addr: u64,
     ) -> AnalyzerResult<(), H, R> {
-        let Some(new_last_bb) = self.reconstruct_ip_and_update_last(ip_reconstruction_pattern)
-        else {
-            // Out-of-context IP
-            if is_pgd {
-                // SYSCALL into kernel codes...
-                self.pre_tip_status = PreTipStatus::Normal;
-                return Ok(());
-            } else {
-                // Single TIP packet emit a out-of-context IP?
-                return Err(AnalyzerError::InvalidPacket);
-            }
+        if !self.static_analyzer.is_recording_instructions() {
+            return Ok(());
+        }
+        let (instructions, undecodable) = self.static_analyzer.block_instructions::<H, R>(
+            &mut self.reader,
+            context.tracee_mode(),
+            block_addr,
+        )?;
+        self.handler
+            .on_block_instructions(block_addr, instructions, undecodable)
+            .map_err(AnalyzerError::ControlFlowHandler)
+    }
+
+    /// Check whether the target of the indirect jump or indirect call terminating
+    /// `src_block` starts with `ENDBR64`/`ENDBR32`, see [`check_endbr`][Self::check_endbr].
+    fn check_indirect_target_endbr(
+        &mut self,
+        context: &DecoderContext,
+        src_terminator: Option<static_analyzer::CfgTerminator>,
+        src_block: u64,
+        dst_block: u64,
+    ) -> AnalyzerResult<(), H, R> {
+        /// Encoding of `ENDBR64`
+        const ENDBR64: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFA];
+        /// Encoding of `ENDBR32`
+        const ENDBR32: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFB];
+
+        if !matches!(
+            src_terminator,
+            Some(
+                static_analyzer::CfgTerminator::IndirectGoto { notrack: false }
+                    | static_analyzer::CfgTerminator::IndirectCall { notrack: false, .. }
+            )
+        ) {
+            // Targets of NOTRACK branches are not tracked by IBT
+            return Ok(());
+        }
+        let endbr = match context.tracee_mode() {
+            TraceeMode::Mode64 => ENDBR64,
+            TraceeMode::Mode32 | TraceeMode::Mode16 => ENDBR32,
         };
-        // If pgd goes out of context, we cannot determin pre tip status since the
-        // memory reader may also miss page. So we should put the ip reconstruction first.
+        let Ok(Some(has_endbr)) = self.reader.read_memory(dst_block, endbr.len(), |bytes| {
+            // Short reads can only be told if they differ from the ENDBR
+            (bytes.len() >= endbr.len() || !endbr.starts_with(bytes))
+                .then(|| bytes.starts_with(&endbr))
+        }) else {
+            // The target cannot be read, which is not our business here
+            return Ok(());
+        };
+        if !has_endbr {
+            self.missing_endbr_count += 1;
+            self.handler
+                .on_missing_endbr(src_block, dst_block)
+                .map_err(AnalyzerError::ControlFlowHandler)?;
+        }
 
-        // For FUP, it flushes the CPU's internal TNT buffer, and thus we should process all
-        // pending TNTs, otherwise they would just be lost.
-        self.process_all_pending_tnts(context)?;
-        self.last_bb = NonZero::new(new_last_bb);
-        match self.pre_tip_status {
-            PreTipStatus::Normal | PreTipStatus::PendingIndirect => {
+        Ok(())
+    }
+
+    /// Push the return address of a CALL into the callstack, dropping the oldest
+    /// one if the callstack is full.
+    #[cfg(not(feature = "cache"))]
+    fn push_return_address(&mut self, return_address: u64) {
+        if self.callstack.len() >= self.max_callstack_depth {
+            self.callstack.pop_front();
+        }
+        if self.max_callstack_depth > 0 {
+            self.callstack.push_back(return_address);
+        }
+    }
+
+    /// Update the callstack with the indirect transition from `src_block` to
+    /// `dst_block`, and report calls and returns to the control flow handler.
+    ///
+    /// `src_terminator` is the terminator of `src_block` if it can be resolved.
+    #[cfg(not(feature = "cache"))]
+    fn update_callstack(
+        &mut self,
+        src_terminator: Option<static_analyzer::CfgTerminator>,
+        src_block: u64,
+        dst_block: u64,
+    ) -> AnalyzerResult<(), H, R> {
+        let Some(src_terminator) = src_terminator else {
+            return Ok(());
+        };
+        match src_terminator {
+            static_analyzer::CfgTerminator::IndirectCall { return_address, .. } => {
+                self.push_return_address(return_address);
                 self.handler
-                    .on_new_block(new_last_bb, ControlFlowTransitionKind::Indirect, false)
+                    .on_call(src_block, dst_block)
                     .map_err(AnalyzerError::ControlFlowHandler)?;
-                self.pre_tip_status = PreTipStatus::Normal;
             }
-            PreTipStatus::PendingFup => {
+            static_analyzer::CfgTerminator::NearRet => {
+                let expected = self.callstack.pop_back();
                 self.handler
-                    .on_new_block(new_last_bb, ControlFlowTransitionKind::NewBlock, false)
+                    .on_return(src_block, expected, dst_block)
                     .map_err(AnalyzerError::ControlFlowHandler)?;
-                self.pre_tip_status = PreTipStatus::Normal;
-                self.tnt_buffer_manager.clear();
-                return Ok(());
-            }
-            PreTipStatus::PendingOvf => {
-                // OVF should be followed by FUP or TIP.PGE
-                return Err(AnalyzerError::InvalidPacket);
             }
+            _ => {}
         }
 
         Ok(())
     }
-}
 
-impl<H, R> HandlePacket for EdgeAnalyzer<H, R>
-where
-    H: HandleControlFlow,
-    R: ReadMemory,
-    AnalyzerError<H, R>: std::error::Error,
-{
-    type Error = AnalyzerError<H, R>;
+    /// Clear all states that are only valid in the current address space.
+    fn clear_address_space_state(&mut self) {
+        self.last_bb = None;
+        self.tnt_buffer_manager.clear();
+        #[cfg(not(feature = "cache"))]
+        self.callstack.clear();
+        self.static_analyzer.clear();
+        #[cfg(feature = "cache")]
+        self.cache_manager.clear_all_cache();
+    }
 
-    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
-        self.last_ip = 0;
+    /// Enter an untraced region due to an out-of-context TIP packet.
+    ///
+    /// The pending TNT bits before the TIP packet are processed, and then all
+    /// decoding states are dropped since the control flow is lost.
+    fn enter_untraced_region(&mut self, context: &DecoderContext) -> AnalyzerResult<(), H, R> {
+        self.process_all_pending_tnts(context)?;
         self.last_bb = None;
         self.pre_tip_status = PreTipStatus::Normal;
         self.tnt_buffer_manager.clear();
+        #[cfg(not(feature = "cache"))]
+        self.callstack.clear();
+        self.in_untraced_region = true;
+
+        Ok(())
+    }
+
+    /// Report the indirect transition from `src_block` to `dst_block`, where
+    /// `src_block` is the basic block terminated by the indirect branch.
+    fn report_indirect_transition(
+        &mut self,
+        context: &DecoderContext,
+        src_block: u64,
+        dst_block: u64,
+    ) -> AnalyzerResult<(), H, R> {
+        let src_terminator = self
+            .static_analyzer
+            .resolve::<H, R>(&mut self.reader, context.tracee_mode(), src_block)
+            .ok()
+            .map(|cfg_node| cfg_node.terminator);
+        let transition_kind = match src_terminator {
+            Some(static_analyzer::CfgTerminator::FarTransfers { kind, .. }) => {
+                ControlFlowTransitionKind::FarTransfer(kind)
+            }
+            _ => ControlFlowTransitionKind::Indirect,
+        };
+        self.static_analyzer
+            .record_indirect_target(context.tracee_mode(), src_block, dst_block);
+        if self.check_endbr {
+            self.check_indirect_target_endbr(context, src_terminator, src_block, dst_block)?;
+        }
+        if matches!(
+            src_terminator,
+            Some(
+                static_analyzer::CfgTerminator::IndirectGoto { .. }
+                    | static_analyzer::CfgTerminator::IndirectCall { .. }
+            )
+        ) {
+            self.handler
+                .on_indirect_edge(src_block, dst_block)
+                .map_err(AnalyzerError::ControlFlowHandler)?;
+        }
         self.handler
-            .at_decode_begin()
+            .on_edge(src_block, dst_block, transition_kind, false)
             .map_err(AnalyzerError::ControlFlowHandler)?;
-        self.reader
-            .at_decode_begin()
-            .map_err(AnalyzerError::MemoryReader)?;
-        #[cfg(feature = "cache")]
-        {
-            if self
-                .handler
-                .should_clear_all_cache()
-                .map_err(AnalyzerError::ControlFlowHandler)?
-                || self.cache_manager.should_clear_all_cache()
-            {
-                self.cache_manager.clear_all_cache();
-            }
+        #[cfg(not(feature = "cache"))]
+        self.report_block_instructions(context, dst_block)?;
+        #[cfg(not(feature = "cache"))]
+        self.update_callstack(src_terminator, src_block, dst_block)?;
+
+        Ok(())
+    }
+
+    /// Handle TIP or TIP.PGD since TIP.PGD can replace TIP packets if
+    /// the destination goes out of ranges.
+    #[expect(clippy::redundant_else)]
+    fn handle_tip_or_tip_pgd_packet(
+        &mut self,
+        context: &DecoderContext,
+        ip_reconstruction_pattern: IpReconstructionPattern,
+        is_pgd: bool,
+    ) -> AnalyzerResult<(), H, R> {
+        let new_last_bb =
+            self.reconstruct_ip_and_update_last(context, ip_reconstruction_pattern)?;
+        if self.in_untraced_region {
+            // Block tracking is suspended until the next TIP.PGE
+            return Ok(());
         }
-        #[cfg(all(feature = "cache", feature = "more_diagnose"))]
-        {
-            self.cache_32bit_hit_count = 0;
-            self.cache_8bit_hit_count = 0;
-            self.cache_trailing_bits_hit_count = 0;
-            self.cache_missed_bit_count = 0;
+        let Some(new_last_bb) = new_last_bb else {
+            // Out-of-context IP
+            if is_pgd {
+                // SYSCALL into kernel codes, or tracing is disabled. The pending
+                // TNT bits are branches executed before the disable point, which
+                // would be cleared with the TIP.PGD packet.
+                self.process_all_pending_tnts(context)?;
+                self.pre_tip_status = PreTipStatus::Normal;
+                return Ok(());
+            } else if self.out_of_context_tip_as_gap {
+                self.enter_untraced_region(context)?;
+                return Ok(());
+            } else {
+                // Single TIP packet emit a out-of-context IP?
+                return Err(AnalyzerError::InvalidPacket);
+            }
+        };
+        // If pgd goes out of context, we cannot determin pre tip status since the
+        // memory reader may also miss page. So we should put the ip reconstruction first.
+
+        // For FUP, it flushes the CPU's internal TNT buffer, and thus we should process all
+        // pending TNTs, otherwise they would just be lost.
+        self.process_all_pending_tnts(context)?;
+        let prev_last_bb = std::mem::replace(&mut self.last_bb, NonZero::new(new_last_bb));
+        match self.pre_tip_status {
+            PreTipStatus::Normal | PreTipStatus::PendingIndirect => {
+                let src_block = match (self.pre_tip_status, prev_last_bb) {
+                    // Direct transitions have been followed when processing TNT bits
+                    (PreTipStatus::PendingIndirect, Some(src_block)) => Some(src_block.get()),
+                    (_, Some(src_block)) if self.needs_indirect_source() => {
+                        self.follow_direct_transitions(context, src_block.get())?
+                    }
+                    _ => None,
+                };
+                if let Some(src_block) = src_block {
+                    self.report_indirect_transition(context, src_block, new_last_bb)?;
+                } else {
+                    self.handler
+                        .on_new_block(new_last_bb, ControlFlowTransitionKind::Indirect, false)
+                        .map_err(AnalyzerError::ControlFlowHandler)?;
+                    #[cfg(not(feature = "cache"))]
+                    self.report_block_instructions(context, new_last_bb)?;
+                }
+                self.pre_tip_status = PreTipStatus::Normal;
+            }
+            PreTipStatus::PendingFup | PreTipStatus::PendingAsyncEvent { .. } => {
+                if let (PreTipStatus::PendingAsyncEvent { event_ip }, Some(block_addr), false) =
+                    (self.pre_tip_status, prev_last_bb, is_pgd)
+                {
+                    self.handler
+                        .on_async_event(block_addr.get(), event_ip)
+                        .map_err(AnalyzerError::ControlFlowHandler)?;
+                }
+                self.handler
+                    .on_new_block(new_last_bb, ControlFlowTransitionKind::NewBlock, false)
+                    .map_err(AnalyzerError::ControlFlowHandler)?;
+                #[cfg(not(feature = "cache"))]
+                self.report_block_instructions(context, new_last_bb)?;
+                self.pre_tip_status = PreTipStatus::Normal;
+                self.tnt_buffer_manager.clear();
+                return Ok(());
+            }
+            PreTipStatus::PendingOvf => {
+                // OVF should be followed by FUP or TIP.PGE
+                return Err(AnalyzerError::InvalidPacket);
+            }
         }
 
         Ok(())
     }
+}
+
+impl<H, R> HandlePacket for EdgeAnalyzer<H, R>
+where
+    H: HandleControlFlow,
+    R: ReadMemory,
+    AnalyzerError<H, R>: std::error::Error,
+{
+    type Error = AnalyzerError<H, R>;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.reset()
+    }
 
     fn on_short_tnt_packet(
         &mut self,
@@ -385,12 +1302,14 @@ where
             // No previous TIP given. Silently ignore those TNTs
             return Ok(());
         };
+        self.tnt_tracee_mode = context.tracee_mode();
         let mut last_bb = last_bb.get();
-        if let Some(full_tnt_buffer) = self.tnt_buffer_manager.extend_with_short_tnt(packet_byte) {
-            let res = self.handle_full_tnt_buffer(context, &mut last_bb, full_tnt_buffer);
-            self.last_bb = NonZero::new(last_bb);
-            res?;
-        }
+        self.tnt_buffer_manager.extend_with_short_tnt(packet_byte)?;
+        let res = self.handle_full_tnt_buffers(context, &mut last_bb);
+        self.last_bb = NonZero::new(last_bb);
+        res?;
+        #[cfg(feature = "more_diagnose")]
+        self.update_max_pending_tnt_bits();
 
         Ok(())
     }
@@ -399,22 +1318,24 @@ where
         &mut self,
         context: &DecoderContext,
         packet_bytes: NonZero<u64>,
-        highest_bit: u32,
+        _highest_bit: u32,
     ) -> Result<(), Self::Error> {
-        if highest_bit == u32::MAX {
-            // No TNT bits
-            return Ok(());
-        }
         let Some(last_bb) = self.last_bb else {
             // No previous TIP given. Silently ignore those TNTs
             return Ok(());
         };
+        self.tnt_tracee_mode = context.tracee_mode();
         let mut last_bb = last_bb.get();
-        if let Some(full_tnt_buffer) = self.tnt_buffer_manager.extend_with_long_tnt(packet_bytes) {
-            let res = self.handle_full_tnt_buffer(context, &mut last_bb, full_tnt_buffer);
+        let res = self.handle_long_tnt_directly(context, &mut last_bb, packet_bytes);
+        self.last_bb = NonZero::new(last_bb);
+        if !res? {
+            self.tnt_buffer_manager.extend_with_long_tnt(packet_bytes)?;
+            let res = self.handle_full_tnt_buffers(context, &mut last_bb);
             self.last_bb = NonZero::new(last_bb);
             res?;
         }
+        #[cfg(feature = "more_diagnose")]
+        self.update_max_pending_tnt_bits();
 
         Ok(())
     }
@@ -442,11 +1363,13 @@ where
 
     fn on_tip_pge_packet(
         &mut self,
-        _context: &DecoderContext,
+        context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
     ) -> Result<(), Self::Error> {
+        self.in_untraced_region = false;
         if matches!(self.pre_tip_status, PreTipStatus::PendingOvf) {
-            let Some(last_bb) = self.reconstruct_ip_and_update_last(ip_reconstruction_pattern)
+            let Some(last_bb) =
+                self.reconstruct_ip_and_update_last(context, ip_reconstruction_pattern)?
             else {
                 // Any IP compression that follows the OVF is guaranteed to
                 // use as a reference `LastIP` the IP payload of an IP packet
@@ -454,17 +1377,23 @@ where
             };
             self.last_bb = NonZero::new(last_bb);
             self.pre_tip_status = PreTipStatus::Normal;
-            self.tnt_buffer_manager.clear();
+            self.discard_tnt_buffer_after_ovf();
             self.handler
                 .on_new_block(last_bb, ControlFlowTransitionKind::NewBlock, false)
                 .map_err(AnalyzerError::ControlFlowHandler)?;
+            #[cfg(not(feature = "cache"))]
+            self.report_block_instructions(context, last_bb)?;
             return Ok(());
         }
-        if let Some(last_bb) = self.reconstruct_ip_and_update_last(ip_reconstruction_pattern) {
+        if let Some(last_bb) =
+            self.reconstruct_ip_and_update_last(context, ip_reconstruction_pattern)?
+        {
             self.last_bb = NonZero::new(last_bb);
             self.handler
                 .on_new_block(last_bb, ControlFlowTransitionKind::NewBlock, false)
                 .map_err(AnalyzerError::ControlFlowHandler)?;
+            #[cfg(not(feature = "cache"))]
+            self.report_block_instructions(context, last_bb)?;
         }
         self.pre_tip_status = PreTipStatus::Normal;
         self.tnt_buffer_manager.clear();
@@ -474,39 +1403,1704 @@ where
 
     fn on_fup_packet(
         &mut self,
-        _context: &DecoderContext,
+        context: &DecoderContext,
         ip_reconstruction_pattern: IpReconstructionPattern,
     ) -> Result<(), Self::Error> {
         if matches!(self.pre_tip_status, PreTipStatus::PendingOvf) {
             self.pre_tip_status = PreTipStatus::Normal;
-            let Some(last_bb) = self.reconstruct_ip_and_update_last(ip_reconstruction_pattern)
+            // The IP is known again after overflow
+            self.in_untraced_region = false;
+            let Some(last_bb) =
+                self.reconstruct_ip_and_update_last(context, ip_reconstruction_pattern)?
             else {
                 // Any IP compression that follows the OVF is guaranteed to
                 // use as a reference `LastIP` the IP payload of an IP packet
                 return Err(AnalyzerError::InvalidPacket);
             };
             self.last_bb = NonZero::new(last_bb);
-            self.tnt_buffer_manager.clear();
+            self.discard_tnt_buffer_after_ovf();
 
             return Ok(());
         }
-        self.reconstruct_ip_and_update_last(ip_reconstruction_pattern);
-        self.pre_tip_status = PreTipStatus::PendingFup;
+        let fup_ip = self.reconstruct_ip_and_update_last(context, ip_reconstruction_pattern)?;
+        if self.in_untraced_region {
+            return Ok(());
+        }
+        if context.is_in_psb() {
+            // FUP in PSB+ only indicates current IP, which is where the
+            // execution continues after PSBEND. No control flow transfer happens.
+            self.last_bb = fup_ip.and_then(NonZero::new);
+            return Ok(());
+        }
+        self.pre_tip_status = match fup_ip {
+            Some(event_ip) => PreTipStatus::PendingAsyncEvent { event_ip },
+            None => PreTipStatus::PendingFup,
+        };
 
         Ok(())
     }
 
     fn on_ovf_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+        self.ovf_count += 1;
         self.pre_tip_status = PreTipStatus::PendingOvf;
+        // CALLs and RETs during the overflow are lost
+        #[cfg(not(feature = "cache"))]
+        self.callstack.clear();
         Ok(())
     }
 
-    fn on_psb_packet(&mut self, _context: &DecoderContext) -> Result<(), Self::Error> {
+    fn on_psb_packet(&mut self, context: &DecoderContext) -> Result<(), Self::Error> {
+        if let Some(psb_index) = context.psb_index() {
+            self.handler
+                .on_psb_region(psb_index)
+                .map_err(AnalyzerError::ControlFlowHandler)?;
+        }
         self.last_bb = None;
         self.last_ip = 0;
-        self.pre_tip_status = PreTipStatus::Normal;
-        self.tnt_buffer_manager.clear();
+        if matches!(self.pre_tip_status, PreTipStatus::PendingOvf) {
+            // OVF -> PSB+ -> FUP/TIP.PGE, the OVF is still outstanding
+            self.discard_tnt_buffer_after_ovf();
+        } else {
+            self.pre_tip_status = PreTipStatus::Normal;
+            self.tnt_buffer_manager.clear();
+        }
+        #[cfg(feature = "cache")]
+        self.clear_all_cache_if_needed()?;
+
+        Ok(())
+    }
+
+    fn on_pip_packet(
+        &mut self,
+        context: &DecoderContext,
+        cr3: u64,
+        _rsvd_nr: bool,
+    ) -> Result<(), Self::Error> {
+        let prev_cr3 = self.last_cr3.replace(cr3);
+        if prev_cr3 == Some(cr3) {
+            // PIP is also emitted in every PSB+, which does not mean a change
+            return Ok(());
+        }
+        let clear_state = prev_cr3.is_some() && self.clear_cache_on_address_space_change;
+        if clear_state {
+            // TNT bits before PIP belong to the previous address space, so they
+            // are processed before the current block and caches are cleared.
+            // Otherwise they are kept pending to be batched with following bits.
+            self.process_all_pending_tnts(context)?;
+        }
+        self.reader
+            .on_address_space_change(cr3)
+            .map_err(AnalyzerError::MemoryReader)?;
+        if clear_state {
+            self.clear_address_space_state();
+        }
+
+        Ok(())
+    }
+
+    fn on_vmcs_packet(
+        &mut self,
+        context: &DecoderContext,
+        vmcs_pointer: u64,
+    ) -> Result<(), Self::Error> {
+        let prev_vmcs = self.last_vmcs.replace(vmcs_pointer);
+        if prev_vmcs == Some(vmcs_pointer) {
+            // VMCS is also emitted in every PSB+, which does not mean a change
+            return Ok(());
+        }
+        // TNT bits before VMCS belong to the previous guest
+        self.process_all_pending_tnts(context)?;
+        self.reader
+            .on_vmcs_change(vmcs_pointer)
+            .map_err(AnalyzerError::MemoryReader)?;
+        // CR3 values are only meaningful within the same guest, so the next
+        // PIP packet should always be notified to the memory reader
+        self.last_cr3 = None;
+        if prev_vmcs.is_none() || !self.clear_cache_on_address_space_change {
+            return Ok(());
+        }
+        self.clear_address_space_state();
 
         Ok(())
     }
+
+    fn on_mode_exec_change(
+        &mut self,
+        context: &DecoderContext,
+        _old_mode: TraceeMode,
+        _new_mode: TraceeMode,
+    ) -> Result<(), Self::Error> {
+        // TNT bits before MODE.exec are resolved in the old mode
+        self.process_all_pending_tnts(context)?;
+        #[cfg(feature = "cache")]
+        if self.clear_cache_on_mode_change {
+            self.cache_manager.clear_all_cache();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iptr_decoder::{DecodeOptions, packet_handler::combined::CombinedPacketHandler};
+
+    use super::*;
+    use crate::test_utils::{
+        BlockRecorder, LOOP_CODE, PtBuilder, SliceMemoryReader, loop_analyzer,
+    };
+
+    #[test]
+    fn test_drive_without_buffer() {
+        let context = DecoderContext::new(TraceeMode::Mode64);
+        let mut psb_context = DecoderContext::new(TraceeMode::Mode64);
+        psb_context.in_psb(true);
+        let mut analyzer = loop_analyzer();
+        analyzer.at_decode_begin().unwrap();
+        analyzer
+            .on_tip_pge_packet(&context, IpReconstructionPattern::EightBytes(0x1000))
+            .unwrap();
+        // FUP in PSB+ only indicates current IP
+        analyzer
+            .on_fup_packet(&psb_context, IpReconstructionPattern::EightBytes(0x1002))
+            .unwrap();
+        analyzer
+            .on_tip_packet(&context, IpReconstructionPattern::EightBytes(0x1000))
+            .unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+        assert_eq!(
+            analyzer.handler().edges,
+            [(0x1002, 0x1000, ControlFlowTransitionKind::Indirect)]
+        );
+    }
+
+    #[test]
+    fn test_psb_regions() {
+        let mut builder = PtBuilder::new();
+        for _ in 0..3 {
+            builder = builder
+                .psb()
+                .psbend()
+                .tip_pge(0x1000)
+                .short_tnt(&[true, false])
+                .tip(0x1000);
+        }
+        let trace = builder.build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let handler = analyzer.handler();
+        assert_eq!(handler.psb_regions, [(0, 0), (1, 4), (2, 8)]);
+        for (psb_index, first_block) in &handler.psb_regions {
+            assert_eq!(
+                handler.blocks[*first_block..*first_block + 4],
+                [
+                    (0x1000, ControlFlowTransitionKind::NewBlock),
+                    (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                    (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                    (0x1000, ControlFlowTransitionKind::Indirect),
+                ],
+                "Unexpected blocks in PSB region #{psb_index}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pending_tnts_before_tip_pgd() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, true, false])
+            .tip_pgd_suppressed()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip_pgd(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.pending_tnt_bits(), 0);
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_block() {
+        let mut analyzer = loop_analyzer();
+        let terminator = analyzer.resolve_block(TraceeMode::Mode64, 0x1000).unwrap();
+        let CfgTerminator::Branch { r#true, r#false } = terminator else {
+            panic!("Unexpected terminator {terminator:?}");
+        };
+        assert_eq!(r#true, 0x1000);
+        assert_eq!(
+            branch_false_target(r#true, r#false, TraceeMode::Mode64),
+            0x1002
+        );
+        assert!(matches!(
+            analyzer.resolve_block(TraceeMode::Mode64, 0x1002),
+            Ok(CfgTerminator::IndirectGoto { notrack: false })
+        ));
+        assert_eq!(analyzer.diagnose().cfg_size, 2);
+        assert!(matches!(
+            analyzer.resolve_block(TraceeMode::Mode64, 0x2000),
+            Err(AnalyzerError::UnmappedAddress { address: 0x2000 })
+        ));
+    }
+
+    #[test]
+    fn test_unmapped_indirect_target() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x5000)
+            .short_tnt(&[true])
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        // The pending TNT bit is processed from the indirect target
+        assert!(matches!(
+            analyzer.flush(),
+            Err(AnalyzerError::UnmappedAddress { address: 0x5000 })
+        ));
+    }
+
+    #[test]
+    fn test_skip_undecodable_blocks() {
+        /// Invalid instruction in 64-bit mode, followed by padding
+        const BAD_CODE: [u8; 17] = [
+            0x06, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+            0xCC, 0xCC, 0xCC,
+        ];
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x2000)
+            .short_tnt(&[true])
+            .tip(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let new_analyzer = || {
+            EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[(0x1000, &LOOP_CODE), (0x2000, &BAD_CODE)]),
+            )
+        };
+
+        let mut analyzer = new_analyzer();
+        assert!(matches!(
+            iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer),
+            Err(iptr_decoder::error::DecoderError::PacketHandler(
+                AnalyzerError::InvalidInstruction { .. }
+            ))
+        ));
+
+        let mut analyzer = new_analyzer();
+        analyzer.skip_undecodable_blocks(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        analyzer.flush().unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x2000, ControlFlowTransitionKind::Indirect),
+                (
+                    0x1000,
+                    ControlFlowTransitionKind::FarTransfer(FarTransferKind::Undecodable)
+                ),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+        assert_eq!(analyzer.diagnose().undecodable_block_count, 1);
+
+        // The undecodable block is reported without instructions
+        #[cfg(not(feature = "cache"))]
+        {
+            let mut analyzer = new_analyzer();
+            analyzer
+                .skip_undecodable_blocks(true)
+                .record_instructions(true);
+            iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+            assert_eq!(analyzer.handler().undecodable_blocks, [0x2000]);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "cache"))]
+    fn test_record_instructions() {
+        /// ```plaintext
+        /// 0x1000: dec ecx
+        /// 0x1002: jnz 0x1000
+        /// 0x1004: jmp rax
+        /// ```
+        const CODE: [u8; 6] = [0xFF, 0xC9, 0x75, 0xFC, 0xFF, 0xE0];
+        // Run the loop twice, then jump back to the loop by `jmp rax`
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, false])
+            .tip(0x1000)
+            .build();
+        let new_analyzer = || {
+            EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[(0x1000, &CODE)]),
+            )
+        };
+
+        let mut analyzer = new_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert!(analyzer.handler().instructions.is_empty());
+
+        let mut analyzer = new_analyzer();
+        analyzer.record_instructions(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let dec = (0x1000, 2, iced_x86::Code::Dec_rm32);
+        let jnz = (0x1002, 2, iced_x86::Code::Jne_rel8_64);
+        let jmp = (0x1004, 2, iced_x86::Code::Jmp_rm64);
+        assert_eq!(
+            analyzer
+                .handler()
+                .instructions
+                .iter()
+                .map(|insn| (insn.ip, insn.len, insn.code))
+                .collect::<Vec<_>>(),
+            [dec, jnz, dec, jnz, jmp, dec, jnz]
+        );
+    }
+
+    #[test]
+    fn test_record_indirect_targets() {
+        // The same loop at 0x1000 and 0x2000, both ended with `jmp rax`
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x2000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let new_analyzer = || {
+            EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[(0x1000, &LOOP_CODE), (0x2000, &LOOP_CODE)]),
+            )
+        };
+
+        let mut analyzer = new_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.iter_indirect_targets().count(), 0);
+
+        let mut analyzer = new_analyzer();
+        analyzer.record_indirect_targets(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.indirect_targets(TraceeMode::Mode64, 0x1002),
+            Some(&HashSet::from([0x1000, 0x2000]))
+        );
+        assert_eq!(
+            analyzer.indirect_targets(TraceeMode::Mode64, 0x2002),
+            Some(&HashSet::from([0x1000]))
+        );
+        // Conditional branches are not recorded
+        assert_eq!(analyzer.indirect_targets(TraceeMode::Mode64, 0x1000), None);
+        // Targets are keyed by tracee mode
+        assert_eq!(analyzer.indirect_targets(TraceeMode::Mode32, 0x1002), None);
+        assert_eq!(analyzer.iter_indirect_targets().count(), 2);
+    }
+
+    #[test]
+    fn test_check_endbr() {
+        /// ```plaintext
+        /// 0x1000: jmp rax
+        /// ```
+        const JMP_CODE: [u8; 2] = [0xFF, 0xE0];
+        /// ```plaintext
+        /// 0x2000: endbr64
+        /// 0x2004: jmp rax
+        /// ```
+        const ENDBR_CODE: [u8; 6] = [0xF3, 0x0F, 0x1E, 0xFA, 0xFF, 0xE0];
+        /// ```plaintext
+        /// 0x4000: notrack jmp rax
+        /// ```
+        const NOTRACK_JMP_CODE: [u8; 3] = [0x3E, 0xFF, 0xE0];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .tip(0x2000)
+            .tip(0x3000)
+            .tip(0x2000)
+            .build();
+        let new_analyzer = || {
+            EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[
+                    (0x1000, &JMP_CODE),
+                    (0x2000, &ENDBR_CODE),
+                    (0x3000, &JMP_CODE),
+                ]),
+            )
+        };
+
+        let mut analyzer = new_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.diagnose().missing_endbr_count, 0);
+        assert!(analyzer.handler().missing_endbrs.is_empty());
+
+        let mut analyzer = new_analyzer();
+        analyzer.check_endbr(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        // Only the target 0x3000 does not start with ENDBR64
+        assert_eq!(analyzer.diagnose().missing_endbr_count, 1);
+        assert_eq!(analyzer.handler().missing_endbrs, [(0x2000, 0x3000)]);
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x4000)
+            .tip(0x3000)
+            .build();
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x3000, &JMP_CODE), (0x4000, &NOTRACK_JMP_CODE)]),
+        );
+        analyzer.check_endbr(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        // Targets of NOTRACK branches are not checked
+        assert_eq!(analyzer.diagnose().missing_endbr_count, 0);
+        assert!(analyzer.handler().missing_endbrs.is_empty());
+    }
+
+    #[test]
+    fn test_check_canonical_address() {
+        use iptr_decoder::utils::{
+            CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING, CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING,
+        };
+
+        let fup_in_psb = |addr| {
+            PtBuilder::new()
+                .psb()
+                .fup(addr)
+                .psbend()
+                .tip_pge(0x1000)
+                .build()
+        };
+
+        // Canonical low and high addresses
+        for addr in [0x0000_7FFF_FFFF_F000, 0xFFFF_8000_0000_1000] {
+            let mut analyzer = loop_analyzer();
+            analyzer.check_canonical_address(Some(CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING));
+            iptr_decoder::decode(&fup_in_psb(addr), DecodeOptions::default(), &mut analyzer)
+                .unwrap();
+        }
+
+        // Non-canonical address is only flagged when checking
+        let trace = fup_in_psb(0x0000_8000_0000_1000);
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let mut analyzer = loop_analyzer();
+        analyzer.check_canonical_address(Some(CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING));
+        assert!(matches!(
+            iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer),
+            Err(iptr_decoder::error::DecoderError::PacketHandler(
+                AnalyzerError::NonCanonicalAddress {
+                    address: 0x0000_8000_0000_1000
+                }
+            ))
+        ));
+
+        // Canonical with 5-level paging
+        let mut analyzer = loop_analyzer();
+        analyzer.check_canonical_address(Some(CANONICAL_ADDRESS_WIDTH_5_LEVEL_PAGING));
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        // Not checked in 32-bit mode
+        let mut options = DecodeOptions::default();
+        options.tracee_mode(TraceeMode::Mode32);
+        let mut analyzer = loop_analyzer();
+        analyzer.check_canonical_address(Some(CANONICAL_ADDRESS_WIDTH_4_LEVEL_PAGING));
+        iptr_decoder::decode(&trace, options, &mut analyzer).unwrap();
+    }
+
+    #[test]
+    fn test_override_tracee_mode() {
+        /// Forces 32-bit mode after each PSB+
+        struct Mode32Overrider;
+
+        impl HandlePacket for Mode32Overrider {
+            type Error = std::convert::Infallible;
+
+            fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+                Ok(())
+            }
+
+            fn override_tracee_mode(
+                &mut self,
+                _context: &DecoderContext,
+            ) -> Result<Option<TraceeMode>, Self::Error> {
+                Ok(Some(TraceeMode::Mode32))
+            }
+        }
+
+        // The upper 32 bits are garbage in 32-bit mode
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1_0000_1000)
+            .short_tnt(&[false])
+            .tip(0x1_0000_1000)
+            .build();
+
+        let mut analyzer = loop_analyzer();
+        assert!(iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).is_err());
+
+        let mut handler = CombinedPacketHandler::new(Mode32Overrider, loop_analyzer());
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut handler).unwrap();
+        assert_eq!(
+            handler.handler2().handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_blocks() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, true, false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_far_transfer_kind() {
+        /// ```plaintext
+        /// 0x1000: syscall
+        /// 0x1002: int3
+        /// ```
+        const FAR_CODE: [u8; 3] = [0x0F, 0x05, 0xCC];
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .tip(0x1002)
+            .tip(0x1000)
+            .build();
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x1000, &FAR_CODE)]),
+        );
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (
+                    0x1002,
+                    ControlFlowTransitionKind::FarTransfer(FarTransferKind::Syscall)
+                ),
+                (
+                    0x1000,
+                    ControlFlowTransitionKind::FarTransfer(FarTransferKind::Interrupt(3))
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_handler_requests_clear_all_cache() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, true, false])
+            .tip(0x1000)
+            .psb()
+            .psbend()
+            .build();
+
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.diagnose().cache_trailing_bits_size, 1);
+
+        // Queries: #0 at decode begin, #1 at first PSB, #2 at second PSB
+        let mut handler = BlockRecorder::default();
+        handler.clear_at_query = Some(2);
+        let mut analyzer =
+            EdgeAnalyzer::new(handler, SliceMemoryReader::new(&[(0x1000, &LOOP_CODE)]));
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.diagnose().cache_trailing_bits_size, 0);
+    }
+
+    #[cfg(all(feature = "cache", feature = "more_diagnose"))]
+    #[test]
+    fn test_dump_cache() {
+        let trace = crate::test_utils::loop_trace();
+
+        let mut analyzer = loop_analyzer();
+        assert_eq!(analyzer.dump_cache(), CacheDump::default());
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let entry = |tnt_bits, bit_count, new_bb| CacheEntry {
+            start_bb: 0x1000,
+            tnt_bits,
+            bit_count,
+            new_bb,
+        };
+        assert_eq!(
+            analyzer.dump_cache(),
+            CacheDump {
+                trailing_bits: vec![entry(0b111_1110, 7, 0x1002)],
+                cache8: vec![entry(0xFF, 8, 0x1000)],
+                cache32: vec![entry(0xFFFF_FFFF, 32, 0x1000)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_address_space_change_clears_cache() {
+        /// ```plaintext
+        /// 0x1000: jnz 0x1004
+        /// 0x1002: jmp rax
+        /// 0x1004: jmp rax
+        /// ```
+        const OTHER_CODE: [u8; 6] = [0x75, 0x02, 0xFF, 0xE0, 0xFF, 0xE0];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .pip(0x1000)
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true])
+            .tip(0x1000)
+            .pip(0x2000)
+            .tip(0x1000)
+            .short_tnt(&[true])
+            .tip(0x1000)
+            .build();
+        let new_analyzer = || {
+            let reader = SliceMemoryReader::new(&[(0x1000, &LOOP_CODE)])
+                .with_address_space(0x2000, &[(0x1000, &OTHER_CODE)]);
+            EdgeAnalyzer::new(BlockRecorder::default(), reader)
+        };
+
+        // By default, the CFG graph resolved in the first address space is kept
+        let mut analyzer = new_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks[4],
+            (0x1000, ControlFlowTransitionKind::ConditionalBranch)
+        );
+
+        let mut analyzer = new_analyzer();
+        analyzer.clear_cache_on_address_space_change(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+                (0x1004, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+
+        // Pending TNT bits are resolved in the previous address space before clearing
+        let trace = PtBuilder::new()
+            .psb()
+            .pip(0x1000)
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true])
+            .pip(0x2000)
+            .tip(0x1000)
+            .build();
+        let mut analyzer = new_analyzer();
+        analyzer.clear_cache_on_address_space_change(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reuse_across_traces() {
+        // Leave pending TNT bits at the end of trace
+        let first_trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true])
+            .build();
+        // No PSB, so nothing is reset by packets
+        let second_trace = PtBuilder::new().short_tnt(&[true]).tip(0x1000).build();
+        let mut options = DecodeOptions::default();
+        options.sync(false);
+
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&first_trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [(0x1000, ControlFlowTransitionKind::NewBlock)]
+        );
+        analyzer.reset().unwrap();
+        assert!(analyzer.handler().blocks.is_empty());
+
+        iptr_decoder::decode(&first_trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        iptr_decoder::decode(&second_trace, options, &mut analyzer).unwrap();
+        // TNT bits without previous TIP are ignored
+        assert_eq!(
+            analyzer.handler().blocks,
+            [(0x1000, ControlFlowTransitionKind::Indirect)]
+        );
+    }
+
+    #[test]
+    fn test_sign_extended_ip() {
+        // Kernel address with bit 47 set, and near-zero address
+        for base in [0xFFFF_8000_0000_1000, 0x10] {
+            let trace = PtBuilder::new()
+                .psb()
+                .psbend()
+                .tip_pge_sext(base)
+                .short_tnt(&[true, false])
+                .tip_sext(base)
+                .build();
+            let mut analyzer = EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[(base, &LOOP_CODE)]),
+            );
+            iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+            assert_eq!(
+                analyzer.handler().blocks,
+                [
+                    (base, ControlFlowTransitionKind::NewBlock),
+                    (base, ControlFlowTransitionKind::ConditionalBranch),
+                    (base + 2, ControlFlowTransitionKind::ConditionalBranch),
+                    (base, ControlFlowTransitionKind::Indirect),
+                ],
+                "{base:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_out_of_context_tip_as_gap() {
+        /// TIP packet with out-of-context IP
+        const OUT_OF_CONTEXT_TIP: [u8; 1] = [0x0D];
+
+        let gap_trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, false])
+            .raw(&OUT_OF_CONTEXT_TIP)
+            // Ignored in untraced region
+            .short_tnt(&[true])
+            .tip(0x1000);
+        let mut analyzer = loop_analyzer();
+        let res = iptr_decoder::decode(
+            &gap_trace.clone().build(),
+            DecodeOptions::default(),
+            &mut analyzer,
+        );
+        assert!(matches!(
+            res,
+            Err(iptr_decoder::error::DecoderError::PacketHandler(
+                AnalyzerError::InvalidPacket
+            ))
+        ));
+
+        analyzer.out_of_context_tip_as_gap(true);
+        iptr_decoder::decode(
+            &gap_trace.clone().build(),
+            DecodeOptions::default(),
+            &mut analyzer,
+        )
+        .unwrap();
+        assert!(analyzer.is_in_untraced_region());
+
+        let trace = gap_trace.tip_pge(0x1000).short_tnt(&[false]).tip(0x1000);
+        let mut analyzer = loop_analyzer();
+        analyzer.out_of_context_tip_as_gap(true);
+        iptr_decoder::decode(&trace.build(), DecodeOptions::default(), &mut analyzer).unwrap();
+        assert!(!analyzer.is_in_untraced_region());
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                // Resumed by TIP.PGE
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ovf_statistics() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, true])
+            .ovf()
+            .tip_pge(0x1000)
+            .short_tnt(&[true])
+            .ovf()
+            .fup(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        let diagnose = analyzer.diagnose();
+        assert_eq!(diagnose.ovf_count, 2);
+        assert_eq!(diagnose.ovf_discarded_tnt_bit_count, 3);
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ovf_followed_by_tip_pge() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .ovf()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ovf_followed_by_psb() {
+        // OVF -> PSB -> PSBEND -> TIP.PGE
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true])
+            .ovf()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(analyzer.diagnose().ovf_discarded_tnt_bit_count, 1);
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+
+        // OVF -> PSB -> FUP -> PSBEND, the FUP binds the IP
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .ovf()
+            .psb()
+            .fup(0x1000)
+            .psbend()
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_segments() {
+        let first_segment = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true])
+            .tip(0x1000)
+            .build();
+        // Garbage before PSB should be skipped
+        let second_segment = PtBuilder::new()
+            .raw(&[0xFF, 0xFF])
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode_segments(
+            [&first_segment, &second_segment],
+            DecodeOptions::default(),
+            &mut analyzer,
+        )
+        .unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_iced_options() {
+        /// ```plaintext
+        /// 0x1000: lock add eax, eax
+        /// 0x1003: jnz 0x1000
+        /// 0x1005: jmp rax
+        /// ```
+        const LOCK_CODE: [u8; 7] = [0xF0, 0x01, 0xC0, 0x75, 0xFB, 0xFF, 0xE0];
+        // Padding, so that the invalid instruction is not treated as cross-page
+        let code = [&LOCK_CODE[..], &[0xCC; 16]].concat();
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x1000, &code)]),
+        );
+        let res = iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer);
+        assert!(matches!(
+            res,
+            Err(iptr_decoder::error::DecoderError::PacketHandler(
+                AnalyzerError::InvalidInstruction { insn_addr: 0x1000 }
+            ))
+        ));
+
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x1000, &code)]),
+        );
+        analyzer.iced_options(iced_x86::DecoderOptions::NO_INVALID_CHECK);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1005, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_instruction_address() {
+        /// ```plaintext
+        /// 0x1000: nop
+        /// 0x1001: (bad)
+        /// ```
+        const BAD_CODE: [u8; 2] = [0x90, 0x06];
+        // Padding, so that the invalid instruction is not treated as cross-page
+        let code = [&BAD_CODE[..], &[0xCC; 16]].concat();
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x1000, &code)]),
+        );
+        let Err(iptr_decoder::error::DecoderError::PacketHandler(err)) =
+            iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer)
+        else {
+            panic!("Expect an analyzer error");
+        };
+        assert!(matches!(
+            err,
+            AnalyzerError::InvalidInstruction { insn_addr: 0x1001 }
+        ));
+        assert_eq!(err.to_string(), "Invalid instruction at 0x1001");
+    }
+
+    #[test]
+    fn test_mode32_branch_wraparound() {
+        /// ```plaintext
+        /// 0xFFFFFFFA: jnz 0x2
+        /// 0xFFFFFFFC: jmp eax
+        /// ```
+        const HIGH_CODE: [u8; 6] = [0x75, 0x06, 0xFF, 0xE0, 0xCC, 0xCC];
+        /// ```plaintext
+        /// 0x2: jmp eax
+        /// ```
+        const LOW_CODE: [u8; 4] = [0xCC, 0xCC, 0xFF, 0xE0];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0xFFFF_FFFA)
+            .short_tnt(&[true])
+            .tip(0xFFFF_FFFA)
+            .short_tnt(&[false])
+            .tip(0xFFFF_FFFA)
+            .build();
+        let mut options = DecodeOptions::default();
+        options.tracee_mode(TraceeMode::Mode32);
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0xFFFF_FFFA, &HIGH_CODE), (0x0, &LOW_CODE)]),
+        );
+        iptr_decoder::decode(&trace, options, &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0xFFFF_FFFA, ControlFlowTransitionKind::NewBlock),
+                (0x2, ControlFlowTransitionKind::ConditionalBranch),
+                (0xFFFF_FFFA, ControlFlowTransitionKind::Indirect),
+                (0xFFFF_FFFC, ControlFlowTransitionKind::ConditionalBranch),
+                (0xFFFF_FFFA, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mode16_near_jump() {
+        /// ```plaintext
+        /// 0xFFF0: jmp 0x13
+        /// ```
+        const HIGH_CODE: [u8; 3] = [0xE9, 0x20, 0x00];
+        /// ```plaintext
+        /// 0x13: jnz 0x13
+        /// 0x15: jmp ax
+        /// ```
+        const LOW_CODE: [u8; 4] = [0x75, 0xFE, 0xFF, 0xE0];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0xFFF0)
+            .short_tnt(&[false])
+            .tip(0xFFF0)
+            .build();
+        let mut options = DecodeOptions::default();
+        options.tracee_mode(TraceeMode::Mode16);
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0xFFF0, &HIGH_CODE), (0x13, &LOW_CODE)]),
+        );
+        iptr_decoder::decode(&trace, options, &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0xFFF0, ControlFlowTransitionKind::NewBlock),
+                (0x13, ControlFlowTransitionKind::DirectJump),
+                (0x15, ControlFlowTransitionKind::ConditionalBranch),
+                (0xFFF0, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_async_event() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .fup(0x1002)
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(analyzer.handler().async_events, [(0x1002, 0x1002)]);
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+            ]
+        );
+
+        // FUP in PSB+ is not an asynchronous event
+        let trace = PtBuilder::new()
+            .psb()
+            .fup(0x1000)
+            .psbend()
+            .tip(0x1000)
+            .build();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert!(analyzer.handler().async_events.is_empty());
+    }
+
+    #[test]
+    fn test_vmcs_switch() {
+        /// ```plaintext
+        /// 0x1000: nop
+        /// 0x1001: jnz 0x1000
+        /// 0x1003: jmp rax
+        /// ```
+        const GUEST_CODE: [u8; 5] = [0x90, 0x75, 0xFD, 0xFF, 0xE0];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .vmcs(0x1_0000)
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .vmcs(0x2_0000)
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[])
+                .with_vmcs(0x1_0000, &[(0x1000, &LOOP_CODE)])
+                .with_vmcs(0x2_0000, &[(0x1000, &GUEST_CODE)]),
+        );
+        analyzer.clear_cache_on_address_space_change(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1003, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_psb_plus_fup() {
+        let trace = PtBuilder::new()
+            .psb()
+            .fup(0x1000)
+            .psbend()
+            .short_tnt(&[true, false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        // The FUP establishes the current block without any edge
+        assert_eq!(
+            analyzer.handler().edges,
+            [
+                (0x1000, 0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, 0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, 0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_cfg_snapshot_round_trip() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let snapshot = serde_json::to_string(&analyzer.cfg_snapshot()).unwrap();
+
+        // No memory available, so CFG nodes can only come from the snapshot
+        let mut analyzer = EdgeAnalyzer::new(BlockRecorder::default(), SliceMemoryReader::new(&[]));
+        analyzer.load_cfg_snapshot(serde_json::from_str(&snapshot).unwrap());
+        assert_eq!(analyzer.diagnose().cfg_size, 2);
+        assert_eq!(
+            serde_json::to_string(&analyzer.cfg_snapshot()).unwrap(),
+            snapshot
+        );
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_prepopulated_cfg_only() {
+        use crate::memory_reader::null::NullMemoryReader;
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        let snapshot = analyzer.cfg_snapshot();
+        let blocks = analyzer.handler().blocks.clone();
+
+        let mut analyzer = EdgeAnalyzer::new(BlockRecorder::default(), NullMemoryReader);
+        analyzer.prepopulated_cfg_only(true);
+        analyzer.load_cfg_snapshot(snapshot);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.handler().blocks, blocks);
+        assert_eq!(analyzer.diagnose().cfg_size, 2);
+
+        // Indirect branch to a basic block not covered by the CFG graph
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x2000)
+            .short_tnt(&[true])
+            .tip(0x1000)
+            .build();
+        assert!(matches!(
+            iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer),
+            Err(iptr_decoder::error::DecoderError::PacketHandler(
+                AnalyzerError::BlockNotInPrepopulatedCfg { address: 0x2000 }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_mode_exec_change() {
+        // 64-bit:
+        // 0x1000: mov rax, 0xF875_0000_0000
+        // 0x100A: jnz 0x1000
+        // 0x100C: jmp rax
+        //
+        // 32-bit:
+        // 0x1000: dec eax
+        // 0x1001: mov eax, 0
+        // 0x1006: jnz 0x1000
+        // 0x1008: add [eax], al
+        // 0x100A: jnz 0x1000
+        // 0x100C: jmp eax
+        const CODE: [u8; 14] = [
+            0x48, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x75, 0xF8, 0x00, 0x00, 0x75, 0xF4, 0xFF, 0xE0,
+        ];
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x1000)
+            .mode_exec(TraceeMode::Mode32)
+            .short_tnt(&[true, false, false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x1000, &CODE)]),
+        );
+        // The same code is executed in different modes
+        #[cfg(feature = "cache")]
+        analyzer.clear_cache_on_mode_change(true);
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x100C, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1008, ControlFlowTransitionKind::ConditionalBranch),
+                (0x100C, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edges() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, true, false])
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        assert_eq!(
+            analyzer.handler().edges,
+            [
+                (0x1000, 0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, 0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, 0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, 0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_follow_direct_transitions() {
+        /// ```plaintext
+        /// 0x2000: jmp 0x2010
+        /// 0x2010: jmp rax
+        /// ```
+        const JMP_CODE: [u8; 18] = [
+            0xEB, 0x0E, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+            0xCC, 0xCC, 0xFF, 0xE0,
+        ];
+
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x2000)
+            .tip(0x1000)
+            .build();
+        let mut analyzer = EdgeAnalyzer::new(
+            BlockRecorder::default(),
+            SliceMemoryReader::new(&[(0x1000, &LOOP_CODE), (0x2000, &JMP_CODE)]),
+        );
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().edges,
+            [
+                (0x1000, 0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, 0x2000, ControlFlowTransitionKind::Indirect),
+                (0x2000, 0x2010, ControlFlowTransitionKind::DirectJump),
+                (0x2010, 0x1000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+
+        // The source is unknown if the previous block is unmapped
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[false])
+            .tip(0x5000)
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(
+            analyzer.handler().edges,
+            [
+                (0x1000, 0x1002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, 0x5000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+        assert_eq!(
+            analyzer.handler().blocks.last(),
+            Some(&(0x1000, ControlFlowTransitionKind::Indirect))
+        );
+    }
+
+    #[test]
+    fn test_flush_trailing_tnts() {
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, true, false])
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+        assert_eq!(analyzer.pending_tnt_bits(), 3);
+        assert_eq!(analyzer.handler().blocks.len(), 1);
+
+        analyzer.flush().unwrap();
+        assert_eq!(analyzer.pending_tnt_bits(), 0);
+        assert_eq!(
+            analyzer.handler().blocks,
+            [
+                (0x1000, ControlFlowTransitionKind::NewBlock),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x1002, ControlFlowTransitionKind::ConditionalBranch),
+            ]
+        );
+
+        // Nothing to flush
+        analyzer.flush().unwrap();
+        assert_eq!(analyzer.handler().blocks.len(), 4);
+    }
+
+    #[test]
+    fn test_resume_context() {
+        const BASE: u64 = 0x7F00_1000;
+        let first = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(BASE)
+            .short_tnt(&[true, false])
+            .build();
+        let second = PtBuilder::new()
+            .tip_two_bytes(BASE)
+            .short_tnt(&[false])
+            .tip_two_bytes(BASE)
+            .build();
+        let analyzer = || {
+            EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[(BASE, &LOOP_CODE)]),
+            )
+        };
+
+        let mut one_shot = analyzer();
+        let trace = [first.as_slice(), second.as_slice()].concat();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut one_shot).unwrap();
+
+        let mut split = analyzer();
+        iptr_decoder::decode(&first, DecodeOptions::default(), &mut split).unwrap();
+        let mut blocks = split.handler().blocks.clone();
+        let resume_context = split.resume_context();
+        let mut options = DecodeOptions::default();
+        options.sync(false);
+        // Without resuming, the compressed IP cannot be reconstructed
+        assert!(iptr_decoder::decode(&second, options, &mut split).is_err());
+        split.resume_with(resume_context);
+        iptr_decoder::decode(&second, options, &mut split).unwrap();
+        blocks.extend_from_slice(&split.handler().blocks);
+        assert_eq!(blocks, one_shot.handler().blocks);
+    }
+
+    #[test]
+    #[cfg(feature = "more_diagnose")]
+    fn test_deferred_tip_statistics() {
+        // All the not-taken bits come before the TIPs of `jmp rax`
+        let trace = PtBuilder::new()
+            .psb()
+            .psbend()
+            .tip_pge(0x1000)
+            .short_tnt(&[true, false, false, false])
+            .tip(0x1000)
+            .tip(0x1000)
+            .tip(0x1000)
+            .build();
+        let mut analyzer = loop_analyzer();
+        iptr_decoder::decode(&trace, DecodeOptions::default(), &mut analyzer).unwrap();
+
+        let diagnose = analyzer.diagnose();
+        assert_eq!(diagnose.deferred_tip_count, 2);
+        assert_eq!(diagnose.max_pending_tnt_bits, 4);
+        assert_eq!(analyzer.pending_tnt_bits(), 0);
+    }
+
+    /// Decode `bits` encoded in both long TNT and short TNT packets, where a TIP
+    /// to 0x1000 is inserted after each segment, and assert the block outputs
+    /// are identical.
+    fn assert_long_tnt_same_as_short_tnt(
+        segments: &[Vec<bool>],
+    ) -> Vec<(u64, ControlFlowTransitionKind)> {
+        /// ```plaintext
+        /// 0x1000: jnz 0x1000
+        /// 0x1002: jz 0x1000
+        /// 0x1004: jmp rax
+        /// ```
+        const BRANCH_CODE: [u8; 6] = [0x75, 0xFE, 0x74, 0xFC, 0xFF, 0xE0];
+
+        let mut long_trace = PtBuilder::new().psb().psbend().tip_pge(0x1000);
+        let mut short_trace = PtBuilder::new().psb().psbend().tip_pge(0x1000);
+        for segment in segments {
+            for bits in segment.chunks(47) {
+                long_trace = long_trace.long_tnt(bits);
+            }
+            for bits in segment.chunks(6) {
+                short_trace = short_trace.short_tnt(bits);
+            }
+            long_trace = long_trace.tip(0x1000);
+            short_trace = short_trace.tip(0x1000);
+        }
+
+        let mut blocks = [long_trace, short_trace].map(|trace| {
+            let mut analyzer = EdgeAnalyzer::new(
+                BlockRecorder::default(),
+                SliceMemoryReader::new(&[(0x1000, &BRANCH_CODE)]),
+            );
+            iptr_decoder::decode(&trace.build(), DecodeOptions::default(), &mut analyzer).unwrap();
+            analyzer.into_handler_and_reader().0.blocks
+        });
+        assert_eq!(blocks[0], blocks[1]);
+        std::mem::take(&mut blocks[0])
+    }
+
+    #[test]
+    fn test_long_tnt_fast_path() {
+        const SEGMENT_COUNT: usize = 64;
+        /// Multiple of both 47 and 6, so that packets of both encodings
+        /// end at the segment end
+        const SEGMENT_BITS: usize = 282;
+
+        // Pseudo-random bits without two consecutive not-taken bits,
+        // except at the end of each segment.
+        let mut seed = 0x1234_5678u32;
+        let segments = (0..SEGMENT_COUNT)
+            .map(|_| {
+                let mut segment = Vec::with_capacity(SEGMENT_BITS);
+                let mut prev = true;
+                for _ in 0..SEGMENT_BITS - 2 {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    let bit = !prev || seed & 0x1000_0000 != 0;
+                    segment.push(bit);
+                    prev = bit;
+                }
+                segment[SEGMENT_BITS - 3] = true;
+                segment.extend([false, false]);
+                segment
+            })
+            .collect::<Vec<_>>();
+        let blocks = assert_long_tnt_same_as_short_tnt(&segments);
+        let indirect_count = blocks
+            .iter()
+            .filter(|(_, kind)| *kind == ControlFlowTransitionKind::Indirect)
+            .count();
+        assert_eq!(indirect_count, SEGMENT_COUNT);
+
+        // Deferred TIPs in the middle of a long TNT packet
+        let mut bits = vec![true; 10];
+        bits.extend([false, false]);
+        bits.extend([true, false].repeat(13));
+        bits.extend([true, false, false]);
+        assert_eq!(bits.len(), 41);
+        let blocks = assert_long_tnt_same_as_short_tnt(&[bits, vec![]]);
+        let indirect_count = blocks
+            .iter()
+            .filter(|(_, kind)| *kind == ControlFlowTransitionKind::Indirect)
+            .count();
+        assert_eq!(indirect_count, 2);
+
+        // Deferred TIP within the first 15 bits of a 47-bit long TNT packet, whose
+        // remaining bits do not fit in a 32-bit buffer
+        let mut bits = vec![false, false];
+        bits.extend([true, false].repeat(21));
+        bits.extend([true, false, false]);
+        assert_eq!(bits.len(), 47);
+        let blocks = assert_long_tnt_same_as_short_tnt(&[bits, vec![]]);
+        let indirect_count = blocks
+            .iter()
+            .filter(|(_, kind)| *kind == ControlFlowTransitionKind::Indirect)
+            .count();
+        assert_eq!(indirect_count, 2);
+    }
 }
@@ -0,0 +1,248 @@
+//! This module contains a memory reader decorator that caches pages read
+//! from the underlying memory reader.
+
+use hashbrown::HashMap;
+
+use super::ReadMemory;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Memory reader that memoizes page reads of the wrapped memory reader.
+///
+/// Each time a page is first accessed, the whole page is read from the wrapped
+/// reader and kept in memory, so that subsequent reads in the same page
+/// will not touch the wrapped reader. This is helpful when the wrapped reader
+/// is expensive to query, e.g., backed by files or sparse mappings.
+///
+/// Reads returned by this reader never cross a page boundary. If a page
+/// cannot be read from its base address (e.g., the mapping starts in the
+/// middle of a page), the read is forwarded to the wrapped reader without
+/// caching.
+///
+/// The cache is invalidated at [`at_decode_begin`][ReadMemory::at_decode_begin],
+/// [`on_address_space_change`][ReadMemory::on_address_space_change] and
+/// [`on_vmcs_change`][ReadMemory::on_vmcs_change].
+pub struct CachingMemoryReader<R: ReadMemory> {
+    reader: R,
+    /// Page base address to page content. Content may be shorter than
+    /// a page if the wrapped reader could not provide a full page.
+    pages: HashMap<u64, Box<[u8]>>,
+}
+
+impl<R: ReadMemory> CachingMemoryReader<R> {
+    /// Create a new caching memory reader wrapping `reader`
+    #[must_use]
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Get shared reference to the wrapped memory reader
+    #[must_use]
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get unique reference to the wrapped memory reader.
+    ///
+    /// If the memory content is modified via the returned reference,
+    /// [`clear`][CachingMemoryReader::clear] should be called.
+    #[must_use]
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume the caching reader and get the wrapped memory reader
+    #[must_use]
+    pub fn into_reader(self) -> R {
+        self.reader
+    }
+
+    /// Get the number of cached pages
+    #[must_use]
+    pub fn cached_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Clear all cached pages
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    /// Get the cached page containing `address`, reading it from the wrapped
+    /// reader if not cached.
+    ///
+    /// Returns the page content and the offset of `address` in it, or [`None`]
+    /// if the page does not cover `address`.
+    #[expect(clippy::cast_possible_truncation)]
+    fn page(&mut self, address: u64) -> Option<(&[u8], usize)> {
+        let page_base = address & !(PAGE_SIZE - 1);
+        let offset = (address - page_base) as usize;
+        let page = match self.pages.entry(page_base) {
+            hashbrown::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                let content = self
+                    .reader
+                    .read_memory(page_base, PAGE_SIZE as usize, |content| {
+                        Box::<[u8]>::from(content)
+                    })
+                    .ok()?;
+                entry.insert(content)
+            }
+        };
+        (offset < page.len()).then_some((&**page, offset))
+    }
+}
+
+impl<R: ReadMemory> ReadMemory for CachingMemoryReader<R> {
+    type Error = R::Error;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        self.reader.at_decode_begin()
+    }
+
+    fn read_memory<T>(
+        &mut self,
+        address: u64,
+        size: usize,
+        callback: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Self::Error> {
+        let Some((page, offset)) = self.page(address) else {
+            return self.reader.read_memory(address, size, callback);
+        };
+        let end = std::cmp::min(page.len(), offset.saturating_add(size));
+        Ok(callback(&page[offset..end]))
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn readable_len(&self, address: u64) -> usize {
+        let readable_len = self.reader.readable_len(address);
+        if readable_len == 0 {
+            return 0;
+        }
+        let page_remain = (PAGE_SIZE - (address & (PAGE_SIZE - 1))) as usize;
+        std::cmp::min(readable_len, page_remain)
+    }
+
+    fn is_mapped(&self, address: u64) -> bool {
+        self.reader.is_mapped(address)
+    }
+
+    fn on_address_space_change(&mut self, cr3: u64) -> Result<(), Self::Error> {
+        self.clear();
+        self.reader.on_address_space_change(cr3)
+    }
+
+    fn on_vmcs_change(&mut self, vmcs_pointer: u64) -> Result<(), Self::Error> {
+        self.clear();
+        self.reader.on_vmcs_change(vmcs_pointer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::SliceMemoryReader;
+
+    /// Memory reader that counts the number of reads
+    struct CountingReader {
+        reader: SliceMemoryReader,
+        reads: usize,
+    }
+
+    impl ReadMemory for CountingReader {
+        type Error = <SliceMemoryReader as ReadMemory>::Error;
+
+        fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+            self.reader.at_decode_begin()
+        }
+
+        fn read_memory<T>(
+            &mut self,
+            address: u64,
+            size: usize,
+            callback: impl FnOnce(&[u8]) -> T,
+        ) -> Result<T, Self::Error> {
+            self.reads += 1;
+            self.reader.read_memory(address, size, callback)
+        }
+
+        fn readable_len(&self, address: u64) -> usize {
+            self.reader.readable_len(address)
+        }
+
+        fn on_address_space_change(&mut self, cr3: u64) -> Result<(), Self::Error> {
+            self.reader.on_address_space_change(cr3)
+        }
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn regions() -> Vec<(u64, Vec<u8>)> {
+        vec![
+            (0x1000, (0..0x2000u32).map(|i| i as u8).collect()),
+            // Mapping not aligned to page
+            (0x5800, vec![0xCC; 0x100]),
+        ]
+    }
+
+    fn slice_reader() -> SliceMemoryReader {
+        let regions = regions();
+        let regions = regions
+            .iter()
+            .map(|(addr, content)| (*addr, content.as_slice()))
+            .collect::<Vec<_>>();
+        SliceMemoryReader::new(&regions)
+    }
+
+    #[test]
+    fn test_same_content() {
+        let mut reader = slice_reader();
+        let mut caching_reader = CachingMemoryReader::new(slice_reader());
+        for (address, size) in [
+            (0x1000, 0x10),
+            (0x1FF8, 0x10),
+            (0x2000, 0x2000),
+            (0x2FFF, 1),
+            (0x5800, 0x10),
+            (0x58F0, 0x20),
+        ] {
+            let expected = reader.read_memory(address, size, <[u8]>::to_vec).unwrap();
+            let got = caching_reader
+                .read_memory(address, size, <[u8]>::to_vec)
+                .unwrap();
+            // Caching reader never reads across pages
+            assert_eq!(got, expected[..got.len()], "{address:#x}");
+            assert!(!got.is_empty());
+            assert!(caching_reader.readable_len(address) <= reader.readable_len(address));
+        }
+        assert!(caching_reader.read_memory(0x4000, 1, |_| ()).is_err());
+        assert!(caching_reader.read_memory(0x3000, 1, |_| ()).is_err());
+    }
+
+    #[test]
+    fn test_cache_hit() {
+        let mut reader = CachingMemoryReader::new(CountingReader {
+            reader: slice_reader(),
+            reads: 0,
+        });
+        reader.read_memory(0x1000, 0x10, |_| ()).unwrap();
+        reader.read_memory(0x1100, 0x10, |_| ()).unwrap();
+        reader.read_memory(0x1FF0, 0x20, |_| ()).unwrap();
+        assert_eq!(reader.reader().reads, 1);
+        assert_eq!(reader.cached_pages(), 1);
+
+        reader.read_memory(0x2000, 0x10, |_| ()).unwrap();
+        assert_eq!(reader.reader().reads, 2);
+
+        reader.on_address_space_change(0).unwrap();
+        assert_eq!(reader.cached_pages(), 0);
+        reader.read_memory(0x1000, 0x10, |_| ()).unwrap();
+        assert_eq!(reader.reader().reads, 3);
+
+        reader.at_decode_begin().unwrap();
+        assert_eq!(reader.cached_pages(), 0);
+    }
+}
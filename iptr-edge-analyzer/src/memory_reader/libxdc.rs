@@ -126,4 +126,24 @@ impl ReadMemory for LibxdcMemoryReader {
         };
         Ok(callback(mem))
     }
+    #[expect(clippy::cast_possible_truncation)]
+    fn readable_len(&self, address: u64) -> usize {
+        let pos = match self
+            .page_maps
+            .binary_search_by_key(&address, |(addr, _)| *addr)
+        {
+            Ok(pos) => pos,
+            Err(0) => return 0,
+            Err(pos) => pos - 1,
+        };
+        let Some((page_addr, _)) = self.page_maps.get(pos) else {
+            return 0;
+        };
+        // Adjacent pages are not guaranteed to be continuous in page dump
+        PAGE_SIZE.saturating_sub((address - page_addr) as usize)
+    }
+
+    fn is_mapped(&self, address: u64) -> bool {
+        self.readable_len(address) != 0
+    }
 }
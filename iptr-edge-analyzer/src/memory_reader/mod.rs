@@ -1,10 +1,16 @@
 //! This module contains the core definition of [`ReadMemory`] trait,
 //! and several implementors like [`PerfMmapBasedMemoryReader`][perf_mmap::PerfMmapBasedMemoryReader].
 
+pub mod caching;
 #[cfg(feature = "libxdc_memory_reader")]
 pub mod libxdc;
+pub mod null;
 #[cfg(feature = "perf_memory_reader")]
 pub mod perf_mmap;
+pub mod privilege_split;
+#[cfg(all(feature = "process_memory_reader", target_os = "linux"))]
+pub mod process;
+pub mod slide;
 
 /// Memory reader
 pub trait ReadMemory {
@@ -31,4 +37,52 @@ pub trait ReadMemory {
         size: usize,
         callback: impl FnOnce(&[u8]) -> T,
     ) -> Result<T, Self::Error>;
+
+    /// Get the length of memory that can be read continuously from `address`.
+    ///
+    /// This is used to size the reads when resolving instructions, so that
+    /// instructions are not split across reads unnecessarily. Only implement this
+    /// if the length can be answered cheaply. By default this returns 0, which means
+    /// the length is unknown and [`read_memory`][ReadMemory::read_memory] should be used.
+    #[expect(unused)]
+    fn readable_len(&self, address: u64) -> usize {
+        0
+    }
+
+    /// Check whether there is memory mapped at `address`.
+    ///
+    /// This is used to tell basic blocks in unmapped memory, which are likely caused
+    /// by trace corruption, from genuine failures of reading memory. Only implement
+    /// this if it can be answered cheaply, e.g., by a range check. By default this
+    /// returns `true`, which means it is unknown and
+    /// [`read_memory`][ReadMemory::read_memory] should be used.
+    #[expect(unused)]
+    fn is_mapped(&self, address: u64) -> bool {
+        true
+    }
+
+    /// Callback when the traced address space changes, i.e., a PIP packet
+    /// with a different CR3 value is encountered.
+    ///
+    /// `cr3` follows the same convention as [`HandlePacket::on_pip_packet`][iptr_decoder::HandlePacket::on_pip_packet].
+    /// Readers that serve multiple address spaces could switch the underlying
+    /// memory here. By default this is a nop.
+    #[expect(unused)]
+    fn on_address_space_change(&mut self, cr3: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Callback when the traced virtual machine changes, i.e., a VMCS packet
+    /// with a different VMCS pointer is encountered.
+    ///
+    /// `vmcs_pointer` follows the same convention as [`HandlePacket::on_vmcs_packet`][iptr_decoder::HandlePacket::on_vmcs_packet].
+    /// Readers that serve multiple guests could select the guest images here, and
+    /// then select the address space inside the guest at
+    /// [`on_address_space_change`][ReadMemory::on_address_space_change], which is
+    /// always invoked for the first PIP packet after this callback.
+    /// By default this is a nop.
+    #[expect(unused)]
+    fn on_vmcs_change(&mut self, vmcs_pointer: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
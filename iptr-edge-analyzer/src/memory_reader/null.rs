@@ -0,0 +1,42 @@
+//! This module contains a memory reader without any memory.
+
+use thiserror::Error;
+
+use super::ReadMemory;
+
+/// Error for [`NullMemoryReader`]
+#[derive(Debug, Error)]
+#[error("No memory at {0:#x} in null memory reader")]
+pub struct NullMemoryReaderError(pub u64);
+
+/// Memory reader without any memory, where all reads fail.
+///
+/// This is useful for replaying traces whose basic blocks are all covered
+/// by a pre-populated CFG graph, e.g., loaded by
+/// `EdgeAnalyzer::load_cfg_snapshot` with the `serde` feature. Use it
+/// together with [`prepopulated_cfg_only`][crate::EdgeAnalyzer::prepopulated_cfg_only],
+/// so that missing basic blocks are reported as
+/// [`AnalyzerError::BlockNotInPrepopulatedCfg`][crate::error::AnalyzerError::BlockNotInPrepopulatedCfg].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullMemoryReader;
+
+impl ReadMemory for NullMemoryReader {
+    type Error = NullMemoryReaderError;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn read_memory<T>(
+        &mut self,
+        address: u64,
+        _size: usize,
+        _callback: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Self::Error> {
+        Err(NullMemoryReaderError(address))
+    }
+
+    fn is_mapped(&self, _address: u64) -> bool {
+        false
+    }
+}
@@ -7,6 +7,8 @@ use std::{
 };
 
 use super::ReadMemory;
+use iced_x86::{Decoder as IcedDecoder, DecoderOptions as IcedDecoderOptions};
+use iptr_decoder::TraceeMode;
 use iptr_perf_pt_reader::PerfMmap2Header;
 use memmap2::{Mmap, MmapOptions};
 use thiserror::Error;
@@ -33,8 +35,26 @@ pub struct PerfMmapBasedMemoryReader {
 pub struct MmappedEntry {
     mmap: Mmap,
     virtual_address: u64,
+    /// Whether the `PROT_EXEC` bit is set when mmapped
+    executable: bool,
+    /// Offsets of executable ELF sections inside the entry, only filled for
+    /// executable entries
+    code_offsets: Vec<usize>,
 }
 
+/// `PROT_EXEC` bit of `prot` in mmap2 headers
+const PROT_EXEC: u32 = 0x4;
+
+/// `SHF_EXECINSTR` bit of `sh_flags` in ELF section headers
+const SHF_EXECINSTR: u64 = 0x4;
+
+/// `SHT_NOBITS` section type in ELF section headers
+const SHT_NOBITS: u32 = 8;
+
+/// Number of instructions decoded for each executable entry in
+/// [`PerfMmapBasedMemoryReader::check_executable_entries`]
+const SELF_CHECK_INSTRUCTION_COUNT: usize = 8;
+
 impl MmappedEntry {
     /// Get the content of mmapped entry
     #[must_use]
@@ -48,6 +68,12 @@ impl MmappedEntry {
     pub fn virtual_address(&self) -> u64 {
         self.virtual_address
     }
+
+    /// Whether the entry is mmapped as executable
+    #[must_use]
+    pub fn executable(&self) -> bool {
+        self.executable
+    }
 }
 
 /// Error type for [`PerfMmapBasedMemoryReader`] in the
@@ -136,9 +162,17 @@ impl PerfMmapBasedMemoryReader {
                 mmap2_header.addr.saturating_add(mmap2_header.len),
                 mmap2_header.filename
             );
+            let executable = mmap2_header.prot & PROT_EXEC != 0;
+            let code_offsets = if executable {
+                elf_code_offsets(&file, mmap2_header.pgoff, mmap2_header.len)
+            } else {
+                Vec::new()
+            };
             entries.push(MmappedEntry {
                 mmap,
                 virtual_address: mmap2_header.addr,
+                executable,
+                code_offsets,
             });
         }
 
@@ -148,6 +182,65 @@ impl PerfMmapBasedMemoryReader {
         Ok(Self { entries })
     }
 
+    /// Find the entry containing `address`, returning the entry and the offset
+    /// of `address` inside the entry
+    #[expect(clippy::cast_possible_truncation)]
+    fn find_entry(&self, address: u64) -> Option<(&MmappedEntry, usize)> {
+        let pos = match self
+            .entries
+            .binary_search_by_key(&address, |entry| entry.virtual_address)
+        {
+            Ok(pos) => pos,
+            Err(0) => return None,
+            Err(pos) => pos - 1,
+        };
+        let entry = self.entries.get(pos)?;
+        let start_offset = (address - entry.virtual_address) as usize;
+        (start_offset < entry.mmap.len()).then_some((entry, start_offset))
+    }
+
+    /// Sanity check whether the mmapped files still match the traced binaries.
+    ///
+    /// For each executable entry, the first few instructions of each executable
+    /// ELF section (e.g., `.text`) inside the entry are decoded in `tracee_mode`.
+    /// If any of them is invalid, the file is probably rebuilt after tracing,
+    /// and a warning is logged. Returns the virtual addresses of such entries.
+    ///
+    /// This is only a heuristic. Entries from legacy `PERF_RECORD_MMAP` records
+    /// do not have protection information, and entries of files without ELF
+    /// section headers (e.g., stripped of section headers or not ELF files) have
+    /// no known instruction boundaries, so they are never checked.
+    #[must_use]
+    pub fn check_executable_entries(&self, tracee_mode: TraceeMode) -> Vec<u64> {
+        let mut suspicious_addresses = Vec::new();
+        for entry in self.entries.iter().filter(|entry| entry.executable) {
+            let invalid_instruction = entry.code_offsets.iter().find_map(|offset| {
+                let mut decoder = IcedDecoder::with_ip(
+                    tracee_mode.bitness(),
+                    &entry.mmap[*offset..],
+                    entry.virtual_address + *offset as u64,
+                    IcedDecoderOptions::NONE,
+                );
+                decoder
+                    .iter()
+                    .take(SELF_CHECK_INSTRUCTION_COUNT)
+                    .find(iced_x86::Instruction::is_invalid)
+            });
+            if let Some(instruction) = invalid_instruction {
+                log::warn!(
+                    "Invalid instruction at {:#x} in mmapped entry {:016x}--{:016x}, the binary may be modified after tracing.",
+                    instruction.ip(),
+                    entry.virtual_address,
+                    entry
+                        .virtual_address
+                        .saturating_add(entry.mmap.len() as u64),
+                );
+                suspicious_addresses.push(entry.virtual_address);
+            }
+        }
+        suspicious_addresses
+    }
+
     /// Get mmapped entries.
     ///
     /// The entries are guaranteed to be sorted by virtual addresses
@@ -157,6 +250,61 @@ impl PerfMmapBasedMemoryReader {
     }
 }
 
+/// Get offsets of executable sections of the little-endian ELF `file`, relative
+/// to the mapping of `len` bytes at file offset `pgoff`.
+///
+/// Returns an empty vector if `file` is not an ELF file or has no section headers.
+#[expect(clippy::cast_possible_truncation)]
+fn elf_code_offsets(file: &File, pgoff: u64, len: u64) -> Vec<usize> {
+    // SAFETY: check the safety requirements of memmap2 documentation
+    let Ok(elf) = (unsafe { Mmap::map(file) }) else {
+        return Vec::new();
+    };
+    let read = |offset: u64, size: usize| -> Option<u64> {
+        let start = usize::try_from(offset).ok()?;
+        let bytes = elf.get(start..start.checked_add(size)?)?;
+        let mut value = [0u8; 8];
+        value[..size].copy_from_slice(bytes);
+        Some(u64::from_le_bytes(value))
+    };
+    // Offsets of `e_shoff`, `e_shentsize` and `e_shnum` in the ELF header, offsets
+    // of `sh_flags`, `sh_offset` and `sh_size` in section headers, and the size of
+    // address-sized fields
+    let (shoff, shentsize, shnum, flags, offset, size, word) = match elf.get(..6) {
+        Some([0x7F, b'E', b'L', b'F', 1, 1]) => (0x20, 0x2E, 0x30, 0x08, 0x10, 0x14, 4),
+        Some([0x7F, b'E', b'L', b'F', 2, 1]) => (0x28, 0x3A, 0x3C, 0x08, 0x18, 0x20, 8),
+        _ => return Vec::new(),
+    };
+    let (Some(shoff), Some(shentsize), Some(shnum)) =
+        (read(shoff, word), read(shentsize, 2), read(shnum, 2))
+    else {
+        return Vec::new();
+    };
+
+    let mut code_offsets = Vec::new();
+    for index in 0..shnum {
+        let header = shoff + index * shentsize;
+        let (Some(sh_type), Some(sh_flags), Some(sh_offset), Some(sh_size)) = (
+            read(header + 0x04, 4),
+            read(header + flags, word),
+            read(header + offset, word),
+            read(header + size, word),
+        ) else {
+            break;
+        };
+        if sh_type as u32 == SHT_NOBITS
+            || sh_flags & SHF_EXECINSTR == 0
+            || sh_size == 0
+            || sh_offset < pgoff
+            || sh_offset - pgoff >= len
+        {
+            continue;
+        }
+        code_offsets.push((sh_offset - pgoff) as usize);
+    }
+    code_offsets
+}
+
 impl ReadMemory for PerfMmapBasedMemoryReader {
     type Error = PerfMmapBasedMemoryReaderError;
 
@@ -201,4 +349,114 @@ impl ReadMemory for PerfMmapBasedMemoryReader {
         };
         Ok(callback(mem))
     }
+    fn readable_len(&self, address: u64) -> usize {
+        self.find_entry(address)
+            .map_or(0, |(entry, start_offset)| entry.mmap.len() - start_offset)
+    }
+
+    fn is_mapped(&self, address: u64) -> bool {
+        self.find_entry(address).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempFile;
+
+    /// Create a memory reader with files of given contents mmapped at given addresses
+    fn reader_with_files(name: &str, files: &[(u64, &[u8], u32)]) -> PerfMmapBasedMemoryReader {
+        let mut headers = Vec::new();
+        let mut temp_files = Vec::new();
+        for (index, (addr, content, prot)) in files.iter().enumerate() {
+            let temp_file = TempFile::new(&format!("iptr-perf-mmap-{name}-{index}"), content);
+            headers.push(PerfMmap2Header {
+                pid: 0,
+                tid: 0,
+                addr: *addr,
+                len: content.len() as u64,
+                pgoff: 0,
+                inode: [0; 24],
+                prot: *prot,
+                flags: 0,
+                filename: temp_file.path().to_string_lossy().into_owned(),
+            });
+            temp_files.push(temp_file);
+        }
+        PerfMmapBasedMemoryReader::new(&headers).unwrap()
+    }
+
+    #[test]
+    fn test_readable_len() {
+        let reader = reader_with_files("readable-len", &[(0x40_0000, &[0xCC; 0x2000], 0)]);
+
+        assert_eq!(reader.readable_len(0x40_0000), 0x2000);
+        assert_eq!(reader.readable_len(0x40_1FFF), 1);
+        assert_eq!(reader.readable_len(0x40_2000), 0);
+        assert_eq!(reader.readable_len(0x3F_FFFF), 0);
+    }
+
+    #[test]
+    fn test_is_mapped() {
+        let reader = reader_with_files(
+            "is-mapped",
+            &[
+                (0x40_0000, &[0xCC; 0x2000], 0),
+                (0x50_0000, &[0xCC; 0x10], 0),
+            ],
+        );
+
+        assert!(reader.is_mapped(0x40_0000));
+        assert!(reader.is_mapped(0x40_1FFF));
+        assert!(reader.is_mapped(0x50_000F));
+        assert!(!reader.is_mapped(0x3F_FFFF));
+        assert!(!reader.is_mapped(0x40_2000));
+        assert!(!reader.is_mapped(0x50_0010));
+        assert!(!reader.is_mapped(0));
+    }
+
+    /// Create a 64-bit ELF file with a single executable section of `code`
+    fn elf_with_code(code: &[u8]) -> Vec<u8> {
+        let shoff = (0x40 + code.len()).next_multiple_of(8);
+        let mut elf = vec![0u8; shoff + 0x40 * 2];
+        elf[..7].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1]);
+        elf[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        elf[0x3A..0x3C].copy_from_slice(&0x40u16.to_le_bytes());
+        elf[0x3C..0x3E].copy_from_slice(&2u16.to_le_bytes());
+        elf[0x40..0x40 + code.len()].copy_from_slice(code);
+        // The first section header is null, and the second one is `.text`
+        let text = shoff + 0x40;
+        // SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR
+        elf[text + 0x04..text + 0x08].copy_from_slice(&1u32.to_le_bytes());
+        elf[text + 0x08..text + 0x10].copy_from_slice(&6u64.to_le_bytes());
+        elf[text + 0x18..text + 0x20].copy_from_slice(&0x40u64.to_le_bytes());
+        elf[text + 0x20..text + 0x28].copy_from_slice(&(code.len() as u64).to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn test_check_executable_entries() {
+        // `push es` is invalid in 64-bit mode
+        let valid_elf = elf_with_code(&[0x90; 0x10]);
+        let invalid_elf = elf_with_code(&[0x06; 0x10]);
+        let reader = reader_with_files(
+            "self-check",
+            &[
+                (0x40_0000, &valid_elf, PROT_EXEC),
+                (0x50_0000, &invalid_elf, PROT_EXEC),
+                (0x60_0000, &invalid_elf, 0),
+                // Not ELF file, so no instruction boundary is known
+                (0x70_0000, &[0x06; 0x1000], PROT_EXEC),
+            ],
+        );
+        assert_eq!(
+            reader.check_executable_entries(TraceeMode::Mode64),
+            [0x50_0000]
+        );
+        assert!(
+            reader
+                .check_executable_entries(TraceeMode::Mode32)
+                .is_empty()
+        );
+    }
 }
@@ -0,0 +1,193 @@
+//! This module contains a memory reader that routes reads to user-space and
+//! kernel-space memory readers by address.
+
+use perfect_derive::perfect_derive;
+use thiserror::Error;
+
+use super::ReadMemory;
+
+/// Memory reader that serves user-space and kernel-space memory by two
+/// different memory readers.
+///
+/// When tracing both user space and kernel space (e.g., without filtering by
+/// CPL), the control flow follows system calls into the kernel as far
+/// transfers, and the kernel image is usually provided by a different source
+/// from the user-space binaries. This reader routes reads at low-canonical
+/// addresses (bit 63 is cleared) to the `user` reader, and reads at
+/// high-canonical addresses (bit 63 is set) to the `kernel` reader, following
+/// the address space layout of 64-bit Linux and Windows.
+///
+/// All other callbacks are forwarded to both readers, user reader first.
+pub struct PrivilegeSplitMemoryReader<Ru: ReadMemory, Rk: ReadMemory> {
+    user: Ru,
+    kernel: Rk,
+}
+
+/// Error for [`PrivilegeSplitMemoryReader`]
+#[derive(Error)]
+#[perfect_derive(Debug)]
+pub enum PrivilegeSplitMemoryReaderError<Ru: ReadMemory, Rk: ReadMemory> {
+    /// Error of the user-space memory reader
+    #[error(transparent)]
+    User(Ru::Error),
+    /// Error of the kernel-space memory reader
+    #[error(transparent)]
+    Kernel(Rk::Error),
+}
+
+/// Whether `address` is a high-canonical address, i.e., in kernel space
+#[inline]
+fn is_kernel_address(address: u64) -> bool {
+    address >> 63 != 0
+}
+
+impl<Ru: ReadMemory, Rk: ReadMemory> PrivilegeSplitMemoryReader<Ru, Rk> {
+    /// Create a new memory reader routing user-space addresses to `user` and
+    /// kernel-space addresses to `kernel`
+    #[must_use]
+    pub fn new(user: Ru, kernel: Rk) -> Self {
+        Self { user, kernel }
+    }
+
+    /// Get shared reference to the user-space memory reader
+    #[must_use]
+    pub fn user(&self) -> &Ru {
+        &self.user
+    }
+
+    /// Get unique reference to the user-space memory reader
+    #[must_use]
+    pub fn user_mut(&mut self) -> &mut Ru {
+        &mut self.user
+    }
+
+    /// Get shared reference to the kernel-space memory reader
+    #[must_use]
+    pub fn kernel(&self) -> &Rk {
+        &self.kernel
+    }
+
+    /// Get unique reference to the kernel-space memory reader
+    #[must_use]
+    pub fn kernel_mut(&mut self) -> &mut Rk {
+        &mut self.kernel
+    }
+
+    /// Consume the reader and get the user-space and kernel-space memory readers
+    #[must_use]
+    pub fn into_inner(self) -> (Ru, Rk) {
+        (self.user, self.kernel)
+    }
+}
+
+impl<Ru: ReadMemory, Rk: ReadMemory> ReadMemory for PrivilegeSplitMemoryReader<Ru, Rk> {
+    type Error = PrivilegeSplitMemoryReaderError<Ru, Rk>;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.user
+            .at_decode_begin()
+            .map_err(PrivilegeSplitMemoryReaderError::User)?;
+        self.kernel
+            .at_decode_begin()
+            .map_err(PrivilegeSplitMemoryReaderError::Kernel)?;
+
+        Ok(())
+    }
+
+    fn read_memory<T>(
+        &mut self,
+        address: u64,
+        size: usize,
+        callback: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Self::Error> {
+        if is_kernel_address(address) {
+            self.kernel
+                .read_memory(address, size, callback)
+                .map_err(PrivilegeSplitMemoryReaderError::Kernel)
+        } else {
+            self.user
+                .read_memory(address, size, callback)
+                .map_err(PrivilegeSplitMemoryReaderError::User)
+        }
+    }
+
+    fn readable_len(&self, address: u64) -> usize {
+        if is_kernel_address(address) {
+            self.kernel.readable_len(address)
+        } else {
+            self.user.readable_len(address)
+        }
+    }
+
+    fn is_mapped(&self, address: u64) -> bool {
+        if is_kernel_address(address) {
+            self.kernel.is_mapped(address)
+        } else {
+            self.user.is_mapped(address)
+        }
+    }
+
+    fn on_address_space_change(&mut self, cr3: u64) -> Result<(), Self::Error> {
+        self.user
+            .on_address_space_change(cr3)
+            .map_err(PrivilegeSplitMemoryReaderError::User)?;
+        self.kernel
+            .on_address_space_change(cr3)
+            .map_err(PrivilegeSplitMemoryReaderError::Kernel)?;
+
+        Ok(())
+    }
+
+    fn on_vmcs_change(&mut self, vmcs_pointer: u64) -> Result<(), Self::Error> {
+        self.user
+            .on_vmcs_change(vmcs_pointer)
+            .map_err(PrivilegeSplitMemoryReaderError::User)?;
+        self.kernel
+            .on_vmcs_change(vmcs_pointer)
+            .map_err(PrivilegeSplitMemoryReaderError::Kernel)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::SliceMemoryReader;
+
+    #[test]
+    fn test_privilege_split() {
+        const USER_CODE: [u8; 4] = [0x75, 0xFE, 0xFF, 0xE0];
+        const KERNEL_CODE: [u8; 2] = [0x0F, 0x07];
+        const KERNEL_BASE: u64 = 0xFFFF_FFFF_8100_0000;
+
+        let mut reader = PrivilegeSplitMemoryReader::new(
+            SliceMemoryReader::new(&[(0x1000, &USER_CODE)]),
+            SliceMemoryReader::new(&[(KERNEL_BASE, &KERNEL_CODE)]),
+        );
+        assert_eq!(
+            reader.read_memory(0x1000, 0x10, <[u8]>::to_vec).unwrap(),
+            USER_CODE
+        );
+        assert_eq!(
+            reader
+                .read_memory(KERNEL_BASE, 0x10, <[u8]>::to_vec)
+                .unwrap(),
+            KERNEL_CODE
+        );
+        assert_eq!(reader.readable_len(0x1001), 3);
+        assert_eq!(reader.readable_len(KERNEL_BASE + 1), 1);
+        assert!(reader.is_mapped(KERNEL_BASE));
+
+        // The same low bits in the other half are not mapped
+        assert!(!reader.is_mapped(0x8000_0000_0000_1000));
+        assert!(matches!(
+            reader.read_memory(0x8000_0000_0000_1000, 1, |_| ()),
+            Err(PrivilegeSplitMemoryReaderError::Kernel(_))
+        ));
+        assert!(matches!(
+            reader.read_memory(KERNEL_BASE & !(1 << 63), 1, |_| ()),
+            Err(PrivilegeSplitMemoryReaderError::User(_))
+        ));
+    }
+}
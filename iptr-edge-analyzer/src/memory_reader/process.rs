@@ -0,0 +1,140 @@
+//! This module contains a memory reader that reads memory of a live process
+//! via `process_vm_readv`.
+
+use thiserror::Error;
+
+use super::ReadMemory;
+
+/// Memory reader that reads memory of a live process via `process_vm_readv`.
+///
+/// Different from file-backed readers, this reader could also read JIT-compiled
+/// code and other anonymous executable regions. Note that the memory is read
+/// at the time of decoding, so the traced process should be kept alive and
+/// its code should not be modified since tracing.
+///
+/// Reading memory of other processes requires the same permission as
+/// `ptrace`, e.g., `CAP_SYS_PTRACE` or being the parent of the traced process
+/// under Yama.
+pub struct ProcessMemoryReader {
+    /// Process id of the traced process
+    pid: libc::pid_t,
+    /// Buffer for holding read memory
+    buf: Vec<u8>,
+}
+
+impl ProcessMemoryReader {
+    /// Create a [`ProcessMemoryReader`] reading memory of process `pid`.
+    ///
+    /// The process is not checked here, errors are reported at reading.
+    #[must_use]
+    pub fn new(pid: libc::pid_t) -> Self {
+        Self {
+            pid,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Get the process id of the traced process
+    #[must_use]
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+}
+
+/// Error type for [`ProcessMemoryReader`] in the
+/// implementation of [`ReadMemory`]
+#[derive(Debug, Error)]
+pub enum ProcessMemoryReaderError {
+    /// The queried address is not mapped in the traced process
+    #[error("Queried address {0:#x} is not mapped in the process")]
+    NotMapped(u64),
+    /// Failed to read memory of the traced process, e.g., the process has
+    /// exited or the permission is denied
+    #[error("Failed to read memory at {address:#x} of the process")]
+    Io {
+        /// Queried address
+        address: u64,
+        /// Underlying error
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl ReadMemory for ProcessMemoryReader {
+    type Error = ProcessMemoryReaderError;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[expect(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn read_memory<T>(
+        &mut self,
+        address: u64,
+        size: usize,
+        callback: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Self::Error> {
+        self.buf.resize(size, 0);
+        let local_iov = libc::iovec {
+            iov_base: self.buf.as_mut_ptr().cast(),
+            iov_len: size,
+        };
+        let remote_iov = libc::iovec {
+            iov_base: address as usize as *mut libc::c_void,
+            iov_len: size,
+        };
+        // SAFETY: local iovec points to a buffer of `size` bytes, and remote
+        // memory is only accessed by kernel.
+        let read_size = unsafe {
+            libc::process_vm_readv(
+                self.pid,
+                &raw const local_iov,
+                1,
+                &raw const remote_iov,
+                1,
+                0,
+            )
+        };
+        if read_size < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EFAULT) {
+                return Err(ProcessMemoryReaderError::NotMapped(address));
+            }
+            return Err(ProcessMemoryReaderError::Io {
+                address,
+                source: err,
+            });
+        }
+        // Partial read happens if the range crosses into an unmapped page
+        let read_size = read_size as usize;
+        if read_size == 0 && size != 0 {
+            return Err(ProcessMemoryReaderError::NotMapped(address));
+        }
+
+        Ok(callback(&self.buf[..read_size]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static KNOWN_BYTES: [u8; 8] = [0xDE, 0xAD, 0xBE, 0xEF, 0xCA, 0xFE, 0xBA, 0xBE];
+
+    #[test]
+    fn test_read_own_memory() {
+        let pid = libc::pid_t::try_from(std::process::id()).unwrap();
+        let mut reader = ProcessMemoryReader::new(pid);
+        let address = KNOWN_BYTES.as_ptr() as u64;
+        let mem = reader
+            .read_memory(address, KNOWN_BYTES.len(), <[u8]>::to_vec)
+            .unwrap();
+        assert_eq!(mem, KNOWN_BYTES);
+
+        // The first page is never mapped
+        assert!(matches!(
+            reader.read_memory(0x10, 4, <[u8]>::to_vec),
+            Err(ProcessMemoryReaderError::NotMapped(0x10))
+        ));
+    }
+}
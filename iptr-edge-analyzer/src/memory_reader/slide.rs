@@ -0,0 +1,140 @@
+//! This module contains a memory reader decorator that translates traced
+//! addresses by an ASLR slide.
+
+use super::ReadMemory;
+
+/// Memory reader that subtracts a slide from all addresses before delegating
+/// to the wrapped memory reader.
+///
+/// This is useful when the wrapped reader serves the memory at the virtual
+/// addresses of a binary file, while the traced process loaded the binary at a
+/// randomized base. The `slide` is the difference between the traced address
+/// and the address in the binary, i.e., a read at traced address `X` is served
+/// by the wrapped reader at `X - slide` (wrapping on overflow).
+///
+/// Note that only addresses passed to [`ReadMemory`] are translated. Addresses
+/// reported to control flow handlers are still the traced addresses.
+pub struct SlideMemoryReader<R: ReadMemory> {
+    reader: R,
+    /// Traced address minus address in the wrapped reader
+    slide: i64,
+}
+
+impl<R: ReadMemory> SlideMemoryReader<R> {
+    /// Create a new slide memory reader wrapping `reader`
+    #[must_use]
+    pub fn new(reader: R, slide: i64) -> Self {
+        Self { reader, slide }
+    }
+
+    /// Get the slide
+    #[must_use]
+    pub fn slide(&self) -> i64 {
+        self.slide
+    }
+
+    /// Set the slide, e.g., when the traced binary is reloaded at another base
+    pub fn set_slide(&mut self, slide: i64) -> &mut Self {
+        self.slide = slide;
+        self
+    }
+
+    /// Get shared reference to the wrapped memory reader
+    #[must_use]
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get unique reference to the wrapped memory reader
+    #[must_use]
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume the slide reader and get the wrapped memory reader
+    #[must_use]
+    pub fn into_reader(self) -> R {
+        self.reader
+    }
+
+    /// Translate traced address to the address in the wrapped reader
+    fn translate(&self, address: u64) -> u64 {
+        address.wrapping_add_signed(self.slide.wrapping_neg())
+    }
+}
+
+impl<R: ReadMemory> ReadMemory for SlideMemoryReader<R> {
+    type Error = R::Error;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.reader.at_decode_begin()
+    }
+
+    fn read_memory<T>(
+        &mut self,
+        address: u64,
+        size: usize,
+        callback: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Self::Error> {
+        self.reader
+            .read_memory(self.translate(address), size, callback)
+    }
+
+    fn readable_len(&self, address: u64) -> usize {
+        self.reader.readable_len(self.translate(address))
+    }
+
+    fn is_mapped(&self, address: u64) -> bool {
+        self.reader.is_mapped(self.translate(address))
+    }
+
+    fn on_address_space_change(&mut self, cr3: u64) -> Result<(), Self::Error> {
+        self.reader.on_address_space_change(cr3)
+    }
+
+    fn on_vmcs_change(&mut self, vmcs_pointer: u64) -> Result<(), Self::Error> {
+        self.reader.on_vmcs_change(vmcs_pointer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::SliceMemoryReader;
+
+    #[test]
+    fn test_slide() {
+        const CONTENT: [u8; 4] = [0x75, 0xFE, 0xFF, 0xE0];
+
+        let mut reader =
+            SlideMemoryReader::new(SliceMemoryReader::new(&[(0x1000, &CONTENT)]), 0x5555_0000);
+        assert_eq!(
+            reader
+                .read_memory(0x5555_1001, 0x10, <[u8]>::to_vec)
+                .unwrap(),
+            CONTENT[1..]
+        );
+        assert_eq!(reader.readable_len(0x5555_1002), 2);
+        assert!(reader.is_mapped(0x5555_1000));
+        assert!(!reader.is_mapped(0x1000));
+        assert!(reader.read_memory(0x1000, 1, |_| ()).is_err());
+
+        // Negative slide
+        reader.set_slide(-0x800);
+        assert_eq!(
+            reader.read_memory(0x800, 0x10, <[u8]>::to_vec).unwrap(),
+            CONTENT
+        );
+
+        // Slide wraps around the address space
+        reader.set_slide(0x2000);
+        assert!(!reader.is_mapped(0x1000));
+        reader.set_slide(i64::MIN);
+        assert_eq!(
+            reader
+                .read_memory(0x8000_0000_0000_1003, 1, <[u8]>::to_vec)
+                .unwrap(),
+            CONTENT[3..]
+        );
+    }
+}
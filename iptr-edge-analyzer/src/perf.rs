@@ -0,0 +1,240 @@
+//! This module contains a one-call API for analyzing `perf.data` files.
+
+use iptr_decoder::{DecodeOptions, error::DecoderError};
+use iptr_perf_pt_reader::ReaderError;
+use perfect_derive::perfect_derive;
+use thiserror::Error;
+
+use crate::{
+    EdgeAnalyzer, HandleControlFlow,
+    memory_reader::perf_mmap::{PerfMmapBasedMemoryReader, PerfMmapBasedMemoryReaderCreateError},
+};
+
+/// Options for [`analyze_perf_data`]
+#[derive(Clone, Copy, Default)]
+pub struct PerfAnalyzeOptions {
+    /// Options for decoding each thread
+    decode_options: DecodeOptions,
+    /// Only analyze auxtraces of this thread, if given
+    tid: Option<u32>,
+}
+
+impl PerfAnalyzeOptions {
+    /// Create a new [`PerfAnalyzeOptions`] with default options
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the options for decoding each thread. Default is
+    /// [`DecodeOptions::default`].
+    pub fn decode_options(&mut self, decode_options: DecodeOptions) -> &mut Self {
+        self.decode_options = decode_options;
+        self
+    }
+
+    /// Only analyze auxtraces of thread `tid` if given. Default is [`None`],
+    /// i.e., all threads are analyzed.
+    pub fn tid(&mut self, tid: Option<u32>) -> &mut Self {
+        self.tid = tid;
+        self
+    }
+}
+
+/// Error for [`analyze_perf_data`]
+#[derive(Error)]
+#[perfect_derive(Debug)]
+pub enum PerfAnalyzeError<H: HandleControlFlow<Error: 'static>> {
+    /// The `perf.data` cannot be parsed
+    #[error("Failed to parse perf.data")]
+    PerfData(#[source] ReaderError),
+    /// Failed to reconstruct memory from mmapped files
+    #[error("Failed to create memory reader")]
+    MemoryReader(#[source] PerfMmapBasedMemoryReaderCreateError),
+    /// Failed to decode auxtraces of a thread
+    #[error("Failed to decode auxtraces of thread {tid}")]
+    Decode {
+        /// Thread id of the auxtraces
+        tid: u32,
+        /// Source of error
+        #[source]
+        source: DecoderError<EdgeAnalyzer<H, PerfMmapBasedMemoryReader>>,
+    },
+}
+
+/// Analyze the Intel PT traces in `perf.data` with the given control flow
+/// handler, and return the handler after analyzing.
+///
+/// This performs the whole pipeline: extracting auxtraces and mmap records,
+/// reconstructing memory with [`PerfMmapBasedMemoryReader`], and decoding the
+/// auxtraces of each thread with an [`EdgeAnalyzer`]. Threads are decoded in
+/// ascending order of thread id, and the analyzer is reset before each thread,
+/// while the CFG graph and caches are kept. Pending TNT bits at the end of
+/// each thread are flushed with [`EdgeAnalyzer::flush`]. Note that the
+/// [`at_decode_begin`][HandleControlFlow::at_decode_begin] of `handler` is
+/// therefore invoked for each thread.
+///
+/// To analyze a `perf.data` file, you could mmap the file and pass the
+/// mmapped content as `perf_data`.
+pub fn analyze_perf_data<H: HandleControlFlow<Error: 'static>>(
+    perf_data: &[u8],
+    handler: H,
+    options: &PerfAnalyzeOptions,
+) -> Result<H, PerfAnalyzeError<H>> {
+    let PerfAnalyzeOptions {
+        decode_options,
+        tid,
+    } = *options;
+    let (pt_auxtraces, mmap2_headers) =
+        iptr_perf_pt_reader::extract_pt_auxtraces_and_mmap_data(perf_data)
+            .map_err(PerfAnalyzeError::PerfData)?;
+    let memory_reader =
+        PerfMmapBasedMemoryReader::new(&mmap2_headers).map_err(PerfAnalyzeError::MemoryReader)?;
+    let mut edge_analyzer = EdgeAnalyzer::new(handler, memory_reader);
+
+    let pt_auxtraces = pt_auxtraces
+        .into_iter()
+        .filter(|pt_auxtrace| tid.is_none_or(|tid| pt_auxtrace.tid == tid))
+        .collect();
+    for (tid, pt_auxtraces) in iptr_perf_pt_reader::group_auxtraces_by_tid(pt_auxtraces) {
+        iptr_decoder::decode_segments(
+            pt_auxtraces
+                .iter()
+                .map(|pt_auxtrace| pt_auxtrace.auxtrace_data),
+            decode_options,
+            &mut edge_analyzer,
+        )
+        .and_then(|()| edge_analyzer.flush().map_err(DecoderError::PacketHandler))
+        .map_err(|source| PerfAnalyzeError::Decode { tid, source })?;
+    }
+
+    Ok(edge_analyzer.into_handler_and_reader().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ControlFlowTransitionKind,
+        test_utils::{BlockRecorder, PtBuilder, TempFile},
+    };
+
+    /// Value of `type` field for mmapped perf header
+    const PERF_RECORD_MMAP2: u32 = 10;
+    /// Value of `type` field for auxtrace header
+    const PERF_RECORD_AUXTRACE: u32 = 71;
+    /// ```plaintext
+    /// 0x40_0000: jnz 0x40_0000
+    /// 0x40_0002: jmp rax
+    /// ```
+    const LOOP_CODE: [u8; 4] = [0x75, 0xFE, 0xFF, 0xE0];
+
+    /// Append a record to data section of `perf.data`. For AUXTRACE, `data`
+    /// is not counted in the size of record.
+    fn push_record(buf: &mut Vec<u8>, r#type: u32, payload: &[u8], data: &[u8]) {
+        buf.extend_from_slice(&r#type.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&u16::try_from(payload.len() + 8).unwrap().to_le_bytes());
+        buf.extend_from_slice(payload);
+        buf.extend_from_slice(data);
+    }
+
+    /// Build a `perf.data` with `code` mmapped at 0x40_0000 and the given
+    /// `(tid, trace)` auxtraces
+    fn perf_data(code_path: &str, auxtraces: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        let mut mmap2 = Vec::new();
+        mmap2.extend_from_slice(&[0; 8]);
+        mmap2.extend_from_slice(&0x40_0000u64.to_le_bytes());
+        mmap2.extend_from_slice(&(LOOP_CODE.len() as u64).to_le_bytes());
+        mmap2.extend_from_slice(&[0; 32]);
+        // prot: PROT_READ | PROT_EXEC, flags
+        mmap2.extend_from_slice(&5u32.to_le_bytes());
+        mmap2.extend_from_slice(&0u32.to_le_bytes());
+        mmap2.extend_from_slice(code_path.as_bytes());
+        mmap2.resize((mmap2.len() + 8) & !7, 0);
+        push_record(&mut data, PERF_RECORD_MMAP2, &mmap2, &[]);
+
+        for (idx, (tid, trace)) in auxtraces.iter().enumerate() {
+            let mut auxtrace = Vec::new();
+            auxtrace.extend_from_slice(&(trace.len() as u64).to_le_bytes());
+            auxtrace.extend_from_slice(&[0; 16]);
+            auxtrace.extend_from_slice(&u32::try_from(idx).unwrap().to_le_bytes());
+            auxtrace.extend_from_slice(&tid.to_le_bytes());
+            auxtrace.extend_from_slice(&[0; 8]);
+            push_record(&mut data, PERF_RECORD_AUXTRACE, &auxtrace, trace);
+        }
+
+        let mut perf_data = Vec::new();
+        perf_data.extend_from_slice(b"PERFILE2");
+        perf_data.extend_from_slice(&104u64.to_le_bytes());
+        perf_data.extend_from_slice(&0u64.to_le_bytes());
+        // attrs section
+        perf_data.extend_from_slice(&56u64.to_le_bytes());
+        perf_data.extend_from_slice(&0u64.to_le_bytes());
+        // data section
+        perf_data.extend_from_slice(&56u64.to_le_bytes());
+        perf_data.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        perf_data.extend_from_slice(&data);
+        perf_data
+    }
+
+    #[test]
+    fn test_analyze_perf_data() {
+        let code_file = TempFile::new("iptr-perf-analyze", &LOOP_CODE);
+        let code_path = code_file.path();
+
+        let trace = |bits: &[bool]| {
+            PtBuilder::new()
+                .psb()
+                .psbend()
+                .tip_pge(0x40_0000)
+                .short_tnt(bits)
+                .tip(0x40_0000)
+                .build()
+        };
+        let buf = perf_data(
+            &code_path.to_string_lossy(),
+            &[(1, trace(&[false])), (2, trace(&[true, false]))],
+        );
+
+        let mut options = PerfAnalyzeOptions::new();
+        let handler = analyze_perf_data(&buf, BlockRecorder::default(), &options).unwrap();
+        // Handler is reset for each thread, so only the last thread is recorded
+        assert_eq!(
+            handler.blocks,
+            [
+                (0x40_0000, ControlFlowTransitionKind::NewBlock),
+                (0x40_0000, ControlFlowTransitionKind::ConditionalBranch),
+                (0x40_0002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x40_0000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+
+        options.tid(Some(1));
+        let handler = analyze_perf_data(&buf, BlockRecorder::default(), &options).unwrap();
+        assert_eq!(
+            handler.blocks,
+            [
+                (0x40_0000, ControlFlowTransitionKind::NewBlock),
+                (0x40_0002, ControlFlowTransitionKind::ConditionalBranch),
+                (0x40_0000, ControlFlowTransitionKind::Indirect),
+            ]
+        );
+
+        // Code of thread 3 is not mmapped
+        let mut bad_trace = trace(&[false]);
+        bad_trace.truncate(bad_trace.len() - 9);
+        bad_trace.extend(
+            PtBuilder::new()
+                .tip(0x50_0000)
+                .short_tnt(&[true])
+                .tip(0x40_0000)
+                .build(),
+        );
+        let buf = perf_data(&code_path.to_string_lossy(), &[(3, bad_trace)]);
+        let res = analyze_perf_data(&buf, BlockRecorder::default(), &PerfAnalyzeOptions::new());
+        assert!(matches!(res, Err(PerfAnalyzeError::Decode { tid: 3, .. })));
+    }
+}
@@ -1,25 +1,59 @@
 //! This module contains static control flow analyzer
 
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use iced_x86::{
     Code, Decoder as IcedDecoder, DecoderOptions as IcedDecoderOptions, FlowControl, Instruction,
+    Mnemonic, Register,
 };
 use iptr_decoder::TraceeMode;
 
 use crate::{
     HandleControlFlow, ReadMemory,
+    control_flow_handler::FarTransferKind,
     error::{AnalyzerError, AnalyzerResult},
 };
 
+/// An instruction in a basic block, see `EdgeAnalyzer::record_instructions`,
+/// which is only available in non-cache mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInstruction {
+    /// Address of the instruction
+    pub ip: u64,
+    /// Length of the instruction in bytes
+    pub len: u8,
+    /// Decoded instruction code
+    pub code: Code,
+}
+
+impl BlockInstruction {
+    /// Create from the decoded instruction
+    #[expect(clippy::cast_possible_truncation)]
+    fn new(instruction: &Instruction) -> Self {
+        Self {
+            ip: instruction.ip(),
+            // Instructions are no longer than 15 bytes
+            len: instruction.len() as u8,
+            code: instruction.code(),
+        }
+    }
+}
+
+/// Recorded instructions of a basic block, and whether the basic block is
+/// undecodable
+type RecordedInstructions = (Box<[BlockInstruction]>, bool);
+
 /// A node in CFG graph (CALL is also treated as a basic block terminator),
 /// which represents a basic block.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CfgNode {
     /// The terminator of this basic block
     pub terminator: CfgTerminator,
 }
 
 /// Terminator of a CFG node.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CfgTerminator {
     /// A conditional JMP
     Branch {
@@ -27,7 +61,7 @@ pub enum CfgTerminator {
         r#true: u64,
         /// Low 32bits of address of Not Taken branch
         ///
-        /// A branch cannot be inconsistent in high 32 bits
+        /// Use [`branch_false_target`] to recover the full address.
         r#false: u32,
     },
     /// A direct JMP
@@ -39,18 +73,31 @@ pub enum CfgTerminator {
     DirectCall {
         /// Address of call target
         target: u64,
+        /// Address of instruction next to the CALL, i.e., the return address
+        return_address: u64,
     },
     /// An indirect JMP
-    IndirectGoto,
+    IndirectGoto {
+        /// Whether the JMP has the `NOTRACK` prefix, whose target is not
+        /// tracked by Indirect Branch Tracking of Intel CET
+        notrack: bool,
+    },
     /// An indirect CALL
-    IndirectCall,
+    IndirectCall {
+        /// Address of instruction next to the CALL, i.e., the return address
+        return_address: u64,
+        /// Whether the CALL has the `NOTRACK` prefix, whose target is not
+        /// tracked by Indirect Branch Tracking of Intel CET
+        notrack: bool,
+    },
     /// A RET
     NearRet,
     /// Other instructions that changes control flow
     FarTransfers {
         /// Address of instruction next to current instruction
-        #[expect(unused)]
         next_instruction: u64,
+        /// Kind of the far transfer instruction
+        kind: FarTransferKind,
     },
 }
 
@@ -58,59 +105,211 @@ impl CfgTerminator {
     /// Convert an [`Instruction`] to a [`CfgTerminator`].
     ///
     /// Return [`None`] if this instruction does not change control flow.
+    /// All addresses are wrapped to the address width of `tracee_mode`.
     #[expect(clippy::cast_possible_truncation)]
-    fn try_from(instruction: &Instruction) -> Option<Self> {
-        let next_insn_addr = instruction.next_ip();
-
-        if instruction.is_jcc_short_or_near() || instruction.is_loop() || instruction.is_loopcc() {
-            // TODO: check whether LOOP/LOOPcc instruction can also be done this way
-            let true_target = instruction.near_branch_target();
-            let false_target = next_insn_addr as u32;
-            debug_assert_eq!(
-                true_target & 0xFFFF_FFFF_0000_0000,
-                next_insn_addr & 0xFFFF_FFFF_0000_0000,
-                "Two branch upper 32 bits mismatch!"
-            );
-            Some(CfgTerminator::Branch {
-                r#true: true_target,
-                r#false: false_target,
-            })
-        } else if instruction.is_jmp_near_indirect() {
-            Some(CfgTerminator::IndirectGoto)
-        } else if instruction.is_call_near_indirect() {
-            Some(CfgTerminator::IndirectCall)
-        } else if instruction.is_jmp_short_or_near() {
-            let target = instruction.near_branch_target();
-            Some(CfgTerminator::DirectGoto { target })
-        } else if instruction.is_call_near() {
-            let target = instruction.near_branch_target();
-            Some(CfgTerminator::DirectCall { target })
-        } else if matches!(
-            instruction.code(),
-            Code::Retnd
-                | Code::Retnd_imm16
-                | Code::Retnq
-                | Code::Retnq_imm16
-                | Code::Retnw
-                | Code::Retnw_imm16
-        ) {
-            Some(CfgTerminator::NearRet)
-        } else if !matches!(instruction.flow_control(), FlowControl::Next) {
-            Some(CfgTerminator::FarTransfers {
+    fn try_from(instruction: &Instruction, tracee_mode: TraceeMode) -> Option<Self> {
+        let next_insn_addr = tracee_mode.mask_address(instruction.next_ip());
+
+        match instruction.flow_control() {
+            // XBEGIN does not generate TIP or TNT packets. Its fallback is only reached
+            // by transaction aborts, which are reported asynchronously by FUP and TIP
+            // packets, just like interrupts.
+            FlowControl::Next | FlowControl::XbeginXabortXend => None,
+            FlowControl::ConditionalBranch
+                if instruction.is_jcc_short_or_near()
+                    || instruction.is_loop()
+                    || instruction.is_loopcc()
+                    || instruction.is_jcx_short() =>
+            {
+                // TODO: check whether LOOP/LOOPcc instruction can also be done this way
+                let true_target = tracee_mode.mask_address(instruction.near_branch_target());
+                let false_target = next_insn_addr as u32;
+                Some(CfgTerminator::Branch {
+                    r#true: true_target,
+                    r#false: false_target,
+                })
+            }
+            // The `NOTRACK` prefix is the DS segment override prefix (`0x3E`)
+            FlowControl::IndirectBranch if instruction.is_jmp_near_indirect() => {
+                Some(CfgTerminator::IndirectGoto {
+                    notrack: instruction.segment_prefix() == Register::DS,
+                })
+            }
+            FlowControl::IndirectCall if instruction.is_call_near_indirect() => {
+                Some(CfgTerminator::IndirectCall {
+                    return_address: next_insn_addr,
+                    notrack: instruction.segment_prefix() == Register::DS,
+                })
+            }
+            FlowControl::UnconditionalBranch if instruction.is_jmp_short_or_near() => {
+                let target = tracee_mode.mask_address(instruction.near_branch_target());
+                Some(CfgTerminator::DirectGoto { target })
+            }
+            FlowControl::Call if instruction.is_call_near() => {
+                let target = tracee_mode.mask_address(instruction.near_branch_target());
+                Some(CfgTerminator::DirectCall {
+                    target,
+                    return_address: next_insn_addr,
+                })
+            }
+            FlowControl::Return
+                if matches!(
+                    instruction.code(),
+                    Code::Retnd
+                        | Code::Retnd_imm16
+                        | Code::Retnq
+                        | Code::Retnq_imm16
+                        | Code::Retnw
+                        | Code::Retnw_imm16
+                ) =>
+            {
+                Some(CfgTerminator::NearRet)
+            }
+            // Far JMP/CALL/RET, IRET, SYSCALL/SYSRET, INT n, and instructions that
+            // always raise exceptions (e.g., UD2). Their targets are reported by TIP
+            // packets.
+            FlowControl::ConditionalBranch
+            | FlowControl::IndirectBranch
+            | FlowControl::IndirectCall
+            | FlowControl::UnconditionalBranch
+            | FlowControl::Call
+            | FlowControl::Return
+            | FlowControl::Interrupt
+            | FlowControl::Exception => Some(CfgTerminator::FarTransfers {
                 next_instruction: next_insn_addr,
-            })
-        } else {
-            None
+                kind: FarTransferKind::from(instruction),
+            }),
+        }
+    }
+}
+
+impl From<&Instruction> for FarTransferKind {
+    fn from(instruction: &Instruction) -> Self {
+        match instruction.mnemonic() {
+            Mnemonic::Syscall => FarTransferKind::Syscall,
+            Mnemonic::Sysenter => FarTransferKind::Sysenter,
+            Mnemonic::Sysret | Mnemonic::Sysretq | Mnemonic::Sysexit | Mnemonic::Sysexitq => {
+                FarTransferKind::SystemReturn
+            }
+            Mnemonic::Int => FarTransferKind::Interrupt(instruction.immediate8()),
+            Mnemonic::Int1 => FarTransferKind::Interrupt(1),
+            Mnemonic::Int3 => FarTransferKind::Interrupt(3),
+            Mnemonic::Into => FarTransferKind::Interrupt(4),
+            Mnemonic::Iret | Mnemonic::Iretd | Mnemonic::Iretq => FarTransferKind::InterruptReturn,
+            Mnemonic::Call => FarTransferKind::FarCall,
+            Mnemonic::Jmp => FarTransferKind::FarJump,
+            Mnemonic::Retf => FarTransferKind::FarReturn,
+            _ if instruction.flow_control() == FlowControl::Exception => FarTransferKind::Exception,
+            _ => FarTransferKind::Other,
         }
     }
 }
 
+/// Recover the full address of Not Taken branch of [`CfgTerminator::Branch`].
+///
+/// The Not Taken address is the one nearest to `r#true` whose low 32 bits are
+/// `r#false`, which is always correct since the displacement of a branch is at most
+/// 32 bits. The result is wrapped to the address width of `tracee_mode`.
+#[inline]
+#[must_use]
+#[expect(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+pub fn branch_false_target(r#true: u64, r#false: u32, tracee_mode: TraceeMode) -> u64 {
+    let delta = r#false.wrapping_sub(r#true as u32) as i32;
+    tracee_mode.mask_address(r#true.wrapping_add_signed(i64::from(delta)))
+}
+
+/// Serializable snapshot of the CFG graph, which can be retrieved by
+/// [`EdgeAnalyzer::cfg_snapshot`][crate::EdgeAnalyzer::cfg_snapshot].
+///
+/// The snapshot only records the resolved terminators of basic blocks,
+/// and is only valid for the same memory content.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct CfgSnapshot {
+    /// CFG nodes as `(bitness, address, node)`, sorted by bitness and
+    /// basic block addresses
+    nodes: Vec<(u32, u64, CfgNode)>,
+}
+
+/// Simple metrics of the CFG graph, which can be retrieved by
+/// [`EdgeAnalyzer::cfg_metrics`][crate::EdgeAnalyzer::cfg_metrics].
+///
+/// This is useful for characterizing the complexity of the traced code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CfgMetrics {
+    /// Number of basic blocks of all tracee modes
+    pub blocks: usize,
+    /// Number of edges, including statically known edges (both targets of
+    /// conditional branches, targets of direct jumps and direct calls) and
+    /// observed targets of indirect branches
+    pub edges: usize,
+    /// Number of distinct observed targets of indirect branches
+    pub indirect_targets: usize,
+    /// Number of loop back-edges, i.e., edges of conditional branches and direct
+    /// jumps whose target is not greater than the start of the source basic block
+    pub back_edges: usize,
+}
+
+/// Policy of handling instructions split across two memory reads when
+/// resolving instructions.
+///
+/// An instruction is split if the read ends in the middle of it, e.g.,
+/// the read reaches the end of memory region or the
+/// [`block_read_size`][crate::EdgeAnalyzer::block_read_size].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrossReadPolicy {
+    /// Copy the bytes of the split instruction into a scratch buffer, and
+    /// concatenate them with the bytes of the next read. No bytes are read twice.
+    #[default]
+    Scratch,
+    /// Start the next read at the split instruction, so that it is decoded
+    /// from a single read without copying. This is useful when most splits are caused
+    /// by a small block read size instead of the boundary of memory regions.
+    ///
+    /// If the split instruction is already at the start of a read, this falls back to
+    /// [`Scratch`][CrossReadPolicy::Scratch].
+    Reread,
+}
+
 /// Static control flow analyzer, maintaining a CFG graph
+#[expect(clippy::struct_excessive_bools)]
 pub struct StaticControlFlowAnalyzer {
-    /// A CFG graph. Key: address of basic block, Value: basic block information
+    /// CFG graphs indexed by [`mode_index`]. Key: address of basic block,
+    /// Value: basic block information
+    ///
+    /// The same address may be decoded differently in different tracee
+    /// modes, so each mode has its own graph.
     ///
     /// This will become very huge after running a long time
-    cfg: HashMap<u64, CfgNode>,
+    cfgs: [HashMap<u64, CfgNode>; 3],
+    /// Options passed to iced-x86 decoder
+    iced_options: u32,
+    /// Maximum size of each memory read when resolving instructions
+    block_read_size: usize,
+    /// Policy of handling instructions split across memory reads
+    cross_read_policy: CrossReadPolicy,
+    /// Whether to skip common non-control-flow instructions without iced-x86,
+    /// see [`fast_non_branch_len`]
+    fast_classify: bool,
+    /// Whether to treat undecodable basic blocks as far transfers instead of
+    /// returning errors
+    skip_undecodable_blocks: bool,
+    /// Number of basic blocks treated as far transfers since they cannot be
+    /// decoded
+    undecodable_block_count: usize,
+    /// Whether to only use the pre-populated CFG graph without reading memory
+    prepopulated_only: bool,
+    /// Whether to record observed targets of indirect branches
+    record_indirect_targets: bool,
+    /// Observed indirect targets indexed by [`mode_index`]. Key: address of
+    /// basic block terminated by indirect branch, Value: observed target addresses
+    indirect_targets: [HashMap<u64, HashSet<u64>>; 3],
+    /// Whether to record instructions of basic blocks when resolving them
+    record_instructions: bool,
+    /// Instructions of basic blocks indexed by [`mode_index`], including the
+    /// terminator. Key: address of basic block, Value: instructions in order,
+    /// and whether the basic block is undecodable
+    block_instructions: [HashMap<u64, RecordedInstructions>; 3],
 }
 
 /// Initial capacity for CFG map.
@@ -119,66 +318,529 @@ pub struct StaticControlFlowAnalyzer {
 /// capacity.
 const CFG_MAP_INITIAL_CAPACITY: usize = 0x1000;
 
+/// Default maximum size of each memory read when resolving instructions.
+const DEFAULT_BLOCK_READ_SIZE: usize = 4096;
+
+/// Minimum size of each memory read when resolving instructions, which
+/// can hold the longest x86 instruction.
+pub const MIN_BLOCK_READ_SIZE: usize = 16;
+
+/// All tracee modes, ordered by [`mode_index`]
+const TRACEE_MODES: [TraceeMode; 3] = [TraceeMode::Mode64, TraceeMode::Mode32, TraceeMode::Mode16];
+
+/// Index of CFG graph of given tracee mode
+#[inline]
+fn mode_index(tracee_mode: TraceeMode) -> usize {
+    match tracee_mode {
+        TraceeMode::Mode64 => 0,
+        TraceeMode::Mode32 => 1,
+        TraceeMode::Mode16 => 2,
+    }
+}
+
 impl StaticControlFlowAnalyzer {
     /// Create a new [`StaticControlFlowAnalyzer`]
     #[must_use]
     pub fn new() -> Self {
         Self {
-            cfg: HashMap::with_capacity(CFG_MAP_INITIAL_CAPACITY),
+            // Most traced codes are 64-bit
+            cfgs: [
+                HashMap::with_capacity(CFG_MAP_INITIAL_CAPACITY),
+                HashMap::new(),
+                HashMap::new(),
+            ],
+            iced_options: IcedDecoderOptions::NONE,
+            block_read_size: DEFAULT_BLOCK_READ_SIZE,
+            cross_read_policy: CrossReadPolicy::Scratch,
+            fast_classify: false,
+            skip_undecodable_blocks: false,
+            undecodable_block_count: 0,
+            prepopulated_only: false,
+            record_indirect_targets: false,
+            indirect_targets: [HashMap::new(), HashMap::new(), HashMap::new()],
+            record_instructions: false,
+            block_instructions: [HashMap::new(), HashMap::new(), HashMap::new()],
+        }
+    }
+
+    /// Set options passed to iced-x86 decoder, see [`IcedDecoderOptions`]
+    pub fn set_iced_options(&mut self, iced_options: u32) {
+        self.iced_options = iced_options;
+    }
+
+    /// Set maximum size of each memory read when resolving instructions.
+    ///
+    /// Values less than [`MIN_BLOCK_READ_SIZE`] are treated as [`MIN_BLOCK_READ_SIZE`].
+    pub fn set_block_read_size(&mut self, block_read_size: usize) {
+        self.block_read_size = block_read_size.max(MIN_BLOCK_READ_SIZE);
+    }
+
+    /// Set policy of handling instructions split across memory reads
+    pub fn set_cross_read_policy(&mut self, cross_read_policy: CrossReadPolicy) {
+        self.cross_read_policy = cross_read_policy;
+    }
+
+    /// Set whether to skip common non-control-flow instructions by their
+    /// opcode bytes, without decoding them by iced-x86
+    pub fn set_fast_classify(&mut self, fast_classify: bool) {
+        self.fast_classify = fast_classify;
+    }
+
+    /// Set whether to treat basic blocks containing invalid instructions as
+    /// far transfers of [`FarTransferKind::Undecodable`] instead of returning
+    /// [`AnalyzerError::InvalidInstruction`]
+    pub fn set_skip_undecodable_blocks(&mut self, skip_undecodable_blocks: bool) {
+        self.skip_undecodable_blocks = skip_undecodable_blocks;
+    }
+
+    /// Get the number of basic blocks treated as far transfers since they
+    /// cannot be decoded
+    pub fn undecodable_block_count(&self) -> usize {
+        self.undecodable_block_count
+    }
+
+    /// Set whether to only use the pre-populated CFG graph, where
+    /// [`resolve`][Self::resolve] never reads memory, and returns
+    /// [`AnalyzerError::BlockNotInPrepopulatedCfg`] for missing basic blocks
+    pub fn set_prepopulated_only(&mut self, prepopulated_only: bool) {
+        self.prepopulated_only = prepopulated_only;
+    }
+
+    /// Set whether to record observed targets of basic blocks terminated by
+    /// [`CfgTerminator::IndirectGoto`] or [`CfgTerminator::IndirectCall`]
+    pub fn set_record_indirect_targets(&mut self, record_indirect_targets: bool) {
+        self.record_indirect_targets = record_indirect_targets;
+    }
+
+    /// Whether observed targets of indirect branches are recorded
+    pub fn is_recording_indirect_targets(&self) -> bool {
+        self.record_indirect_targets
+    }
+
+    /// Record `target` as an observed target of the indirect branch terminating
+    /// the basic block at `block_addr`.
+    ///
+    /// Nothing is recorded if recording is not enabled by
+    /// [`set_record_indirect_targets`][Self::set_record_indirect_targets], or the
+    /// basic block is not resolved as terminated by an indirect branch.
+    pub fn record_indirect_target(
+        &mut self,
+        tracee_mode: TraceeMode,
+        block_addr: u64,
+        target: u64,
+    ) {
+        if !self.record_indirect_targets {
+            return;
+        }
+        let Some(CfgNode {
+            terminator: CfgTerminator::IndirectGoto { .. } | CfgTerminator::IndirectCall { .. },
+        }) = self.cfgs[mode_index(tracee_mode)].get(&block_addr)
+        else {
+            return;
+        };
+        self.indirect_targets[mode_index(tracee_mode)]
+            .entry(block_addr)
+            .or_default()
+            .insert(target);
+    }
+
+    /// Get observed targets of the indirect branch terminating the basic block
+    /// at `block_addr` under `tracee_mode`, or `None` if no target is observed.
+    #[must_use]
+    pub fn indirect_targets(
+        &self,
+        tracee_mode: TraceeMode,
+        block_addr: u64,
+    ) -> Option<&HashSet<u64>> {
+        self.indirect_targets[mode_index(tracee_mode)].get(&block_addr)
+    }
+
+    /// Iterate over all observed indirect targets, keyed by the tracee mode and
+    /// the address of basic block terminated by indirect branch
+    pub fn iter_indirect_targets(&self) -> impl Iterator<Item = (TraceeMode, u64, &HashSet<u64>)> {
+        TRACEE_MODES.into_iter().flat_map(move |tracee_mode| {
+            self.indirect_targets[mode_index(tracee_mode)]
+                .iter()
+                .map(move |(block_addr, targets)| (tracee_mode, *block_addr, targets))
+        })
+    }
+
+    /// Set whether to record instructions of basic blocks when resolving them,
+    /// which can be retrieved by [`block_instructions`][Self::block_instructions]
+    #[cfg(not(feature = "cache"))]
+    pub fn set_record_instructions(&mut self, record_instructions: bool) {
+        self.record_instructions = record_instructions;
+    }
+
+    /// Whether instructions of basic blocks are recorded
+    #[cfg(not(feature = "cache"))]
+    pub fn is_recording_instructions(&self) -> bool {
+        self.record_instructions
+    }
+
+    /// Get the instructions of basic block at `block_addr` in order, including the
+    /// terminator, and whether the basic block is undecodable.
+    ///
+    /// For undecodable basic blocks (see [`set_skip_undecodable_blocks`][Self::set_skip_undecodable_blocks]),
+    /// only the instructions before the undecodable one are returned.
+    ///
+    /// The instructions are decoded at most once for each basic block. If the
+    /// basic block is resolved without recording instructions (e.g., loaded from a
+    /// snapshot), it is decoded again here.
+    #[cfg(not(feature = "cache"))]
+    pub fn block_instructions<H: HandleControlFlow, R: ReadMemory>(
+        &mut self,
+        memory_reader: &mut R,
+        tracee_mode: TraceeMode,
+        block_addr: u64,
+    ) -> AnalyzerResult<(&[BlockInstruction], bool), H, R> {
+        let index = mode_index(tracee_mode);
+        if !self.block_instructions[index].contains_key(&block_addr) {
+            let mut instructions = Vec::new();
+            let undecodable = match calculate_terminator(
+                memory_reader,
+                tracee_mode,
+                self.iced_options,
+                self.block_read_size,
+                self.cross_read_policy,
+                false,
+                block_addr,
+                Some(&mut instructions),
+            ) {
+                Ok(_) => false,
+                Err(AnalyzerError::InvalidInstruction { .. }) if self.skip_undecodable_blocks => {
+                    true
+                }
+                Err(err) => return Err(err),
+            };
+            self.block_instructions[index]
+                .insert(block_addr, (instructions.into_boxed_slice(), undecodable));
         }
+        let (instructions, undecodable) = &self.block_instructions[index][&block_addr];
+        Ok((instructions, *undecodable))
     }
 
-    /// Get the size of CFG nodes
+    /// Get the size of CFG nodes of all tracee modes
     pub fn cfg_size(&self) -> usize {
-        self.cfg.len()
+        self.cfgs.iter().map(HashMap::len).sum()
+    }
+
+    /// Calculate metrics of the CFG graph and observed indirect targets
+    pub fn metrics(&self) -> CfgMetrics {
+        let mut metrics = CfgMetrics {
+            blocks: self.cfg_size(),
+            ..Default::default()
+        };
+        for tracee_mode in TRACEE_MODES {
+            for (block_addr, node) in &self.cfgs[mode_index(tracee_mode)] {
+                let local_targets = match node.terminator {
+                    CfgTerminator::Branch { r#true, r#false } => [
+                        Some(r#true),
+                        Some(branch_false_target(r#true, r#false, tracee_mode)),
+                    ],
+                    CfgTerminator::DirectGoto { target } => [Some(target), None],
+                    CfgTerminator::DirectCall { .. } => {
+                        metrics.edges += 1;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                for target in local_targets.into_iter().flatten() {
+                    metrics.edges += 1;
+                    if target <= *block_addr {
+                        metrics.back_edges += 1;
+                    }
+                }
+            }
+        }
+        for indirect_targets_of_mode in &self.indirect_targets {
+            let mut indirect_targets = HashSet::new();
+            for targets in indirect_targets_of_mode.values() {
+                metrics.edges += targets.len();
+                indirect_targets.extend(targets.iter().copied());
+            }
+            metrics.indirect_targets += indirect_targets.len();
+        }
+        metrics
+    }
+
+    /// Clear all CFG nodes, observed indirect targets and recorded instructions
+    pub fn clear(&mut self) {
+        for cfg in &mut self.cfgs {
+            cfg.clear();
+        }
+        for indirect_targets in &mut self.indirect_targets {
+            indirect_targets.clear();
+        }
+        for block_instructions in &mut self.block_instructions {
+            block_instructions.clear();
+        }
+    }
+
+    /// Save all CFG nodes into a snapshot
+    #[cfg(feature = "serde")]
+    pub fn save_snapshot(&self) -> CfgSnapshot {
+        let mut nodes = Vec::with_capacity(self.cfg_size());
+        for tracee_mode in TRACEE_MODES {
+            let bitness = tracee_mode.bitness();
+            let start = nodes.len();
+            nodes.extend(
+                self.cfgs[mode_index(tracee_mode)]
+                    .iter()
+                    .map(|(addr, node)| (bitness, *addr, node.clone())),
+            );
+            nodes[start..].sort_unstable_by_key(|(_, addr, _)| *addr);
+        }
+        CfgSnapshot { nodes }
+    }
+
+    /// Load CFG nodes from a snapshot. Existing nodes with the same address
+    /// will be overwritten. Nodes with unknown bitness are ignored.
+    ///
+    /// The snapshot is not validated against the memory.
+    #[cfg(feature = "serde")]
+    pub fn load_snapshot(&mut self, snapshot: CfgSnapshot) {
+        for (bitness, addr, node) in snapshot.nodes {
+            let Some(tracee_mode) = TraceeMode::try_from_bitness(bitness) else {
+                continue;
+            };
+            self.cfgs[mode_index(tracee_mode)].insert(addr, node);
+        }
     }
 
     /// Resolve the given `insn_addr` to a [`CfgNode`].
     ///
     /// The `insn_addr` should be the start address of a basic block, and
-    /// will always be inserted to the CFG graph.
+    /// will always be inserted to the CFG graph of `tracee_mode`.
     ///
     /// This function will read memory at `insn_addr` by querying the
     /// `memory_reader`, and decoding the corresponding instruction until
-    /// reach a basic block terminator.
+    /// reach a basic block terminator. If only the pre-populated CFG graph
+    /// is used (see [`set_prepopulated_only`][Self::set_prepopulated_only]),
+    /// [`AnalyzerError::BlockNotInPrepopulatedCfg`] is returned instead.
     pub fn resolve<H: HandleControlFlow, R: ReadMemory>(
         &mut self,
         memory_reader: &mut R,
         tracee_mode: TraceeMode,
         insn_addr: u64,
     ) -> AnalyzerResult<&mut CfgNode, H, R> {
-        match self.cfg.entry(insn_addr) {
+        match self.cfgs[mode_index(tracee_mode)].entry(insn_addr) {
             hashbrown::hash_map::Entry::Occupied(entry) => Ok(entry.into_mut()),
+            hashbrown::hash_map::Entry::Vacant(_) if self.prepopulated_only => {
+                Err(AnalyzerError::BlockNotInPrepopulatedCfg { address: insn_addr })
+            }
             hashbrown::hash_map::Entry::Vacant(entry) => {
-                Ok(entry.insert(calculate_terminator(memory_reader, tracee_mode, insn_addr)?))
+                let mut instructions = self.record_instructions.then(Vec::new);
+                let mut undecodable = false;
+                let node = match calculate_terminator(
+                    memory_reader,
+                    tracee_mode,
+                    self.iced_options,
+                    self.block_read_size,
+                    self.cross_read_policy,
+                    self.fast_classify,
+                    insn_addr,
+                    instructions.as_mut(),
+                ) {
+                    Err(AnalyzerError::InvalidInstruction { .. })
+                        if self.skip_undecodable_blocks =>
+                    {
+                        self.undecodable_block_count += 1;
+                        undecodable = true;
+                        CfgNode {
+                            terminator: CfgTerminator::FarTransfers {
+                                next_instruction: insn_addr,
+                                kind: FarTransferKind::Undecodable,
+                            },
+                        }
+                    }
+                    res => res?,
+                };
+                if let Some(instructions) = instructions {
+                    self.block_instructions[mode_index(tracee_mode)]
+                        .insert(insn_addr, (instructions.into_boxed_slice(), undecodable));
+                }
+                Ok(entry.insert(node))
             }
         }
     }
 }
 
+/// Get the `reg` field of the ModRM byte at the start of `bytes`
+fn modrm_reg(bytes: &[u8]) -> Option<u8> {
+    bytes.first().map(|modrm| (modrm >> 3) & 0b111)
+}
+
+/// Length of the ModRM byte, SIB byte and displacement at the start of `bytes`,
+/// with 32-bit or 64-bit addressing.
+///
+/// Return [`None`] if `bytes` is too short.
+fn modrm_len(bytes: &[u8]) -> Option<usize> {
+    let modrm = *bytes.first()?;
+    let r#mod = modrm >> 6;
+    let rm = modrm & 0b111;
+    if r#mod == 0b11 {
+        return Some(1);
+    }
+    let mut len = 1;
+    let mut base = rm;
+    if rm == 0b100 {
+        // SIB
+        base = *bytes.get(1)? & 0b111;
+        len += 1;
+    }
+    len += match r#mod {
+        0b00 if base == 0b101 => 4,
+        0b00 => 0,
+        0b01 => 1,
+        _ => 4,
+    };
+    Some(len)
+}
+
+/// Get the length of the instruction at the start of `bytes`, if it is
+/// definitely a valid instruction that does not change control flow.
+///
+/// This is a conservative fast path which only recognizes a small set of
+/// the most common opcodes (e.g., MOV, PUSH, POP, arithmetic and logic
+/// instructions) in 32-bit and 64-bit modes without legacy prefixes. Return
+/// [`None`] if not sure, or if the instruction exceeds `bytes`, and the caller
+/// should decode it by iced-x86 instead.
+#[expect(clippy::match_same_arms)]
+fn fast_non_branch_len(bytes: &[u8], tracee_mode: TraceeMode) -> Option<usize> {
+    let is_64bit = match tracee_mode {
+        TraceeMode::Mode64 => true,
+        TraceeMode::Mode32 => false,
+        // Different ModRM encodings and operand sizes
+        TraceeMode::Mode16 => return None,
+    };
+    let mut prefix_len = 0;
+    let mut rex_w = false;
+    let mut opcode = *bytes.first()?;
+    if is_64bit && opcode & 0xF0 == 0x40 {
+        // REX prefix
+        rex_w = opcode & 0b1000 != 0;
+        prefix_len = 1;
+        opcode = *bytes.get(1)?;
+    }
+    let rest = &bytes[prefix_len + 1..];
+    let len = match opcode {
+        0x0F => {
+            let opcode = *rest.first()?;
+            let rest = &rest[1..];
+            1 + match opcode {
+                // NOP r/m
+                0x1F if modrm_reg(rest)? == 0 => modrm_len(rest)?,
+                // CMOVcc, IMUL, MOVZX, MOVSX
+                0x40..=0x4F | 0xAF | 0xB6 | 0xB7 | 0xBE | 0xBF => modrm_len(rest)?,
+                _ => return None,
+            }
+        }
+        // ADD, OR, ADC, SBB, AND, SUB, XOR, CMP
+        0x00..=0x3F => match opcode & 0b111 {
+            0..=3 => modrm_len(rest)?,
+            4 => 1,
+            5 => 4,
+            // Segment prefixes, PUSH/POP segment registers, DAA, etc.
+            _ => return None,
+        },
+        // INC, DEC. In 64-bit mode, this is a redundant REX prefix
+        0x40..=0x4F if !is_64bit => 0,
+        // PUSH, POP
+        0x50..=0x5F => 0,
+        // MOVSXD
+        0x63 if is_64bit => modrm_len(rest)?,
+        // IMUL
+        0x69 => modrm_len(rest)? + 4,
+        0x6B => modrm_len(rest)? + 1,
+        // Group 1 with immediate
+        0x80 | 0x83 => modrm_len(rest)? + 1,
+        0x81 => modrm_len(rest)? + 4,
+        // TEST, XCHG, MOV
+        0x84..=0x8B => modrm_len(rest)?,
+        // LEA, only with memory operand
+        0x8D if rest.first().is_some_and(|modrm| modrm >> 6 != 0b11) => modrm_len(rest)?,
+        // POP r/m
+        0x8F if modrm_reg(rest)? == 0 => modrm_len(rest)?,
+        // NOP, XCHG, CWDE, CDQ
+        0x90..=0x99 => 0,
+        // TEST with immediate
+        0xA8 => 1,
+        0xA9 => 4,
+        // MOV with immediate
+        0xB0..=0xB7 => 1,
+        0xB8..=0xBF if rex_w => 8,
+        0xB8..=0xBF => 4,
+        // Group 2 shifts, except the SAL alias
+        0xC0 | 0xC1 if modrm_reg(rest)? != 6 => modrm_len(rest)? + 1,
+        0xD0..=0xD3 if modrm_reg(rest)? != 6 => modrm_len(rest)?,
+        // MOV r/m with immediate (XABORT and XBEGIN are encoded with other ModRM.reg)
+        0xC6 if modrm_reg(rest)? == 0 => modrm_len(rest)? + 1,
+        0xC7 if modrm_reg(rest)? == 0 => modrm_len(rest)? + 4,
+        // Group 3, except the TEST alias
+        0xF6 | 0xF7 => match modrm_reg(rest)? {
+            0 if opcode == 0xF6 => modrm_len(rest)? + 1,
+            0 => modrm_len(rest)? + 4,
+            2..=7 => modrm_len(rest)?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let len = prefix_len + 1 + len;
+    (len <= bytes.len()).then_some(len)
+}
+
 #[expect(clippy::too_many_lines)]
+#[expect(clippy::too_many_arguments)]
 fn calculate_terminator<H: HandleControlFlow, R: ReadMemory>(
     memory_reader: &mut R,
     tracee_mode: TraceeMode,
+    iced_options: u32,
+    block_read_size: usize,
+    cross_read_policy: CrossReadPolicy,
+    fast_classify: bool,
     insn_addr: u64,
+    mut instructions: Option<&mut Vec<BlockInstruction>>,
 ) -> AnalyzerResult<CfgNode, H, R> {
+    // Instructions skipped by fast path are not decoded
+    let fast_classify = fast_classify && instructions.is_none();
+    if !memory_reader.is_mapped(insn_addr) {
+        if insn_addr
+            .checked_sub(1)
+            .is_some_and(|last_addr| memory_reader.is_mapped(last_addr))
+        {
+            return Err(AnalyzerError::BlockAtRegionEnd { address: insn_addr });
+        }
+        return Err(AnalyzerError::UnmappedAddress { address: insn_addr });
+    }
+    let block_addr = insn_addr;
     let mut instruction = Instruction::default();
     let mut insn_addr = insn_addr;
     let mut cross_page_insn_buf = [0u8; 16];
     let mut cross_page_insn_processed_bytes = None;
     let cfg_terminator = loop {
+        let read_size = match memory_reader.readable_len(insn_addr) {
+            // Unknown
+            0 => block_read_size,
+            // Read to the end of segment if possible, so that no cross-page
+            // instructions within this segment
+            readable_len => readable_len.min(block_read_size),
+        };
         let (cfg_terminator, next_insn_addr) = memory_reader
-            .read_memory(insn_addr, 4096, |mut insn_buf| {
+            .read_memory(insn_addr, read_size, |mut insn_buf| {
                 let mut insn_addr = insn_addr;
                 if let Some(processed_bytes) = cross_page_insn_processed_bytes.take() {
-                    // Previously we have a cross-page instruction
-                    let remain_bytes = 16 - processed_bytes;
-                    // remain bytes will never be zero since processed bytes is always less than 16
-                    let Some(remain_buf) = insn_buf.get(0..remain_bytes) else {
+                    // Previously we have a cross-page instruction. The instruction
+                    // may be shorter than 16 bytes and end near the end of memory.
+                    let remain_bytes = std::cmp::min(16 - processed_bytes, insn_buf.len());
+                    if remain_bytes == 0 {
                         // Very unexpected. This means the next page is also missing?
-                        return Err(AnalyzerError::InvalidInstruction);
-                    };
-                    // SAFETY: remain buf has remain_bytes length, and processed_bytes + remain_bytes == 16
+                        return Err(AnalyzerError::InvalidInstruction {
+                            insn_addr: insn_addr - processed_bytes as u64,
+                        });
+                    }
+                    let remain_buf = &insn_buf[..remain_bytes];
+                    // SAFETY: remain buf has remain_bytes length, and processed_bytes + remain_bytes <= 16
                     unsafe {
                         std::ptr::copy_nonoverlapping(
                             remain_buf.as_ptr(),
@@ -188,9 +850,9 @@ fn calculate_terminator<H: HandleControlFlow, R: ReadMemory>(
                     }
                     let mut decoder = IcedDecoder::with_ip(
                         tracee_mode.bitness(),
-                        &cross_page_insn_buf,
+                        &cross_page_insn_buf[..processed_bytes + remain_bytes],
                         insn_addr - processed_bytes as u64,
-                        IcedDecoderOptions::NONE,
+                        iced_options,
                     );
                     if !decoder.can_decode() {
                         // Unexpected! The instruction length exceeds 16 bytes?
@@ -199,10 +861,16 @@ fn calculate_terminator<H: HandleControlFlow, R: ReadMemory>(
                     decoder.decode_out(&mut instruction);
                     if instruction.is_invalid() {
                         // Even concated cross page instruction, it is still invalid
-                        return Err(AnalyzerError::InvalidInstruction);
+                        return Err(AnalyzerError::InvalidInstruction {
+                            insn_addr: instruction.ip(),
+                        });
+                    }
+                    if let Some(instructions) = instructions.as_deref_mut() {
+                        instructions.push(BlockInstruction::new(&instruction));
                     }
                     let next_insn_addr = instruction.next_ip();
-                    if let Some(cfg_terminator) = CfgTerminator::try_from(&instruction) {
+                    if let Some(cfg_terminator) = CfgTerminator::try_from(&instruction, tracee_mode)
+                    {
                         cross_page_insn_buf = [0u8; 16];
                         return Ok((Some(cfg_terminator), next_insn_addr));
                     }
@@ -218,30 +886,49 @@ fn calculate_terminator<H: HandleControlFlow, R: ReadMemory>(
                     cross_page_insn_buf = [0u8; 16];
                 }
 
-                let mut decoder = IcedDecoder::with_ip(
-                    tracee_mode.bitness(),
-                    insn_buf,
-                    insn_addr,
-                    IcedDecoderOptions::NONE,
-                );
+                if insn_buf.is_empty() && insn_addr == block_addr {
+                    // The reader does not know whether the address is mapped, but
+                    // reads nothing at the block start
+                    return Err(AnalyzerError::BlockAtRegionEnd { address: insn_addr });
+                }
+                let mut decoder =
+                    IcedDecoder::with_ip(tracee_mode.bitness(), insn_buf, insn_addr, iced_options);
                 let mut last_next_insn_addr = None;
                 loop {
                     if !decoder.can_decode() {
                         let Some(next_insn_addr) = last_next_insn_addr else {
                             // Even the first instruction cannot be decoded
-                            return Err(AnalyzerError::InvalidInstruction);
+                            return Err(AnalyzerError::InvalidInstruction { insn_addr });
                         };
                         // Have readed all instructions
                         return Ok((None, next_insn_addr));
                     }
                     let instr_pos = decoder.position();
+                    if fast_classify
+                        && let Some(instr_len) =
+                            fast_non_branch_len(&insn_buf[instr_pos..], tracee_mode)
+                    {
+                        let next_insn_addr = decoder.ip().wrapping_add(instr_len as u64);
+                        decoder
+                            .set_position(instr_pos + instr_len)
+                            .map_err(|_| AnalyzerError::Unexpected)?;
+                        decoder.set_ip(next_insn_addr);
+                        last_next_insn_addr = Some(next_insn_addr);
+                        continue;
+                    }
                     decoder.decode_out(&mut instruction);
                     if instruction.is_invalid() {
                         let processed_bytes = insn_buf.len().saturating_sub(instr_pos);
                         if processed_bytes >= 16 {
-                            return Err(AnalyzerError::InvalidInstruction);
+                            return Err(AnalyzerError::InvalidInstruction {
+                                insn_addr: instruction.ip(),
+                            });
                         }
                         // This instruction may cross page
+                        if cross_read_policy == CrossReadPolicy::Reread && instr_pos != 0 {
+                            // Read again from this instruction
+                            return Ok((None, instruction.ip()));
+                        }
                         let next_insn_addr = instruction.ip() + processed_bytes as u64;
                         // SAFETY: Bounds: saturating sub is always less than or equal to
                         debug_assert!(
@@ -264,10 +951,14 @@ fn calculate_terminator<H: HandleControlFlow, R: ReadMemory>(
                         return Ok((None, next_insn_addr));
                     }
 
+                    if let Some(instructions) = instructions.as_deref_mut() {
+                        instructions.push(BlockInstruction::new(&instruction));
+                    }
                     let next_insn_addr = instruction.next_ip();
                     last_next_insn_addr = Some(next_insn_addr);
 
-                    if let Some(cfg_terminator) = CfgTerminator::try_from(&instruction) {
+                    if let Some(cfg_terminator) = CfgTerminator::try_from(&instruction, tracee_mode)
+                    {
                         return Ok((Some(cfg_terminator), next_insn_addr));
                     }
                 }
@@ -284,3 +975,563 @@ fn calculate_terminator<H: HandleControlFlow, R: ReadMemory>(
     };
     Ok(node)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{BlockRecorder, SliceMemoryReader};
+
+    #[test]
+    fn test_resolve_per_mode() {
+        // 64-bit:
+        // 0x1000: mov rax, 0xF875_0000_0000
+        // 0x100A: jnz 0x1000
+        //
+        // 32-bit:
+        // 0x1000: dec eax
+        // 0x1001: mov eax, 0
+        // 0x1006: jnz 0x1000
+        const CODE: [u8; 12] = [
+            0x48, 0xB8, 0x00, 0x00, 0x00, 0x00, 0x75, 0xF8, 0x00, 0x00, 0x75, 0xF4,
+        ];
+        let mut reader = SliceMemoryReader::new(&[(0x1000, &CODE)]);
+        let mut analyzer = StaticControlFlowAnalyzer::new();
+        for (tracee_mode, expected_false) in
+            [(TraceeMode::Mode64, 0x100C), (TraceeMode::Mode32, 0x1008)]
+        {
+            let node = analyzer
+                .resolve::<BlockRecorder, _>(&mut reader, tracee_mode, 0x1000)
+                .unwrap();
+            let CfgTerminator::Branch { r#true, r#false } = node.terminator else {
+                panic!("Unexpected terminator {:?}", node.terminator);
+            };
+            assert_eq!(r#true, 0x1000);
+            assert_eq!(
+                branch_false_target(r#true, r#false, tracee_mode),
+                expected_false
+            );
+        }
+        assert_eq!(analyzer.cfg_size(), 2);
+    }
+
+    #[test]
+    fn test_metrics() {
+        // 0x1000: dec ecx
+        // 0x1002: jnz 0x1000
+        // 0x1004: call 0x1010
+        // 0x1009: jmp rax
+        // 0x100B: nop (5 times)
+        // 0x1010: ret
+        const CODE: [u8; 17] = [
+            0xFF, 0xC9, 0x75, 0xFC, 0xE8, 0x07, 0x00, 0x00, 0x00, 0xFF, 0xE0, 0x90, 0x90, 0x90,
+            0x90, 0x90, 0xC3,
+        ];
+        let mut reader = SliceMemoryReader::new(&[(0x1000, &CODE)]);
+        let mut analyzer = StaticControlFlowAnalyzer::new();
+        analyzer.set_record_indirect_targets(true);
+        assert_eq!(analyzer.metrics(), CfgMetrics::default());
+
+        for addr in [0x1000, 0x1004, 0x1009, 0x1010] {
+            analyzer
+                .resolve::<BlockRecorder, _>(&mut reader, TraceeMode::Mode64, addr)
+                .unwrap();
+        }
+        for target in [0x1000, 0x1010, 0x1000] {
+            analyzer.record_indirect_target(TraceeMode::Mode64, 0x1009, target);
+        }
+        assert_eq!(
+            analyzer.metrics(),
+            CfgMetrics {
+                blocks: 4,
+                // jnz: 2, call: 1, jmp rax: 2
+                edges: 5,
+                indirect_targets: 2,
+                // Only jnz 0x1000, since the call is not a loop
+                back_edges: 1,
+            }
+        );
+
+        analyzer.clear();
+        assert_eq!(analyzer.metrics(), CfgMetrics::default());
+    }
+
+    #[test]
+    fn test_block_read_size() {
+        let mut code = Vec::new();
+        // 0x1000: mov rax, 0x1122_3344_5566_7788 (12 times)
+        for _ in 0..12 {
+            code.extend([0x48, 0xB8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        }
+        // 0x1078: jnz 0x1000
+        code.extend([0x75, 0x86]);
+        // 0x107A: nop dword ptr [rax + rax] (5 times)
+        for _ in 0..5 {
+            code.extend([0x0F, 0x1F, 0x44, 0x00, 0x00]);
+        }
+        // 0x1093: call 0x1000
+        code.extend([0xE8, 0x68, 0xFF, 0xFF, 0xFF]);
+
+        let resolve = |block_read_size, cross_read_policy| {
+            let mut reader = SliceMemoryReader::new(&[(0x1000, &code)]);
+            let mut analyzer = StaticControlFlowAnalyzer::new();
+            analyzer.set_block_read_size(block_read_size);
+            analyzer.set_cross_read_policy(cross_read_policy);
+            [0x1000, 0x107A].map(|addr| {
+                let node = analyzer
+                    .resolve::<BlockRecorder, _>(&mut reader, TraceeMode::Mode64, addr)
+                    .unwrap();
+                node.terminator
+            })
+        };
+        let expected = resolve(DEFAULT_BLOCK_READ_SIZE, CrossReadPolicy::Scratch);
+        assert_eq!(
+            expected,
+            [
+                CfgTerminator::Branch {
+                    r#true: 0x1000,
+                    r#false: 0x107A,
+                },
+                CfgTerminator::DirectCall {
+                    target: 0x1000,
+                    return_address: 0x1098,
+                },
+            ]
+        );
+        for block_read_size in [0, 16, 17, 23, 64] {
+            for cross_read_policy in [CrossReadPolicy::Scratch, CrossReadPolicy::Reread] {
+                assert_eq!(
+                    resolve(block_read_size, cross_read_policy),
+                    expected,
+                    "{block_read_size} {cross_read_policy:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[expect(clippy::too_many_lines)]
+    fn test_terminator_of_flow_control() {
+        let terminator = |tracee_mode: TraceeMode, bytes: &[u8]| {
+            let mut decoder = IcedDecoder::with_ip(
+                tracee_mode.bitness(),
+                bytes,
+                0x1000,
+                IcedDecoderOptions::NONE,
+            );
+            let instruction = decoder.decode();
+            assert!(!instruction.is_invalid(), "{bytes:x?}");
+            assert_eq!(instruction.len(), bytes.len(), "{bytes:x?}");
+            (
+                instruction.flow_control(),
+                CfgTerminator::try_from(&instruction, tracee_mode),
+            )
+        };
+        // `FarTransfers` whose next instruction is filled as right after the bytes
+        let far = |kind| {
+            Some(CfgTerminator::FarTransfers {
+                next_instruction: 0,
+                kind,
+            })
+        };
+        let branch = Some(CfgTerminator::Branch {
+            r#true: 0x1000,
+            r#false: 0x1002,
+        });
+        let cases: [(TraceeMode, &[u8], FlowControl, Option<CfgTerminator>); _] = [
+            // nop
+            (TraceeMode::Mode64, &[0x90], FlowControl::Next, None),
+            // endbr64
+            (
+                TraceeMode::Mode64,
+                &[0xF3, 0x0F, 0x1E, 0xFA],
+                FlowControl::Next,
+                None,
+            ),
+            // jnz 0x1000
+            (
+                TraceeMode::Mode64,
+                &[0x75, 0xFE],
+                FlowControl::ConditionalBranch,
+                branch,
+            ),
+            // loop 0x1000
+            (
+                TraceeMode::Mode64,
+                &[0xE2, 0xFE],
+                FlowControl::ConditionalBranch,
+                branch,
+            ),
+            // jrcxz 0x1000
+            (
+                TraceeMode::Mode64,
+                &[0xE3, 0xFE],
+                FlowControl::ConditionalBranch,
+                branch,
+            ),
+            // jmp 0x1000
+            (
+                TraceeMode::Mode64,
+                &[0xEB, 0xFE],
+                FlowControl::UnconditionalBranch,
+                Some(CfgTerminator::DirectGoto { target: 0x1000 }),
+            ),
+            // jmp far 0x8:0x1000
+            (
+                TraceeMode::Mode32,
+                &[0xEA, 0x00, 0x10, 0x00, 0x00, 0x08, 0x00],
+                FlowControl::UnconditionalBranch,
+                far(FarTransferKind::FarJump),
+            ),
+            // jmp rax
+            (
+                TraceeMode::Mode64,
+                &[0xFF, 0xE0],
+                FlowControl::IndirectBranch,
+                Some(CfgTerminator::IndirectGoto { notrack: false }),
+            ),
+            // bnd jmp rax
+            (
+                TraceeMode::Mode64,
+                &[0xF2, 0xFF, 0xE0],
+                FlowControl::IndirectBranch,
+                Some(CfgTerminator::IndirectGoto { notrack: false }),
+            ),
+            // notrack jmp rax
+            (
+                TraceeMode::Mode64,
+                &[0x3E, 0xFF, 0xE0],
+                FlowControl::IndirectBranch,
+                Some(CfgTerminator::IndirectGoto { notrack: true }),
+            ),
+            // jmp far [rax]
+            (
+                TraceeMode::Mode64,
+                &[0xFF, 0x28],
+                FlowControl::IndirectBranch,
+                far(FarTransferKind::FarJump),
+            ),
+            // call 0x1000
+            (
+                TraceeMode::Mode64,
+                &[0xE8, 0xFB, 0xFF, 0xFF, 0xFF],
+                FlowControl::Call,
+                Some(CfgTerminator::DirectCall {
+                    target: 0x1000,
+                    return_address: 0x1005,
+                }),
+            ),
+            // call far 0x8:0x1000
+            (
+                TraceeMode::Mode32,
+                &[0x9A, 0x00, 0x10, 0x00, 0x00, 0x08, 0x00],
+                FlowControl::Call,
+                far(FarTransferKind::FarCall),
+            ),
+            // syscall
+            (
+                TraceeMode::Mode64,
+                &[0x0F, 0x05],
+                FlowControl::Call,
+                far(FarTransferKind::Syscall),
+            ),
+            // call rax
+            (
+                TraceeMode::Mode64,
+                &[0xFF, 0xD0],
+                FlowControl::IndirectCall,
+                Some(CfgTerminator::IndirectCall {
+                    return_address: 0x1002,
+                    notrack: false,
+                }),
+            ),
+            // call far [rax]
+            (
+                TraceeMode::Mode64,
+                &[0xFF, 0x18],
+                FlowControl::IndirectCall,
+                far(FarTransferKind::FarCall),
+            ),
+            // ret
+            (
+                TraceeMode::Mode64,
+                &[0xC3],
+                FlowControl::Return,
+                Some(CfgTerminator::NearRet),
+            ),
+            // retf
+            (
+                TraceeMode::Mode64,
+                &[0xCB],
+                FlowControl::Return,
+                far(FarTransferKind::FarReturn),
+            ),
+            // iretq
+            (
+                TraceeMode::Mode64,
+                &[0x48, 0xCF],
+                FlowControl::Return,
+                far(FarTransferKind::InterruptReturn),
+            ),
+            // sysretq
+            (
+                TraceeMode::Mode64,
+                &[0x48, 0x0F, 0x07],
+                FlowControl::Return,
+                far(FarTransferKind::SystemReturn),
+            ),
+            // int3
+            (
+                TraceeMode::Mode64,
+                &[0xCC],
+                FlowControl::Interrupt,
+                far(FarTransferKind::Interrupt(0x3)),
+            ),
+            // int 0x80
+            (
+                TraceeMode::Mode32,
+                &[0xCD, 0x80],
+                FlowControl::Interrupt,
+                far(FarTransferKind::Interrupt(0x80)),
+            ),
+            // ud2
+            (
+                TraceeMode::Mode64,
+                &[0x0F, 0x0B],
+                FlowControl::Exception,
+                far(FarTransferKind::Exception),
+            ),
+            // xbegin 0x1000
+            (
+                TraceeMode::Mode64,
+                &[0xC7, 0xF8, 0xFA, 0xFF, 0xFF, 0xFF],
+                FlowControl::XbeginXabortXend,
+                None,
+            ),
+            // xabort 0
+            (
+                TraceeMode::Mode64,
+                &[0xC6, 0xF8, 0x00],
+                FlowControl::Next,
+                None,
+            ),
+            // xend
+            (
+                TraceeMode::Mode64,
+                &[0x0F, 0x01, 0xD5],
+                FlowControl::Next,
+                None,
+            ),
+        ];
+        for (tracee_mode, bytes, flow_control, mut expected) in cases {
+            if let Some(CfgTerminator::FarTransfers {
+                next_instruction, ..
+            }) = &mut expected
+            {
+                *next_instruction = 0x1000 + bytes.len() as u64;
+            }
+            assert_eq!(
+                terminator(tracee_mode, bytes),
+                (flow_control, expected),
+                "{bytes:x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_fast_non_branch_len_matches_iced() {
+        let check = |tracee_mode: TraceeMode, bytes: &[u8]| {
+            let Some(len) = fast_non_branch_len(bytes, tracee_mode) else {
+                return false;
+            };
+            let mut decoder = IcedDecoder::with_ip(
+                tracee_mode.bitness(),
+                bytes,
+                0x1000,
+                IcedDecoderOptions::NONE,
+            );
+            let instruction = decoder.decode();
+            assert!(!instruction.is_invalid(), "{bytes:x?}");
+            assert_eq!(instruction.len(), len, "{bytes:x?}");
+            assert!(
+                CfgTerminator::try_from(&instruction, tracee_mode).is_none(),
+                "{bytes:x?}"
+            );
+            true
+        };
+
+        // Opcode, ModRM and SIB bytes, with and without REX prefix
+        let mut fast_count = 0;
+        for (tracee_mode, prefix) in [
+            (TraceeMode::Mode64, &[][..]),
+            (TraceeMode::Mode64, &[0x48]),
+            (TraceeMode::Mode64, &[0x41]),
+            (TraceeMode::Mode32, &[]),
+        ] {
+            for opcode in 0..=u8::MAX {
+                for modrm in 0..=u8::MAX {
+                    for sib in [0x00, 0x24, 0x25, 0x44, 0x80, 0xC0, 0xF8] {
+                        let mut bytes = prefix.to_vec();
+                        bytes.extend_from_slice(&[opcode, modrm, sib]);
+                        bytes.resize(16, 0x11);
+                        if check(tracee_mode, &bytes) {
+                            fast_count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert_ne!(fast_count, 0);
+    }
+
+    #[test]
+    fn test_fast_non_branch_len() {
+        let cases: [(TraceeMode, &[u8], Option<usize>); _] = [
+            // push rbp
+            (TraceeMode::Mode64, &[0x55], Some(1)),
+            // mov rbp, rsp
+            (TraceeMode::Mode64, &[0x48, 0x89, 0xE5], Some(3)),
+            // sub rsp, 0x20
+            (TraceeMode::Mode64, &[0x48, 0x83, 0xEC, 0x20], Some(4)),
+            // mov eax, [rip + 0x10]
+            (
+                TraceeMode::Mode64,
+                &[0x8B, 0x05, 0x10, 0x00, 0x00, 0x00],
+                Some(6),
+            ),
+            // lea rax, [rsp + 8]
+            (TraceeMode::Mode64, &[0x48, 0x8D, 0x44, 0x24, 0x08], Some(5)),
+            // mov rax, 0x1122_3344_5566_7788
+            (
+                TraceeMode::Mode64,
+                &[0x48, 0xB8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+                Some(10),
+            ),
+            // movzx eax, byte [rdi]
+            (TraceeMode::Mode64, &[0x0F, 0xB6, 0x07], Some(3)),
+            // nop dword [rax + rax]
+            (TraceeMode::Mode64, &[0x0F, 0x1F, 0x44, 0x00, 0x00], Some(5)),
+            // inc eax
+            (TraceeMode::Mode32, &[0x40], Some(1)),
+            // Truncated mov eax, [rip + 0x10]
+            (TraceeMode::Mode64, &[0x8B, 0x05, 0x10, 0x00], None),
+            // jnz 0x1000
+            (TraceeMode::Mode64, &[0x75, 0xFE], None),
+            // ret
+            (TraceeMode::Mode64, &[0xC3], None),
+            // jmp rax
+            (TraceeMode::Mode64, &[0xFF, 0xE0], None),
+            // xbegin
+            (
+                TraceeMode::Mode64,
+                &[0xC7, 0xF8, 0x00, 0x00, 0x00, 0x00],
+                None,
+            ),
+            // lock add eax, eax
+            (TraceeMode::Mode64, &[0xF0, 0x01, 0xC0], None),
+            // push bp
+            (TraceeMode::Mode16, &[0x55], None),
+        ];
+        for (tracee_mode, bytes, expected) in cases {
+            assert_eq!(
+                fast_non_branch_len(bytes, tracee_mode),
+                expected,
+                "{bytes:x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_fast_classify() {
+        /// In 64-bit mode (other modes are also checked):
+        ///
+        /// ```plaintext
+        /// 0x1000: push rbp
+        /// 0x1001: mov rbp, rsp
+        /// 0x1004: lea rax, [rsp + 8]
+        /// 0x1009: rex.w nop
+        /// 0x100B: cmp eax, 1
+        /// 0x100E: jnz 0x1000
+        /// 0x1010: mov ecx, 1
+        /// 0x1015: cpuid
+        /// 0x1017: movzx eax, byte [rdi]
+        /// 0x101A: call 0x1000
+        /// 0x101F: ret
+        /// ```
+        const CODE: [u8; 32] = [
+            0x55, 0x48, 0x89, 0xE5, 0x48, 0x8D, 0x44, 0x24, 0x08, 0x48, 0x90, 0x83, 0xF8, 0x01,
+            0x75, 0xF0, 0xB9, 0x01, 0x00, 0x00, 0x00, 0x0F, 0xA2, 0x0F, 0xB6, 0x07, 0xE8, 0xE1,
+            0xFF, 0xFF, 0xFF, 0xC3,
+        ];
+        let mut reader = SliceMemoryReader::new(&[(0x1000, &CODE)]);
+        for tracee_mode in [TraceeMode::Mode64, TraceeMode::Mode32, TraceeMode::Mode16] {
+            for insn_addr in 0x1000..0x1000 + CODE.len() as u64 {
+                let [fast, iced] = [true, false].map(|fast_classify| {
+                    let mut analyzer = StaticControlFlowAnalyzer::new();
+                    analyzer.set_fast_classify(fast_classify);
+                    analyzer.set_block_read_size(MIN_BLOCK_READ_SIZE);
+                    analyzer
+                        .resolve::<BlockRecorder, _>(&mut reader, tracee_mode, insn_addr)
+                        .map(|node| node.terminator)
+                        .map_err(|err| std::mem::discriminant(&err))
+                });
+                assert_eq!(fast, iced, "{tracee_mode:?} {insn_addr:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_at_region_end() {
+        /// Memory reader that reads nothing at the end of region, and does not
+        /// know whether an address is mapped
+        struct EmptyAtEndReader(SliceMemoryReader);
+
+        impl ReadMemory for EmptyAtEndReader {
+            type Error = <SliceMemoryReader as ReadMemory>::Error;
+
+            fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+                Ok(())
+            }
+
+            fn read_memory<T>(
+                &mut self,
+                address: u64,
+                size: usize,
+                callback: impl FnOnce(&[u8]) -> T,
+            ) -> Result<T, Self::Error> {
+                if address == 0x1004 {
+                    return Ok(callback(&[]));
+                }
+                self.0.read_memory(address, size, callback)
+            }
+        }
+
+        /// ```plaintext
+        /// 0x1000: jnz 0x1000
+        /// 0x1002: jmp rax
+        /// ```
+        const CODE: [u8; 4] = [0x75, 0xFE, 0xFF, 0xE0];
+
+        let mut analyzer = StaticControlFlowAnalyzer::new();
+        let mut reader = SliceMemoryReader::new(&[(0x1000, &CODE)]);
+        assert!(matches!(
+            analyzer.resolve::<BlockRecorder, _>(&mut reader, TraceeMode::Mode64, 0x1004),
+            Err(AnalyzerError::BlockAtRegionEnd { address: 0x1004 })
+        ));
+        assert!(matches!(
+            analyzer.resolve::<BlockRecorder, _>(&mut reader, TraceeMode::Mode64, 0x1005),
+            Err(AnalyzerError::UnmappedAddress { address: 0x1005 })
+        ));
+
+        let mut reader = EmptyAtEndReader(SliceMemoryReader::new(&[(0x1000, &CODE)]));
+        assert!(matches!(
+            analyzer.resolve::<BlockRecorder, _>(&mut reader, TraceeMode::Mode64, 0x1004),
+            Err(AnalyzerError::BlockAtRegionEnd { address: 0x1004 })
+        ));
+        assert!(matches!(
+            analyzer.resolve::<BlockRecorder, _>(&mut reader, TraceeMode::Mode64, 0x1002),
+            Ok(CfgNode {
+                terminator: CfgTerminator::IndirectGoto { notrack: false }
+            })
+        ));
+    }
+}
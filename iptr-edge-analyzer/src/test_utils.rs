@@ -0,0 +1,462 @@
+//! Utilities shared by unit tests of this crate.
+
+use iptr_decoder::TraceeMode;
+use thiserror::Error;
+
+#[cfg(not(feature = "cache"))]
+use crate::BlockInstruction;
+use crate::{ControlFlowTransitionKind, EdgeAnalyzer, HandleControlFlow, ReadMemory};
+
+/// `(virtual address, content)` pairs
+type Regions = Vec<(u64, Vec<u8>)>;
+
+/// Memory reader backed by in-memory byte regions
+pub struct SliceMemoryReader {
+    /// Regions of current address space
+    regions: Regions,
+    /// `(cr3, regions)` pairs of other address spaces
+    address_spaces: Vec<(u64, Regions)>,
+    /// `(vmcs pointer, regions)` pairs of guests
+    guests: Vec<(u64, Regions)>,
+}
+
+/// Error for [`SliceMemoryReader`]
+#[derive(Debug, Error)]
+pub enum SliceMemoryReaderError {
+    /// The queried address is not included
+    #[error("Not mapped area {0:#x} accessed")]
+    NotMapped(u64),
+}
+
+impl SliceMemoryReader {
+    /// Create a new reader from `(virtual address, content)` pairs
+    pub fn new(regions: &[(u64, &[u8])]) -> Self {
+        Self {
+            regions: Self::to_owned_regions(regions),
+            address_spaces: Vec::new(),
+            guests: Vec::new(),
+        }
+    }
+
+    /// Add regions of address space `cr3`, which will be used after
+    /// switching to that address space
+    pub fn with_address_space(mut self, cr3: u64, regions: &[(u64, &[u8])]) -> Self {
+        self.address_spaces
+            .push((cr3, Self::to_owned_regions(regions)));
+        self
+    }
+
+    /// Add regions of guest `vmcs_pointer`, which will be used after
+    /// switching to that guest
+    pub fn with_vmcs(mut self, vmcs_pointer: u64, regions: &[(u64, &[u8])]) -> Self {
+        self.guests
+            .push((vmcs_pointer, Self::to_owned_regions(regions)));
+        self
+    }
+
+    fn to_owned_regions(regions: &[(u64, &[u8])]) -> Regions {
+        regions
+            .iter()
+            .map(|(addr, content)| (*addr, content.to_vec()))
+            .collect()
+    }
+}
+
+impl ReadMemory for SliceMemoryReader {
+    type Error = SliceMemoryReaderError;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn read_memory<T>(
+        &mut self,
+        address: u64,
+        size: usize,
+        callback: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Self::Error> {
+        for (start, content) in &self.regions {
+            if (*start..(*start + content.len() as u64)).contains(&address) {
+                let offset = (address - start) as usize;
+                let end = std::cmp::min(content.len(), offset.saturating_add(size));
+                return Ok(callback(&content[offset..end]));
+            }
+        }
+        Err(SliceMemoryReaderError::NotMapped(address))
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn readable_len(&self, address: u64) -> usize {
+        self.regions
+            .iter()
+            .find(|(start, content)| (*start..(*start + content.len() as u64)).contains(&address))
+            .map_or(0, |(start, content)| {
+                content.len() - (address - start) as usize
+            })
+    }
+
+    fn is_mapped(&self, address: u64) -> bool {
+        self.readable_len(address) != 0
+    }
+
+    fn on_address_space_change(&mut self, cr3: u64) -> Result<(), Self::Error> {
+        if let Some((_, regions)) = self.address_spaces.iter().find(|(key, _)| *key == cr3) {
+            self.regions.clone_from(regions);
+        }
+        Ok(())
+    }
+
+    fn on_vmcs_change(&mut self, vmcs_pointer: u64) -> Result<(), Self::Error> {
+        if let Some((_, regions)) = self.guests.iter().find(|(key, _)| *key == vmcs_pointer) {
+            self.regions.clone_from(regions);
+        }
+        Ok(())
+    }
+}
+
+/// Control flow handler recording every block transition.
+///
+/// In cache mode, the recorded transitions are cached as well, and
+/// replayed when cache is reused. As a result, [`blocks`][Self::blocks]
+/// is the same no matter whether `cache` feature is on.
+#[derive(Default)]
+pub struct BlockRecorder {
+    /// All block transitions
+    pub blocks: Vec<(u64, ControlFlowTransitionKind)>,
+    /// All `(from, to, transition_kind)` edges, excluding those replayed from cache
+    pub edges: Vec<(u64, u64, ControlFlowTransitionKind)>,
+    /// All `(block_addr, event_ip)` of asynchronous events
+    pub async_events: Vec<(u64, u64)>,
+    /// All executed instructions, see [`HandleControlFlow::on_block_instructions`]
+    #[cfg(not(feature = "cache"))]
+    pub instructions: Vec<BlockInstruction>,
+    /// All executed basic blocks whose instructions are only partially decoded
+    #[cfg(not(feature = "cache"))]
+    pub undecodable_blocks: Vec<u64>,
+    /// All `(src_block, dst_block)` of indirect branch targets without ENDBR
+    pub missing_endbrs: Vec<(u64, u64)>,
+    /// All `(psb_index, first_block)` of PSB regions, where `first_block` is the
+    /// index of the first block of the region in [`blocks`][Self::blocks]
+    pub psb_regions: Vec<(usize, usize)>,
+    /// Transitions in current cache
+    #[cfg(feature = "cache")]
+    current_cache: Vec<(u64, ControlFlowTransitionKind)>,
+    /// Count of [`should_clear_all_cache`][HandleControlFlow::should_clear_all_cache] calls
+    #[cfg(feature = "cache")]
+    clear_query_count: usize,
+    /// Require clearing all caches at this query count
+    #[cfg(feature = "cache")]
+    pub clear_at_query: Option<usize>,
+}
+
+impl HandleControlFlow for BlockRecorder {
+    type Error = std::convert::Infallible;
+    #[cfg(feature = "cache")]
+    type CachedKey = Vec<(u64, ControlFlowTransitionKind)>;
+    const NEEDS_INDIRECT_SOURCE: bool = true;
+
+    fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+        self.blocks.clear();
+        self.edges.clear();
+        self.async_events.clear();
+        #[cfg(not(feature = "cache"))]
+        self.instructions.clear();
+        #[cfg(not(feature = "cache"))]
+        self.undecodable_blocks.clear();
+        self.psb_regions.clear();
+        self.missing_endbrs.clear();
+        Ok(())
+    }
+
+    fn on_edge(
+        &mut self,
+        from: u64,
+        to: u64,
+        transition_kind: ControlFlowTransitionKind,
+        cache: bool,
+    ) -> Result<(), Self::Error> {
+        self.edges.push((from, to, transition_kind));
+        self.on_new_block(to, transition_kind, cache)
+    }
+
+    fn on_async_event(&mut self, block_addr: u64, event_ip: u64) -> Result<(), Self::Error> {
+        self.async_events.push((block_addr, event_ip));
+        Ok(())
+    }
+
+    fn on_missing_endbr(&mut self, src_block: u64, dst_block: u64) -> Result<(), Self::Error> {
+        self.missing_endbrs.push((src_block, dst_block));
+        Ok(())
+    }
+
+    fn on_psb_region(&mut self, psb_index: usize) -> Result<(), Self::Error> {
+        self.psb_regions.push((psb_index, self.blocks.len()));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "cache"))]
+    fn on_block_instructions(
+        &mut self,
+        block_addr: u64,
+        instructions: &[BlockInstruction],
+        undecodable: bool,
+    ) -> Result<(), Self::Error> {
+        self.instructions.extend_from_slice(instructions);
+        if undecodable {
+            self.undecodable_blocks.push(block_addr);
+        }
+        Ok(())
+    }
+
+    fn on_new_block(
+        &mut self,
+        block_addr: u64,
+        transition_kind: ControlFlowTransitionKind,
+        cache: bool,
+    ) -> Result<(), Self::Error> {
+        self.blocks.push((block_addr, transition_kind));
+        #[cfg(feature = "cache")]
+        if cache {
+            self.current_cache.push((block_addr, transition_kind));
+        }
+        #[cfg(not(feature = "cache"))]
+        let _ = cache;
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    fn cache_prev_cached_key(&mut self, cached_key: Self::CachedKey) -> Result<(), Self::Error> {
+        self.current_cache.extend(cached_key);
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    fn take_cache(&mut self) -> Result<Option<Self::CachedKey>, Self::Error> {
+        if self.current_cache.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(std::mem::take(&mut self.current_cache)))
+    }
+
+    #[cfg(feature = "cache")]
+    fn clear_current_cache(&mut self) -> Result<(), Self::Error> {
+        self.current_cache.clear();
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    fn on_reused_cache(
+        &mut self,
+        cached_key: &Self::CachedKey,
+        _new_bb: u64,
+    ) -> Result<(), Self::Error> {
+        self.blocks.extend_from_slice(cached_key);
+        Ok(())
+    }
+
+    #[cfg(feature = "cache_self_check")]
+    fn is_same_cache(&self, cached_key: &Self::CachedKey, other: &Self::CachedKey) -> bool {
+        cached_key == other
+    }
+
+    #[cfg(feature = "cache")]
+    fn should_clear_all_cache(&mut self) -> Result<bool, Self::Error> {
+        let query_count = self.clear_query_count;
+        self.clear_query_count += 1;
+        Ok(self.clear_at_query == Some(query_count))
+    }
+}
+
+/// Builder of Intel PT packet streams
+#[derive(Default, Clone)]
+pub struct PtBuilder {
+    buf: Vec<u8>,
+}
+
+impl PtBuilder {
+    /// Create an empty packet stream
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the built packet stream
+    pub fn build(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Append raw bytes
+    pub fn raw(mut self, bytes: &[u8]) -> Self {
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    /// Append a packet with 8-byte IP payload
+    fn ip_packet(self, header: u8, addr: u64) -> Self {
+        self.raw(&[(0b110 << 5) | header]).raw(&addr.to_le_bytes())
+    }
+
+    /// Append a packet with 6-byte IP payload, which is sign-extended by bit 47
+    fn ip_packet_sext(self, header: u8, addr: u64) -> Self {
+        self.raw(&[(0b011 << 5) | header])
+            .raw(&addr.to_le_bytes()[..6])
+    }
+
+    /// Append a PSB packet
+    pub fn psb(self) -> Self {
+        self.raw(&[0x02, 0x82].repeat(8))
+    }
+
+    /// Append a PSBEND packet
+    pub fn psbend(self) -> Self {
+        self.raw(&[0x02, 0x23])
+    }
+
+    /// Append an OVF packet
+    pub fn ovf(self) -> Self {
+        self.raw(&[0x02, 0xF3])
+    }
+
+    /// Append a TIP packet with full IP
+    pub fn tip(self, addr: u64) -> Self {
+        self.ip_packet(0b0_1101, addr)
+    }
+
+    /// Append a TIP packet with only the lower 2 bytes of IP, which are
+    /// combined with the last IP
+    pub fn tip_two_bytes(self, addr: u64) -> Self {
+        self.raw(&[(0b001 << 5) | 0b0_1101])
+            .raw(&addr.to_le_bytes()[..2])
+    }
+
+    /// Append a TIP packet with sign-extended 6-byte IP
+    pub fn tip_sext(self, addr: u64) -> Self {
+        self.ip_packet_sext(0b0_1101, addr)
+    }
+
+    /// Append a TIP.PGE packet with full IP
+    pub fn tip_pge(self, addr: u64) -> Self {
+        self.ip_packet(0b1_0001, addr)
+    }
+
+    /// Append a TIP.PGE packet with sign-extended 6-byte IP
+    pub fn tip_pge_sext(self, addr: u64) -> Self {
+        self.ip_packet_sext(0b1_0001, addr)
+    }
+
+    /// Append a TIP.PGD packet with full IP
+    pub fn tip_pgd(self, addr: u64) -> Self {
+        self.ip_packet(0b0_0001, addr)
+    }
+
+    /// Append a TIP.PGD packet with suppressed IP
+    pub fn tip_pgd_suppressed(self) -> Self {
+        self.raw(&[0b0_0001])
+    }
+
+    /// Append a FUP packet with full IP
+    pub fn fup(self, addr: u64) -> Self {
+        self.ip_packet(0b1_1101, addr)
+    }
+
+    /// Append a PIP packet with NR bit cleared.
+    ///
+    /// `cr3` follows the convention of [`HandlePacket::on_pip_packet`][iptr_decoder::HandlePacket::on_pip_packet].
+    pub fn pip(self, cr3: u64) -> Self {
+        self.raw(&[0x02, 0x43]).raw(&(cr3 >> 5).to_le_bytes()[..6])
+    }
+
+    /// Append a VMCS packet.
+    ///
+    /// `vmcs_pointer` follows the convention of [`HandlePacket::on_vmcs_packet`][iptr_decoder::HandlePacket::on_vmcs_packet].
+    pub fn vmcs(self, vmcs_pointer: u64) -> Self {
+        self.raw(&[0x02, 0xC8])
+            .raw(&(vmcs_pointer >> 12).to_le_bytes()[..5])
+    }
+
+    /// Append a MODE.exec packet
+    pub fn mode_exec(self, tracee_mode: TraceeMode) -> Self {
+        let mode = match tracee_mode {
+            TraceeMode::Mode16 => 0b00,
+            TraceeMode::Mode64 => 0b01,
+            TraceeMode::Mode32 => 0b10,
+        };
+        self.raw(&[0x99, mode])
+    }
+
+    /// Append a short TNT packet, `bits` are ordered from the oldest to the newest
+    pub fn short_tnt(self, bits: &[bool]) -> Self {
+        assert!(bits.len() <= 6, "Too many bits for short TNT");
+        let mut byte = 1u8;
+        for bit in bits {
+            byte = (byte << 1) | u8::from(*bit);
+        }
+        self.raw(&[byte << 1])
+    }
+
+    /// Long TNT packet with given bits, from the oldest to the newest
+    pub fn long_tnt(self, bits: &[bool]) -> Self {
+        assert!(bits.len() <= 47, "Too many bits for long TNT");
+        let mut payload = 1u64;
+        for bit in bits {
+            payload = (payload << 1) | u64::from(*bit);
+        }
+        self.raw(&[0x02, 0xA3]).raw(&payload.to_le_bytes()[..6])
+    }
+}
+
+/// ```plaintext
+/// 0x1000: jnz 0x1000
+/// 0x1002: jmp rax
+/// ```
+pub const LOOP_CODE: [u8; 4] = [0x75, 0xFE, 0xFF, 0xE0];
+
+/// Create an analyzer with [`LOOP_CODE`] at `0x1000`
+pub fn loop_analyzer() -> EdgeAnalyzer<BlockRecorder, SliceMemoryReader> {
+    EdgeAnalyzer::new(
+        BlockRecorder::default(),
+        SliceMemoryReader::new(&[(0x1000, &LOOP_CODE)]),
+    )
+}
+
+/// Trace of [`LOOP_CODE`] with 39 TNT bits: 38 taken and the last not taken,
+/// which fills the 32-bit, 8-bit and trailing bits caches
+#[cfg(any(
+    feature = "cache_self_check",
+    all(feature = "cache", feature = "more_diagnose")
+))]
+pub fn loop_trace() -> Vec<u8> {
+    let mut builder = PtBuilder::new().psb().psbend().tip_pge(0x1000);
+    for _ in 0..6 {
+        builder = builder.short_tnt(&[true; 6]);
+    }
+    builder.short_tnt(&[true, true, false]).tip(0x1000).build()
+}
+
+/// Temporary file which is removed when dropped, even if the test panics
+#[cfg(feature = "perf_memory_reader")]
+pub struct TempFile {
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "perf_memory_reader")]
+impl TempFile {
+    /// Create a file named `name` in the temporary directory with given content
+    pub fn new(name: &str, content: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        Self { path }
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "perf_memory_reader")]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -51,18 +51,42 @@ impl TntBuffer {
     }
 }
 
-/// Manager for TNT buffers
-pub struct TntBufferManager {
-    /// The internal buffer
-    buf: TntBuffer,
+/// Split TNT bits in a long TNT packet into a full TNT buffer holding
+/// the first 32 bits, and a TNT buffer holding the remaining bits.
+///
+/// Returns [`None`] if there are less than 32 TNT bits. The requirement of
+/// `long_tnt_packet` is the same as [`TntBufferManager::extend_with_long_tnt`].
+#[expect(clippy::cast_possible_truncation)]
+#[must_use]
+pub fn split_long_tnt(long_tnt_packet: NonZero<u64>) -> Option<(TntBuffer, TntBuffer)> {
+    // Bits below the upmost one are all TNT bits
+    let tnt_count = u64::BITS - 1 - long_tnt_packet.leading_zeros();
+    let remain_count = tnt_count.checked_sub(u32::BITS)?;
+    let long_tnt_packet = long_tnt_packet.get();
+    let full_buf = TntBuffer {
+        value: (long_tnt_packet >> remain_count) as u32,
+        bits: u32::BITS,
+    };
+    let remain_buf = TntBuffer {
+        value: (long_tnt_packet as u32).unbounded_shl(u32::BITS - remain_count),
+        bits: remain_count,
+    };
+    Some((full_buf, remain_buf))
 }
 
-impl Default for TntBufferManager {
-    fn default() -> Self {
-        Self {
-            buf: TntBuffer { value: 0, bits: 0 },
-        }
-    }
+/// Manager for TNT buffers
+///
+/// Pending TNT bits are accumulated here, and taken out as [`TntBuffer`]s
+/// holding no more than 32 bits. Since TNT bits after a deferred TIP are
+/// re-injected into the manager, there may be more than 32 pending bits, e.g.,
+/// 46 bits of a long TNT packet after a deferred TIP detected at its first bit.
+/// As a result, the pending bits are stored in a [`u128`].
+#[derive(Clone, Default)]
+pub struct TntBufferManager {
+    /// Pending TNT bits, the oldest bit is the most significant one
+    value: u128,
+    /// Number of pending bits, no more than [`u128::BITS`].
+    bits: u32,
 }
 
 /// The LSB bit in short TNT packet
@@ -77,21 +101,58 @@ impl TntBufferManager {
         Self::default()
     }
 
+    /// Get the number of pending TNT bits
+    pub fn pending_bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Whether there are no pending TNT bits
+    pub fn is_empty(&self) -> bool {
+        self.pending_bits() == 0
+    }
+
+    /// Get the first (at most 32) pending TNT bits without taking them
+    pub fn peek(&self) -> TntBuffer {
+        TntBuffer {
+            value: (self.value >> (u128::BITS - u32::BITS)) as u32,
+            bits: self.bits.min(u32::BITS),
+        }
+    }
+
     /// Clear current TNT buffer
     pub fn clear(&mut self) {
-        self.buf.value = 0;
-        self.buf.bits = 0;
+        self.value = 0;
+        self.bits = 0;
+    }
+
+    /// Append `tnt_count` TNT bits stored in the lower bits of `tnt_bits`.
+    ///
+    /// This function will return error if the TNT buffer exceeded.
+    fn append<H: HandleControlFlow, R: ReadMemory>(
+        &mut self,
+        tnt_bits: u64,
+        tnt_count: u32,
+    ) -> AnalyzerResult<(), H, R> {
+        if self.bits + tnt_count > u128::BITS {
+            return Err(AnalyzerError::ExceededTntBuffer);
+        }
+        self.bits += tnt_count;
+        self.value |= u128::from(tnt_bits) << (u128::BITS - self.bits);
+
+        Ok(())
     }
 
     /// Insert TNT bits in a short TNT packet into the TNT buffer.
     ///
-    /// This function will return a full 64-bits TNT buffer if current buffer
-    /// is full after the insertion
+    /// Full TNT buffers should be taken by [`take_full`][Self::take_full] after
+    /// the insertion. This function will return error if the TNT buffer exceeded.
     ///
     /// You must pass a short TNT format packet here
     #[expect(clippy::manual_range_contains)]
-    #[must_use]
-    pub fn extend_with_short_tnt(&mut self, short_tnt_packet: NonZero<u8>) -> Option<TntBuffer> {
+    pub fn extend_with_short_tnt<H: HandleControlFlow, R: ReadMemory>(
+        &mut self,
+        short_tnt_packet: NonZero<u8>,
+    ) -> AnalyzerResult<(), H, R> {
         debug_assert!(
             short_tnt_packet.leading_zeros() <= 6,
             "There should be at least one leading one bit"
@@ -101,7 +162,7 @@ impl TntBufferManager {
         let highest_bit = u8::BITS - 1 - 1 - short_tnt_packet.leading_zeros();
         if highest_bit == SHORT_TNT_PREFIX_BIT_COUNT - 1 {
             // Nothing to extend: 0b0000_0010
-            return None;
+            return Ok(());
         }
         let lz = short_tnt_packet.leading_zeros();
         // SAFETY: Obvious
@@ -120,37 +181,20 @@ impl TntBufferManager {
         let short_tnt_packet = short_tnt_packet.get() & (u8::MAX >> (u8::BITS - 1 - highest_bit)); // Clear the leading one
         let short_tnt_packet = short_tnt_packet >> SHORT_TNT_PREFIX_BIT_COUNT; // Remove the ending zero
 
-        if tnt_count + self.buf.bits < u32::BITS {
-            // Not full
-            self.buf.bits += tnt_count; // self.buf.bits will never get u64::BITS
-            self.buf.value |= (short_tnt_packet as u32) << (u32::BITS - self.buf.bits);
-            None
-        } else {
-            // With this packet, get full
-            // The buf may already been full, then this will still function normally
-            let this_tnt_count = u32::BITS - self.buf.bits;
-            let remain_count = tnt_count - this_tnt_count;
-            self.buf.value |= (short_tnt_packet >> remain_count) as u32;
-            let full_buf = TntBuffer {
-                value: self.buf.value,
-                bits: u32::BITS,
-            };
-            self.buf.value = (short_tnt_packet as u32).unbounded_shl(u32::BITS - remain_count);
-            self.buf.bits = remain_count;
-            Some(full_buf)
-        }
+        self.append(u64::from(short_tnt_packet), tnt_count)
     }
 
     /// Insert TNT bits in a long TNT packet into the TNT buffer.
     ///
-    /// This function will return a full 64-bits TNT buffer if current buffer
-    /// is full after the insertion
+    /// Full TNT buffers should be taken by [`take_full`][Self::take_full] after
+    /// the insertion. This function will return error if the TNT buffer exceeded.
     ///
     /// You must pass a long TNT format packet here. The first two bytes must be stripped,
     /// as done by decoder. As a result, the upmost two bytes of `long_tnt_packet` are cleared.
-    #[expect(clippy::cast_possible_truncation)]
-    #[must_use]
-    pub fn extend_with_long_tnt(&mut self, long_tnt_packet: NonZero<u64>) -> Option<TntBuffer> {
+    pub fn extend_with_long_tnt<H: HandleControlFlow, R: ReadMemory>(
+        &mut self,
+        long_tnt_packet: NonZero<u64>,
+    ) -> AnalyzerResult<(), H, R> {
         debug_assert_eq!(
             long_tnt_packet.get() >> 48,
             0,
@@ -167,67 +211,75 @@ impl TntBufferManager {
         let highest_bit = (u64::BITS - 1 - 1).wrapping_sub(long_tnt_packet.leading_zeros());
         if highest_bit == LONG_TNT_PREFIX_BIT_COUNT.wrapping_sub(1) {
             // Nothing to extend
-            return None;
+            return Ok(());
         }
         let tnt_count = highest_bit.wrapping_sub(LONG_TNT_PREFIX_BIT_COUNT.wrapping_sub(1));
         let long_tnt_packet = long_tnt_packet.get() & (u64::MAX >> (u64::BITS - 1 - highest_bit)); // Clear the leading one
         let long_tnt_packet = long_tnt_packet >> LONG_TNT_PREFIX_BIT_COUNT; // Remove the ending zero
 
-        if tnt_count + self.buf.bits < u32::BITS {
-            // Not full
-            self.buf.bits += tnt_count; // self.buf.bits will never get u64::BITS
-            self.buf.value |= (long_tnt_packet as u32) << (u32::BITS - self.buf.bits);
-            None
-        } else {
-            // With this packet, get full
-            // The buf may already been full, then this will still function normally
-            let this_tnt_count = u32::BITS - self.buf.bits;
-            let remain_count = tnt_count - this_tnt_count;
-            self.buf.value |= (long_tnt_packet >> remain_count) as u32;
-            let full_buf = TntBuffer {
-                value: self.buf.value,
-                bits: u32::BITS,
-            };
-            self.buf.value = long_tnt_packet.unbounded_shl(u32::BITS - remain_count) as u32;
-            self.buf.bits = remain_count;
-            Some(full_buf)
-        }
+        self.append(long_tnt_packet, tnt_count)
     }
 
     /// Prepend given buf to the internal TNT buffer.
     ///
-    /// This function will return error if the TNT buffer exceeded.
+    /// This function will return error if the TNT buffer exceeded, i.e., there
+    /// would be more than [`u128::BITS`] pending bits.
     pub fn prepend_buf<H: HandleControlFlow, R: ReadMemory>(
         &mut self,
         buf: TntBuffer,
     ) -> AnalyzerResult<(), H, R> {
-        if self.buf.bits() + buf.bits() > u64::BITS {
+        if self.bits + buf.bits() > u128::BITS {
             return Err(AnalyzerError::ExceededTntBuffer);
         }
-        self.buf.value = self.buf.value.unbounded_shr(buf.bits());
-        self.buf.value |= buf.value;
-        self.buf.bits += buf.bits();
+        self.value = self.value.unbounded_shr(buf.bits());
+        self.value |= u128::from(buf.value) << (u128::BITS - u32::BITS);
+        self.bits += buf.bits();
 
         Ok(())
     }
 
-    /// Take current tnt buffer out of the manager, leaving
-    /// a new buffer in place.
+    /// Take the first (at most 32) pending TNT bits out of the manager, leaving
+    /// the remaining bits in place.
     pub fn take(&mut self) -> TntBuffer {
-        let buf = self.buf;
-        self.clear();
+        let buf = self.peek();
+        self.value = self.value.unbounded_shl(buf.bits());
+        self.bits -= buf.bits();
         buf
     }
+
+    /// Take the first 32 pending TNT bits out of the manager if there are
+    /// at least 32 pending bits.
+    #[must_use]
+    pub fn take_full(&mut self) -> Option<TntBuffer> {
+        (self.bits >= u32::BITS).then(|| self.take())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::{BlockRecorder, SliceMemoryReader};
+
+    type Result<T> = AnalyzerResult<T, BlockRecorder, SliceMemoryReader>;
 
     impl TntBufferManager {
         /// May not be full
         fn buffer(&self) -> TntBuffer {
-            self.buf
+            self.peek()
+        }
+
+        /// Extend with short TNT packet, and take the full TNT buffer if any
+        fn extend_short(&mut self, short_tnt_packet: NonZero<u8>) -> Option<TntBuffer> {
+            let res: Result<()> = self.extend_with_short_tnt(short_tnt_packet);
+            res.unwrap();
+            self.take_full()
+        }
+
+        /// Extend with long TNT packet, and take the full TNT buffer if any
+        fn extend_long(&mut self, long_tnt_packet: NonZero<u64>) -> Option<TntBuffer> {
+            let res: Result<()> = self.extend_with_long_tnt(long_tnt_packet);
+            res.unwrap();
+            self.take_full()
         }
     }
 
@@ -236,7 +288,7 @@ mod tests {
         let mut buffer_manager = TntBufferManager::default();
 
         // No TNT bits in this packet
-        let full_buf = buffer_manager.extend_with_short_tnt(NonZero::new(0b0000_0010).unwrap());
+        let full_buf = buffer_manager.extend_short(NonZero::new(0b0000_0010).unwrap());
         assert!(full_buf.is_none());
         let buf = buffer_manager.buffer();
         assert_eq!(buf.value, 0b0);
@@ -247,7 +299,7 @@ mod tests {
     fn test_buffer_extend_short_tnt_from_zero() {
         let mut buffer_manager = TntBufferManager::default();
 
-        let full_buf = buffer_manager.extend_with_short_tnt(NonZero::new(0b0110_1010).unwrap());
+        let full_buf = buffer_manager.extend_short(NonZero::new(0b0110_1010).unwrap());
         assert!(full_buf.is_none());
         let buf = buffer_manager.buffer();
         assert_eq!(buf.value, u32::from_le_bytes([0, 0, 0, 0b10101_000]));
@@ -260,11 +312,11 @@ mod tests {
 
         for loop_count in 0..6 {
             // Each time will add 5 bits, after 7 times, will reach full
-            let full_buf = buffer_manager.extend_with_short_tnt(NonZero::new(0b0110_1010).unwrap());
+            let full_buf = buffer_manager.extend_short(NonZero::new(0b0110_1010).unwrap());
             assert!(full_buf.is_none());
             assert_eq!(buffer_manager.buffer().bits, 5 * (loop_count + 1));
         }
-        let full_buf = buffer_manager.extend_with_short_tnt(NonZero::new(0b0110_1010).unwrap());
+        let full_buf = buffer_manager.extend_short(NonZero::new(0b0110_1010).unwrap());
         assert!(full_buf.is_some());
         if let Some(full_buf) = full_buf {
             assert_eq!(full_buf.value, 0b10101_10101_10101_10101_10101_10101_10);
@@ -280,9 +332,8 @@ mod tests {
         let mut buffer_manager = TntBufferManager::default();
 
         // No TNT bits in this packet
-        let full_buf = buffer_manager.extend_with_long_tnt(
-            NonZero::new(u64::from_le_bytes([0x1, 0, 0, 0, 0, 0, 0, 0])).unwrap(),
-        );
+        let full_buf = buffer_manager
+            .extend_long(NonZero::new(u64::from_le_bytes([0x1, 0, 0, 0, 0, 0, 0, 0])).unwrap());
         assert!(full_buf.is_none());
         let buf = buffer_manager.buffer();
         assert_eq!(buf.value, 0b0);
@@ -293,7 +344,7 @@ mod tests {
     fn test_buffer_extend_long_tnt_from_zero() {
         let mut buffer_manager = TntBufferManager::default();
 
-        let full_buf = buffer_manager.extend_with_long_tnt(
+        let full_buf = buffer_manager.extend_long(
             NonZero::new(u64::from_le_bytes([0b1111_1101, 0, 0, 0, 0, 0, 0, 0])).unwrap(),
         );
         assert!(full_buf.is_none());
@@ -307,7 +358,7 @@ mod tests {
         let mut buffer_manager = TntBufferManager::default();
 
         // This will add 47 TNT bits, remain 15 bits
-        let full_buf = buffer_manager.extend_with_long_tnt(
+        let full_buf = buffer_manager.extend_long(
             NonZero::new(u64::from_le_bytes([
                 0b1111_1101,
                 0b1111_1101,
@@ -329,4 +380,58 @@ mod tests {
         assert_eq!(buf.value, 0b111_1101_1111_1101_0_0000_0000_0000_0000);
         assert_eq!(buf.bits, 15);
     }
+
+    #[test]
+    fn test_prepend_more_than_32_bits() {
+        let mut buffer_manager = TntBufferManager::default();
+
+        // 15 bits remain after a full buffer
+        let full_buf = buffer_manager
+            .extend_long(
+                NonZero::new(u64::from_le_bytes([
+                    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0,
+                ]))
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(buffer_manager.pending_bits(), 15);
+        // Deferred TIP detected after the first 2 bits of the full buffer
+        let res: Result<()> = buffer_manager.prepend_buf(full_buf.remove_first_n_bits(2));
+        res.unwrap();
+        assert_eq!(buffer_manager.pending_bits(), 45);
+
+        let buf = buffer_manager.take();
+        assert_eq!(buf.value, u32::MAX);
+        assert_eq!(buf.bits, u32::BITS);
+        let buf = buffer_manager.take();
+        assert_eq!(buf.value, u32::MAX << (u32::BITS - 13));
+        assert_eq!(buf.bits, 13);
+        assert!(buffer_manager.is_empty());
+    }
+
+    #[test]
+    fn test_split_long_tnt() {
+        let long_tnt_packet = NonZero::new(u64::from_le_bytes([
+            0b1111_1101,
+            0b1111_1101,
+            0b1111_1101,
+            0b1111_1101,
+            0b1111_1101,
+            0b1111_1101,
+            0,
+            0,
+        ]))
+        .unwrap();
+        let (full_buf, remain_buf) = split_long_tnt(long_tnt_packet).unwrap();
+        // Same as extending an empty buffer manager
+        let mut buffer_manager = TntBufferManager::default();
+        let expected_full_buf = buffer_manager.extend_long(long_tnt_packet).unwrap();
+        assert_eq!(full_buf.value, expected_full_buf.value);
+        assert_eq!(full_buf.bits, u32::BITS);
+        assert_eq!(remain_buf.value, buffer_manager.buffer().value);
+        assert_eq!(remain_buf.bits, 15);
+
+        // Less than 32 bits
+        assert!(split_long_tnt(NonZero::new(0b1111_1101).unwrap()).is_none());
+    }
 }
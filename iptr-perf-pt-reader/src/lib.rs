@@ -4,21 +4,30 @@
 
 extern crate alloc;
 
-use core::ffi::CStr;
+use core::{cmp::Reverse, ffi::CStr};
 
 use alloc::{
+    collections::{BTreeMap, BinaryHeap},
     string::{String, ToString},
     vec::Vec,
 };
 
 mod error;
+mod source;
 mod util;
 
 pub use crate::error::ReaderError;
 use crate::error::ReaderResult;
+pub use crate::source::{
+    AuxSegment, AuxSource, PerThreadAuxSource, PerfDataAuxSource, RawAuxSource,
+};
 
+/// Value of `type` field for legacy mmapped perf header
+const PERF_RECORD_MMAP: u32 = 1;
 /// Value of `type`` field for mmapped perf header
 const PERF_RECORD_MMAP2: u32 = 10;
+/// Value of `type` field for auxtrace info header
+const PERF_RECORD_AUXTRACE_INFO: u32 = 70;
 /// Value of `type` field for auxtrace header
 const PERF_RECORD_AUXTRACE: u32 = 71;
 
@@ -27,7 +36,10 @@ const PERF_RECORD_AUXTRACE: u32 = 71;
 pub fn extract_pt_auxtraces(perf_data: &[u8]) -> ReaderResult<Vec<PerfRecordAuxtrace<'_>>> {
     let mut pt_auxtraces = Vec::new();
 
-    let (pos, total_size) = read_perf_header(perf_data)?;
+    let PerfFileHeader {
+        data_section: (pos, total_size),
+        ..
+    } = read_perf_header(perf_data)?;
     let mut pos = pos as usize;
     let end_pos = pos.saturating_add(total_size as usize);
     let Some(perf_data) = perf_data.get(0..end_pos) else {
@@ -62,15 +74,60 @@ pub fn extract_pt_auxtraces(perf_data: &[u8]) -> ReaderResult<Vec<PerfRecordAuxt
     Ok(pt_auxtraces)
 }
 
+/// Extract the first `PERF_RECORD_AUXTRACE_INFO` record from `perf.data`.
+///
+/// Returns [`None`] if there is no such record.
+#[expect(clippy::cast_possible_truncation)]
+pub fn extract_auxtrace_info(perf_data: &[u8]) -> ReaderResult<Option<PerfRecordAuxtraceInfo>> {
+    let PerfFileHeader {
+        data_section: (pos, total_size),
+        ..
+    } = read_perf_header(perf_data)?;
+    let mut pos = pos as usize;
+    let end_pos = pos.saturating_add(total_size as usize);
+    let Some(perf_data) = perf_data.get(0..end_pos) else {
+        return Err(ReaderError::UnexpectedEOF);
+    };
+
+    while pos < end_pos {
+        let perf_header_start_pos = pos;
+        let Some(perf_event_header) = read_perf_event_header(perf_data, &mut pos) else {
+            return Err(ReaderError::UnexpectedEOF);
+        };
+        if perf_event_header.size == 0 {
+            // This will lead to infinite loop
+            return Err(ReaderError::InvalidPerfData);
+        }
+        let record_end_pos = perf_header_start_pos.saturating_add(perf_event_header.size as usize);
+        if perf_event_header.r#type == PERF_RECORD_AUXTRACE_INFO {
+            let Some(auxtrace_info) = read_auxtrace_info(perf_data, pos, record_end_pos) else {
+                return Err(ReaderError::InvalidPerfData);
+            };
+            return Ok(Some(auxtrace_info));
+        }
+        pos = record_end_pos;
+    }
+
+    Ok(None)
+}
+
 /// Extract raw Intel PT traces alongwith mmapped information from `perf.data`.
+///
+/// Legacy `PERF_RECORD_MMAP` records are converted to [`PerfMmap2Header`] with
+/// zeroed `inode`, `prot` and `flags`. They are only used if there are no
+/// `PERF_RECORD_MMAP2` records.
 #[expect(clippy::cast_possible_truncation)]
 pub fn extract_pt_auxtraces_and_mmap_data(
     perf_data: &[u8],
 ) -> ReaderResult<(Vec<PerfRecordAuxtrace<'_>>, Vec<PerfMmap2Header>)> {
     let mut pt_auxtraces = Vec::new();
     let mut mmap2_headers = Vec::new();
+    let mut legacy_mmap_headers = Vec::new();
 
-    let (pos, total_size) = read_perf_header(perf_data)?;
+    let PerfFileHeader {
+        data_section: (pos, total_size),
+        ..
+    } = read_perf_header(perf_data)?;
     let mut pos = pos as usize;
     let end_pos = pos.saturating_add(total_size as usize);
     let Some(perf_data) = perf_data.get(0..end_pos) else {
@@ -104,16 +161,38 @@ pub fn extract_pt_auxtraces_and_mmap_data(
                 mmap2_headers.push(mmap2_header);
                 pos = end_pos;
             }
+            PERF_RECORD_MMAP => {
+                let end_pos = perf_header_start_pos.saturating_add(perf_event_header.size as usize);
+                let Some(legacy_mmap_header) = read_mmap(perf_data, pos, end_pos) else {
+                    return Err(ReaderError::InvalidPerfData);
+                };
+                legacy_mmap_headers.push(legacy_mmap_header);
+                pos = end_pos;
+            }
             _ => {
                 pos = perf_header_start_pos.saturating_add(perf_event_header.size as usize);
             }
         }
     }
 
+    if mmap2_headers.is_empty() {
+        mmap2_headers = legacy_mmap_headers;
+    }
+
     Ok((pt_auxtraces, mmap2_headers))
 }
 
-fn read_perf_header(perf_data: &[u8]) -> ReaderResult<(u64, u64)> {
+/// Header of `perf.data`
+struct PerfFileHeader {
+    /// Size of each entry in attrs section
+    attr_size: u64,
+    /// `(offset, size)` of attrs section
+    attrs_section: (u64, u64),
+    /// `(offset, size)` of data section
+    data_section: (u64, u64),
+}
+
+fn read_perf_header(perf_data: &[u8]) -> ReaderResult<PerfFileHeader> {
     let mut pos = 0;
     let magic = util::read_u64(perf_data, pos).ok_or(ReaderError::UnexpectedEOF)?;
     pos += 8;
@@ -124,16 +203,19 @@ fn read_perf_header(perf_data: &[u8]) -> ReaderResult<(u64, u64)> {
     let _size = util::read_u64(perf_data, pos).ok_or(ReaderError::UnexpectedEOF)?;
     pos += 8;
 
-    let _attr_size = util::read_u64(perf_data, pos).ok_or(ReaderError::UnexpectedEOF)?;
+    let attr_size = util::read_u64(perf_data, pos).ok_or(ReaderError::UnexpectedEOF)?;
     pos += 8;
 
-    let _attrs_section =
+    let attrs_section =
         read_perf_file_section(perf_data, &mut pos).ok_or(ReaderError::UnexpectedEOF)?;
     let data_section =
         read_perf_file_section(perf_data, &mut pos).ok_or(ReaderError::UnexpectedEOF)?;
 
-    let (offset, size) = data_section;
-    Ok((offset, size))
+    Ok(PerfFileHeader {
+        attr_size,
+        attrs_section,
+        data_section,
+    })
 }
 
 fn read_perf_file_section(perf_data: &[u8], pos: &mut usize) -> Option<(u64, u64)> {
@@ -163,15 +245,378 @@ fn read_perf_event_header(perf_data: &[u8], pos: &mut usize) -> Option<PerfEvent
     Some(PerfEventHeader { r#type, misc, size })
 }
 
+/// Group auxtraces by their thread ids.
+///
+/// Auxtraces of a multi-threaded target are interleaved in `perf.data`. Each
+/// group should be decoded with its own analyzer state to reconstruct the
+/// control flow of each thread. The order of auxtraces in each group is
+/// preserved.
+#[must_use]
+pub fn group_auxtraces_by_tid(
+    auxtraces: Vec<PerfRecordAuxtrace<'_>>,
+) -> BTreeMap<u32, Vec<PerfRecordAuxtrace<'_>>> {
+    let mut groups = BTreeMap::<u32, Vec<_>>::new();
+    for auxtrace in auxtraces {
+        groups.entry(auxtrace.tid).or_default().push(auxtrace);
+    }
+    groups
+}
+
+/// Group auxtraces by their CPU ids.
+///
+/// For system-wide traces, each CPU has its own Intel PT trace, and auxtraces
+/// of different CPUs are interleaved in `perf.data`. The order of auxtraces in
+/// each group is preserved.
+#[must_use]
+pub fn group_auxtraces_by_cpu(
+    auxtraces: Vec<PerfRecordAuxtrace<'_>>,
+) -> BTreeMap<u32, Vec<PerfRecordAuxtrace<'_>>> {
+    let mut groups = BTreeMap::<u32, Vec<_>>::new();
+    for auxtrace in auxtraces {
+        groups.entry(auxtrace.cpu).or_default().push(auxtrace);
+    }
+    groups
+}
+
+/// Auxtraces coalesced from consecutive [`PerfRecordAuxtrace`]s of the same
+/// AUX buffer, returned by [`coalesce_auxtraces`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoalescedAuxtrace {
+    /// Offset of the first coalesced auxtrace
+    pub offset: u64,
+    /// Index of the AUX buffer
+    pub idx: u32,
+    /// Thread id
+    pub tid: u32,
+    /// CPU id
+    pub cpu: u32,
+    /// Concatenated Intel PT data
+    pub auxtrace_data: Vec<u8>,
+}
+
+/// Coalesce consecutive auxtraces that are split from a contiguous region of
+/// the same AUX buffer.
+///
+/// perf may split a single region of AUX buffer into several AUXTRACE records,
+/// and a packet may straddle the boundary of records, which cannot be decoded
+/// if each record is decoded separately. An auxtrace is appended to the previous
+/// one if they have the same [`idx`][PerfRecordAuxtrace::idx],
+/// [`tid`][PerfRecordAuxtrace::tid] and [`cpu`][PerfRecordAuxtrace::cpu], and its
+/// [`offset`][PerfRecordAuxtrace::offset] is exactly the end of the previous one.
+///
+/// Only adjacent auxtraces in `auxtraces` are coalesced, so `auxtraces` should be
+/// in the order they are recorded in `perf.data` (e.g., returned by
+/// [`extract_pt_auxtraces`]), where the records of each AUX buffer are in ascending
+/// order of offset. If records of different AUX buffers are interleaved, group them
+/// first by [`group_auxtraces_by_tid`] or [`group_auxtraces_by_cpu`], and coalesce
+/// each group.
+#[must_use]
+pub fn coalesce_auxtraces(auxtraces: &[PerfRecordAuxtrace<'_>]) -> Vec<CoalescedAuxtrace> {
+    let mut coalesced = Vec::<CoalescedAuxtrace>::new();
+    for auxtrace in auxtraces {
+        if let Some(last) = coalesced.last_mut()
+            && last.idx == auxtrace.idx
+            && last.tid == auxtrace.tid
+            && last.cpu == auxtrace.cpu
+            && last
+                .offset
+                .checked_add(last.auxtrace_data.len() as u64)
+                .is_some_and(|end| end == auxtrace.offset)
+        {
+            last.auxtrace_data.extend_from_slice(auxtrace.auxtrace_data);
+            continue;
+        }
+        coalesced.push(CoalescedAuxtrace {
+            offset: auxtrace.offset,
+            idx: auxtrace.idx,
+            tid: auxtrace.tid,
+            cpu: auxtrace.cpu,
+            auxtrace_data: auxtrace.auxtrace_data.to_vec(),
+        });
+    }
+    coalesced
+}
+
+/// An event of a CPU with timestamp, returned by [`merge_by_time`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent<T> {
+    /// CPU id
+    pub cpu: u32,
+    /// Timestamp of the event
+    pub timestamp: u64,
+    /// The event
+    pub event: T,
+}
+
+/// Interleave events of each CPU in global time order.
+///
+/// `per_cpu_events` maps CPU id to `(timestamp, event)` pairs of that CPU, e.g.,
+/// events collected by decoding each group of [`group_auxtraces_by_cpu`]. The
+/// timestamps should be in the same clock across CPUs, such as TSC values
+/// reconstructed from TSC packets (and refined by MTC packets), and events of
+/// each CPU should already be in time order.
+///
+/// Events with the same timestamp are ordered by CPU id, and the order of events
+/// of each CPU is always preserved.
+#[must_use]
+pub fn merge_by_time<T>(per_cpu_events: BTreeMap<u32, Vec<(u64, T)>>) -> Vec<TimedEvent<T>> {
+    let total_len = per_cpu_events.values().map(Vec::len).sum();
+    let mut merged = Vec::with_capacity(total_len);
+    let mut iters = per_cpu_events
+        .into_iter()
+        .map(|(cpu, events)| (cpu, events.into_iter().peekable()))
+        .collect::<Vec<_>>();
+    // Min-heap of `(timestamp, index of iters)`. Since iters are sorted by
+    // CPU id, events with the same timestamp are ordered by CPU id.
+    let mut heap = iters
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(idx, (_, events))| Some(Reverse((events.peek()?.0, idx))))
+        .collect::<BinaryHeap<_>>();
+    while let Some(Reverse((_, idx))) = heap.pop() {
+        let (cpu, events) = &mut iters[idx];
+        let Some((timestamp, event)) = events.next() else {
+            continue;
+        };
+        merged.push(TimedEvent {
+            cpu: *cpu,
+            timestamp,
+            event,
+        });
+        if let Some((next_timestamp, _)) = events.peek() {
+            heap.push(Reverse((*next_timestamp, idx)));
+        }
+    }
+    merged
+}
+
+/// `perf_event_attr` in the attrs section of `perf.data`
+///
+/// Only the leading fields needed for interpreting records are parsed.
+pub struct PerfEventAttr {
+    /// Major type of event, e.g., the dynamic PMU type of `intel_pt`
+    pub r#type: u32,
+    /// Type-specific configuration
+    pub config: u64,
+    /// Fields included in samples, which is a bitmask of `PERF_SAMPLE_*`,
+    /// e.g., [`PERF_SAMPLE_TIME`][Self::PERF_SAMPLE_TIME]
+    pub sample_type: u64,
+    /// Format of values read from the event, which is a bitmask of
+    /// `PERF_FORMAT_*`
+    pub read_format: u64,
+    /// Whether non-sample records carry the `sample_id` trailer selected by
+    /// [`sample_type`][Self::sample_type]
+    pub sample_id_all: bool,
+}
+
+impl PerfEventAttr {
+    /// Samples contain instruction pointer
+    pub const PERF_SAMPLE_IP: u64 = 1 << 0;
+    /// Samples contain process id and thread id
+    pub const PERF_SAMPLE_TID: u64 = 1 << 1;
+    /// Samples contain timestamp
+    pub const PERF_SAMPLE_TIME: u64 = 1 << 2;
+    /// Samples contain address
+    pub const PERF_SAMPLE_ADDR: u64 = 1 << 3;
+    /// Samples contain event id
+    pub const PERF_SAMPLE_ID: u64 = 1 << 6;
+    /// Samples contain CPU id
+    pub const PERF_SAMPLE_CPU: u64 = 1 << 7;
+    /// Samples contain event id at a fixed position
+    pub const PERF_SAMPLE_IDENTIFIER: u64 = 1 << 16;
+
+    /// Bit of `sample_id_all` in the flags of `perf_event_attr`
+    const SAMPLE_ID_ALL_BIT: u64 = 1 << 18;
+    /// Size of the leading fields parsed in [`PerfEventAttr`]
+    const PARSED_SIZE: usize = 48;
+
+    /// Whether samples contain timestamps
+    #[must_use]
+    pub fn has_sample_time(&self) -> bool {
+        self.sample_type & Self::PERF_SAMPLE_TIME != 0
+    }
+}
+
+/// Extract all `perf_event_attr` records from the attrs section of `perf.data`.
+///
+/// The attrs section is needed for interpreting timestamps and other fields of
+/// records, such as the `sample_id` trailers.
+#[expect(clippy::cast_possible_truncation)]
+pub fn extract_perf_event_attrs(perf_data: &[u8]) -> ReaderResult<Vec<PerfEventAttr>> {
+    let PerfFileHeader {
+        attr_size,
+        attrs_section: (offset, size),
+        ..
+    } = read_perf_header(perf_data)?;
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    // Each entry is a `perf_event_attr` followed by the file section of its ids
+    let attr_size = attr_size as usize;
+    if attr_size < PerfEventAttr::PARSED_SIZE + 16 || size % attr_size as u64 != 0 {
+        return Err(ReaderError::InvalidPerfData);
+    }
+    let start_pos = offset as usize;
+    let end_pos = start_pos.saturating_add(size as usize);
+    let Some(attrs_data) = perf_data.get(start_pos..end_pos) else {
+        return Err(ReaderError::UnexpectedEOF);
+    };
+
+    attrs_data
+        .chunks_exact(attr_size)
+        .map(|attr_data| read_perf_event_attr(attr_data).ok_or(ReaderError::InvalidPerfData))
+        .collect()
+}
+
+fn read_perf_event_attr(attr_data: &[u8]) -> Option<PerfEventAttr> {
+    let mut pos = 0;
+    let r#type = util::read_u32(attr_data, pos)?;
+    pos += 4;
+    let _size = util::read_u32(attr_data, pos)?;
+    pos += 4;
+    let config = util::read_u64(attr_data, pos)?;
+    pos += 8;
+    let _sample_period = util::read_u64(attr_data, pos)?;
+    pos += 8;
+    let sample_type = util::read_u64(attr_data, pos)?;
+    pos += 8;
+    let read_format = util::read_u64(attr_data, pos)?;
+    pos += 8;
+    let flags = util::read_u64(attr_data, pos)?;
+
+    Some(PerfEventAttr {
+        r#type,
+        config,
+        sample_type,
+        read_format,
+        sample_id_all: flags & PerfEventAttr::SAMPLE_ID_ALL_BIT != 0,
+    })
+}
+
+/// AUXTRACE_INFO in `perf.data`
+pub struct PerfRecordAuxtraceInfo {
+    /// Type of auxtrace, which is `PERF_AUXTRACE_INTEL_PT` (1) for Intel PT
+    pub r#type: u32,
+    /// Private data of auxtrace.
+    ///
+    /// For Intel PT, this is indexed by `INTEL_PT_*` enumerations in the
+    /// `intel-pt.h` of perf, e.g., [`INTEL_PT_TSC_CTC_N`][Self::INTEL_PT_TSC_CTC_N].
+    pub private_data: Vec<u64>,
+}
+
+/// Parameters of converting TSC values to perf timestamps (in nanoseconds),
+/// taken from the `perf_event_mmap_page` of the traced event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PerfTscConversion {
+    /// `time_shift` of `perf_event_mmap_page`
+    pub time_shift: u16,
+    /// `time_mult` of `perf_event_mmap_page`
+    pub time_mult: u32,
+    /// `time_zero` of `perf_event_mmap_page`
+    pub time_zero: u64,
+}
+
+impl PerfTscConversion {
+    /// Convert a TSC value to perf timestamp, as `tsc_to_perf_time` of perf does
+    #[must_use]
+    pub fn tsc_to_perf_time(&self, tsc: u64) -> u64 {
+        let time_shift = u32::from(self.time_shift);
+        let time_mult = u64::from(self.time_mult);
+        let quot = tsc.wrapping_shr(time_shift);
+        let rem = tsc & (1u64.wrapping_shl(time_shift).wrapping_sub(1));
+        self.time_zero
+            .wrapping_add(quot.wrapping_mul(time_mult))
+            .wrapping_add(rem.wrapping_mul(time_mult).wrapping_shr(time_shift))
+    }
+}
+
+impl PerfRecordAuxtraceInfo {
+    /// Type of auxtrace for Intel PT
+    pub const PERF_AUXTRACE_INTEL_PT: u32 = 1;
+    /// Index of `time_shift` in private data of Intel PT
+    pub const INTEL_PT_TIME_SHIFT: usize = 1;
+    /// Index of `time_mult` in private data of Intel PT
+    pub const INTEL_PT_TIME_MULT: usize = 2;
+    /// Index of `time_zero` in private data of Intel PT
+    pub const INTEL_PT_TIME_ZERO: usize = 3;
+    /// Index of `cap_user_time_zero` in private data of Intel PT
+    pub const INTEL_PT_CAP_USER_TIME_ZERO: usize = 4;
+    /// Index of the numerator of TSC/CTC ratio in private data of Intel PT
+    pub const INTEL_PT_TSC_CTC_N: usize = 12;
+    /// Index of the denominator of TSC/CTC ratio in private data of Intel PT
+    pub const INTEL_PT_TSC_CTC_D: usize = 13;
+
+    /// Get the TSC/CTC ratio as `(numerator, denominator)` for Intel PT.
+    ///
+    /// Returns [`None`] if the ratio is not recorded, e.g., the auxtrace is not
+    /// Intel PT or the `perf.data` is recorded by old versions of perf. The ratio
+    /// can be used to create `PtTimingConfig` of `iptr-decoder`.
+    #[must_use]
+    pub fn tsc_ctc_ratio(&self) -> Option<(u64, u64)> {
+        let numerator = *self.private_data.get(Self::INTEL_PT_TSC_CTC_N)?;
+        let denominator = *self.private_data.get(Self::INTEL_PT_TSC_CTC_D)?;
+        (numerator != 0 && denominator != 0).then_some((numerator, denominator))
+    }
+
+    /// Get the parameters of converting TSC values to perf timestamps for Intel PT.
+    ///
+    /// Returns [`None`] if the auxtrace is not Intel PT, or the kernel does not
+    /// provide `time_zero` (i.e., `cap_user_time_zero` is not set).
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn tsc_conversion(&self) -> Option<PerfTscConversion> {
+        if self.r#type != Self::PERF_AUXTRACE_INTEL_PT
+            || *self.private_data.get(Self::INTEL_PT_CAP_USER_TIME_ZERO)? == 0
+        {
+            return None;
+        }
+        Some(PerfTscConversion {
+            time_shift: *self.private_data.get(Self::INTEL_PT_TIME_SHIFT)? as u16,
+            time_mult: *self.private_data.get(Self::INTEL_PT_TIME_MULT)? as u32,
+            time_zero: *self.private_data.get(Self::INTEL_PT_TIME_ZERO)?,
+        })
+    }
+}
+
+fn read_auxtrace_info(
+    perf_data: &[u8],
+    mut pos: usize,
+    end_pos: usize,
+) -> Option<PerfRecordAuxtraceInfo> {
+    let r#type = util::read_u32(perf_data, pos)?;
+    pos += 4;
+    let _reserved = util::read_u32(perf_data, pos)?;
+    pos += 4;
+
+    let private_data = perf_data
+        .get(pos..end_pos)?
+        .chunks_exact(8)
+        .filter_map(<[u8]>::first_chunk::<8>)
+        .map(|bytes| u64::from_ne_bytes(*bytes))
+        .collect();
+
+    Some(PerfRecordAuxtraceInfo {
+        r#type,
+        private_data,
+    })
+}
+
 /// AUXTRACE in `perf.data`
 pub struct PerfRecordAuxtrace<'a> {
     /// Size of [`auxtrace_data`][Self::auxtrace_data]
     pub size: u64,
     /// Offset
     pub offset: u64,
-    /// Referemce
+    /// Reference, which is used by perf to match AUXTRACE records with the
+    /// AUX buffers.
+    ///
+    /// For Intel PT, this is the TSC value read by perf when copying the AUX
+    /// data out of the AUX buffer, i.e., a timestamp slightly after the trace
+    /// data is generated. Use [`reference_tsc`][Self::reference_tsc] and
+    /// [`reference_perf_time`][Self::reference_perf_time] to interpret it.
     pub reference: u64,
-    /// Index of auxtrace in perf.data
+    /// Index of the AUX buffer (e.g., each CPU has its own buffer for
+    /// system-wide traces)
     pub idx: u32,
     /// Thread id
     pub tid: u32,
@@ -181,6 +626,26 @@ pub struct PerfRecordAuxtrace<'a> {
     pub auxtrace_data: &'a [u8],
 }
 
+impl PerfRecordAuxtrace<'_> {
+    /// Get the [`reference`][Self::reference] as a TSC value, if the auxtrace
+    /// described by `auxtrace_info` is Intel PT.
+    #[must_use]
+    pub fn reference_tsc(&self, auxtrace_info: &PerfRecordAuxtraceInfo) -> Option<u64> {
+        (auxtrace_info.r#type == PerfRecordAuxtraceInfo::PERF_AUXTRACE_INTEL_PT)
+            .then_some(self.reference)
+    }
+
+    /// Get the [`reference`][Self::reference] as a perf timestamp in nanoseconds,
+    /// which is comparable with the `time` of samples, if the TSC conversion is
+    /// available, see [`PerfRecordAuxtraceInfo::tsc_conversion`].
+    #[must_use]
+    pub fn reference_perf_time(&self, auxtrace_info: &PerfRecordAuxtraceInfo) -> Option<u64> {
+        let tsc = self.reference_tsc(auxtrace_info)?;
+        let tsc_conversion = auxtrace_info.tsc_conversion()?;
+        Some(tsc_conversion.tsc_to_perf_time(tsc))
+    }
+}
+
 #[expect(clippy::cast_possible_truncation)]
 fn read_auxtrace<'a>(perf_data: &'a [u8], pos: &mut usize) -> Option<PerfRecordAuxtrace<'a>> {
     let size = util::read_u64(perf_data, *pos)?;
@@ -237,6 +702,45 @@ pub struct PerfMmap2Header {
     pub filename: String,
 }
 
+/// Read a legacy `PERF_RECORD_MMAP` record as [`PerfMmap2Header`]
+fn read_mmap(perf_data: &[u8], start_pos: usize, end_pos: usize) -> Option<PerfMmap2Header> {
+    let mut pos = start_pos;
+    let pid = util::read_u32(perf_data, pos)?;
+    pos += 4;
+    let tid = util::read_u32(perf_data, pos)?;
+    pos += 4;
+    let addr = util::read_u64(perf_data, pos)?;
+    pos += 8;
+    let len = util::read_u64(perf_data, pos)?;
+    pos += 8;
+    let pgoff = util::read_u64(perf_data, pos)?;
+    pos += 8;
+    let filename = read_filename(perf_data, pos, end_pos)?;
+
+    Some(PerfMmap2Header {
+        pid,
+        tid,
+        addr,
+        len,
+        pgoff,
+        inode: [0; 24],
+        prot: 0,
+        flags: 0,
+        filename,
+    })
+}
+
+/// Read the nul-terminated filename in mmap records
+fn read_filename(perf_data: &[u8], pos: usize, end_pos: usize) -> Option<String> {
+    if pos >= end_pos {
+        return None;
+    }
+    let filename_buf = perf_data.get(pos..end_pos)?;
+    let filename_c_str = CStr::from_bytes_until_nul(filename_buf).ok()?;
+    let filename_str = filename_c_str.to_str().ok()?;
+    Some(filename_str.to_string())
+}
+
 fn read_mmap2(perf_data: &[u8], start_pos: usize, end_pos: usize) -> Option<PerfMmap2Header> {
     let mut pos = start_pos;
     let pid = util::read_u32(perf_data, pos)?;
@@ -257,13 +761,7 @@ fn read_mmap2(perf_data: &[u8], start_pos: usize, end_pos: usize) -> Option<Perf
     pos += 4;
     let flags = util::read_u32(perf_data, pos)?;
     pos += 4;
-    if pos >= end_pos {
-        return None;
-    }
-    let filename_buf = perf_data.get(pos..end_pos)?;
-    let filename_c_str = CStr::from_bytes_until_nul(filename_buf).ok()?;
-    let filename_str = filename_c_str.to_str().ok()?;
-    let filename = filename_str.to_string();
+    let filename = read_filename(perf_data, pos, end_pos)?;
 
     Some(PerfMmap2Header {
         pid,
@@ -277,3 +775,448 @@ fn read_mmap2(perf_data: &[u8], start_pos: usize, end_pos: usize) -> Option<Perf
         filename,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a synthetic `perf.data` with given records in data section
+    fn perf_data(records: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        perf_data_with_attrs(0, &[], records)
+    }
+
+    /// Build a synthetic `perf.data` with given attrs and records
+    fn perf_data_with_attrs(attr_size: u64, attrs: &[u8], records: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (r#type, payload) in records {
+            data.extend_from_slice(&r#type.to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+            let size = u16::try_from(payload.len() + 8).unwrap();
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(payload);
+        }
+        let mut perf_data = Vec::new();
+        perf_data.extend_from_slice(b"PERFILE2");
+        // size, attr_size
+        perf_data.extend_from_slice(&104u64.to_le_bytes());
+        perf_data.extend_from_slice(&attr_size.to_le_bytes());
+        // attrs section
+        perf_data.extend_from_slice(&56u64.to_le_bytes());
+        perf_data.extend_from_slice(&(attrs.len() as u64).to_le_bytes());
+        // data section
+        perf_data.extend_from_slice(&(56 + attrs.len() as u64).to_le_bytes());
+        perf_data.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        perf_data.extend_from_slice(attrs);
+        perf_data.extend_from_slice(&data);
+        perf_data
+    }
+
+    /// Payload of mmap records, `inode_prot_flags` is only present in MMAP2
+    fn mmap_payload(addr: u64, inode_prot_flags: Option<[u8; 32]>, filename: &str) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&2u32.to_le_bytes());
+        payload.extend_from_slice(&addr.to_le_bytes());
+        payload.extend_from_slice(&0x1000u64.to_le_bytes());
+        payload.extend_from_slice(&0u64.to_le_bytes());
+        if let Some(inode_prot_flags) = inode_prot_flags {
+            payload.extend_from_slice(&inode_prot_flags);
+        }
+        payload.extend_from_slice(filename.as_bytes());
+        // Nul-terminated and 8-byte aligned
+        payload.resize((payload.len() + 8) & !7, 0);
+        payload
+    }
+
+    #[test]
+    fn test_legacy_mmap() {
+        let buf = perf_data(&[
+            (PERF_RECORD_MMAP, mmap_payload(0x40_0000, None, "/bin/true")),
+            (
+                PERF_RECORD_MMAP,
+                mmap_payload(0x50_0000, None, "/lib/libc.so"),
+            ),
+        ]);
+        let (auxtraces, headers) = extract_pt_auxtraces_and_mmap_data(&buf).unwrap();
+        assert!(auxtraces.is_empty());
+        assert_eq!(headers.len(), 2);
+        let header = &headers[1];
+        assert_eq!((header.pid, header.tid), (1, 2));
+        assert_eq!(
+            (header.addr, header.len, header.pgoff),
+            (0x50_0000, 0x1000, 0)
+        );
+        assert_eq!((header.inode, header.prot, header.flags), ([0; 24], 0, 0));
+        assert_eq!(header.filename, "/lib/libc.so");
+
+        // MMAP2 is preferred
+        let buf = perf_data(&[
+            (PERF_RECORD_MMAP, mmap_payload(0x40_0000, None, "/bin/true")),
+            (
+                PERF_RECORD_MMAP2,
+                mmap_payload(0x60_0000, Some([0xFF; 32]), "/bin/false"),
+            ),
+        ]);
+        let (_, headers) = extract_pt_auxtraces_and_mmap_data(&buf).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].addr, 0x60_0000);
+        assert_eq!(headers[0].prot, u32::MAX);
+        assert_eq!(headers[0].filename, "/bin/false");
+    }
+
+    #[test]
+    fn test_auxtrace_info() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        for index in 0..16u64 {
+            let value = match index {
+                12 => 0xB4,
+                13 => 2,
+                _ => 0,
+            };
+            payload.extend_from_slice(&u64::to_le_bytes(value));
+        }
+        let buf = perf_data(&[
+            (PERF_RECORD_MMAP, mmap_payload(0x40_0000, None, "/bin/true")),
+            (PERF_RECORD_AUXTRACE_INFO, payload),
+        ]);
+        let auxtrace_info = extract_auxtrace_info(&buf).unwrap().unwrap();
+        assert_eq!(auxtrace_info.r#type, 1);
+        assert_eq!(auxtrace_info.private_data.len(), 16);
+        assert_eq!(auxtrace_info.tsc_ctc_ratio(), Some((0xB4, 2)));
+
+        let buf = perf_data(&[(PERF_RECORD_MMAP, mmap_payload(0x40_0000, None, "/bin/true"))]);
+        assert!(extract_auxtrace_info(&buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reference_tsc() {
+        // time_shift 10, time_mult 512 and time_zero 1000, i.e., 2 TSC ticks per
+        // nanosecond with an offset of 1000 nanoseconds
+        let mut private_data = Vec::from([0; 16]);
+        private_data[PerfRecordAuxtraceInfo::INTEL_PT_TIME_SHIFT] = 10;
+        private_data[PerfRecordAuxtraceInfo::INTEL_PT_TIME_MULT] = 512;
+        private_data[PerfRecordAuxtraceInfo::INTEL_PT_TIME_ZERO] = 1000;
+        private_data[PerfRecordAuxtraceInfo::INTEL_PT_CAP_USER_TIME_ZERO] = 1;
+        let mut auxtrace_info = PerfRecordAuxtraceInfo {
+            r#type: PerfRecordAuxtraceInfo::PERF_AUXTRACE_INTEL_PT,
+            private_data,
+        };
+        let auxtrace = PerfRecordAuxtrace {
+            reference: 0x1_0000_0001,
+            ..auxtrace(0, 100, &[])
+        };
+        assert_eq!(auxtrace.reference_tsc(&auxtrace_info), Some(0x1_0000_0001));
+        assert_eq!(
+            auxtrace.reference_perf_time(&auxtrace_info),
+            Some(1000 + 0x8000_0000)
+        );
+
+        // No time_zero from the kernel
+        auxtrace_info.private_data[PerfRecordAuxtraceInfo::INTEL_PT_CAP_USER_TIME_ZERO] = 0;
+        assert_eq!(auxtrace.reference_tsc(&auxtrace_info), Some(0x1_0000_0001));
+        assert_eq!(auxtrace.reference_perf_time(&auxtrace_info), None);
+
+        // Not Intel PT
+        auxtrace_info.r#type = 2;
+        assert_eq!(auxtrace.reference_tsc(&auxtrace_info), None);
+    }
+
+    fn auxtrace(idx: u32, tid: u32, data: &[u8]) -> PerfRecordAuxtrace<'_> {
+        PerfRecordAuxtrace {
+            size: data.len() as u64,
+            offset: 0,
+            reference: 0,
+            idx,
+            tid,
+            cpu: 0,
+            auxtrace_data: data,
+        }
+    }
+
+    #[test]
+    fn test_group_auxtraces_by_cpu_and_merge_by_time() {
+        let auxtraces = [(0, 1), (1, 0), (2, 1), (3, 0)]
+            .into_iter()
+            .map(|(idx, cpu)| PerfRecordAuxtrace {
+                cpu,
+                ..auxtrace(idx, 100, &[])
+            })
+            .collect();
+        let groups = group_auxtraces_by_cpu(auxtraces);
+        assert_eq!(groups.len(), 2);
+        let idxs = |cpu| {
+            groups[&cpu]
+                .iter()
+                .map(|auxtrace| auxtrace.idx)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(idxs(0), [1, 3]);
+        assert_eq!(idxs(1), [0, 2]);
+
+        // Events decoded from each CPU, with synthetic TSC values
+        let per_cpu_events = BTreeMap::from([
+            (0, alloc::vec![(10, 'a'), (30, 'b'), (30, 'c'), (50, 'd')]),
+            (1, alloc::vec![(5, 'e'), (30, 'f'), (60, 'g')]),
+            (2, alloc::vec![]),
+        ]);
+        let merged = merge_by_time(per_cpu_events)
+            .into_iter()
+            .map(|event| (event.cpu, event.timestamp, event.event))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            merged,
+            [
+                (1, 5, 'e'),
+                (0, 10, 'a'),
+                (0, 30, 'b'),
+                (0, 30, 'c'),
+                (1, 30, 'f'),
+                (0, 50, 'd'),
+                (1, 60, 'g'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_auxtraces_by_tid() {
+        let auxtraces = alloc::vec![
+            auxtrace(0, 100, &[0]),
+            auxtrace(1, 200, &[1]),
+            auxtrace(2, 100, &[2]),
+            auxtrace(3, 200, &[3]),
+            auxtrace(4, 100, &[4]),
+        ];
+        let groups = group_auxtraces_by_tid(auxtraces);
+        assert_eq!(groups.len(), 2);
+        let data = |tid| {
+            groups[&tid]
+                .iter()
+                .map(|auxtrace| auxtrace.auxtrace_data[0])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(data(100), [0, 2, 4]);
+        assert_eq!(data(200), [1, 3]);
+    }
+
+    /// Payload of AUXTRACE records, followed by the Intel PT data
+    fn auxtrace_payload(idx: u32, tid: u32, cpu: u32, data: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        // offset and reference
+        payload.extend_from_slice(&0u64.to_le_bytes());
+        payload.extend_from_slice(&0u64.to_le_bytes());
+        payload.extend_from_slice(&idx.to_le_bytes());
+        payload.extend_from_slice(&tid.to_le_bytes());
+        payload.extend_from_slice(&cpu.to_le_bytes());
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        payload.extend_from_slice(data);
+        payload
+    }
+
+    #[test]
+    fn test_aux_sources() {
+        fn segments(source: &impl AuxSource) -> Vec<AuxSegment<'_>> {
+            source.segments().collect()
+        }
+
+        let raw = [0x02, 0x82, 0x02, 0x23];
+        let source = RawAuxSource::new(&raw);
+        assert_eq!(
+            segments(&source),
+            [AuxSegment {
+                tid: None,
+                cpu: None,
+                data: &raw,
+            }]
+        );
+
+        let buf = perf_data(&[
+            (PERF_RECORD_AUXTRACE, auxtrace_payload(0, 200, 1, &[1])),
+            (PERF_RECORD_AUXTRACE, auxtrace_payload(1, 100, 0, &[2])),
+            (PERF_RECORD_AUXTRACE, auxtrace_payload(0, 200, 1, &[3])),
+        ]);
+        let source = PerfDataAuxSource::new(&buf).unwrap();
+        assert_eq!(source.auxtraces().len(), 3);
+        assert_eq!(
+            segments(&source),
+            [
+                AuxSegment {
+                    tid: Some(200),
+                    cpu: Some(1),
+                    data: &[1],
+                },
+                AuxSegment {
+                    tid: Some(100),
+                    cpu: Some(0),
+                    data: &[2],
+                },
+                AuxSegment {
+                    tid: Some(200),
+                    cpu: Some(1),
+                    data: &[3],
+                },
+            ]
+        );
+
+        let source = PerThreadAuxSource::new(&buf).unwrap();
+        let data = source
+            .segments()
+            .map(|segment| (segment.tid, segment.data[0]))
+            .collect::<Vec<_>>();
+        assert_eq!(data, [(Some(100), 2), (Some(200), 1), (Some(200), 3)]);
+        let threads = source
+            .threads()
+            .map(|(tid, segments)| {
+                (
+                    tid,
+                    segments.map(|segment| segment.data[0]).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(threads, [(100, alloc::vec![2]), (200, alloc::vec![1, 3])]);
+
+        assert!(matches!(
+            PerfDataAuxSource::new(&raw),
+            Err(ReaderError::InvalidPerfData | ReaderError::UnexpectedEOF)
+        ));
+    }
+
+    #[test]
+    fn test_coalesce_auxtraces() {
+        struct TipCounter(usize);
+
+        impl iptr_decoder::HandlePacket for TipCounter {
+            type Error = core::convert::Infallible;
+
+            fn at_decode_begin(&mut self) -> Result<(), Self::Error> {
+                Ok(())
+            }
+
+            fn on_tip_packet(
+                &mut self,
+                _context: &iptr_decoder::DecoderContext,
+                _ip_reconstruction_pattern: iptr_decoder::IpReconstructionPattern,
+            ) -> Result<(), Self::Error> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        // PSB, PSBEND, TIP 0x40_0000, TIP 0x40_0000
+        let mut trace = [0x02, 0x82].repeat(8);
+        trace.extend_from_slice(&[0x02, 0x23]);
+        for _ in 0..2 {
+            trace.push(0xCD);
+            trace.extend_from_slice(&0x40_0000u64.to_le_bytes());
+        }
+        // Split in the middle of the first TIP packet
+        let (first, second) = trace.split_at(22);
+        let auxtraces = [
+            auxtrace(0, 100, first),
+            PerfRecordAuxtrace {
+                offset: first.len() as u64,
+                ..auxtrace(0, 100, second)
+            },
+            // Not adjacent
+            PerfRecordAuxtrace {
+                offset: 0x1000,
+                ..auxtrace(0, 100, &trace)
+            },
+            // Another buffer
+            auxtrace(1, 100, &trace),
+        ];
+
+        for auxtrace in &auxtraces[..2] {
+            let mut counter = TipCounter(0);
+            assert!(
+                iptr_decoder::decode(
+                    auxtrace.auxtrace_data,
+                    iptr_decoder::DecodeOptions::default(),
+                    &mut counter
+                )
+                .is_err()
+            );
+        }
+
+        let coalesced = coalesce_auxtraces(&auxtraces);
+        assert_eq!(
+            coalesced
+                .iter()
+                .map(|auxtrace| (auxtrace.idx, auxtrace.offset))
+                .collect::<Vec<_>>(),
+            [(0, 0), (0, 0x1000), (1, 0)]
+        );
+        assert_eq!(coalesced[0].auxtrace_data, trace);
+        for auxtrace in &coalesced {
+            let mut counter = TipCounter(0);
+            iptr_decoder::decode(
+                &auxtrace.auxtrace_data,
+                iptr_decoder::DecodeOptions::default(),
+                &mut counter,
+            )
+            .unwrap();
+            assert_eq!(counter.0, 2);
+        }
+    }
+
+    /// `perf_event_attr` of `PERF_ATTR_SIZE_VER0` followed by ids section
+    fn attr_entry(r#type: u32, config: u64, sample_type: u64, flags: u64) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&r#type.to_le_bytes());
+        entry.extend_from_slice(&64u32.to_le_bytes());
+        entry.extend_from_slice(&config.to_le_bytes());
+        entry.extend_from_slice(&1u64.to_le_bytes());
+        entry.extend_from_slice(&sample_type.to_le_bytes());
+        entry.extend_from_slice(&0u64.to_le_bytes());
+        entry.extend_from_slice(&flags.to_le_bytes());
+        entry.resize(64, 0);
+        // ids section
+        entry.extend_from_slice(&[0; 16]);
+        entry
+    }
+
+    #[test]
+    fn test_perf_event_attrs() {
+        let sample_type = PerfEventAttr::PERF_SAMPLE_TID
+            | PerfEventAttr::PERF_SAMPLE_TIME
+            | PerfEventAttr::PERF_SAMPLE_CPU
+            | PerfEventAttr::PERF_SAMPLE_IDENTIFIER;
+        let mut attrs = attr_entry(8, 0x300_e601, sample_type, 1 << 18);
+        attrs.extend(attr_entry(1, 9, PerfEventAttr::PERF_SAMPLE_IP, 0));
+        let buf = perf_data_with_attrs(
+            80,
+            &attrs,
+            &[(PERF_RECORD_MMAP, mmap_payload(0x40_0000, None, "/bin/true"))],
+        );
+
+        let attrs = extract_perf_event_attrs(&buf).unwrap();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(attrs[0].r#type, 8);
+        assert_eq!(attrs[0].config, 0x300_e601);
+        assert_eq!(attrs[0].sample_type, sample_type);
+        assert!(attrs[0].has_sample_time());
+        assert!(attrs[0].sample_id_all);
+        assert_eq!(attrs[1].sample_type, PerfEventAttr::PERF_SAMPLE_IP);
+        assert!(!attrs[1].has_sample_time());
+        assert!(!attrs[1].sample_id_all);
+        // Data section is still located correctly
+        let (_, headers) = extract_pt_auxtraces_and_mmap_data(&buf).unwrap();
+        assert_eq!(headers.len(), 1);
+
+        // Entries not aligned to attr size
+        let mut attrs = attr_entry(8, 0, 0, 0);
+        attrs.truncate(72);
+        let buf = perf_data_with_attrs(80, &attrs, &[]);
+        assert!(matches!(
+            extract_perf_event_attrs(&buf),
+            Err(ReaderError::InvalidPerfData)
+        ));
+        assert!(
+            extract_perf_event_attrs(&perf_data(&[]))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Sources of raw Intel PT data, abstracting over raw traces and `perf.data`.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{PerfRecordAuxtrace, ReaderResult, extract_pt_auxtraces, group_auxtraces_by_tid};
+
+/// A segment of raw Intel PT data yielded by an [`AuxSource`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuxSegment<'a> {
+    /// Thread id, if known
+    pub tid: Option<u32>,
+    /// CPU id, if known
+    pub cpu: Option<u32>,
+    /// Raw Intel PT data
+    pub data: &'a [u8],
+}
+
+impl<'a> From<&PerfRecordAuxtrace<'a>> for AuxSegment<'a> {
+    fn from(auxtrace: &PerfRecordAuxtrace<'a>) -> Self {
+        Self {
+            tid: Some(auxtrace.tid),
+            cpu: Some(auxtrace.cpu),
+            data: auxtrace.auxtrace_data,
+        }
+    }
+}
+
+/// Source of raw Intel PT data.
+///
+/// Each source yields segments of Intel PT data in the order they should be
+/// decoded, e.g., by `iptr_decoder::decode_segments`. The source does not own
+/// the file, so that the file can be read or mapped into memory in any way.
+pub trait AuxSource {
+    /// Iterate over all segments of raw Intel PT data
+    fn segments(&self) -> impl Iterator<Item = AuxSegment<'_>>;
+}
+
+/// Raw Intel PT data without any container, e.g., `.pt` files used by libipt
+/// tools. The whole data is yielded as a single segment without thread id or
+/// CPU id.
+#[derive(Debug, Clone, Copy)]
+pub struct RawAuxSource<'a> {
+    /// Raw Intel PT data
+    data: &'a [u8],
+}
+
+impl<'a> RawAuxSource<'a> {
+    /// Create a source of raw Intel PT data
+    #[must_use]
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl AuxSource for RawAuxSource<'_> {
+    fn segments(&self) -> impl Iterator<Item = AuxSegment<'_>> {
+        core::iter::once(AuxSegment {
+            tid: None,
+            cpu: None,
+            data: self.data,
+        })
+    }
+}
+
+/// Intel PT data in `perf.data`, where each AUXTRACE record is yielded as a
+/// segment in the order they are recorded.
+pub struct PerfDataAuxSource<'a> {
+    /// All AUXTRACE records
+    auxtraces: Vec<PerfRecordAuxtrace<'a>>,
+}
+
+impl<'a> PerfDataAuxSource<'a> {
+    /// Parse `perf.data` and create a source of its Intel PT data
+    pub fn new(perf_data: &'a [u8]) -> ReaderResult<Self> {
+        Ok(Self::from_auxtraces(extract_pt_auxtraces(perf_data)?))
+    }
+
+    /// Create a source from already extracted AUXTRACE records, e.g., after
+    /// filtering
+    #[must_use]
+    pub fn from_auxtraces(auxtraces: Vec<PerfRecordAuxtrace<'a>>) -> Self {
+        Self { auxtraces }
+    }
+
+    /// Get all AUXTRACE records
+    #[must_use]
+    pub fn auxtraces(&self) -> &[PerfRecordAuxtrace<'a>] {
+        &self.auxtraces
+    }
+}
+
+impl AuxSource for PerfDataAuxSource<'_> {
+    fn segments(&self) -> impl Iterator<Item = AuxSegment<'_>> {
+        self.auxtraces.iter().map(AuxSegment::from)
+    }
+}
+
+/// Intel PT data in `perf.data` grouped by thread ids, see
+/// [`group_auxtraces_by_tid`].
+///
+/// Segments are yielded thread by thread in ascending order of thread ids,
+/// and the order of AUXTRACE records of each thread is preserved. Each
+/// thread should be decoded independently, see [`threads`][Self::threads].
+pub struct PerThreadAuxSource<'a> {
+    /// AUXTRACE records grouped by thread ids
+    groups: BTreeMap<u32, Vec<PerfRecordAuxtrace<'a>>>,
+}
+
+impl<'a> PerThreadAuxSource<'a> {
+    /// Parse `perf.data` and create a source of its Intel PT data grouped by
+    /// thread ids
+    pub fn new(perf_data: &'a [u8]) -> ReaderResult<Self> {
+        Ok(Self::from_auxtraces(extract_pt_auxtraces(perf_data)?))
+    }
+
+    /// Create a source from already extracted AUXTRACE records, e.g., after
+    /// filtering
+    #[must_use]
+    pub fn from_auxtraces(auxtraces: Vec<PerfRecordAuxtrace<'a>>) -> Self {
+        Self {
+            groups: group_auxtraces_by_tid(auxtraces),
+        }
+    }
+
+    /// Iterate over thread ids and the segments of each thread
+    pub fn threads(&self) -> impl Iterator<Item = (u32, impl Iterator<Item = AuxSegment<'_>>)> {
+        self.groups
+            .iter()
+            .map(|(tid, auxtraces)| (*tid, auxtraces.iter().map(AuxSegment::from)))
+    }
+}
+
+impl AuxSource for PerThreadAuxSource<'_> {
+    fn segments(&self) -> impl Iterator<Item = AuxSegment<'_>> {
+        self.groups.values().flatten().map(AuxSegment::from)
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fs::File, path::PathBuf, time::Instant};
+use std::{fs::File, num::NonZero, path::PathBuf, time::Instant};
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -28,6 +28,14 @@ struct Cmdline {
     /// Path to page addr file
     #[arg(long)]
     page_addr: PathBuf,
+    /// Filter range in the form of `START-END` (inclusive hexadecimal
+    /// addresses), if given.
+    ///
+    /// This can be given multiple times, e.g., for multiple IP filter
+    /// ranges. For instructions out of all filter ranges, the fuzzing
+    /// bitmap will not be updated.
+    #[arg(long = "range")]
+    ranges: Vec<String>,
     /// Start address of filter range, if given.
     ///
     /// For instructions out of the filter range, the fuzzing
@@ -51,6 +59,10 @@ struct Cmdline {
     /// The value should be greater than 1.
     #[arg(long)]
     round: usize,
+    /// Log the number of covered bitmap entries every given number of
+    /// executed basic blocks, forming a coverage-growth curve of each round.
+    #[arg(long)]
+    checkpoint_interval: Option<NonZero<u64>>,
 }
 
 #[expect(clippy::cast_precision_loss)]
@@ -61,19 +73,27 @@ fn main() -> Result<()> {
         input,
         page_dump,
         page_addr,
+        ranges,
         range_start,
         range_end,
         round,
+        checkpoint_interval,
     } = Cmdline::parse();
 
-    let range = iptr_libxdc_exp::extract_range(range_start, range_end)?;
+    let range = iptr_libxdc_exp::extract_ranges(&ranges, range_start, range_end)?;
 
     let mut bitmap = vec![0u8; 0x10000].into_boxed_slice();
 
     let memory_reader = LibxdcMemoryReader::new(&page_dump, &page_addr)
         .context("Failed to create memory reader")?;
-    let control_flow_handler =
-        FuzzBitmapControlFlowHandler::new(bitmap.as_mut(), range.as_ref().map(<[_; _]>::as_slice));
+    let mut control_flow_handler =
+        FuzzBitmapControlFlowHandler::new(bitmap.as_mut(), range.as_deref());
+    if let Some(checkpoint_interval) = checkpoint_interval {
+        control_flow_handler.on_checkpoint(checkpoint_interval, |block_count, bitmap| {
+            let coverage = bitmap.iter().filter(|count| **count != 0).count();
+            log::info!("block_count = {block_count}, coverage = {coverage}");
+        });
+    }
     let edge_analyzer = EdgeAnalyzer::new(control_flow_handler, memory_reader);
     #[cfg(feature = "debug")]
     let mut packet_handler = iptr_decoder::packet_handler::combined::CombinedPacketHandler::new(
@@ -97,6 +117,17 @@ fn main() -> Result<()> {
         &packet_handler.handler().diagnose(),
     );
 
+    // Time of decoding without handlers, as the baseline. This runs after the
+    // cold run so that the cold run is the first to touch the mapped trace.
+    let instant = Instant::now();
+    let validation_summary = iptr_decoder::validate(&buf, DecodeOptions::default());
+    let validate_time = instant.elapsed();
+    log::info!(
+        "validate_time = {}, packet_count = {}",
+        validate_time.as_nanos(),
+        validation_summary.packet_count
+    );
+
     if round <= 1 {
         return Err(anyhow::anyhow!("--round should be greater than 1"));
     }
@@ -28,6 +28,14 @@ struct Cmdline {
     /// Path to page addr file
     #[arg(long)]
     page_addr: PathBuf,
+    /// Filter range in the form of `START-END` (inclusive hexadecimal
+    /// addresses), if given.
+    ///
+    /// This can be given multiple times, e.g., for multiple IP filter
+    /// ranges. For instructions out of all filter ranges, the fuzzing
+    /// bitmap will not be updated.
+    #[arg(long = "range")]
+    ranges: Vec<String>,
     /// Start address of filter range, if given.
     ///
     /// For instructions out of the filter range, the fuzzing
@@ -61,19 +69,19 @@ fn main() -> Result<()> {
         input,
         page_dump,
         page_addr,
+        ranges,
         range_start,
         range_end,
         bitmap_output,
     } = Cmdline::parse();
 
-    let range = iptr_libxdc_exp::extract_range(range_start, range_end)?;
+    let range = iptr_libxdc_exp::extract_ranges(&ranges, range_start, range_end)?;
 
     let mut bitmap = vec![0u8; 0x10000].into_boxed_slice();
 
     let memory_reader = LibxdcMemoryReader::new(&page_dump, &page_addr)
         .context("Failed to create memory reader")?;
-    let control_flow_handler =
-        FuzzBitmapControlFlowHandler::new(bitmap.as_mut(), range.as_ref().map(<[_; _]>::as_slice));
+    let control_flow_handler = FuzzBitmapControlFlowHandler::new(bitmap.as_mut(), range.as_deref());
     let edge_analyzer = EdgeAnalyzer::new(control_flow_handler, memory_reader);
     #[cfg(feature = "debug")]
     let mut packet_handler = iptr_decoder::packet_handler::combined::CombinedPacketHandler::new(
@@ -4,25 +4,56 @@ use iptr_edge_analyzer::{
     DiagnosticInformation, control_flow_handler::fuzz_bitmap::FuzzBitmapDiagnosticInformation,
 };
 
-pub fn extract_range(
+/// Parse a hexadecimal address with optional `0x` prefix
+fn parse_address(address: &str) -> Option<u64> {
+    let address = address.strip_prefix("0x").unwrap_or(address);
+    u64::from_str_radix(address, 16).ok()
+}
+
+/// Extract filter ranges from `--range START-END` arguments and the
+/// `--range-start` and `--range-end` pair.
+///
+/// Return [`None`] if no range is given, and an error if any range has its
+/// start greater than its end.
+pub fn extract_ranges(
+    ranges: &[String],
     range_start: Option<String>,
     range_end: Option<String>,
-) -> Result<Option<[(u64, u64); 1]>> {
+) -> Result<Option<Vec<(u64, u64)>>> {
+    let mut filter_ranges = Vec::with_capacity(ranges.len() + 1);
+    for range in ranges {
+        let (start, end) = range
+            .split_once('-')
+            .with_context(|| format!("Invalid --range {range}, expected START-END"))?;
+        let start = parse_address(start).with_context(|| format!("Invalid --range {range}"))?;
+        let end = parse_address(end).with_context(|| format!("Invalid --range {range}"))?;
+        if start > end {
+            return Err(anyhow::anyhow!(
+                "Invalid --range {range}, start is greater than end"
+            ));
+        }
+        filter_ranges.push((start, end));
+    }
     match (range_start, range_end) {
         (Some(start), Some(end)) => {
-            let start = start.strip_prefix("0x").unwrap_or(&start);
-            let start = u64::from_str_radix(start, 16).context("Invalid --range-start")?;
-
-            let end = end.strip_prefix("0x").unwrap_or(&end);
-            let end = u64::from_str_radix(end, 16).context("Invalid --range-start")?;
-
-            Ok(Some([(start, end)]))
+            let start = parse_address(&start).context("Invalid --range-start")?;
+            let end = parse_address(&end).context("Invalid --range-end")?;
+            if start > end {
+                return Err(anyhow::anyhow!(
+                    "--range-start {start:#x} is greater than --range-end {end:#x}"
+                ));
+            }
+            filter_ranges.push((start, end));
+        }
+        (None, None) => {}
+        _ => {
+            return Err(anyhow::anyhow!(
+                "--range-start and --range-end should be given at the same time"
+            ));
         }
-        (None, None) => Ok(None),
-        _ => Err(anyhow::anyhow!(
-            "--range-start and --range-end should be given at the same time"
-        )),
     }
+
+    Ok((!filter_ranges.is_empty()).then_some(filter_ranges))
 }
 
 #[cfg(all(not(feature = "debug"), feature = "diagnose"))]
@@ -32,6 +63,12 @@ pub fn report_diagnose(
 ) {
     let DiagnosticInformation {
         cfg_size,
+        ovf_count,
+        ovf_discarded_tnt_bit_count,
+        undecodable_block_count,
+        missing_endbr_count,
+        deferred_tip_count,
+        max_pending_tnt_bits,
         cache_trailing_bits_size,
         cache8_size,
         cache32_size,
@@ -46,6 +83,14 @@ pub fn report_diagnose(
     log::info!(
         "Analyzer diagnose statistics
 CFG size {cfg_size}
+\t{undecodable_block_count} undecodable blocks
+\t{missing_endbr_count} indirect targets without ENDBR
+OVF
+\t{ovf_count} packets
+\t{ovf_discarded_tnt_bit_count} discarded TNT bits
+TNT buffer
+\t{deferred_tip_count} deferred TIPs
+\t{max_pending_tnt_bits} max pending TNT bits
 Cache size
 \t{cache_trailing_bits_size} trailing bits
 \t{cache8_size} 8bits
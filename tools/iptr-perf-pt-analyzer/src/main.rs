@@ -2,8 +2,9 @@ mod control_flow_handler;
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use iptr_decoder::DecodeOptions;
+use iptr_decoder::{DecodeOptions, TraceeMode};
 use iptr_edge_analyzer::{EdgeAnalyzer, memory_reader::perf_mmap::PerfMmapBasedMemoryReader};
+use iptr_perf_pt_reader::PerThreadAuxSource;
 
 use std::{fs::File, path::PathBuf};
 
@@ -15,12 +16,32 @@ struct Cmdline {
     /// Path of intel PT trace in perf.data format
     #[arg(short, long)]
     input: PathBuf,
+    /// Check whether the mmapped binaries match the trace before decoding
+    #[arg(long)]
+    self_check: bool,
+    /// Only decode auxtraces of the given thread id
+    #[arg(long)]
+    tid: Option<u32>,
+    /// Only decode auxtraces recorded on the given CPU
+    #[arg(long)]
+    cpu: Option<u32>,
+    /// Only decode auxtraces of the given AUX buffer index
+    ///
+    /// In system-wide mode, there is an AUX buffer for each CPU
+    #[arg(long)]
+    aux_buffer_idx: Option<u32>,
 }
 
 fn main() -> Result<()> {
     env_logger::init();
 
-    let Cmdline { input } = Cmdline::parse();
+    let Cmdline {
+        input,
+        self_check,
+        tid,
+        cpu,
+        aux_buffer_idx,
+    } = Cmdline::parse();
 
     let file = File::open(input).context("Failed to open input file")?;
     // SAFETY: check the safety requirements of memmap2 documentation
@@ -32,6 +53,10 @@ fn main() -> Result<()> {
 
     let control_flow_handler = control_flow_handler::PerfAnalyzerControlFlowHandler::default();
     let memory_reader = PerfMmapBasedMemoryReader::new(&mmap2_headers)?;
+    if self_check {
+        // Suspicious entries are logged
+        let _ = memory_reader.check_executable_entries(TraceeMode::Mode64);
+    }
 
     let edge_analyzer = EdgeAnalyzer::new(control_flow_handler, memory_reader);
     #[cfg(feature = "debug")]
@@ -42,12 +67,21 @@ fn main() -> Result<()> {
     #[cfg(not(feature = "debug"))]
     let mut packet_handler = edge_analyzer;
 
-    for pt_auxtrace in pt_auxtraces {
-        iptr_decoder::decode(
-            pt_auxtrace.auxtrace_data,
+    let pt_auxtraces = pt_auxtraces.into_iter().filter(|pt_auxtrace| {
+        tid.is_none_or(|tid| pt_auxtrace.tid == tid)
+            && cpu.is_none_or(|cpu| pt_auxtrace.cpu == cpu)
+            && aux_buffer_idx.is_none_or(|idx| pt_auxtrace.idx == idx)
+    });
+    // Each thread is decoded independently. The analyzer state is reset at the
+    // beginning of each decoding, while the CFG graph and caches are kept.
+    let source = PerThreadAuxSource::from_auxtraces(pt_auxtraces.collect());
+    for (tid, segments) in source.threads() {
+        iptr_decoder::decode_segments(
+            segments.map(|segment| segment.data),
             DecodeOptions::default(),
             &mut packet_handler,
-        )?;
+        )
+        .with_context(|| format!("Failed to decode auxtraces of thread {tid}"))?;
     }
 
     Ok(())
@@ -0,0 +1,98 @@
+//! Tests of auxtrace selection over a synthetic `perf.data`.
+
+use std::{path::PathBuf, process::Command};
+
+const PERF_RECORD_AUXTRACE: u32 = 71;
+
+/// PSB and PSBEND only, which is valid without any memory
+const EMPTY_TRACE: [u8; 18] = [
+    0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82,
+    0x02, 0x23,
+];
+
+/// Trace that reads memory at 0x1000, which is not mmapped
+fn unmapped_trace() -> Vec<u8> {
+    let mut trace = EMPTY_TRACE.to_vec();
+    // TIP.PGE 0x1000
+    trace.push(0xD1);
+    trace.extend_from_slice(&0x1000u64.to_le_bytes());
+    // TNT with single not-taken bit
+    trace.push(0b100);
+    // TIP 0x1000
+    trace.push(0xCD);
+    trace.extend_from_slice(&0x1000u64.to_le_bytes());
+    trace
+}
+
+/// Build a synthetic `perf.data` with `(idx, tid, cpu, data)` auxtraces
+fn perf_data(auxtraces: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (idx, tid, cpu, auxtrace_data) in auxtraces {
+        data.extend_from_slice(&PERF_RECORD_AUXTRACE.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        // Header size does not include auxtrace data
+        data.extend_from_slice(&48u16.to_le_bytes());
+        // size, offset, reference
+        data.extend_from_slice(&(auxtrace_data.len() as u64).to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&idx.to_le_bytes());
+        data.extend_from_slice(&tid.to_le_bytes());
+        data.extend_from_slice(&cpu.to_le_bytes());
+        // reserved
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(auxtrace_data);
+    }
+    let mut perf_data = Vec::new();
+    perf_data.extend_from_slice(b"PERFILE2");
+    // size, attr_size
+    perf_data.extend_from_slice(&104u64.to_le_bytes());
+    perf_data.extend_from_slice(&0u64.to_le_bytes());
+    // attrs section
+    perf_data.extend_from_slice(&56u64.to_le_bytes());
+    perf_data.extend_from_slice(&0u64.to_le_bytes());
+    // data section
+    perf_data.extend_from_slice(&56u64.to_le_bytes());
+    perf_data.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    perf_data.extend_from_slice(&data);
+    perf_data
+}
+
+/// Run the analyzer over `input` with extra arguments, returns whether it succeeds
+fn analyze(input: &PathBuf, args: &[&str]) -> bool {
+    Command::new(env!("CARGO_BIN_EXE_iptr-perf-pt-analyzer"))
+        .arg("--input")
+        .arg(input)
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+#[test]
+fn test_select_auxtraces() {
+    let unmapped_trace = unmapped_trace();
+    let buf = perf_data(&[
+        // The AUX buffer index is not the position of the auxtrace record
+        (1, 100, 0, &EMPTY_TRACE),
+        (0, 200, 1, &unmapped_trace),
+        (1, 100, 0, &EMPTY_TRACE),
+    ]);
+    let input = std::env::temp_dir().join(format!(
+        "iptr-perf-pt-analyzer-select-{}.data",
+        std::process::id()
+    ));
+    std::fs::write(&input, buf).unwrap();
+
+    assert!(!analyze(&input, &[]));
+    assert!(analyze(&input, &["--tid", "100"]));
+    assert!(!analyze(&input, &["--tid", "200"]));
+    assert!(analyze(&input, &["--cpu", "0"]));
+    assert!(!analyze(&input, &["--cpu", "1"]));
+    assert!(analyze(&input, &["--aux-buffer-idx", "1"]));
+    assert!(!analyze(&input, &["--aux-buffer-idx", "0"]));
+    assert!(analyze(&input, &["--tid", "200", "--cpu", "0"]));
+
+    std::fs::remove_file(&input).unwrap();
+}
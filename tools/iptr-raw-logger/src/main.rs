@@ -2,7 +2,11 @@ use std::{fs::File, path::PathBuf};
 
 use anyhow::Context;
 use clap::{Parser, ValueEnum};
-use iptr_decoder::{DecodeOptions, packet_handler::log::PacketHandlerRawLogger};
+use iptr_decoder::{
+    DecodeOptions, HandlePacket,
+    packet_handler::{histogram::PacketHistogram, log::PacketHandlerRawLogger},
+};
+use iptr_perf_pt_reader::{AuxSource, PerfDataAuxSource, RawAuxSource};
 
 /// Decode target intel PT packets in the low level and logs all details.
 ///
@@ -15,6 +19,9 @@ struct Cmdline {
     /// Input file format. Default is pure Intel PT
     #[arg(short, long, value_enum)]
     format: Option<FileFormat>,
+    /// Only print the count of each packet type instead of logging each packet
+    #[arg(long)]
+    count_only: bool,
 }
 
 /// Format of input file
@@ -30,32 +37,61 @@ enum FileFormat {
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    let Cmdline { input, format } = Cmdline::parse();
+    let Cmdline {
+        input,
+        format,
+        count_only,
+    } = Cmdline::parse();
 
     let file = File::open(input).context("Failed to open input file")?;
     // SAFETY: check the safety requirements of memmap2 documentation
     let buf = unsafe { memmap2::Mmap::map(&file).context("Failed to mmap input file")? };
+    let format = format.unwrap_or(FileFormat::default());
 
-    let mut packet_handler = PacketHandlerRawLogger {};
-
-    match format.unwrap_or(FileFormat::default()) {
-        FileFormat::IntelPt => {
-            iptr_decoder::decode(&buf, DecodeOptions::default(), &mut packet_handler)?;
+    if count_only {
+        let mut histogram = PacketHistogram::new();
+        decode(&buf, format, &mut histogram)?;
+        for (packet_type, count) in histogram.iter() {
+            println!("{:<12}{count}", packet_type.to_string());
         }
+        println!("{:<12}{}", "Total", histogram.total_count());
+    } else {
+        decode(&buf, format, &mut PacketHandlerRawLogger {})?;
+    }
+
+    Ok(())
+}
+
+/// Decode the input file of given format
+fn decode<H>(buf: &[u8], format: FileFormat, packet_handler: &mut H) -> anyhow::Result<()>
+where
+    H: HandlePacket<Error: Send + Sync + 'static> + 'static,
+{
+    match format {
+        FileFormat::IntelPt => decode_source(&RawAuxSource::new(buf), packet_handler),
         FileFormat::PerfData => {
-            let pt_auxtraces = iptr_perf_pt_reader::extract_pt_auxtraces(&buf)
-                .context("Failed to parse perf.data format")?;
-            for pt_auxtrace in pt_auxtraces {
-                log::trace!("============================================");
-                log::trace!("For Intel PT AUXTRACE with index {}", pt_auxtrace.idx);
-                iptr_decoder::decode(
-                    pt_auxtrace.auxtrace_data,
-                    DecodeOptions::default(),
-                    &mut packet_handler,
-                )?;
-            }
+            let source = PerfDataAuxSource::new(buf).context("Failed to parse perf.data format")?;
+            decode_source(&source, packet_handler)
         }
     }
+}
+
+/// Decode all segments of the Intel PT source
+fn decode_source<H>(source: &impl AuxSource, packet_handler: &mut H) -> anyhow::Result<()>
+where
+    H: HandlePacket<Error: Send + Sync + 'static> + 'static,
+{
+    iptr_decoder::decode_segments(
+        source.segments().map(|segment| {
+            if let (Some(tid), Some(cpu)) = (segment.tid, segment.cpu) {
+                log::trace!("============================================");
+                log::trace!("For Intel PT AUXTRACE of thread {tid} on CPU {cpu}");
+            }
+            segment.data
+        }),
+        DecodeOptions::default(),
+        packet_handler,
+    )?;
 
     Ok(())
 }
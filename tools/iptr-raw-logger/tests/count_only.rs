@@ -0,0 +1,90 @@
+//! Tests of packet type histogram over synthetic traces.
+
+use std::{path::Path, process::Command};
+
+const PERF_RECORD_AUXTRACE: u32 = 71;
+
+/// PSB, PSBEND, TIP, short TNT, PAD, short TNT and PAD
+const TRACE: [u8; 25] = [
+    0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82,
+    0x02, 0x23, 0x2D, 0x34, 0x12, 0b110, 0x00, 0b110, 0x00,
+];
+
+/// Build a synthetic `perf.data` with given auxtraces
+fn perf_data(auxtraces: &[&[u8]]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (idx, auxtrace_data) in (0u32..).zip(auxtraces) {
+        data.extend_from_slice(&PERF_RECORD_AUXTRACE.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        // Header size does not include auxtrace data
+        data.extend_from_slice(&48u16.to_le_bytes());
+        // size, offset, reference
+        data.extend_from_slice(&(auxtrace_data.len() as u64).to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        // idx, tid, cpu, reserved
+        data.extend_from_slice(&idx.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(auxtrace_data);
+    }
+    let mut perf_data = Vec::new();
+    perf_data.extend_from_slice(b"PERFILE2");
+    // size, attr_size
+    perf_data.extend_from_slice(&104u64.to_le_bytes());
+    perf_data.extend_from_slice(&0u64.to_le_bytes());
+    // attrs section
+    perf_data.extend_from_slice(&56u64.to_le_bytes());
+    perf_data.extend_from_slice(&0u64.to_le_bytes());
+    // data section
+    perf_data.extend_from_slice(&56u64.to_le_bytes());
+    perf_data.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    perf_data.extend_from_slice(&data);
+    perf_data
+}
+
+/// Run the logger with `--count-only` over `input` of given format, returns stdout
+fn count_only(input: &Path, format: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_iptr-raw-logger"))
+        .arg("--input")
+        .arg(input)
+        .args(["--format", format, "--count-only"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_count_only() {
+    let input = std::env::temp_dir().join(format!(
+        "iptr-raw-logger-count-only-{}.pt",
+        std::process::id()
+    ));
+
+    std::fs::write(&input, TRACE).unwrap();
+    assert_eq!(
+        count_only(&input, "intel-pt"),
+        "TNT.short   2\n\
+         TIP         1\n\
+         PAD         2\n\
+         PSB         1\n\
+         PSBEND      1\n\
+         Total       7\n"
+    );
+
+    // Counts are accumulated across auxtraces
+    std::fs::write(&input, perf_data(&[&TRACE, &TRACE])).unwrap();
+    assert_eq!(
+        count_only(&input, "perf-data"),
+        "TNT.short   4\n\
+         TIP         2\n\
+         PAD         4\n\
+         PSB         2\n\
+         PSBEND      2\n\
+         Total       14\n"
+    );
+
+    std::fs::remove_file(&input).unwrap();
+}